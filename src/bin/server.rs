@@ -3,6 +3,7 @@ use futures::executor::block_on;
 use log::{error, info, LevelFilter};
 use rustls::{Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::io::{BufReader, Seek, SeekFrom};
@@ -10,7 +11,9 @@ use std::net::SocketAddrV4;
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
-use wuziqi::start_server;
+use wuziqi::{
+    start_server, ServerInfo, ServerOptions, DEFAULT_SERVER_MAX_CONN, DEFAULT_USER_MAX_CONN,
+};
 
 fn main() {
     env_logger::builder()
@@ -47,8 +50,39 @@ fn main() {
                 )
                 .expect("failed to build server config"),
         );
+        // comma separated admin user names
+        let admins: HashSet<String> = env::var("WUZIQI_ADMINS")
+            .map(|names| names.split(',').map(|n| n.trim().to_string()).collect())
+            .unwrap_or_default();
+        // maximum number of concurrent connections of one user
+        let user_max_conn = env::var("WUZIQI_USER_MAX_CONN")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(DEFAULT_USER_MAX_CONN);
+        // maximum number of concurrent connections in total
+        let server_max_conn = env::var("WUZIQI_SERVER_MAX_CONN")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(DEFAULT_SERVER_MAX_CONN);
+        // name sent to clients in `Welcome`
+        let mut server_info = ServerInfo::default();
+        if let Ok(name) = env::var("WUZIQI_SERVER_NAME") {
+            server_info.server_name = name;
+        }
         info!("server started");
-        if let Err(e) = block_on(start_server(ipv4, server_config, &Path::new(db_path))) {
+        let options = ServerOptions {
+            admins,
+            user_max_conn,
+            server_max_conn,
+            info: server_info,
+            ..ServerOptions::default()
+        };
+        if let Err(e) = block_on(start_server(
+            ipv4,
+            server_config,
+            &Path::new(db_path),
+            options,
+        )) {
             error!("server ended in error: {e}");
         }
     }
@@ -1,12 +1,11 @@
 use anyhow::{Error, Result};
-use async_std::channel::Sender;
+use async_std::channel::{bounded, Receiver, Sender};
 use async_std::io::{stdin, BufReader, Stdin};
 use async_std::net::TcpStream;
+use async_std::sync::Mutex;
 use async_std::task;
 use async_std::task::{block_on, JoinHandle};
-use futures::{join, AsyncBufReadExt, StreamExt};
-use tokio_rustls;
-use tokio_rustls::{TlsConnector, TlsStream};
+use futures::{select, AsyncBufReadExt, Future, StreamExt};
 use log::{error, info, warn, LevelFilter};
 use rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore};
 use rustls_pemfile::certs;
@@ -15,10 +14,18 @@ use std::fs::File;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio_rustls;
+use tokio_rustls::{TlsConnector, TlsStream};
 use webpki_roots;
-use wuziqi::{Color, Conn, Messages, Received, Responses, RoomState, RoomToken, SessionConfig};
+use wuziqi::{
+    render_board, BoardStyle, Color, Conn, FieldState, JoinRoomFailure, Messages, Received,
+    Responses, RoomState, RoomToken, SessionConfig,
+};
 
 const PING_INTERVAL: Option<Duration> = Some(Duration::from_secs(5));
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_BOARD_SIZE: (u8, u8) = (15, 15);
 
 fn main() {
     env_logger::builder().filter_level(LevelFilter::Info).init();
@@ -62,39 +69,97 @@ async fn run_client() -> Result<()> {
                 .with_root_certificates(root_certs)
                 .with_no_client_auth(),
         );
+        let max_attempts = env::var("CLIENT_CONNECT_ATTEMPTS")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(DEFAULT_CONNECT_ATTEMPTS);
         let tls = TlsConnector::from(config);
-        let tls = TlsStream::Client(
-            tls.connect(domain, TcpStream::connect(address).await?)
-                .await?,
+        let (input_sender, mut input) = bounded(1);
+        let last_login = Arc::new(Mutex::new(None));
+        let board_size = Arc::new(Mutex::new(DEFAULT_BOARD_SIZE));
+        accept_input(
+            stdin(),
+            input_sender,
+            last_login.clone(),
+            board_size.clone(),
         );
-        let conn = Conn::init(tls, PING_INTERVAL, 512);
-        let handle1 = accept_input(stdin(), conn.sender().clone());
-        let handle2 = print_server_responses(conn);
-        join!(handle1, handle2);
+        loop {
+            let conn = retry_with_backoff(max_attempts, INITIAL_BACKOFF, || async {
+                let tcp = TcpStream::connect(address).await?;
+                Ok(TlsStream::Client(tls.connect(domain.clone(), tcp).await?))
+            })
+            .await?;
+            let conn = Conn::init(conn, PING_INTERVAL, 512);
+            // re-authenticate on reconnection
+            let login = last_login.lock().await.clone();
+            if let Some(login) = login {
+                let _ = conn.sender().send(login).await;
+            }
+            if run_connection(conn, &mut input, &board_size).await {
+                break;
+            }
+            println!("connection lost, reconnecting");
+        }
     }
     Ok(())
 }
 
-fn accept_input(input: Stdin, sender: Sender<Messages>) -> JoinHandle<()> {
+/// retry `connect` with exponential backoff, at most `max_attempts` times
+async fn retry_with_backoff<T, F, Fut>(
+    max_attempts: u32,
+    initial_backoff: Duration,
+    mut connect: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff = initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match connect().await {
+            Ok(t) => break Ok(t),
+            Err(e) if attempt >= max_attempts => break Err(e),
+            Err(e) => {
+                warn!(
+                    "connection attempt {} failed ({}), retry in {:?}",
+                    attempt, e, backoff
+                );
+                task::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// read user input, remember the latest login for re-authentication,
+/// positions are checked against the latest known board size
+fn accept_input(
+    input: Stdin,
+    sender: Sender<Messages>,
+    last_login: Arc<Mutex<Option<Messages>>>,
+    board_size: Arc<Mutex<(u8, u8)>>,
+) -> JoinHandle<()> {
     task::spawn(async move {
         let reader = BufReader::new(input);
         let mut lines = reader.lines();
         while let Some(line) = lines.next().await {
             match line {
                 Ok(line) => {
-                    if let Some(msg) = string_to_msg(&line) {
-                        match msg {
-                            Messages::ExitGame => {
-                                let _ = sender.send(Messages::ExitGame).await;
-                                break;
+                    let size = *board_size.lock().await;
+                    match string_to_msg(&line, size) {
+                        Ok(msg) => {
+                            if let Messages::Login(_, _) = &msg {
+                                last_login.lock().await.replace(msg.clone());
                             }
-                            msg => {
-                                if sender.send(msg).await.is_err() {
-                                    info!("server closed");
-                                    break;
-                                }
+                            let is_exit = msg == Messages::ExitGame;
+                            if sender.send(msg).await.is_err() || is_exit {
+                                break;
                             }
                         }
+                        Err(ParseError::BadArguments(e)) => println!("{}", e),
+                        Err(ParseError::Unknown) => print_help(),
                     }
                 }
                 Err(e) => {
@@ -105,137 +170,237 @@ fn accept_input(input: Stdin, sender: Sender<Messages>) -> JoinHandle<()> {
     })
 }
 
-fn print_server_responses(mut conn: Conn<Messages, Responses>) -> JoinHandle<()> {
-    task::spawn(async move {
-        while let Some(rsp) = conn.next().await {
-            match rsp {
-                Received::Response(rsp) => {
+/// forward user input and print server responses,
+/// returns `true` if user exits, `false` if connection lost
+async fn run_connection(
+    conn: Conn<Messages, Responses>,
+    input: &mut Receiver<Messages>,
+    board_size: &Mutex<(u8, u8)>,
+) -> bool {
+    let sender = conn.sender().clone();
+    let mut conn = conn.fuse();
+    loop {
+        select! {
+            rsp = conn.next() => match rsp {
+                Some(Received::Response(rsp)) => {
+                    if let Some(size) = board_size_of(&rsp) {
+                        *board_size.lock().await = size;
+                    }
+                    let started = matches!(rsp, Responses::GameStarted(_));
                     println!("{}", rsp_to_string(rsp));
+                    // the board is shown, the clock may start
+                    if started {
+                        let _ = sender.send(Messages::Ack).await;
+                    }
                 }
-                Received::Ping => {}
-                Received::Error(e) => {
+                Some(Received::Ping) => {}
+                Some(Received::Reconnected) => println!("reconnected"),
+                Some(Received::Closed) => {}
+                Some(Received::Error(e)) => {
                     error!("connection error: {}", e);
-                    break;
+                    break false;
                 }
-                Received::RemoteError(e) => {
+                Some(Received::RemoteError(e)) => {
                     error!("server side connection error: {}", e);
-                    break;
+                    break false;
                 }
-            }
+                None => {
+                    println!("connection closed");
+                    break false;
+                }
+            },
+            msg = input.next() => match msg {
+                Some(Messages::ExitGame) => {
+                    let _ = sender.send(Messages::ExitGame).await;
+                    break true;
+                }
+                Some(msg) => {
+                    if sender.send(msg).await.is_err() {
+                        info!("server closed");
+                        break false;
+                    }
+                }
+                None => break true,
+            },
         }
-        println!("connection closed");
-    })
+    }
+}
+
+/// error of parsing user input
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    /// the command is recognized, but its arguments are not
+    BadArguments(String),
+    /// the command is not recognized
+    Unknown,
 }
 
-fn string_to_msg(msg: &str) -> Option<Messages> {
+fn string_to_msg(msg: &str, (width, height): (u8, u8)) -> Result<Messages, ParseError> {
     let msg = msg.to_lowercase();
+    let bad_args = |usage: &str| Err(ParseError::BadArguments(format!("usage: {}", usage)));
     if msg.starts_with("new room") {
-        Some(Messages::CreateRoom(SessionConfig {
-            undo_request_timeout: 10,
-            undo_dialogue_extra_seconds: 5,
-            play_timeout: 0,
-        }))
+        let password = msg.splitn(3, ' ').nth(2).map(|p| p.to_string());
+        Ok(Messages::CreateRoom(room_config(0), password))
     } else if msg.starts_with("join") {
-        match msg.splitn(2, " ").last() {
-            None => {
-                print_help();
-                None
-            }
-            Some(token) => match RoomToken::from_code(token) {
-                Ok(token) => Some(Messages::JoinRoom(token)),
-                Err(e) => {
-                    println!("invalid token: {}", e);
-                    None
-                }
-            },
+        let cmd: Vec<&str> = msg.splitn(3, ' ').collect();
+        if cmd.len() < 2 {
+            return bad_args("join 'token' ['password']");
         }
+        match RoomToken::from_code(cmd[1]) {
+            Ok(token) => Ok(Messages::JoinRoom(token, cmd.get(2).map(|p| p.to_string()))),
+            Err(e) => Err(ParseError::BadArguments(format!("invalid token: {}", e))),
+        }
+    } else if msg.starts_with("query") {
+        let cmd: Vec<&str> = msg.split_whitespace().collect();
+        if cmd.len() != 2 {
+            return bad_args("query 'token'");
+        }
+        match RoomToken::from_code(cmd[1]) {
+            Ok(token) => Ok(Messages::QueryRoom(token)),
+            Err(e) => Err(ParseError::BadArguments(format!("invalid token: {}", e))),
+        }
+    } else if msg.starts_with("peek") {
+        let cmd: Vec<&str> = msg.splitn(3, ' ').collect();
+        if cmd.len() < 2 {
+            return bad_args("peek 'token' ['password']");
+        }
+        match RoomToken::from_code(cmd[1]) {
+            Ok(token) => Ok(Messages::PeekGame(token, cmd.get(2).map(|p| p.to_string()))),
+            Err(e) => Err(ParseError::BadArguments(format!("invalid token: {}", e))),
+        }
+    } else if msg.starts_with("subscribe") {
+        Ok(Messages::SubscribeLobby)
+    } else if msg.starts_with("unsubscribe") {
+        Ok(Messages::UnsubscribeLobby)
     } else if msg.starts_with("login") {
         let cmd: Vec<String> = msg.splitn(3, " ").map(|x| x.to_string()).collect();
         if cmd.len() < 3 {
-            print_help();
-            None
+            bad_args("login name password")
         } else {
-            Some(Messages::Login(cmd[1].clone(), cmd[2].clone()))
+            Ok(Messages::Login(cmd[1].clone(), cmd[2].clone()))
         }
     } else if msg.starts_with("register") {
         let cmd: Vec<String> = msg.splitn(3, " ").map(|x| x.to_string()).collect();
         if cmd.len() < 3 {
-            print_help();
-            None
+            bad_args("register name password")
         } else {
-            Some(Messages::CreateAccount(cmd[1].clone(), cmd[2].clone()))
+            Ok(Messages::CreateAccount(cmd[1].clone(), cmd[2].clone()))
         }
     } else if msg.starts_with("update") {
         let cmd: Vec<String> = msg.splitn(4, " ").map(|x| x.to_string()).collect();
         if cmd.len() < 4 {
-            print_help();
-            None
+            bad_args("update name old_password new_password")
         } else {
-            Some(Messages::UpdateAccount(
+            Ok(Messages::UpdateAccount(
                 cmd[1].clone(),
                 cmd[2].clone(),
                 cmd[3].clone(),
             ))
         }
     } else if msg.starts_with("quit room") {
-        Some(Messages::QuitRoom)
+        Ok(Messages::QuitRoom)
+    } else if msg.starts_with("timeout") {
+        let cmd: Vec<&str> = msg.split_whitespace().collect();
+        match cmd.get(1).map(|t| u64::from_str(t)) {
+            Some(Ok(play_timeout)) if cmd.len() == 2 => {
+                Ok(Messages::UpdateRoomConfig(room_config(play_timeout)))
+            }
+            _ => bad_args("timeout 'seconds', where 0 means no timeout"),
+        }
+    } else if msg.starts_with("ban") {
+        let cmd: Vec<&str> = msg.splitn(2, ' ').collect();
+        if cmd.len() < 2 {
+            bad_args("ban name")
+        } else {
+            Ok(Messages::BanFromRoom(cmd[1].to_string()))
+        }
     } else if msg.starts_with("ready") {
-        Some(Messages::Ready)
+        Ok(Messages::Ready)
     } else if msg.starts_with("unready") {
-        Some(Messages::Unready)
+        Ok(Messages::Unready)
     } else if msg.starts_with("play") {
-        let cmd: Vec<String> = msg.splitn(3, " ").map(|x| x.to_string()).collect();
-        if cmd.len() < 3 {
-            print_help();
-            None
-        } else {
-            match (u8::from_str(&cmd[1]), u8::from_str(&cmd[2])) {
-                (Ok(x), Ok(y)) => Some(Messages::Play(x, y)),
-                _ => {
-                    print_help();
-                    None
-                }
-            }
-        }
+        let cmd: Vec<&str> = msg.split_whitespace().collect();
+        if cmd.len() != 3 {
+            return bad_args("play 'x' 'y'");
+        }
+        match (u8::from_str(cmd[1]), u8::from_str(cmd[2])) {
+            (Ok(x), Ok(y)) if x < width && y < height => Ok(Messages::Play(x, y)),
+            (Ok(x), Ok(y)) => Err(ParseError::BadArguments(format!(
+                "({}, {}) is out of the {} x {} board",
+                x, y, width, height
+            ))),
+            _ => bad_args("play 'x' 'y', where x and y are numbers"),
+        }
+    } else if msg.starts_with("confirm moves") {
+        Ok(Messages::SetConfirmMoves(!msg.ends_with("off")))
+    } else if msg.starts_with("confirm") {
+        Ok(Messages::ConfirmMove)
+    } else if msg.starts_with("cancel") {
+        Ok(Messages::CancelMove)
     } else if msg.starts_with("request undo") {
-        Some(Messages::RequestUndo)
+        Ok(Messages::RequestUndo)
     } else if msg.starts_with("approve undo") {
-        Some(Messages::ApproveUndo)
+        Ok(Messages::ApproveUndo)
+    } else if msg.starts_with("request redo") {
+        Ok(Messages::RequestRedo)
+    } else if msg.starts_with("approve redo") {
+        Ok(Messages::ApproveRedo)
+    } else if msg.starts_with("reject redo") {
+        Ok(Messages::RejectRedo)
     } else if msg.starts_with("search") {
         let cmd: Vec<String> = msg.splitn(2, " ").map(|x| x.to_string()).collect();
         if cmd.len() < 2 {
-            Some(Messages::SearchOnlinePlayers(None, 20))
+            Ok(Messages::SearchOnlinePlayers(None, 20))
         } else {
-            Some(Messages::SearchOnlinePlayers(Some(cmd[1].clone()), 20))
+            Ok(Messages::SearchOnlinePlayers(Some(cmd[1].clone()), 20))
         }
     } else if msg.starts_with("reject undo") {
-        Some(Messages::RejectUndo)
+        Ok(Messages::RejectUndo)
     } else if msg.starts_with("quit session") {
-        Some(Messages::QuitGameSession)
+        Ok(Messages::QuitGameSession)
     } else if msg.starts_with("chat") {
-        match msg.splitn(2, " ").last() {
-            None => {
-                print_help();
-                None
-            }
-            Some(msg) => Some(Messages::ChatMessage(msg.to_string())),
+        match msg.split_once(" ") {
+            None => bad_args("chat 'msg'"),
+            Some((_, msg)) => Ok(Messages::ChatMessage(msg.to_string())),
         }
+    } else if msg.starts_with("leave") {
+        Ok(Messages::Leave)
     } else if msg.starts_with("exit") {
-        Some(Messages::ExitGame)
+        Ok(Messages::ExitGame)
     } else if msg.starts_with("to") {
         let messages: Vec<&str> = msg.splitn(3, " ").collect();
         if messages.len() < 3 {
-            print_help();
-            None
+            bad_args("to 'player' 'msg'")
         } else {
-            Some(Messages::ToPlayer(
+            Ok(Messages::ToPlayer(
                 messages[1].to_string(),
                 Vec::from(messages[2]),
             ))
         }
     } else {
-        print_help();
-        None
+        Err(ParseError::Unknown)
+    }
+}
+
+/// config of rooms created or updated by this client
+fn room_config(play_timeout: u64) -> SessionConfig {
+    SessionConfig::builder()
+        .undo_request_timeout(10)
+        .undo_dialogue_extra_seconds(5)
+        .play_timeout(play_timeout)
+        .build()
+}
+
+/// board size learned from server responses
+fn board_size_of(rsp: &Responses) -> Option<(u8, u8)> {
+    match rsp {
+        Responses::RoomInfo { session_config, .. } => {
+            Some((session_config.board_width, session_config.board_height))
+        }
+        Responses::RoomConfigUpdated(config) => Some((config.board_width, config.board_height)),
+        Responses::FieldUpdate(f) => Some((f.field.width() as u8, f.field.height() as u8)),
+        Responses::Undo(f) => Some((f.field.width() as u8, f.field.height() as u8)),
+        _ => None,
     }
 }
 
@@ -246,22 +411,41 @@ fn print_help() {
         - register name password\n\
         - update name password\n\
         - to `player` `msg`\n\
-        - new room\n\
+        - new room ['password']\n\
         - search 'name'\n\
-        - join 'token'\n\
+        - join 'token' ['password']\n\
+        - query 'token'\n\
+        - peek 'token' ['password']\n\
+        - subscribe\n\
+        - unsubscribe\n\
         - quit room\n\
+        - ban name\n\
+        - timeout 'seconds'\n\
         - ready\n\
         - unready\n\
         - play 'x' 'y'\n\
+        - confirm moves [on|off]\n\
+        - confirm\n\
+        - cancel\n\
         - request undo\n\
         - approve undo\n\
         - reject undo\n\
+        - request redo\n\
+        - approve redo\n\
+        - reject redo\n\
         - quit session\n\
         - chat 'msg'\n\
+        - leave\n\
         - exit"
     );
 }
 
+/// the board with the latest move bracketed
+fn render_field(f: &FieldState) -> String {
+    let (x, y, _) = f.latest;
+    render_board(&f.field, Some((x, y)), BoardStyle::default())
+}
+
 fn rsp_to_string(rsp: Responses) -> String {
     match rsp {
         Responses::LoginSuccess(name) => format!("{} login success", name),
@@ -288,11 +472,55 @@ fn rsp_to_string(rsp: Responses) -> String {
                 )
             }
         },
-        Responses::JoinRoomFailureTokenNotFound => "room token does not exit".to_string(),
-        Responses::JoinRoomFailureRoomFull => "cannot join room. room is full.".to_string(),
+        Responses::RoomCreationRateLimited => {
+            "too many rooms created recently, try later".to_string()
+        }
+        Responses::ServerDraining => "server draining, finish the game and leave".to_string(),
+        Responses::RoomClosed => "room closed by server".to_string(),
+        Responses::JoinRoomFailure(JoinRoomFailure::TokenNotFound) => {
+            "room token does not exit".to_string()
+        }
+        Responses::JoinRoomFailure(JoinRoomFailure::RoomFull) => {
+            "cannot join room. room is full.".to_string()
+        }
+        Responses::JoinRoomFailure(JoinRoomFailure::BadPassword) => {
+            "cannot join room. wrong password.".to_string()
+        }
+        Responses::JoinRoomFailure(JoinRoomFailure::Banned) => {
+            "cannot join room. banned from this room.".to_string()
+        }
+        Responses::JoinRoomFailure(JoinRoomFailure::RoomClosed) => {
+            "cannot join room. room closed recently.".to_string()
+        }
+        Responses::RoomInfo {
+            occupants,
+            session_config,
+            in_game,
+        } => format!(
+            "room info: players {:?}, config {:?}, in game: {}",
+            occupants, session_config, in_game
+        ),
+        Responses::GameSnapshot {
+            field,
+            black_clock,
+            white_clock,
+            to_move,
+        } => format!(
+            "game snapshot: {:?} to move, clocks (ms) black {:?} white {:?}\n{}",
+            to_move,
+            black_clock,
+            white_clock,
+            render_board(&field, None, BoardStyle::default())
+        ),
+        Responses::NoGameInProgress => "no game in progress in the room".to_string(),
+        Responses::RoomListUpdate(summary) => format!(
+            "room {} ({} players, {:?}): {:?}",
+            summary.token, summary.occupants, summary.status, summary.session_config
+        ),
         Responses::OpponentJoinRoom(name) => {
             format!("opponent ({}) joins room", name)
         }
+        Responses::RoomReady => "room is full, get ready to play".to_string(),
         Responses::OpponentQuitRoom => {
             format!("opponent quits room")
         }
@@ -307,20 +535,78 @@ fn rsp_to_string(rsp: Responses) -> String {
             Color::White => format!("game started, your play O, (X first)"),
         },
         Responses::FieldUpdate(f) => {
-            format!("field updated:\n{:?}", f)
-        }
+            format!("field updated:\n{}", render_field(&f))
+        }
+        Responses::InvalidMove(x, y) => format!("invalid move ({}, {})", x, y),
+        Responses::MovePending(x, y) => format!("move ({}, {}) pending, confirm or cancel", x, y),
+        Responses::MoveCancelled => "pending move cancelled".to_string(),
+        Responses::NotYourTurn => "not your turn".to_string(),
+        Responses::MovingTooFast => "moving too fast, move ignored".to_string(),
+        Responses::SpectateDenied => "spectating denied, room password required".to_string(),
+        Responses::RedoRequest => "received redo request".to_string(),
+        Responses::RedoTimeoutRejected => "redo request rejected by timeout".to_string(),
+        Responses::RedoAutoRejected => "redo request invalid".to_string(),
+        Responses::Redo(f) => format!("redo permitted:\n{}", render_field(&f)),
+        Responses::RedoRejectedByOpponent => "redo request rejected".to_string(),
+        Responses::RedoRequestInProgress => {
+            "redo request rejected, opponent is requesting undo or redo".to_string()
+        }
+        Responses::GameSeed(seed) => format!("game seed: {}", seed),
+        Responses::CannotReadyEmptyRoom => "cannot ready before an opponent joins".to_string(),
+        Responses::OpponentKicked(reason) => format!("opponent kicked: {}", reason),
+        Responses::SearchRejected => "search query rejected".to_string(),
+        Responses::ConnectionClosing => "connection closing".to_string(),
+        Responses::TurnPassed(color) => match color {
+            Color::Black => "black player timeout, white to move".to_string(),
+            Color::White => "white player timeout, black to move".to_string(),
+        },
+        Responses::Batch(rsps) => rsps
+            .into_iter()
+            .map(rsp_to_string)
+            .collect::<Vec<_>>()
+            .join("\n"),
         Responses::UndoRequest => "received undo request".to_string(),
         Responses::UndoTimeoutRejected => "undo request rejected by timeout".to_string(),
         Responses::UndoAutoRejected => "undo request invalid".to_string(),
         Responses::Undo(f) => {
-            format!("undo permitted:\n{:?}", f)
+            let latest = f.latest.map(|(x, y, _)| (x, y));
+            let board = render_board(&f.field, latest, BoardStyle::default());
+            format!("undo permitted:\n{}", board)
         }
         Responses::UndoRejectedByOpponent => "undo request rejected".to_string(),
+        Responses::UndoRequestInProgress => {
+            "undo request rejected, opponent is requesting undo".to_string()
+        }
         Responses::GameEndBlackTimeout => "black player timeout".to_string(),
         Responses::GameEndWhiteTimeout => "white player timeout".to_string(),
         Responses::GameEndBlackWins => "black player wins".to_string(),
         Responses::GameEndWhiteWins => "white player wins".to_string(),
         Responses::GameEndDraw => "game end: Draw".to_string(),
+        Responses::GameEndAborted => "game aborted before any move".to_string(),
+        Responses::GameEndWithBoard { result, field } => {
+            format!("game end: {}\n{:?}", result, field)
+        }
+        Responses::GameSummary {
+            moves,
+            duration_ms,
+            black_time_ms,
+            white_time_ms,
+            result,
+        } => format!(
+            "game summary: {} in {} moves, {:.1}s (black {:.1}s, white {:.1}s)",
+            result,
+            moves,
+            duration_ms as f64 / 1000.0,
+            black_time_ms as f64 / 1000.0,
+            white_time_ms as f64 / 1000.0
+        ),
+        Responses::GameEndPending { result, field } => {
+            format!(
+                "game end pending: {}, undo to resume\n{}",
+                result,
+                render_field(&field)
+            )
+        }
         Responses::RoomScores((n1, p1), (n2, p2)) => {
             format!("score update ({}: {} / {}: {})", n1, p1, n2, p2)
         }
@@ -368,8 +654,138 @@ fn rsp_to_string(rsp: Responses) -> String {
         Responses::QuitRoomSuccess => {
             format!("quit room success")
         }
+        Responses::BannedFromRoom => "banned from room by the room creator".to_string(),
         Responses::QuitGameSessionSuccess => {
             format!("quit session success")
         }
+        Responses::ClientErrorAcknowledged => "client error acknowledged".to_string(),
+        Responses::RoomDebug(debug) => debug,
+        Responses::PermissionDenied => "permission denied".to_string(),
+        Responses::Welcome {
+            server_name,
+            protocol_version,
+            features,
+        } => format!(
+            "welcome to {} (protocol {}), features: {:?}",
+            server_name, protocol_version, features
+        ),
+        Responses::TimeControl {
+            play_timeout,
+            undo_request_timeout,
+            undo_dialogue_extra_seconds,
+            final_undo_window,
+        } => format!(
+            "time control (s): play {}, undo request {} (+{} after), final undo window {}",
+            play_timeout, undo_request_timeout, undo_dialogue_extra_seconds, final_undo_window
+        ),
+        Responses::RoomConfigProposed(config) => format!(
+            "opponent proposes play timeout {}s, send `timeout {}` to agree",
+            config.play_timeout, config.play_timeout
+        ),
+        Responses::RoomConfigUpdated(config) => {
+            format!("room config updated: {:?}", config)
+        }
+        Responses::InvalidRoomConfig => "room config invalid, ignored".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test_client {
+    use super::*;
+    use async_std::net::TcpListener;
+    use std::time::Instant;
+
+    #[test]
+    fn test_connect_to_late_server() {
+        let address = "127.0.0.1:9997";
+        // server comes up slightly late
+        let server = task::spawn(async move {
+            task::sleep(Duration::from_millis(300)).await;
+            let listener = TcpListener::bind(address).await.unwrap();
+            let _ = listener.accept().await.unwrap();
+        });
+        let start = Instant::now();
+        let mut attempts = 0;
+        let connected = block_on(retry_with_backoff(5, Duration::from_millis(100), || {
+            attempts += 1;
+            async { Ok(TcpStream::connect(address).await?) }
+        }));
+        assert!(connected.is_ok());
+        assert!(attempts > 1);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        block_on(server);
+    }
+
+    #[test]
+    fn test_give_up_after_max_attempts() {
+        let mut attempts = 0;
+        let result: Result<()> = block_on(retry_with_backoff(3, Duration::from_millis(10), || {
+            attempts += 1;
+            async { Err(Error::msg("unreachable server")) }
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_parse_valid_commands() {
+        assert_eq!(
+            string_to_msg("play 3 4", (15, 15)),
+            Ok(Messages::Play(3, 4))
+        );
+        assert_eq!(
+            string_to_msg("Play 17 18", (20, 20)),
+            Ok(Messages::Play(17, 18))
+        );
+        assert_eq!(
+            string_to_msg("chat hello there", (15, 15)),
+            Ok(Messages::ChatMessage("hello there".to_string()))
+        );
+        assert_eq!(string_to_msg("ready", (15, 15)), Ok(Messages::Ready));
+        assert_eq!(string_to_msg("leave", (15, 15)), Ok(Messages::Leave));
+        assert!(matches!(
+            string_to_msg("new room secret", (15, 15)),
+            Ok(Messages::CreateRoom(_, Some(password))) if password == "secret"
+        ));
+        assert!(matches!(
+            string_to_msg("new room", (15, 15)),
+            Ok(Messages::CreateRoom(_, None))
+        ));
+    }
+
+    #[test]
+    fn test_parse_bad_arguments() {
+        assert_eq!(
+            string_to_msg("play 15 3", (15, 15)),
+            Err(ParseError::BadArguments(
+                "(15, 3) is out of the 15 x 15 board".to_string()
+            ))
+        );
+        assert!(matches!(
+            string_to_msg("play 99 99", (15, 15)),
+            Err(ParseError::BadArguments(_))
+        ));
+        assert!(matches!(
+            string_to_msg("play 3", (15, 15)),
+            Err(ParseError::BadArguments(_))
+        ));
+        assert!(matches!(
+            string_to_msg("play a b", (15, 15)),
+            Err(ParseError::BadArguments(_))
+        ));
+        assert!(matches!(
+            string_to_msg("join", (15, 15)),
+            Err(ParseError::BadArguments(_))
+        ));
+        assert!(matches!(
+            string_to_msg("login name", (15, 15)),
+            Err(ParseError::BadArguments(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_unknown_command() {
+        assert_eq!(string_to_msg("dance", (15, 15)), Err(ParseError::Unknown));
+        assert_eq!(string_to_msg("", (15, 15)), Err(ParseError::Unknown));
     }
 }
@@ -1,10 +1,10 @@
 use crate::game::game_field::field::{Field, GameState};
 use crate::game::game_field::Color;
-use crate::game::session::{FieldState, FieldStateNullable};
+use crate::game::session::{FieldState, FieldStateNullable, GameResult, SimultaneousWin};
 use crate::{FieldInner, CHANNEL_SIZE};
 use anyhow::{Error, Result};
 use async_std::channel::{bounded, Receiver, Sender};
-use async_std::task;
+use async_std::task::{self, JoinHandle};
 use futures::StreamExt;
 use log::error;
 #[allow(unused_imports)]
@@ -13,8 +13,14 @@ use std::collections::VecDeque;
 
 #[derive(Debug)]
 pub(crate) enum GameCommand {
-    Do { x: u8, y: u8, color: Color },
+    Do {
+        x: u8,
+        y: u8,
+        color: Color,
+    },
     Undo,
+    /// replay the latest undone move
+    Redo,
     Kill,
 }
 
@@ -22,23 +28,109 @@ pub(crate) enum GameCommand {
 pub(crate) enum GameResponse {
     /// color in field indicates the color of the latest step
     Field(FieldState),
-    BlackWins,
-    WhiteWins,
-    Draw,
+    /// the final field together with the result
+    GameEnd(FieldState, GameResult),
     Undo(FieldStateNullable),
+    /// the undone move is replayed, followed by `GameEnd` if it ends the game
+    Redo(FieldState),
+    /// the move led to an impossible state and was rolled back
+    InvalidMove(u8, u8, Color),
     GameError(String),
 }
 
+/// Moves played on a field, bounded by the number of cells.
+///
+/// Each accepted move occupies an empty cell, so exceeding the bound
+/// indicates a bug in move validation.
+///
+/// Undone moves are kept for redo until a new move is played.
+struct History {
+    moves: VecDeque<(u8, u8, Color)>,
+    undone: Vec<(u8, u8, Color)>,
+    cell_count: usize,
+}
+
+impl History {
+    fn new(width: u8, height: u8) -> Self {
+        let cell_count = width as usize * height as usize;
+        History {
+            moves: VecDeque::with_capacity(cell_count),
+            undone: Vec::new(),
+            cell_count,
+        }
+    }
+
+    /// error if the history is already as long as the cell count
+    fn check_capacity(&self) -> Result<()> {
+        if self.moves.len() < self.cell_count {
+            Ok(())
+        } else {
+            Err(Error::msg("history exceeds cell count"))
+        }
+    }
+
+    /// a new move, which cannot be followed by redo
+    fn push(&mut self, step: (u8, u8, Color)) {
+        debug_assert!(self.moves.len() < self.cell_count);
+        self.undone.clear();
+        self.moves.push_back(step);
+    }
+
+    /// undo the latest move, keeping it for redo
+    fn pop(&mut self) -> Option<(u8, u8, Color)> {
+        let step = self.moves.pop_back()?;
+        self.undone.push(step);
+        Some(step)
+    }
+
+    /// the latest undone move back into the history
+    fn redo(&mut self) -> Option<(u8, u8, Color)> {
+        let step = self.undone.pop()?;
+        debug_assert!(self.moves.len() < self.cell_count);
+        self.moves.push_back(step);
+        Some(step)
+    }
+
+    fn latest(&self) -> Option<(u8, u8, Color)> {
+        self.moves.back().copied()
+    }
+}
+
 /// Start a new field.
 ///
 /// This actor stops when it is gets a `Kill` signal or when its response receiver
 /// gets dropped.
-pub(crate) fn new_field(session_id: u64) -> (Sender<GameCommand>, Receiver<GameResponse>) {
+///
+/// With `recover_impossible`, a move leading to an impossible state
+/// is rolled back and answered with `InvalidMove` instead of `GameError`.
+///
+/// With `early_draw`, the game is a draw once neither color can get five in a row.
+///
+/// A move completing five in a row for both colors is scored by `simultaneous_win`.
+///
+/// The `opening` is placed on the field without entering the history,
+/// so it cannot be undone.
+pub(crate) fn new_field(
+    session_id: u64,
+    width: u8,
+    height: u8,
+    recover_impossible: bool,
+    early_draw: bool,
+    simultaneous_win: SimultaneousWin,
+    opening: &[(u8, u8, Color)],
+) -> (Sender<GameCommand>, Receiver<GameResponse>, JoinHandle<()>) {
     let (cmd_s, mut commands) = bounded(CHANNEL_SIZE);
     let (response, rsp_r) = bounded(CHANNEL_SIZE);
-    let mut history = VecDeque::with_capacity(225);
-    let mut field = Field::new();
-    task::spawn(async move {
+    let mut history = History::new(width, height);
+    let mut field = Field::new(width, height)
+        .with_early_draw(early_draw)
+        .with_simultaneous_win(simultaneous_win);
+    for &(x, y, color) in opening {
+        if let Err(e) = field.play(x as usize, y as usize, color) {
+            error!("game no {} opening error: {}", session_id, e);
+        }
+    }
+    let handle = task::spawn(async move {
         while let Some(command) = commands.next().await {
             #[cfg(debug_assertions)]
             trace!(
@@ -46,9 +138,16 @@ pub(crate) fn new_field(session_id: u64) -> (Sender<GameCommand>, Receiver<GameR
                 session_id,
                 command
             );
-            if execute_command(session_id, &mut field, command, &response, &mut history)
-                .await
-                .is_err()
+            if execute_command(
+                session_id,
+                &mut field,
+                command,
+                &response,
+                &mut history,
+                recover_impossible,
+            )
+            .await
+            .is_err()
             {
                 #[cfg(debug_assertions)]
                 trace!("field thread of game {} stopped on err", session_id);
@@ -58,7 +157,7 @@ pub(crate) fn new_field(session_id: u64) -> (Sender<GameCommand>, Receiver<GameR
         #[cfg(debug_assertions)]
         trace!("field thread of game {} stopped", session_id);
     });
-    (cmd_s, rsp_r)
+    (cmd_s, rsp_r, handle)
 }
 
 /// the error of this function means game killed or receivers dropped, just exit
@@ -67,14 +166,20 @@ async fn execute_command(
     field: &mut Field,
     command: GameCommand,
     response: &Sender<GameResponse>,
-    history: &mut VecDeque<(u8, u8, Color)>,
+    history: &mut History,
+    recover_impossible: bool,
 ) -> Result<()> {
     match command {
         GameCommand::Do { x, y, color } => {
-            let _ = do_play(game_id, field, x, y, color, history, response).await;
+            let _ = if recover_impossible {
+                do_play_or_roll_back(game_id, field, x, y, color, history, response).await
+            } else {
+                do_play(game_id, field, x, y, color, history, response).await
+            };
             Ok(())
         }
         GameCommand::Undo => undo_play(game_id, field, history, response).await,
+        GameCommand::Redo => redo_play(game_id, field, history, response).await,
         GameCommand::Kill => Err(Error::msg("game killed")),
     }
 }
@@ -85,30 +190,90 @@ async fn do_play(
     x: u8,
     y: u8,
     color: Color,
-    history: &mut VecDeque<(u8, u8, Color)>,
+    history: &mut History,
     response: &Sender<GameResponse>,
 ) -> Result<()> {
-    if let Err(e) = field.play(x as usize, y as usize, color) {
+    let played = history
+        .check_capacity()
+        .and_then(|_| field.play(x as usize, y as usize, color));
+    if let Err(e) = played {
         send_unlikely_error(e, game_id, response).await
     } else {
-        history.push_back((x, y, color));
+        history.push((x, y, color));
         send_game_state(x, y, color, field, response).await
     }
 }
 
+/// like `do_play`, but roll back the move if it leads to an impossible state
+async fn do_play_or_roll_back(
+    game_id: u64,
+    field: &mut Field,
+    x: u8,
+    y: u8,
+    color: Color,
+    history: &mut History,
+    response: &Sender<GameResponse>,
+) -> Result<()> {
+    let played = history
+        .check_capacity()
+        .and_then(|_| field.play(x as usize, y as usize, color));
+    if let Err(e) = played {
+        return send_unlikely_error(e, game_id, response).await;
+    }
+    if *field.get_field_state() != GameState::Impossible {
+        history.push((x, y, color));
+        return send_game_state(x, y, color, field, response).await;
+    }
+    error!(
+        "game no {} reached impossible state by {:?} at ({}, {}), rolling back:\n{:?}",
+        game_id,
+        color,
+        x,
+        y,
+        FieldState {
+            latest: (x, y, color),
+            field: FieldInner(field.get_field().clone()),
+        }
+    );
+    if let Err(e) = field.clear(x as usize, y as usize) {
+        send_unlikely_error(e, game_id, response).await
+    } else {
+        Ok(response
+            .send(GameResponse::InvalidMove(x, y, color))
+            .await?)
+    }
+}
+
 /// the error of this function can only come from being receivers being closed, just exit
 async fn undo_play(
     game_id: u64,
     field: &mut Field,
-    history: &mut VecDeque<(u8, u8, Color)>,
+    history: &mut History,
     response: &Sender<GameResponse>,
 ) -> Result<()> {
-    if let Some((x, y, _)) = history.pop_back() {
+    if let Some((x, y, _)) = history.pop() {
         if let Err(e) = field.clear(x as usize, y as usize) {
             send_unlikely_error(e, game_id, response).await
         } else {
-            let prev = history.back().map(|&(x, y, c)| (x, y, c));
-            send_undo_state(prev, field, response).await
+            send_undo_state(history.latest(), field, response).await
+        }
+    } else {
+        Ok(())
+    }
+}
+
+/// the error of this function can only come from being receivers being closed, just exit
+async fn redo_play(
+    game_id: u64,
+    field: &mut Field,
+    history: &mut History,
+    response: &Sender<GameResponse>,
+) -> Result<()> {
+    if let Some((x, y, color)) = history.redo() {
+        if let Err(e) = field.play(x as usize, y as usize, color) {
+            send_unlikely_error(e, game_id, response).await
+        } else {
+            send_redo_state(x, y, color, field, response).await
         }
     } else {
         Ok(())
@@ -123,25 +288,30 @@ async fn send_game_state(
     field: &Field,
     response: &Sender<GameResponse>,
 ) -> Result<()> {
-    // send field update
-    response
-        .send(GameResponse::Field(FieldState {
-            latest: (x, y, color),
-            field: FieldInner(*field.get_field()),
-        }))
-        .await?;
-    // send field state
-    match field.get_field_state() {
-        GameState::BlackWins => response.send(GameResponse::BlackWins).await?,
-        GameState::WhiteWins => response.send(GameResponse::WhiteWins).await?,
-        GameState::Draw => response.send(GameResponse::Draw).await?,
+    let field_state = FieldState {
+        latest: (x, y, color),
+        field: FieldInner(field.get_field().clone()),
+    };
+    // the final field is sent along with the result in a single response
+    let result = match field.get_field_state() {
+        GameState::BlackWins => GameResult::BlackWins,
+        GameState::WhiteWins => GameResult::WhiteWins,
+        GameState::Draw => GameResult::Draw,
         GameState::Impossible => {
+            response.send(GameResponse::Field(field_state)).await?;
             response
                 .send(GameResponse::GameError("impossible_game_state".to_string()))
-                .await?
+                .await?;
+            return Ok(());
         }
-        GameState::UnFinished => {}
-    }
+        GameState::UnFinished => {
+            response.send(GameResponse::Field(field_state)).await?;
+            return Ok(());
+        }
+    };
+    response
+        .send(GameResponse::GameEnd(field_state, result))
+        .await?;
     Ok(())
 }
 
@@ -154,11 +324,33 @@ async fn send_undo_state(
     Ok(response
         .send(GameResponse::Undo(FieldStateNullable {
             latest: prev,
-            field: FieldInner(*field.get_field()),
+            field: FieldInner(field.get_field().clone()),
         }))
         .await?)
 }
 
+/// a game ending move undone within `final_undo_window` ends the game again
+#[inline(always)]
+async fn send_redo_state(
+    x: u8,
+    y: u8,
+    color: Color,
+    field: &Field,
+    response: &Sender<GameResponse>,
+) -> Result<()> {
+    response
+        .send(GameResponse::Redo(FieldState {
+            latest: (x, y, color),
+            field: FieldInner(field.get_field().clone()),
+        }))
+        .await?;
+    if *field.get_field_state() == GameState::UnFinished {
+        Ok(())
+    } else {
+        send_game_state(x, y, color, field, response).await
+    }
+}
+
 /// the error of this function can only come from being receivers being closed, just exit
 #[cold]
 async fn send_unlikely_error(
@@ -171,3 +363,175 @@ async fn send_unlikely_error(
         .send(GameResponse::GameError(e.to_string()))
         .await?)
 }
+
+#[cfg(test)]
+mod test_field_api {
+    use super::*;
+    use crate::game::game_field::State;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_history_bounded_by_cell_count() {
+        let (width, height) = (3, 4);
+        let mut field = Field::new(width, height);
+        let mut history = History::new(width, height);
+        let (response, rsp_r) = bounded(CHANNEL_SIZE * 4);
+        let mut color = Color::Black;
+        block_on(async {
+            // fill the board, then try every cell again
+            for _ in 0..2 {
+                for x in 0..width {
+                    for y in 0..height {
+                        let command = GameCommand::Do { x, y, color };
+                        execute_command(0, &mut field, command, &response, &mut history, false)
+                            .await
+                            .unwrap();
+                        assert!(history.moves.len() <= history.cell_count);
+                        color = color.switch();
+                        while rsp_r.try_recv().is_ok() {}
+                    }
+                }
+            }
+        });
+        assert_eq!(history.moves.len(), 12);
+        assert!(history.check_capacity().is_err());
+    }
+
+    #[test]
+    fn test_redo_cleared_by_new_move() {
+        let mut field = Field::new(15, 15);
+        let mut history = History::new(15, 15);
+        let (response, rsp_r) = bounded(CHANNEL_SIZE);
+        let black = Color::Black;
+        block_on(async {
+            for command in [
+                GameCommand::Do {
+                    x: 1,
+                    y: 1,
+                    color: black,
+                },
+                GameCommand::Undo,
+                GameCommand::Redo,
+            ] {
+                execute_command(0, &mut field, command, &response, &mut history, false)
+                    .await
+                    .unwrap();
+            }
+            assert!(matches!(rsp_r.recv().await, Ok(GameResponse::Field(_))));
+            assert!(matches!(rsp_r.recv().await, Ok(GameResponse::Undo(_))));
+            match rsp_r.recv().await {
+                Ok(GameResponse::Redo(field_state)) => {
+                    assert_eq!(field_state.latest, (1, 1, black));
+                    assert_eq!(field_state.field.0[1][1], State::B);
+                }
+                _ => panic!("undone move not replayed"),
+            }
+            // a new move after undo drops the undone move
+            let command = GameCommand::Undo;
+            execute_command(0, &mut field, command, &response, &mut history, false)
+                .await
+                .unwrap();
+            let command = GameCommand::Do {
+                x: 2,
+                y: 2,
+                color: black,
+            };
+            execute_command(0, &mut field, command, &response, &mut history, false)
+                .await
+                .unwrap();
+            let command = GameCommand::Redo;
+            execute_command(0, &mut field, command, &response, &mut history, false)
+                .await
+                .unwrap();
+            assert!(matches!(rsp_r.recv().await, Ok(GameResponse::Undo(_))));
+            assert!(matches!(rsp_r.recv().await, Ok(GameResponse::Field(_))));
+            assert!(rsp_r.try_recv().is_err());
+        });
+        assert_eq!(field.get_field()[1][1], State::E);
+        assert_eq!(history.latest(), Some((2, 2, black)));
+    }
+
+    /// black plays the gap of `xxx_xx`, an overline is an impossible state
+    fn overline_field() -> Field {
+        let mut field = Field::new(10, 10);
+        for y in [0, 1, 2, 4, 5] {
+            field.play(3, y, Color::Black).unwrap();
+            field.play(6, y, Color::White).unwrap();
+        }
+        field
+    }
+
+    #[test]
+    fn test_impossible_state_rolled_back() {
+        let mut field = overline_field();
+        let mut history = History::new(10, 10);
+        let (response, rsp_r) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let command = GameCommand::Do {
+                x: 3,
+                y: 3,
+                color: Color::Black,
+            };
+            execute_command(0, &mut field, command, &response, &mut history, true)
+                .await
+                .unwrap();
+            assert!(matches!(
+                rsp_r.recv().await,
+                Ok(GameResponse::InvalidMove(3, 3, Color::Black))
+            ));
+            assert!(rsp_r.try_recv().is_err());
+        });
+        assert_eq!(field.get_field()[3][3], State::E);
+        assert_eq!(*field.get_field_state(), GameState::UnFinished);
+        assert!(history.latest().is_none());
+    }
+
+    #[test]
+    fn test_impossible_state_game_error() {
+        let mut field = overline_field();
+        let mut history = History::new(10, 10);
+        let (response, rsp_r) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let command = GameCommand::Do {
+                x: 3,
+                y: 3,
+                color: Color::Black,
+            };
+            execute_command(0, &mut field, command, &response, &mut history, false)
+                .await
+                .unwrap();
+            assert!(matches!(rsp_r.recv().await, Ok(GameResponse::Field(_))));
+            assert!(matches!(rsp_r.recv().await, Ok(GameResponse::GameError(_))));
+        });
+    }
+
+    #[test]
+    fn test_game_end_with_board() {
+        let mut field = Field::new(15, 15);
+        let mut history = History::new(15, 15);
+        for y in 0..4 {
+            field.play(7, y, Color::White).unwrap();
+        }
+        let (response, rsp_r) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let command = GameCommand::Do {
+                x: 7,
+                y: 4,
+                color: Color::White,
+            };
+            execute_command(0, &mut field, command, &response, &mut history, false)
+                .await
+                .unwrap();
+            match rsp_r.recv().await {
+                Ok(GameResponse::GameEnd(field_state, result)) => {
+                    assert_eq!(result, GameResult::WhiteWins);
+                    assert_eq!(field_state.latest, (7, 4, Color::White));
+                    assert_eq!(field_state.field.0[7][4], State::W);
+                    assert_eq!(&field_state.field.0, field.get_field());
+                }
+                _ => panic!("expected a single game end response"),
+            }
+            assert!(rsp_r.try_recv().is_err());
+        });
+    }
+}
@@ -1,49 +1,46 @@
 use crate::game::game_field::State::{self, B, E, W};
-use unroll::unroll_for_loops;
 
 const EMPTY_BIT_FLAG: u8 = 0b0000_0010;
 const BLACK_BIT_FLAG: u8 = 0b0000_0001;
 const WHITE_BIT_FLAG: u8 = 0u8;
 
+/// compress each row (of length `height`) into `(height + 3) / 4` bytes
 #[inline]
-#[unroll_for_loops]
-pub const fn compress_field(field: &[[State; 15]; 15]) -> [(u8, u8, u8, u8); 15] {
-    let mut compress_result = [(0u8, 0u8, 0u8, 0u8); 15];
-    for n in 0..15 {
-        compress_result[n] = compress_15_states(&field[n]);
-    }
-    compress_result
+pub fn compress_field<R: AsRef<[State]>>(field: &[R]) -> Vec<Vec<u8>> {
+    field.iter().map(|row| compress_row(row.as_ref())).collect()
 }
 
+/// inverse of `compress_field`, `height` is the length of each row
 #[inline]
-#[unroll_for_loops]
-pub const fn decompress_field(dat: &[(u8, u8, u8, u8); 15]) -> [[State; 15]; 15] {
-    let mut result = [[E; 15]; 15];
-    for n in 0..15 {
-        result[n] = decompress_15_states(dat[n])
-    }
-    result
+pub fn decompress_field<R: AsRef<[u8]>>(dat: &[R], height: usize) -> Vec<Vec<State>> {
+    dat.iter()
+        .map(|row| decompress_row(row.as_ref(), height))
+        .collect()
 }
 
 #[inline]
-const fn compress_15_states(row: &[State; 15]) -> (u8, u8, u8, u8) {
-    (
-        compress_four_states(&row[0], &row[1], &row[2], &row[3]),
-        compress_four_states(&row[4], &row[5], &row[6], &row[7]),
-        compress_four_states(&row[8], &row[9], &row[10], &row[11]),
-        compress_four_states(&row[12], &row[13], &row[14], &E),
-    )
+fn compress_row(row: &[State]) -> Vec<u8> {
+    row.chunks(4)
+        .map(|c| {
+            compress_four_states(
+                &c[0],
+                c.get(1).unwrap_or(&E),
+                c.get(2).unwrap_or(&E),
+                c.get(3).unwrap_or(&E),
+            )
+        })
+        .collect()
 }
 
 #[inline]
-const fn decompress_15_states((b0, b1, b2, b3): (u8, u8, u8, u8)) -> [State; 15] {
-    let (p0, p1, p2, p3) = decompress_four_states(b0);
-    let (p4, p5, p6, p7) = decompress_four_states(b1);
-    let (p8, p9, p10, p11) = decompress_four_states(b2);
-    let (p12, p13, p14, _) = decompress_four_states(b3);
-    [
-        p0, p1, p2, p3, p4, p5, p6, p7, p8, p9, p10, p11, p12, p13, p14,
-    ]
+fn decompress_row(dat: &[u8], height: usize) -> Vec<State> {
+    dat.iter()
+        .flat_map(|&b| {
+            let (p0, p1, p2, p3) = decompress_four_states(b);
+            [p0, p1, p2, p3]
+        })
+        .take(height)
+        .collect()
 }
 
 #[inline]
@@ -113,9 +110,21 @@ mod compress {
     fn test_decompress_compress() {
         let data = compress_field(&FIELD);
         let data_t = compress_field(&rotate(&FIELD));
-        let field_decompressed = decompress_field(&data);
-        let field_decompressed_t = decompress_field(&data_t);
+        let field_decompressed = decompress_field(&data, 15);
+        let field_decompressed_t = decompress_field(&data_t, 15);
         assert_eq!(field_decompressed, FIELD);
         assert_eq!(field_decompressed_t, rotate(&FIELD))
     }
+
+    #[test]
+    fn test_decompress_compress_rectangular() {
+        let field: Vec<Vec<State>> = (0..10)
+            .map(|i| (0..19).map(|j| [B, W, E][(i + j * 7) % 3]).collect())
+            .collect();
+        let data = compress_field(&field);
+        assert!(data.iter().all(|row| row.len() == 5));
+        assert_eq!(decompress_field(&data, 19), field);
+        let data_t = compress_field(&rotate(&field));
+        assert_eq!(decompress_field(&data_t, 10), rotate(&field));
+    }
 }
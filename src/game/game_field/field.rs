@@ -1,9 +1,13 @@
 use crate::game::game_field::field::GameState::{
     BlackWins, Draw, Impossible, UnFinished, WhiteWins,
 };
-use crate::game::game_field::utility::{diagonal_b_w_max, reduce_tuple_max, rotate, rows_b_w_max};
+use crate::game::game_field::utility::{
+    diagonal_b_w_max, five_still_possible, near_stone, reduce_tuple_max, rotate, rows_b_w_max,
+    run_counts, windows,
+};
 use crate::game::game_field::State::{B, E, W};
 use crate::game::game_field::{Color, State};
+use crate::game::session::SimultaneousWin;
 use anyhow::{Error, Result};
 
 impl Color {
@@ -35,23 +39,78 @@ pub(crate) enum GameState {
     Impossible,
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct Field {
-    inner: [[State; 15]; 15],
+/// stage of a game by the share of the board covered with stones
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GamePhase {
+    Opening,
+    Midgame,
+    Endgame,
+}
+
+/// percentages of the board covered with stones where a phase begins
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PhaseThresholds {
+    pub midgame_percent: u8,
+    pub endgame_percent: u8,
+}
+
+impl Default for PhaseThresholds {
+    /// midgame from 10% of the board, endgame from 40%
+    fn default() -> Self {
+        PhaseThresholds {
+            midgame_percent: 10,
+            endgame_percent: 40,
+        }
+    }
+}
+
+/// weights of `score_heuristic` for runs of two, three and four stones open at both ends
+pub const OPEN_RUN_WEIGHTS: [i32; 3] = [10, 100, 10_000];
+/// weights of `score_heuristic` for runs of two, three and four stones open at one end,
+/// runs blocked at both ends count nothing
+pub const HALF_OPEN_RUN_WEIGHTS: [i32; 3] = [1, 10, 1_000];
+/// weight of `score_heuristic` for five in a row
+pub const FIVE_WEIGHT: i32 = 1_000_000;
+
+/// A board to play on and analyze, e.g. by bots and clients.
+///
+/// `inner[x][y]`, where `x` in `[0, width)` and `y` in `[0, height)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    inner: Vec<Vec<State>>,
     field_state: GameState,
-    e_count: u8,
+    e_count: usize,
+    /// `Draw` as soon as neither color can get five in a row
+    early_draw: bool,
+    /// scoring of both colors having five in a row after a move
+    simultaneous_win: SimultaneousWin,
 }
 
 impl Field {
     #[inline(always)]
-    pub fn new() -> Self {
+    pub fn new(width: u8, height: u8) -> Self {
         Field {
-            inner: [[E; 15]; 15],
+            inner: vec![vec![E; height as usize]; width as usize],
             field_state: UnFinished,
-            e_count: 225,
+            e_count: width as usize * height as usize,
+            early_draw: false,
+            simultaneous_win: SimultaneousWin::MoverWins,
         }
     }
 
+    /// declare `Draw` once no five in a row is possible, before the field is full
+    pub fn with_early_draw(mut self, early_draw: bool) -> Self {
+        self.early_draw = early_draw;
+        self
+    }
+
+    /// score a move completing five in a row for both colors,
+    /// instead of the `MoverWins` default
+    pub fn with_simultaneous_win(mut self, simultaneous_win: SimultaneousWin) -> Self {
+        self.simultaneous_win = simultaneous_win;
+        self
+    }
+
     /// play black and white
     pub fn play(&mut self, x: usize, y: usize, color: Color) -> Result<()> {
         match self.inner.get_mut(x) {
@@ -64,7 +123,7 @@ impl Field {
                     } else {
                         self.e_count -= 1;
                         *s = color.into();
-                        self.update_field_state();
+                        self.update_field_state(Some(color));
                         Ok(())
                     }
                 }
@@ -82,7 +141,7 @@ impl Field {
                     if *s != E {
                         self.e_count += 1;
                         *s = E;
-                        self.update_field_state();
+                        self.update_field_state(None);
                         Ok(())
                     } else {
                         unlikely_error(Err(Error::msg("already empty")))
@@ -93,19 +152,21 @@ impl Field {
     }
 
     /// read the internal representation of field
-    pub fn get_field(&self) -> &[[State; 15]; 15] {
+    pub fn get_field(&self) -> &Vec<Vec<State>> {
         &self.inner
     }
 
     /// read the field state
     ///
     /// this method is inert, it reads from cached field state
-    pub fn get_field_state(&self) -> &GameState {
+    pub(crate) fn get_field_state(&self) -> &GameState {
         &self.field_state
     }
 
-    /// called in play()
-    fn update_field_state(&mut self) {
+    /// called in play() with the color played, and in clear()
+    ///
+    /// both colors having five in a row is `Impossible` unless caused by a move
+    fn update_field_state(&mut self, mover: Option<Color>) {
         let rotated = rotate(&self.inner);
         let rows_max = rows_b_w_max(&self.inner);
         let cols_max = rows_b_w_max(&rotated);
@@ -115,14 +176,115 @@ impl Field {
             reduce_tuple_max([rows_max, cols_max, diag_max, diag_max_t].into_iter());
         self.field_state = match (black_max, white_max, self.e_count) {
             (0..=4, 0..=4, 0) => Draw,
-            (0..=4, 0..=4, 1..=225) => UnFinished,
+            (0..=4, 0..=4, _) if self.early_draw && !five_still_possible(&self.inner) => Draw,
+            (0..=4, 0..=4, _) => UnFinished,
             (5, 0..=4, _) => BlackWins,
             (0..=4, 5, _) => WhiteWins,
+            (5, 5, _) => match (self.simultaneous_win, mover) {
+                (SimultaneousWin::MoverWins, Some(Color::Black)) => BlackWins,
+                (SimultaneousWin::MoverWins, Some(Color::White)) => WhiteWins,
+                (SimultaneousWin::Draw, Some(_)) => Draw,
+                (_, None) => Impossible,
+            },
             _ => Impossible,
         }
     }
 }
 
+/// move generation for bots and move suggestion, threat detection for client warnings,
+/// and position comparison for opening databases, not used by the server itself
+impl Field {
+    /// whether `other` is the same position under one of the 8 symmetries,
+    /// the rotations and reflections of the board
+    pub fn equivalent(&self, other: &Field) -> bool {
+        let mut image = self.inner.clone();
+        for _ in 0..2 {
+            for _ in 0..4 {
+                if image == other.inner {
+                    return true;
+                }
+                image = rotate(&image);
+            }
+            // reflect along x
+            image.reverse();
+        }
+        false
+    }
+
+    /// all empty cells `(x, y)`
+    pub fn available_moves(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.inner.iter().enumerate().flat_map(|(x, row)| {
+            row.iter()
+                .enumerate()
+                .filter(|(_, s)| **s == E)
+                .map(move |(y, _)| (x as u8, y as u8))
+        })
+    }
+
+    /// empty cells within `radius` (in both directions) of some stone,
+    /// nothing on an empty field
+    pub fn available_near(&self, radius: u8) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.available_moves()
+            .filter(move |&(x, y)| near_stone(&self.inner, x as usize, y as usize, radius as usize))
+    }
+
+    /// cells of every straight window of `len` cells, along `x`, along `y` and both diagonals,
+    /// e.g. the windows that may hold five in a row for `len == 5`
+    pub fn iter_lines(&self, len: u8) -> impl Iterator<Item = Vec<(u8, u8)>> {
+        let height = self.inner.first().map_or(0, |row| row.len());
+        windows(self.inner.len(), height, len as usize)
+            .map(|cells| cells.into_iter().map(|(x, y)| (x as u8, y as u8)).collect())
+    }
+
+    /// the phase of the game with the default `PhaseThresholds`
+    pub fn phase(&self) -> GamePhase {
+        self.phase_with(PhaseThresholds::default())
+    }
+
+    /// the phase of the game by the number of stones relative to the board size
+    pub fn phase_with(&self, thresholds: PhaseThresholds) -> GamePhase {
+        let cells = self.inner.len() * self.inner.first().map_or(0, |row| row.len());
+        let stones_percent = (cells - self.e_count) * 100;
+        if stones_percent < thresholds.midgame_percent as usize * cells {
+            GamePhase::Opening
+        } else if stones_percent < thresholds.endgame_percent as usize * cells {
+            GamePhase::Midgame
+        } else {
+            GamePhase::Endgame
+        }
+    }
+
+    /// positional evaluation from runs of both colors, positive if favoring `color`
+    pub fn score_heuristic(&self, color: Color) -> i32 {
+        let evaluate = |color: Color| {
+            let counts = run_counts(&self.inner, color.into());
+            let runs: i32 = (2..5)
+                .map(|len| {
+                    counts[len][2] as i32 * OPEN_RUN_WEIGHTS[len - 2]
+                        + counts[len][1] as i32 * HALF_OPEN_RUN_WEIGHTS[len - 2]
+                })
+                .sum();
+            runs + counts[5].iter().sum::<u16>() as i32 * FIVE_WEIGHT
+        };
+        evaluate(color) - evaluate(color.switch())
+    }
+
+    /// empty cells where `color` completes five in a row, i.e. its immediate winning moves,
+    /// excluding those making an overline
+    pub fn fours(&self, color: Color) -> Vec<(u8, u8)> {
+        let wins = match color {
+            Color::Black => BlackWins,
+            Color::White => WhiteWins,
+        };
+        self.available_near(1)
+            .filter(|&(x, y)| {
+                let mut next = self.clone();
+                next.play(x as usize, y as usize, color).is_ok() && next.field_state == wins
+            })
+            .collect()
+    }
+}
+
 #[cold]
 fn unlikely_error<T>(e: T) -> T {
     e
@@ -136,7 +298,7 @@ mod test_field {
     #[test]
     fn test_field_1() {
         // 测试各种棋盘状态
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         f.play(0, 0, Black).unwrap();
         f.play(14, 14, White).unwrap();
         f.play(1, 1, Black).unwrap();
@@ -170,7 +332,7 @@ mod test_field {
     #[test]
     fn test_play_b_win() {
         // test play method for BlackWins
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         for i in 0..5 {
             f.play(i, i, Black).unwrap();
         }
@@ -180,7 +342,7 @@ mod test_field {
     #[test]
     fn test_play_w_win() {
         // test play method for WhiteWins
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         for i in 0..5 {
             f.play(i, i, White).unwrap();
         }
@@ -189,7 +351,7 @@ mod test_field {
 
     #[test]
     fn test_play_unfinished() {
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         for i in 0..4 {
             f.play(i, i, White).unwrap();
         }
@@ -201,7 +363,7 @@ mod test_field {
 
     #[test]
     fn test_play_draw() {
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         for i in 0..15 {
             let row_order_switcher = (i / 3) % 2 == 0;
             for j in 0..15 {
@@ -225,20 +387,304 @@ mod test_field {
         assert_eq!(f.get_field_state(), &Draw);
     }
 
+    #[test]
+    fn test_early_draw() {
+        // one stone of each color in every row, column and diagonal
+        let moves = [
+            (0, 0, Black),
+            (0, 1, White),
+            (1, 2, Black),
+            (1, 3, White),
+            (2, 4, Black),
+            (2, 0, White),
+            (3, 1, Black),
+            (3, 2, White),
+            (4, 3, Black),
+            (4, 4, White),
+        ];
+        let mut early = Field::new(5, 5).with_early_draw(true);
+        let mut late = Field::new(5, 5);
+        for (i, &(x, y, color)) in moves.iter().enumerate() {
+            early.play(x, y, color).unwrap();
+            late.play(x, y, color).unwrap();
+            if i + 1 < moves.len() {
+                assert_eq!(early.get_field_state(), &UnFinished);
+            }
+        }
+        // 15 empty cells left
+        assert_eq!(early.get_field_state(), &Draw);
+        assert_eq!(late.get_field_state(), &UnFinished);
+        // undoing the last move reopens the main diagonal
+        early.clear(4, 4).unwrap();
+        assert_eq!(early.get_field_state(), &UnFinished);
+    }
+
     #[test]
     fn test_play_impossible() {
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         for i in 0..6 {
             f.play(i, i, Black).unwrap();
         }
         assert_eq!(f.get_field_state(), &Impossible);
     }
 
+    #[test]
+    fn test_simultaneous_win() {
+        for (resolution, result) in [
+            (SimultaneousWin::MoverWins, BlackWins),
+            (SimultaneousWin::Draw, Draw),
+        ] {
+            let mut f = Field::new(15, 15).with_simultaneous_win(resolution);
+            // seeded position, white already has five in a row
+            for y in 0..5 {
+                f.play(7, y, White).unwrap();
+            }
+            for y in 0..4 {
+                f.play(9, y, Black).unwrap();
+            }
+            f.play(9, 4, Black).unwrap();
+            assert_eq!(f.get_field_state(), &result);
+            // without a move, five in a row for both colors cannot be scored
+            f.play(0, 0, White).unwrap();
+            f.clear(0, 0).unwrap();
+            assert_eq!(f.get_field_state(), &Impossible);
+        }
+    }
+
     #[test]
     fn test_play_out_of_range() {
-        let mut f = Field::new();
+        let mut f = Field::new(15, 15);
         if f.play(17, 21, Black).is_ok() {
             panic!("error not thrown")
         }
     }
+
+    #[test]
+    fn test_rectangular_horizontal_win() {
+        // x along width 10, y along height 20
+        let mut f = Field::new(10, 20);
+        for x in 5..10 {
+            f.play(x, 19, Black).unwrap();
+            assert_eq!(
+                f.get_field_state(),
+                if x < 9 { &UnFinished } else { &BlackWins }
+            );
+        }
+    }
+
+    #[test]
+    fn test_rectangular_vertical_win() {
+        let mut f = Field::new(10, 20);
+        for y in 15..20 {
+            f.play(9, y, White).unwrap();
+        }
+        assert_eq!(f.get_field_state(), &WhiteWins);
+        assert!(f.play(10, 0, Black).is_err());
+        assert!(f.play(0, 20, Black).is_err());
+    }
+
+    #[test]
+    fn test_rectangular_diagonal_win() {
+        let mut f = Field::new(10, 20);
+        for i in 0..5 {
+            f.play(5 + i, 15 + i, Black).unwrap();
+        }
+        assert_eq!(f.get_field_state(), &BlackWins);
+        let mut f = Field::new(10, 20);
+        for i in 0..5 {
+            f.play(9 - i, 10 + i, White).unwrap();
+        }
+        assert_eq!(f.get_field_state(), &WhiteWins);
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let stones = [(3, 4, Black), (7, 7, White), (10, 2, Black), (0, 14, White)];
+        type Symmetry = fn(usize, usize) -> (usize, usize);
+        let symmetries: [Symmetry; 8] = [
+            |x, y| (x, y),
+            |x, y| (y, 14 - x),
+            |x, y| (14 - x, 14 - y),
+            |x, y| (14 - y, x),
+            |x, y| (14 - x, y),
+            |x, y| (x, 14 - y),
+            |x, y| (y, x),
+            |x, y| (14 - y, 14 - x),
+        ];
+        let position = |map: Symmetry| {
+            let mut f = Field::new(15, 15);
+            for &(x, y, color) in stones.iter() {
+                let (x, y) = map(x, y);
+                f.play(x, y, color).unwrap();
+            }
+            f
+        };
+        let original = position(symmetries[0]);
+        for map in symmetries {
+            let image = position(map);
+            assert!(original.equivalent(&image));
+            assert!(image.equivalent(&original));
+        }
+        // a stone moved
+        let mut moved = Field::new(15, 15);
+        for &(x, y, color) in stones[1..].iter() {
+            moved.play(x, y, color).unwrap();
+        }
+        moved.play(3, 5, Black).unwrap();
+        assert!(!original.equivalent(&moved));
+        // colors swapped
+        let mut swapped = Field::new(15, 15);
+        for &(x, y, color) in stones.iter() {
+            swapped.play(x, y, color.switch()).unwrap();
+        }
+        assert!(!original.equivalent(&swapped));
+        // rectangular boards, rotated by a half turn
+        let mut f = Field::new(10, 20);
+        let mut g = Field::new(10, 20);
+        f.play(1, 2, Black).unwrap();
+        g.play(8, 17, Black).unwrap();
+        assert!(f.equivalent(&g));
+        assert!(!f.equivalent(&Field::new(20, 10)));
+    }
+
+    #[test]
+    fn test_available_moves() {
+        let mut f = Field::new(7, 5);
+        assert_eq!(f.available_moves().count(), 35);
+        assert_eq!(f.available_near(1).count(), 0);
+        f.play(0, 0, Black).unwrap();
+        f.play(4, 2, White).unwrap();
+        assert_eq!(f.available_moves().count(), 33);
+        assert!(f.available_moves().all(|m| m != (0, 0) && m != (4, 2)));
+        let near: Vec<_> = f.available_near(1).collect();
+        assert_eq!(
+            near,
+            vec![
+                (0, 1),
+                (1, 0),
+                (1, 1),
+                (3, 1),
+                (3, 2),
+                (3, 3),
+                (4, 1),
+                (4, 3),
+                (5, 1),
+                (5, 2),
+                (5, 3),
+            ]
+        );
+        // 3x3 and 5x5 neighborhoods clipped by the field, overlapping at x = 2
+        assert_eq!(f.available_near(2).count(), 9 + 25 - 3 - 2);
+    }
+
+    #[test]
+    fn test_fours() {
+        let mut f = Field::new(15, 15);
+        assert!(f.fours(Black).is_empty());
+        // a three is no four
+        for y in 3..6 {
+            f.play(7, y, Black).unwrap();
+        }
+        assert!(f.fours(Black).is_empty());
+        // an open four, winning at either end
+        f.play(7, 6, Black).unwrap();
+        assert_eq!(f.fours(Black), vec![(7, 2), (7, 7)]);
+        assert!(f.fours(White).is_empty());
+        // blocked at one end
+        f.play(7, 2, White).unwrap();
+        assert_eq!(f.fours(Black), vec![(7, 7)]);
+        // a broken four on a diagonal adds another winning cell
+        for i in [1, 2, 4, 5] {
+            f.play(i, 14 - i, Black).unwrap();
+        }
+        assert_eq!(f.fours(Black), vec![(3, 11), (7, 7)]);
+    }
+
+    #[test]
+    fn test_fours_overline() {
+        let mut f = Field::new(15, 15);
+        for y in [0, 1, 2, 3, 5] {
+            f.play(7, y, Black).unwrap();
+        }
+        // (7, 4) makes six in a row
+        assert!(f.fours(Black).is_empty());
+    }
+
+    #[test]
+    fn test_iter_lines() {
+        let f = Field::new(15, 15);
+        assert_eq!(f.iter_lines(5).count(), 572);
+        let f = Field::new(5, 7);
+        // 7 along x, 3 along y for each x, 3 on each diagonal
+        assert_eq!(f.iter_lines(5).count(), 7 + 5 * 3 + 3 + 3);
+        assert!(f
+            .iter_lines(5)
+            .any(|w| w == [(0, 4), (1, 3), (2, 2), (3, 1), (4, 0)]));
+    }
+
+    #[test]
+    fn test_phase() {
+        let mut f = Field::new(15, 15);
+        assert_eq!(f.phase(), GamePhase::Opening);
+        // 10% of 225 cells is 22.5 stones, 40% is 90 stones
+        let mut cells = (0..15).flat_map(|x| (0..15).map(move |y| (x, y)));
+        let mut play_until = |f: &mut Field, stones: usize| {
+            while 225 - f.e_count < stones {
+                let (x, y) = cells.next().unwrap();
+                let color = if (x / 2 + y) % 2 == 0 { Black } else { White };
+                f.play(x, y, color).unwrap();
+            }
+        };
+        play_until(&mut f, 22);
+        assert_eq!(f.phase(), GamePhase::Opening);
+        play_until(&mut f, 23);
+        assert_eq!(f.phase(), GamePhase::Midgame);
+        play_until(&mut f, 89);
+        assert_eq!(f.phase(), GamePhase::Midgame);
+        play_until(&mut f, 90);
+        assert_eq!(f.phase(), GamePhase::Endgame);
+        let thresholds = PhaseThresholds {
+            midgame_percent: 50,
+            endgame_percent: 80,
+        };
+        assert_eq!(f.phase_with(thresholds), GamePhase::Opening);
+    }
+
+    #[test]
+    fn test_score_heuristic() {
+        let empty = Field::new(15, 15);
+        assert_eq!(empty.score_heuristic(Black), 0);
+        // four stones apart from each other
+        let mut scattered = Field::new(15, 15);
+        for (x, y) in [(1, 1), (4, 9), (8, 3), (12, 12)] {
+            scattered.play(x, y, Black).unwrap();
+        }
+        let mut open_four = Field::new(15, 15);
+        for y in 5..9 {
+            open_four.play(7, y, Black).unwrap();
+        }
+        assert!(open_four.score_heuristic(Black) > 100 * scattered.score_heuristic(Black).max(1));
+        assert_eq!(
+            open_four.score_heuristic(White),
+            -open_four.score_heuristic(Black)
+        );
+        // blocking one end lowers the score, blocking both makes the four dead
+        open_four.play(7, 4, White).unwrap();
+        let half_open = open_four.score_heuristic(Black);
+        assert!(half_open < OPEN_RUN_WEIGHTS[2]);
+        open_four.play(7, 9, White).unwrap();
+        assert!(open_four.score_heuristic(Black) < half_open);
+    }
+
+    #[test]
+    fn test_rectangular_draw() {
+        let mut f = Field::new(2, 3);
+        for x in 0..2 {
+            for y in 0..3 {
+                f.play(x, y, if (x + y) % 2 == 0 { Black } else { White })
+                    .unwrap();
+            }
+        }
+        assert_eq!(f.get_field_state(), &Draw);
+    }
 }
@@ -26,3 +26,7 @@ pub enum State {
 
 pub(crate) use api::{new_field, GameCommand, GameResponse};
 pub use compression::{compress_field, decompress_field};
+pub use field::{
+    Field, GamePhase, PhaseThresholds, FIVE_WEIGHT, HALF_OPEN_RUN_WEIGHTS, OPEN_RUN_WEIGHTS,
+};
+pub(crate) use utility::near_stone;
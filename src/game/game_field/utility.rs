@@ -1,44 +1,189 @@
 use crate::game::game_field::State::{self, B, E, W};
-use unroll::unroll_for_loops;
 
-/// rotate a field
-#[inline(always)]
-#[unroll_for_loops]
-pub(crate) const fn rotate(field: &[[State; 15]; 15]) -> [[State; 15]; 15] {
-    let mut new_field = [[E; 15]; 15];
-    for i in 0..15 {
-        for j in 0..15 {
-            new_field[i][j] = field[14 - j][i];
-        }
-    }
-    new_field
+/// rotate a field, a `width x height` field becomes `height x width`
+#[inline]
+pub(crate) fn rotate<R: AsRef<[State]>>(field: &[R]) -> Vec<Vec<State>> {
+    let width = field.len();
+    let height = field.first().map_or(0, |r| r.as_ref().len());
+    (0..height)
+        .map(|i| {
+            (0..width)
+                .map(|j| field[width - 1 - j].as_ref()[i])
+                .collect()
+        })
+        .collect()
 }
 
 /// compute max consecutive for each rows
 #[inline]
-pub(crate) fn rows_b_w_max(field: &[[State; 15]; 15]) -> (u8, u8) {
-    reduce_tuple_max(field.iter().map(|x| max_consecutive_black_white(x.iter())))
+pub(crate) fn rows_b_w_max<R: AsRef<[State]>>(field: &[R]) -> (u8, u8) {
+    reduce_tuple_max(
+        field
+            .iter()
+            .map(|x| max_consecutive_black_white(x.as_ref().iter())),
+    )
 }
 
-/// compute max consecutive for diagonals
+/// all diagonals (left-lower to right-upper) of a `width x height` field,
+/// i.e. positions `(i, j)` of `i + j == s` for each `s`,
+/// including the short ones at the corners
+pub(crate) fn diagonals(width: usize, height: usize) -> impl Iterator<Item = Vec<(usize, usize)>> {
+    let count = if width == 0 || height == 0 {
+        0
+    } else {
+        width + height - 1
+    };
+    (0..count).map(move |s| {
+        let i_min = s.saturating_sub(height - 1);
+        let i_max = s.min(width - 1);
+        (i_min..=i_max).map(|i| (i, s - i)).collect()
+    })
+}
+
+/// compute max consecutive for diagonals (left-lower to right-upper)
 #[inline]
-pub(crate) fn diagonal_b_w_max(field: &[[State; 15]; 15]) -> (u8, u8) {
+pub(crate) fn diagonal_b_w_max<R: AsRef<[State]>>(field: &[R]) -> (u8, u8) {
+    let width = field.len();
+    let height = field.first().map_or(0, |r| r.as_ref().len());
+    // skip diagonals shorter than 5, which never hold a win
     reduce_tuple_max(
-        (4usize..15)
-            .map(|x| {
-                max_consecutive_black_white((0..=x).rev().zip(0..=x).map(|(i, j)| &field[i][j]))
-            })
-            .chain((1usize..=10).map(|x| {
-                max_consecutive_black_white((x..=14).rev().zip(x..=14).map(|(i, j)| &field[i][j]))
-            })),
+        diagonals(width, height)
+            .filter(|diagonal| diagonal.len() >= 5)
+            .map(|diagonal| {
+                max_consecutive_black_white(diagonal.iter().map(|&(i, j)| &field[i].as_ref()[j]))
+            }),
     )
 }
 
+/// maximal runs of `color` in a line as `(length, open ends)`,
+/// an end being open if the cell next to it is empty
+fn runs(line: &[State], color: State) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] != color {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < line.len() && line[i] == color {
+            i += 1;
+        }
+        let open_start = start > 0 && line[start - 1] == E;
+        let open_end = i < line.len() && line[i] == E;
+        runs.push((i - start, open_start as usize + open_end as usize));
+    }
+    runs
+}
+
+/// count runs of `color` along `x`, along `y` and both diagonals,
+/// as `counts[length][open ends]` with lengths above 5 counted as 5,
+/// skipping diagonals shorter than 5 like `diagonal_b_w_max`
+pub(crate) fn run_counts<R: AsRef<[State]>>(field: &[R], color: State) -> [[u16; 3]; 6] {
+    let width = field.len();
+    let height = field.first().map_or(0, |r| r.as_ref().len());
+    let rotated = rotate(field);
+    let mut counts = [[0u16; 3]; 6];
+    let mut count = |line: &[State]| {
+        for (len, open) in runs(line, color) {
+            counts[len.min(5)][open] += 1;
+        }
+    };
+    for row in field {
+        count(row.as_ref());
+    }
+    for row in &rotated {
+        count(row);
+    }
+    for diagonal in diagonals(width, height).filter(|d| d.len() >= 5) {
+        let line: Vec<_> = diagonal
+            .iter()
+            .map(|&(i, j)| field[i].as_ref()[j])
+            .collect();
+        count(&line);
+    }
+    for diagonal in diagonals(height, width).filter(|d| d.len() >= 5) {
+        let line: Vec<_> = diagonal.iter().map(|&(i, j)| rotated[i][j]).collect();
+        count(&line);
+    }
+    counts
+}
+
+/// whether some stone lies within `radius` of `(x, y)` along `x` and `y`,
+/// the square neighborhood clipped by the field
+pub(crate) fn near_stone<R: AsRef<[State]>>(
+    field: &[R],
+    x: usize,
+    y: usize,
+    radius: usize,
+) -> bool {
+    let height = field.first().map_or(0, |r| r.as_ref().len());
+    let rows = x.saturating_sub(radius)..(x + radius + 1).min(field.len());
+    let cols = y.saturating_sub(radius)..(y + radius + 1).min(height);
+    field[rows]
+        .iter()
+        .any(|row| row.as_ref()[cols.clone()].iter().any(|s| *s != E))
+}
+
+/// directions of lines: along `x`, along `y`, and the two diagonals
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+/// starting positions and directions of all straight windows of `len` cells
+/// that fit on a `width x height` field
+fn window_starts(
+    width: usize,
+    height: usize,
+    len: usize,
+) -> impl Iterator<Item = ((isize, isize), (isize, isize))> {
+    let (width, height) = (width as isize, height as isize);
+    let reach = len as isize - 1;
+    let fits = move |x: isize, y: isize| (0..width).contains(&x) && (0..height).contains(&y);
+    (0..width)
+        .flat_map(move |x| (0..height).map(move |y| (x, y)))
+        .flat_map(|start| DIRECTIONS.iter().map(move |&d| (start, d)))
+        .filter(move |&((x, y), (dx, dy))| len > 0 && fits(x + reach * dx, y + reach * dy))
+}
+
+/// cells of all straight windows of `len` cells in the four directions
+/// that fit on a `width x height` field
+pub(crate) fn windows(
+    width: usize,
+    height: usize,
+    len: usize,
+) -> impl Iterator<Item = Vec<(usize, usize)>> {
+    window_starts(width, height, len).map(move |((x, y), (dx, dy))| {
+        (0..len as isize)
+            .map(|i| ((x + i * dx) as usize, (y + i * dy) as usize))
+            .collect()
+    })
+}
+
+/// whether either color can still get exactly five in a row,
+/// i.e. some window of five cells has no opponent stone,
+/// and is not flanked by an own stone (which would make an overline)
+pub(crate) fn five_still_possible<R: AsRef<[State]>>(field: &[R]) -> bool {
+    let width = field.len();
+    let height = field.first().map_or(0, |r| r.as_ref().len());
+    let at = |x: isize, y: isize| {
+        if (0..width as isize).contains(&x) && (0..height as isize).contains(&y) {
+            Some(field[x as usize].as_ref()[y as usize])
+        } else {
+            None
+        }
+    };
+    let open_for = |x: isize, y: isize, (dx, dy): (isize, isize), me: State, opponent: State| {
+        (0..5).all(|i| at(x + i * dx, y + i * dy) != Some(opponent))
+            && at(x - dx, y - dy) != Some(me)
+            && at(x + 5 * dx, y + 5 * dy) != Some(me)
+    };
+    window_starts(width, height, 5)
+        .any(|((x, y), d)| open_for(x, y, d, B, W) || open_for(x, y, d, W, B))
+}
+
 /// compute max for two streams of zipped integers
 #[inline(always)]
 pub(crate) fn reduce_tuple_max(iter: impl Iterator<Item = (u8, u8)>) -> (u8, u8) {
-    iter.reduce(|(mx_b, mx_w), (b, w)| (mx_b.max(b), mx_w.max(w)))
-        .unwrap()
+    iter.fold((0, 0), |(mx_b, mx_w), (b, w)| (mx_b.max(b), mx_w.max(w)))
 }
 
 /// compute max number of consecutive black and white pieces
@@ -187,4 +332,83 @@ mod test_field_utility {
         assert_eq!(diagonal_b_w_max(&rotate(&field_5_3)), (7, 7));
         assert_eq!(diagonal_b_w_max(&rotate(&field_6_4)), (3, 7));
     }
+
+    #[test]
+    fn test_corner_diagonals() {
+        let lengths: Vec<_> = diagonals(3, 2).map(|d| d.len()).collect();
+        assert_eq!(lengths, [1, 2, 2, 1]);
+        assert_eq!(diagonals(0, 3).count(), 0);
+        // a three on the short diagonal at the upper left corner
+        let mut field = [[E; 15]; 15];
+        for (i, j) in [(0, 2), (1, 1), (2, 0)] {
+            field[i][j] = B;
+        }
+        assert_eq!(diagonal_b_w_max(&field), (0, 0));
+        let corner = diagonals(15, 15)
+            .map(|d| max_consecutive_black_white(d.iter().map(|&(i, j)| &field[i][j])))
+            .find(|&(b, _)| b > 0);
+        assert_eq!(corner, Some((3, 0)));
+        // every position is on exactly one diagonal
+        assert_eq!(diagonals(15, 15).map(|d| d.len()).sum::<usize>(), 225);
+    }
+
+    #[test]
+    fn test_five_still_possible() {
+        assert!(five_still_possible(&FIELD_2_3));
+        assert!(five_still_possible(&[[E; 5]]));
+        // no window of five fits
+        assert!(!five_still_possible(&[[E; 4]; 4]));
+        // every window holds stones of both colors
+        assert!(!five_still_possible(&[
+            [B, E, E, W, E, B],
+            [W, E, B, E, E, W]
+        ]));
+        // filling either window would make an overline for black
+        assert!(!five_still_possible(&[[B, E, E, E, E, B]]));
+        assert!(five_still_possible(&[[B, E, E, E, E, E]]));
+    }
+
+    #[test]
+    fn test_run_counts() {
+        assert_eq!(
+            runs(&[B, B, E, W, B, B, B, E, B], B),
+            [(2, 1), (3, 1), (1, 1)]
+        );
+        assert_eq!(runs(&[E, W, W, E, B, W, W], W), [(2, 2), (2, 0)]);
+        // three runs of seven along x for each color, one of them open at both ends
+        let counts = run_counts(&FIELD_2_3, B);
+        assert_eq!(counts[5], [0, 2, 1]);
+        assert_eq!(run_counts(&FIELD_2_3, W)[5], [0, 2, 1]);
+        // two stones along y, e.g. (4, 1) and (4, 2), open at both ends
+        assert!(counts[2][2] > 0);
+        assert_eq!(run_counts(&[[E; 15]; 15], B), [[0; 3]; 6]);
+    }
+
+    #[test]
+    fn test_windows() {
+        // each direction has `(15 - 4) x 15` or `(15 - 4) x (15 - 4)` windows
+        assert_eq!(windows(15, 15, 5).count(), 2 * 11 * 15 + 2 * 11 * 11);
+        assert_eq!(windows(15, 15, 1).count(), 4 * 225);
+        assert_eq!(windows(15, 15, 15).count(), 2 * 15 + 2);
+        assert_eq!(windows(15, 15, 16).count(), 0);
+        assert_eq!(windows(15, 15, 0).count(), 0);
+        // 2 x 6 field, only along y
+        assert_eq!(windows(2, 6, 5).count(), 2 * 2);
+        assert!(windows(2, 6, 5).all(|w| w.len() == 5 && w.iter().all(|&(x, y)| x < 2 && y < 6)));
+        assert!(windows(3, 3, 3).any(|w| w == [(0, 2), (1, 1), (2, 0)]));
+    }
+
+    #[test]
+    fn test_rectangular() {
+        // 2 x 6 field
+        let field = [[B, B, B, B, B, W], [W, W, E, E, E, E]];
+        let field_rotated = [[W, B], [W, B], [E, B], [E, B], [E, B], [E, W]];
+        assert_eq!(rotate(&field), field_rotated);
+        assert_eq!(rotate(&rotate(&rotate(&rotate(&field)))), field);
+        assert_eq!(rows_b_w_max(&field), (5, 2));
+        assert_eq!(rows_b_w_max(&rotate(&field)), (1, 1));
+        // no diagonal of length 5
+        assert_eq!(diagonal_b_w_max(&field), (0, 0));
+        assert_eq!(diagonal_b_w_max(&rotate(&field)), (0, 0));
+    }
 }
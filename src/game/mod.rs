@@ -5,17 +5,29 @@
 mod game_field;
 mod session;
 
-pub use game_field::{compress_field, decompress_field, Color, State};
+pub use game_field::{
+    compress_field, decompress_field, Color, Field, GamePhase, PhaseThresholds, State, FIVE_WEIGHT,
+    HALF_OPEN_RUN_WEIGHTS, OPEN_RUN_WEIGHTS,
+};
 pub use session::{
-    new_session, Commands, FieldInner, FieldState, FieldStateNullable, GameQuitResponse,
-    GameResult, PlayerQuitReason, PlayerResponse, SessionConfig, UndoResponse,
+    new_seeded_session, new_session, new_session_with_handle, new_session_with_listeners,
+    render_board, AbandonPolicy, BoardStyle, Commands, FieldInner, FieldState, FieldStateNullable,
+    GameQuitResponse, GameResult, GameSnapshot, GameSummary, Glyphs, OpeningBook, PlayerEvent,
+    PlayerQuitReason, PlayerResponse, RedoResponse, RoomPersistence, SessionConfig,
+    SessionConfigBuilder, SessionHandle, SimultaneousWin, TimeoutBehavior, UndoResponse,
+    MAX_MOVE_INTERVAL_MILLIS, MAX_TIMEOUT_SECONDS, MIN_BOARD_SIZE,
 };
+pub(crate) use session::{new_seeded_session_with_view, GameView};
 
 #[cfg(test)]
 mod test_game {
     use crate::game::Color::{Black, White};
+    use crate::game::State;
     use crate::game::{
-        new_session, Color, Commands, PlayerQuitReason, PlayerResponse, SessionConfig,
+        new_seeded_session, new_seeded_session_with_view, new_session, new_session_with_handle,
+        new_session_with_listeners, Color, Commands, GameQuitResponse, GameResult, GameView,
+        OpeningBook, PlayerEvent, PlayerQuitReason, PlayerResponse, RedoResponse, SessionConfig,
+        TimeoutBehavior, UndoResponse,
     };
     use async_std::channel::Receiver;
     use async_std::task;
@@ -23,6 +35,7 @@ mod test_game {
     use futures::executor::block_on;
     use futures::future::join3;
     use futures::StreamExt;
+    use std::collections::HashSet;
     use std::time::Duration;
 
     fn responses_future(color: Color, mut listener: Receiver<PlayerResponse>) -> JoinHandle<()> {
@@ -42,10 +55,8 @@ mod test_game {
     #[test]
     fn test_white_wins() {
         let config = SessionConfig::default();
-        let (mut black, mut white) = new_session(1000, 100, 200, config);
-        let rsp_b = responses_future(Black, black.get_listener().unwrap());
-        let rsp_w = responses_future(White, white.get_listener().unwrap());
-        let actions = task::spawn(async move {
+        let (black, white) = new_session(1000, 100, 200, config);
+        let actions = async {
             play_and_wait(&black, 5, 5).await;
             play_and_wait(&white, 5, 6).await;
             play_and_wait(&black, 6, 6).await;
@@ -57,16 +68,73 @@ mod test_game {
             play_and_wait(&black, 9, 8).await;
             // this should result in white wins and end game
             play_and_wait(&white, 5, 10).await;
-        });
-        block_on(join3(rsp_b, rsp_w, actions));
+        };
+        let (b_end, w_end, _) = block_on(join3(
+            black.await_game_end(),
+            white.await_game_end(),
+            actions,
+        ));
+        for end in [b_end, w_end] {
+            assert!(matches!(
+                end,
+                GameQuitResponse::GameEndWithBoard(GameResult::WhiteWins, _)
+            ));
+        }
     }
 
     #[test]
     fn test_black_wins() {
         let config = SessionConfig::default();
-        let (mut black, mut white) = new_session(1000, 100, 200, config);
-        let rsp_b = responses_future(Black, black.get_listener().unwrap());
-        let rsp_w = responses_future(White, white.get_listener().unwrap());
+        let (black, white) = new_session(1000, 100, 200, config);
+        let mut updates = 0;
+        let actions = async {
+            play_and_wait(&black, 5, 5).await;
+            play_and_wait(&white, 5, 6).await;
+            play_and_wait(&black, 6, 6).await;
+            play_and_wait(&white, 5, 7).await;
+            play_and_wait(&black, 7, 7).await;
+            play_and_wait(&white, 5, 8).await;
+            play_and_wait(&black, 8, 8).await;
+            play_and_wait(&white, 5, 9).await;
+            // this should result in black wins and end game
+            play_and_wait(&black, 9, 9).await;
+        };
+        let (b_end, w_end, _) = block_on(join3(
+            black.await_game_end(),
+            white.await_game_end_with(|rsp| {
+                if let PlayerResponse::FieldUpdate(_) = rsp {
+                    updates += 1;
+                }
+            }),
+            actions,
+        ));
+        for end in [b_end, w_end] {
+            assert!(matches!(
+                end,
+                GameQuitResponse::GameEndWithBoard(GameResult::BlackWins, _)
+            ));
+        }
+        // the winning move comes with the terminal response only
+        assert_eq!(updates, 8);
+    }
+
+    #[test]
+    fn test_await_game_end_without_listener() {
+        let (mut black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let _listener = black.get_listener().unwrap();
+        assert!(matches!(
+            block_on(black.await_game_end()),
+            GameQuitResponse::GameError(_)
+        ));
+    }
+
+    #[test]
+    fn test_new_session_with_listeners() {
+        let config = SessionConfig::default();
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        let rsp_b = responses_future(Black, b_listener);
+        let rsp_w = responses_future(White, w_listener);
         let actions = task::spawn(async move {
             play_and_wait(&black, 5, 5).await;
             play_and_wait(&white, 5, 6).await;
@@ -82,6 +150,447 @@ mod test_game {
         block_on(join3(rsp_b, rsp_w, actions));
     }
 
+    #[test]
+    fn test_session_shutdown() {
+        let config = SessionConfig::default();
+        let (mut black, mut white, handle) = new_session_with_handle(1000, 100, 200, config);
+        let b_listener = black.get_listener().unwrap();
+        let w_listener = white.get_listener().unwrap();
+        block_on(async {
+            play_and_wait(&black, 5, 5).await;
+            // stopped mid-game
+            handle.shutdown().await;
+            assert!(b_listener.is_closed());
+            assert!(w_listener.is_closed());
+            // buffered responses drain, then the listeners end
+            let _ = b_listener.collect::<Vec<_>>().await;
+            let _ = w_listener.collect::<Vec<_>>().await;
+            // commands no longer block
+            white.play(5, 6).await;
+            black.quit(PlayerQuitReason::QuitSession).await;
+        });
+    }
+
+    #[test]
+    fn test_white_first() {
+        let config = SessionConfig {
+            first_player: White,
+            ..SessionConfig::default()
+        };
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            // black cannot move first
+            play_and_wait(&black, 7, 7).await;
+            assert!(b_listener.try_recv().is_err());
+            play_and_wait(&white, 5, 5).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::FieldUpdate(f)) => assert_eq!(f.latest, (5, 5, White)),
+                    _ => panic!("white move not accepted"),
+                }
+            }
+            play_and_wait(&black, 7, 7).await;
+            assert!(matches!(
+                w_listener.try_recv(),
+                Ok(PlayerResponse::FieldUpdate(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_opening_book() {
+        let config = SessionConfig::builder()
+            .opening_book(OpeningBook::Fixed(vec![(7, 7), (7, 8), (8, 8)]))
+            .build();
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            for listener in [&b_listener, &w_listener] {
+                for latest in [(7, 7, Black), (7, 8, White), (8, 8, Black)] {
+                    match listener.try_recv() {
+                        Ok(PlayerResponse::FieldUpdate(f)) => assert_eq!(f.latest, latest),
+                        _ => panic!("opening not applied"),
+                    }
+                }
+            }
+            // white moves next, and not on the opening
+            play_and_wait(&black, 0, 0).await;
+            play_and_wait(&white, 8, 8).await;
+            assert!(w_listener.try_recv().is_err());
+            play_and_wait(&white, 6, 6).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::FieldUpdate(f)) => {
+                        assert_eq!(f.latest, (6, 6, White));
+                        assert_eq!(f.field[7][8], State::W);
+                    }
+                    _ => panic!("white move not accepted"),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_seeded_opening() {
+        let openings = (0..15).map(|i| vec![(i, i)]).collect();
+        let config = SessionConfig::builder()
+            .opening_book(OpeningBook::Random(openings))
+            .build();
+        let opening = |seed| {
+            let (mut black, _, handle) = new_seeded_session(1000, 100, 200, config.clone(), seed);
+            let listener = black.get_listener().unwrap();
+            block_on(async move {
+                let (x, y, _) = match listener.recv().await {
+                    Ok(PlayerResponse::FieldUpdate(f)) => f.latest,
+                    _ => panic!("opening not applied"),
+                };
+                handle.shutdown().await;
+                (x, y)
+            })
+        };
+        // the same seed reproduces the opening
+        assert_eq!(opening(42), opening(42));
+        let picked: HashSet<_> = (0..10).map(opening).collect();
+        assert!(picked.len() > 1);
+    }
+
+    #[test]
+    fn test_undo_game_ending_move() {
+        let config = SessionConfig::builder().final_undo_window(1).build();
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            for y in 5..9 {
+                play_and_wait(&black, y, y).await;
+                play_and_wait(&white, 5, y + 1).await;
+                while b_listener.try_recv().is_ok() {}
+                while w_listener.try_recv().is_ok() {}
+            }
+            // black wins, but the result is not final yet
+            play_and_wait(&black, 9, 9).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::GameEndPending(f, GameResult::BlackWins)) => {
+                        assert_eq!(f.latest, (9, 9, Black))
+                    }
+                    _ => panic!("game end not pending"),
+                }
+            }
+            // nobody plays while the game end is pending
+            play_and_wait(&white, 0, 0).await;
+            assert!(w_listener.try_recv().is_err());
+            // undo by mutual agreement within the window
+            black.request_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(matches!(
+                w_listener.try_recv(),
+                Ok(PlayerResponse::UndoRequest)
+            ));
+            white.approve_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::Undo(UndoResponse::Undo(f))) => {
+                        assert_eq!(f.latest, Some((5, 9, White)))
+                    }
+                    _ => panic!("game ending move not undone"),
+                }
+            }
+            // the game resumes, and is not ended by the window
+            task::sleep(Duration::from_millis(1200)).await;
+            assert!(b_listener.try_recv().is_err());
+            play_and_wait(&black, 10, 0).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::FieldUpdate(f)) => assert_eq!(f.latest, (10, 0, Black)),
+                    _ => panic!("game not resumed"),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_free_undo() {
+        let config = SessionConfig::builder().free_undo(true).build();
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            play_and_wait(&black, 5, 5).await;
+            play_and_wait(&white, 5, 6).await;
+            while b_listener.try_recv().is_ok() {}
+            while w_listener.try_recv().is_ok() {}
+            // undone without black approving
+            white.request_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::Undo(UndoResponse::Undo(f))) => {
+                        assert_eq!(f.latest, Some((5, 5, Black)));
+                        assert_eq!(f.field[5][6], State::E);
+                    }
+                    _ => panic!("undo not granted at once"),
+                }
+            }
+            assert_eq!(black.snapshot().await.to_move, Some(White));
+            play_and_wait(&white, 6, 6).await;
+            assert!(matches!(
+                b_listener.try_recv(),
+                Ok(PlayerResponse::FieldUpdate(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_unconfirmed_move_cancelled() {
+        let ((black, b_listener), (_white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, SessionConfig::default());
+        block_on(async move {
+            black.set_confirm_moves(true).await;
+            play_and_wait(&black, 5, 5).await;
+            assert!(matches!(
+                b_listener.try_recv(),
+                Ok(PlayerResponse::MovePending(5, 5))
+            ));
+            // cancelled by the server after the confirmation pause of tests
+            task::sleep(Duration::from_millis(2100)).await;
+            assert!(matches!(
+                b_listener.try_recv(),
+                Ok(PlayerResponse::MoveCancelled)
+            ));
+            assert!(w_listener.try_recv().is_err());
+            // still black to move
+            play_and_wait(&black, 6, 6).await;
+            black.confirm_move().await;
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(matches!(
+                b_listener.try_recv(),
+                Ok(PlayerResponse::MovePending(6, 6))
+            ));
+            assert!(matches!(
+                w_listener.try_recv(),
+                Ok(PlayerResponse::FieldUpdate(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_events_with_ticks() {
+        let config = SessionConfig::builder().play_timeout(5).build();
+        let (mut black, white) = new_session(1000, 100, 200, config);
+        let events = black.events(Duration::from_millis(50)).unwrap();
+        assert!(black.get_listener().is_none());
+        let collected = task::spawn(events.collect::<Vec<_>>());
+        let merged = block_on(async {
+            task::sleep(Duration::from_millis(120)).await;
+            black.play(5, 5).await;
+            task::sleep(Duration::from_millis(120)).await;
+            white.quit(PlayerQuitReason::QuitSession).await;
+            collected.await
+        });
+        let clocks = |event: &PlayerEvent| match event {
+            PlayerEvent::Tick {
+                black_clock,
+                white_clock,
+            } => Some((black_clock.is_some(), white_clock.is_some())),
+            PlayerEvent::Response(_) => None,
+        };
+        let update = merged
+            .iter()
+            .position(|e| matches!(e, PlayerEvent::Response(PlayerResponse::FieldUpdate(_))))
+            .unwrap();
+        // black clock ticking before the move, white clock after it
+        let before: Vec<_> = merged[..update].iter().filter_map(clocks).collect();
+        let after: Vec<_> = merged[update..].iter().filter_map(clocks).collect();
+        assert!(before.len() >= 2 && before.iter().all(|&c| c == (true, false)));
+        assert!(!after.is_empty() && after.iter().all(|&c| c == (false, true)));
+        // ends right after the quit response
+        assert!(matches!(
+            merged.last(),
+            Some(PlayerEvent::Response(PlayerResponse::Quit(_)))
+        ));
+    }
+
+    #[test]
+    fn test_hide_opponent_clock() {
+        for hide in [false, true] {
+            let config = SessionConfig::builder()
+                .play_timeout(5)
+                .hide_opponent_clock(hide)
+                .build();
+            let view = GameView::new(15, 15);
+            let (black, white, _) =
+                new_seeded_session_with_view(1000, 100, 200, config, 0, view.clone());
+            block_on(async {
+                task::sleep(Duration::from_millis(100)).await;
+                // black to move
+                let own = black.snapshot().await.black_clock.unwrap();
+                assert!(own > Duration::from_secs(4) && own <= Duration::from_secs(5));
+                assert_eq!(white.snapshot().await.black_clock.is_some(), !hide);
+                play_and_wait(&black, 5, 5).await;
+                // white to move
+                assert!(white.snapshot().await.white_clock.is_some());
+                assert_eq!(black.snapshot().await.white_clock.is_some(), !hide);
+                // the shared view is not affected
+                assert!(view.snapshot().await.white_clock.is_some());
+            });
+        }
+    }
+
+    #[test]
+    fn test_undo_dialogue_clock_isolation() {
+        let config = SessionConfig::builder()
+            .play_timeout(10)
+            .undo_request_timeout(1)
+            .undo_dialogue_extra_seconds(5)
+            .build();
+        let (black, white) = new_session(1000, 100, 200, config);
+        block_on(async {
+            play_and_wait(&black, 5, 5).await;
+            play_and_wait(&white, 5, 6).await;
+            // black approves the undo request of white, with black's clock paused
+            white.request_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            let paused = black.snapshot().await.black_clock.unwrap();
+            task::sleep(Duration::from_millis(300)).await;
+            let snapshot = white.snapshot().await;
+            assert_eq!(snapshot.black_clock, Some(paused));
+            assert_eq!(snapshot.white_clock, None);
+            // timeout rejected, only black is compensated
+            task::sleep(Duration::from_millis(1000)).await;
+            let snapshot = white.snapshot().await;
+            assert!(snapshot.black_clock.unwrap() > paused + Duration::from_secs(4));
+            assert_eq!(snapshot.white_clock, None);
+            // white gets the plain play timeout on the next turn
+            play_and_wait(&black, 6, 6).await;
+            let white_clock = black.snapshot().await.white_clock.unwrap();
+            assert!(white_clock > Duration::from_secs(9));
+            assert!(white_clock <= Duration::from_secs(10));
+        });
+    }
+
+    #[test]
+    fn test_timeout_auto_pass() {
+        let config = SessionConfig::builder()
+            .play_timeout(1)
+            .timeout_behavior(TimeoutBehavior::AutoPass)
+            .build();
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            // black runs out of time
+            task::sleep(Duration::from_millis(1200)).await;
+            for listener in [&b_listener, &w_listener] {
+                assert!(matches!(
+                    listener.try_recv(),
+                    Ok(PlayerResponse::TurnPassed(Black))
+                ));
+            }
+            assert_eq!(black.snapshot().await.to_move, Some(White));
+            // black's move is ignored, white plays on
+            play_and_wait(&black, 5, 5).await;
+            assert!(b_listener.try_recv().is_err());
+            play_and_wait(&white, 5, 5).await;
+            // undoing the first move after the pass gives the turn back to white
+            white.request_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            black.approve_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            let snapshot = black.snapshot().await;
+            assert_eq!(snapshot.field.0[5][5], State::E);
+            assert_eq!(snapshot.to_move, Some(White));
+            play_and_wait(&white, 6, 6).await;
+            play_and_wait(&black, 7, 7).await;
+            let snapshot = white.snapshot().await;
+            assert_eq!(snapshot.field.0[6][6], State::W);
+            assert_eq!(snapshot.field.0[7][7], State::B);
+            assert_eq!(snapshot.to_move, Some(White));
+        });
+    }
+
+    #[test]
+    fn test_proximity_rule() {
+        let config = SessionConfig::builder().proximity_rule(Some(2)).build();
+        let ((black, _), (white, w_listener)) = new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            // the opening move is exempt
+            play_and_wait(&black, 0, 0).await;
+            assert!(matches!(
+                w_listener.try_recv(),
+                Ok(PlayerResponse::FieldUpdate(_))
+            ));
+            // three cells away from the only stone
+            play_and_wait(&white, 3, 0).await;
+            assert!(matches!(
+                w_listener.try_recv(),
+                Ok(PlayerResponse::InvalidMove(3, 0))
+            ));
+            assert_eq!(white.snapshot().await.to_move, Some(White));
+            play_and_wait(&white, 2, 2).await;
+            assert!(matches!(
+                w_listener.try_recv(),
+                Ok(PlayerResponse::FieldUpdate(_))
+            ));
+            assert_eq!(black.snapshot().await.field.0[2][2], State::W);
+        });
+    }
+
+    #[test]
+    fn test_undo_then_redo() {
+        let config = SessionConfig::default();
+        let ((black, b_listener), (white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            play_and_wait(&black, 5, 5).await;
+            play_and_wait(&white, 5, 6).await;
+            while b_listener.try_recv().is_ok() {}
+            let _ = w_listener.try_recv();
+            let played = match w_listener.try_recv() {
+                Ok(PlayerResponse::FieldUpdate(f)) => f,
+                _ => panic!("white move not accepted"),
+            };
+            assert_eq!(played.latest, (5, 6, White));
+            let before_undo = black.snapshot().await;
+            white.request_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            black.approve_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            while b_listener.try_recv().is_ok() {}
+            while w_listener.try_recv().is_ok() {}
+            assert_eq!(black.snapshot().await.to_move, Some(White));
+            // only the player whose move was undone may redo
+            black.request_redo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(w_listener.try_recv().is_err());
+            white.request_redo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(matches!(
+                b_listener.try_recv(),
+                Ok(PlayerResponse::RedoRequest)
+            ));
+            black.approve_redo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            for listener in [&b_listener, &w_listener] {
+                match listener.try_recv() {
+                    Ok(PlayerResponse::Redo(RedoResponse::Redo(f))) => assert!(f == played),
+                    _ => panic!("undone move not redone"),
+                }
+            }
+            // the exact board and turn before the undo
+            let after_redo = black.snapshot().await;
+            assert!(after_redo.field == before_undo.field);
+            assert_eq!(after_redo.to_move, Some(Black));
+            assert_eq!(before_undo.to_move, Some(Black));
+            // nothing left to redo once black plays
+            play_and_wait(&black, 6, 6).await;
+            while b_listener.try_recv().is_ok() {}
+            white.request_redo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(b_listener.try_recv().is_err());
+        });
+    }
+
     #[test]
     fn test_ignore_repeated_request() {
         let config = SessionConfig::default();
@@ -123,6 +632,60 @@ mod test_game {
         block_on(join3(rsp_b, rsp_w, actions));
     }
 
+    #[test]
+    fn test_quick_games_deliver_quit() {
+        for game in 0..100 {
+            let config = SessionConfig::default();
+            let (black, white) = new_session(game, 100, 200, config);
+            let actions = async {
+                match game % 3 {
+                    0 => black.quit(PlayerQuitReason::QuitSession).await,
+                    1 => {
+                        black.play(5, 5).await;
+                        white.quit(PlayerQuitReason::QuitSession).await;
+                    }
+                    _ => {
+                        futures::join!(
+                            black.quit(PlayerQuitReason::QuitSession),
+                            white.quit(PlayerQuitReason::QuitSession)
+                        );
+                    }
+                }
+            };
+            let (b_end, w_end, _) = block_on(join3(
+                black.await_game_end(),
+                white.await_game_end(),
+                actions,
+            ));
+            for end in [b_end, w_end] {
+                assert!(
+                    !matches!(end, GameQuitResponse::GameError(_)),
+                    "game {game} lost the quit response"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_kicked_from_game() {
+        let config = SessionConfig::default();
+        let ((black, _b_listener), (_white, w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            play_and_wait(&black, 5, 5).await;
+            while w_listener.try_recv().is_ok() {}
+            black
+                .quit(PlayerQuitReason::Kicked("idle".to_string()))
+                .await;
+            match w_listener.recv().await {
+                Ok(PlayerResponse::Quit(GameQuitResponse::OpponentKicked(100, reason))) => {
+                    assert_eq!(reason, "idle")
+                }
+                _ => panic!("kick not told apart"),
+            }
+        });
+    }
+
     #[test]
     fn test_undo_approve_game() {
         let config = SessionConfig::default();
@@ -198,6 +761,41 @@ mod test_game {
         block_on(join3(rsp_b, rsp_w, actions));
     }
 
+    #[test]
+    fn test_snapshot() {
+        let config = SessionConfig::builder()
+            .play_timeout(10)
+            .undo_request_timeout(10)
+            .build();
+        let ((black, _b_listener), (white, _w_listener)) =
+            new_session_with_listeners(1000, 100, 200, config);
+        block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            let snapshot = black.snapshot().await;
+            assert_eq!(snapshot.to_move, Some(Black));
+            assert!(snapshot.black_clock.unwrap() <= Duration::from_secs(10));
+            assert!(snapshot.white_clock.is_none());
+            play_and_wait(&black, 7, 7).await;
+            let snapshot = white.snapshot().await;
+            assert_eq!(snapshot.field[7][7], State::B);
+            assert_eq!(snapshot.to_move, Some(White));
+            assert!(snapshot.black_clock.is_none());
+            let white_clock = snapshot.white_clock.unwrap();
+            assert!(white_clock <= Duration::from_secs(10));
+            assert!(white_clock > Duration::from_secs(9));
+            // the clock of white stops while answering the undo request
+            black.request_undo().await;
+            task::sleep(Duration::from_millis(100)).await;
+            let paused = white.snapshot().await.white_clock.unwrap();
+            task::sleep(Duration::from_millis(200)).await;
+            assert_eq!(white.snapshot().await.white_clock, Some(paused));
+            // both sides observe the same game
+            let (b, w) = (black.snapshot().await, white.snapshot().await);
+            assert!(b.field == w.field);
+            assert_eq!(b.to_move, w.to_move);
+        });
+    }
+
     #[test]
     fn test_approve_play_timeout_pause() {
         let mut config = SessionConfig::default();
@@ -1,22 +1,33 @@
 use crate::game::game_field::{Color, State};
 use crate::game::session::messages::{
-    PlayerAction::{self, Play, Quit, RequestUndo, Undo},
+    PlayerAction::{
+        self, CancelMove, ConfirmMove, Play, Quit, Redo, RequestRedo, RequestUndo, SetConfirmMoves,
+        Undo,
+    },
     UndoAction::{Approve, Reject},
 };
+use crate::game::session::view::GameView;
 use crate::{compress_field, decompress_field};
 use async_std::channel::{Receiver, Sender};
+use async_std::task;
 use bincode::de::read::Reader;
 use bincode::de::Decoder;
 use bincode::enc::write::Writer;
 use bincode::enc::Encoder;
 use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode};
+use futures::stream::{self, Stream};
+use futures::{select, FutureExt};
 use std::ops::Deref;
+use std::time::{Duration, Instant};
 
 /// Public API used for interacting with the game
 pub struct Commands {
     listener: Option<Receiver<PlayerResponse>>,
     action_sender: Sender<PlayerAction>,
+    view: GameView,
+    /// the opponent whose clock is hidden, with `hide_opponent_clock`
+    hidden_clock: Option<Color>,
 }
 
 /// all player actions are here
@@ -26,6 +37,22 @@ impl Commands {
         let _ = self.action_sender.send(Play(x, y)).await;
     }
 
+    /// commit the pending move when confirm-moves mode is on
+    pub async fn confirm_move(&self) {
+        let _ = self.action_sender.send(ConfirmMove).await;
+    }
+
+    /// discard the pending move when confirm-moves mode is on
+    pub async fn cancel_move(&self) {
+        let _ = self.action_sender.send(CancelMove).await;
+    }
+
+    /// in confirm-moves mode, `play` only proposes a move
+    /// which is committed by `confirm_move`
+    pub async fn set_confirm_moves(&self, on: bool) {
+        let _ = self.action_sender.send(SetConfirmMoves(on)).await;
+    }
+
     pub async fn request_undo(&self) {
         let _ = self.action_sender.send(RequestUndo).await;
     }
@@ -38,6 +65,19 @@ impl Commands {
         let _ = self.action_sender.send(Undo(Reject)).await;
     }
 
+    /// replay my latest undone move, on my turn right after the undo
+    pub async fn request_redo(&self) {
+        let _ = self.action_sender.send(RequestRedo).await;
+    }
+
+    pub async fn approve_redo(&self) {
+        let _ = self.action_sender.send(Redo(Approve)).await;
+    }
+
+    pub async fn reject_redo(&self) {
+        let _ = self.action_sender.send(Redo(Reject)).await;
+    }
+
     /// `quit()` should be called before ending the game to properly
     /// notify the other player.
     ///
@@ -53,17 +93,103 @@ impl Commands {
         self.listener.take()
     }
 
+    /// Merge the responses with a `Tick` every `tick_interval`,
+    /// so that a UI can drive its countdown from a single stream.
+    ///
+    /// Takes the listener like `get_listener`, `None` if it was already taken.
+    /// The stream ends after the `Quit` response.
+    pub fn events(&mut self, tick_interval: Duration) -> Option<impl Stream<Item = PlayerEvent>> {
+        let listener = self.listener.take()?;
+        let view = self.view.clone();
+        let hidden_clock = self.hidden_clock;
+        let first_tick = Instant::now() + tick_interval;
+        let events = stream::unfold(
+            (listener, first_tick, false),
+            move |(listener, next_tick, ended)| {
+                let view = view.clone();
+                async move {
+                    if ended {
+                        return None;
+                    }
+                    let wait = next_tick.saturating_duration_since(Instant::now());
+                    select! {
+                        rsp = listener.recv().fuse() => {
+                            let rsp = rsp.ok()?;
+                            let ended = matches!(rsp, PlayerResponse::Quit(_));
+                            Some((PlayerEvent::Response(rsp), (listener, next_tick, ended)))
+                        }
+                        _ = task::sleep(wait).fuse() => {
+                            let snapshot = hide_clock(view.snapshot().await, hidden_clock);
+                            let tick = PlayerEvent::Tick {
+                                black_clock: snapshot.black_clock,
+                                white_clock: snapshot.white_clock,
+                            };
+                            Some((tick, (listener, next_tick + tick_interval, false)))
+                        }
+                    }
+                }
+            },
+        );
+        Some(events)
+    }
+
+    /// Wait until the game ends, and return the terminal `Quit` response.
+    ///
+    /// Other responses are discarded. Returns `GameError` if the listener was taken
+    /// by `get_listener`, or if the session stopped without a `Quit` response.
+    pub async fn await_game_end(&self) -> GameQuitResponse {
+        self.await_game_end_with(|_| {}).await
+    }
+
+    /// like `await_game_end`, passing the other responses to `on_response`
+    pub async fn await_game_end_with(
+        &self,
+        mut on_response: impl FnMut(PlayerResponse),
+    ) -> GameQuitResponse {
+        let listener = match &self.listener {
+            Some(listener) => listener,
+            None => return GameQuitResponse::GameError("listener taken".to_string()),
+        };
+        while let Ok(rsp) = listener.recv().await {
+            match rsp {
+                PlayerResponse::Quit(quit_rsp) => return quit_rsp,
+                rsp => on_response(rsp),
+            }
+        }
+        GameQuitResponse::GameError("session stopped without quit response".to_string())
+    }
+
+    /// observe the game once, without affecting it,
+    /// the opponent's clock is `None` with `hide_opponent_clock`
+    pub async fn snapshot(&self) -> GameSnapshot {
+        hide_clock(self.view.snapshot().await, self.hidden_clock)
+    }
+
     pub(crate) fn new(
         action_sender: Sender<PlayerAction>,
         listener: Receiver<PlayerResponse>,
+        view: GameView,
+        hidden_clock: Option<Color>,
     ) -> Commands {
         Commands {
             listener: Some(listener),
             action_sender,
+            view,
+            hidden_clock,
         }
     }
 }
 
+/// clear the clock of `hidden` in `snapshot`
+fn hide_clock(mut snapshot: GameSnapshot, hidden: Option<Color>) -> GameSnapshot {
+    match hidden {
+        Some(Color::Black) => snapshot.black_clock = None,
+        Some(Color::White) => snapshot.white_clock = None,
+        None => {}
+    }
+    snapshot
+}
+
 /// the reason of player quit
 #[derive(Debug)]
 pub enum PlayerQuitReason {
@@ -71,20 +197,54 @@ pub enum PlayerQuitReason {
     ExitGame,
     Disconnected,
     Error(String),
+    /// removed by moderation, with the reason
+    Kicked(String),
 }
 
 /// response to players
 #[derive(Clone, Debug)]
 pub enum PlayerResponse {
     FieldUpdate(FieldState),
+    /// in confirm-moves mode, the move waiting for confirmation
+    MovePending(u8, u8),
+    /// in confirm-moves mode, the pending move is discarded
+    MoveCancelled,
+    /// the move (x, y) was rolled back, it is still my turn
+    InvalidMove(u8, u8),
+    /// a move was played on the opponent's turn and ignored,
+    /// only with `notify_not_your_turn`
+    NotYourTurn,
+    /// a move was played within `min_move_interval` of the previous one and ignored
+    MovingTooFast,
     UndoRequest,
     Undo(UndoResponse),
+    RedoRequest,
+    Redo(RedoResponse),
+    /// the latest move ends the game with the result,
+    /// unless it is undone within `final_undo_window`
+    GameEndPending(FieldState, GameResult),
+    /// the player of the color ran out of time and the turn passes to the opponent,
+    /// only with `TimeoutBehavior::AutoPass`
+    TurnPassed(Color),
+    /// the same to both players right before `Quit`, when the game ends with a result
+    GameSummary(GameSummary),
     /// Other player quit or game error.
     /// Game session will end automatically on
     /// receiving Quit response
     Quit(GameQuitResponse),
 }
 
+/// item of `Commands::events`
+#[derive(Clone, Debug)]
+pub enum PlayerEvent {
+    Response(PlayerResponse),
+    /// local estimates of the clocks between responses, as in `GameSnapshot`
+    Tick {
+        black_clock: Option<Duration>,
+        white_clock: Option<Duration>,
+    },
+}
+
 /// response to players
 #[derive(Clone, Debug)]
 pub enum UndoResponse {
@@ -96,13 +256,32 @@ pub enum UndoResponse {
     RejectedByOpponent,
     /// send only to requester
     AutoRejected,
+    /// send only to requester, when the opponent has an undo request pending
+    RequestInProgress,
+}
+
+/// response to players, like `UndoResponse`
+#[derive(Clone, Debug)]
+pub enum RedoResponse {
+    /// broadcast to both players
+    TimeoutRejected,
+    /// broadcast to both players, the undone move is played again
+    Redo(FieldState),
+    /// send only to requester
+    RejectedByOpponent,
+    /// send only to requester
+    AutoRejected,
+    /// send only to requester, when the opponent has an undo or redo request pending
+    RequestInProgress,
 }
 
 /// reason of game session end
 #[derive(Clone, Debug)]
 pub enum GameQuitResponse {
-    /// broadcast to both players
+    /// broadcast to both players, when the game ends by timeout
     GameEnd(GameResult),
+    /// broadcast to both players, when the game ends by the latest move in `FieldState`
+    GameEndWithBoard(GameResult, FieldState),
     /// send to both players
     PlayerQuitSession(u64),
     /// send to opponent
@@ -111,18 +290,48 @@ pub enum GameQuitResponse {
     OpponentDisconnected(u64),
     /// send to opponent
     OpponentError(u64, String),
+    /// send to opponent, the player was removed by moderation with the reason
+    OpponentKicked(u64, String),
     /// broadcast to both players
     GameError(String),
 }
 
+/// recap of a game ending with a result
+#[derive(Clone, PartialEq, Debug)]
+pub struct GameSummary {
+    /// moves played, the opening excluded
+    pub moves: u16,
+    /// since the opening
+    pub duration: Duration,
+    /// the sum of the time black took for each move since the previous change of the board
+    pub black_time: Duration,
+    /// like `black_time`
+    pub white_time: Duration,
+    pub result: GameResult,
+}
+
 /// result of the game
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
 pub enum GameResult {
     BlackTimeout,
     WhiteTimeout,
     BlackWins,
     WhiteWins,
     Draw,
+    /// a player left before any move was played, no contest
+    Aborted,
+}
+
+/// the game as observed at one instant
+#[derive(Clone, Debug)]
+pub struct GameSnapshot {
+    pub field: FieldInner,
+    /// time left for black to play, `None` unless black is to move with a play timeout
+    pub black_clock: Option<Duration>,
+    /// time left for white to play, `None` unless white is to move with a play timeout
+    pub white_clock: Option<Duration>,
+    /// `None` while a move is being committed, or while the game end is pending
+    pub to_move: Option<Color>,
 }
 
 /// this struct represents a game field
@@ -133,6 +342,13 @@ pub struct FieldState {
     pub field: FieldInner,
 }
 
+impl FieldState {
+    /// compare only the stones on the field, ignoring `latest`
+    pub fn same_board(&self, other: &FieldState) -> bool {
+        self.field == other.field
+    }
+}
+
 /// this struct represents a game field
 /// and also the coordinate of the latest position
 #[derive(Clone, PartialEq, Encode, Decode)]
@@ -141,62 +357,435 @@ pub struct FieldStateNullable {
     pub field: FieldInner,
 }
 
+/// `field[x][y]`, `width` rows of `height` states
 #[derive(Clone, PartialEq)]
-pub struct FieldInner(pub [[State; 15]; 15]);
+pub struct FieldInner(pub Vec<Vec<State>>);
+
+impl FieldInner {
+    pub fn width(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.0.first().map_or(0, |row| row.len())
+    }
+}
 
 impl Deref for FieldInner {
-    type Target = [[State; 15]; 15];
+    type Target = Vec<Vec<State>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+/// structure: `[WIDTH, HEIGHT, ROW_0, ..., ROW_{WIDTH-1}]`,
+/// each row compressed into `(HEIGHT + 3) / 4` bytes
 impl Decode for FieldInner {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
-        decoder.claim_bytes_read(4 * 15)?;
+        decoder.claim_bytes_read(2)?;
         let reader = decoder.reader();
-        let mut arr = [0u8, 0u8, 0u8, 0u8];
-        let mut field_data = [(0u8, 0u8, 0u8, 0u8); 15];
+        let mut size = [0u8, 0u8];
+        reader.read(&mut size)?;
+        let (width, height) = (size[0] as usize, size[1] as usize);
+        let row_bytes = height.div_ceil(4);
+        decoder.claim_bytes_read(width * row_bytes)?;
+        let reader = decoder.reader();
+        let mut field_data = vec![vec![0u8; row_bytes]; width];
         for row in field_data.iter_mut() {
-            reader.read(&mut arr)?;
-            row.0 = arr[0];
-            row.1 = arr[1];
-            row.2 = arr[2];
-            row.3 = arr[3];
+            reader.read(row)?;
         }
-        Ok(FieldInner(decompress_field(&field_data)))
+        Ok(FieldInner(decompress_field(&field_data, height)))
     }
 }
 
 impl Encode for FieldInner {
     fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
         let writer = encoder.writer();
-        let bytes_array = compress_field(self);
-        for bytes in bytes_array {
-            writer.write(&[bytes.0, bytes.1, bytes.2, bytes.3])?;
+        writer.write(&[self.width() as u8, self.height() as u8])?;
+        for bytes in compress_field(self) {
+            writer.write(&bytes)?;
         }
         Ok(())
     }
 }
 
+/// shortest board side of a valid `SessionConfig`
+pub const MIN_BOARD_SIZE: u8 = 5;
+/// longest timeout of a valid `SessionConfig`, in seconds
+pub const MAX_TIMEOUT_SECONDS: u64 = 24 * 60 * 60;
+/// longest `min_move_interval` of a valid `SessionConfig`, in milliseconds
+pub const MAX_MOVE_INTERVAL_MILLIS: u64 = 60 * 1000;
+
 /// client time should be shorter
 ///
 /// 0 means no timeout
+///
+/// positions are `(x, y)` with `x` in `[0, board_width)`
+/// and `y` in `[0, board_height)`
 #[derive(Clone, PartialEq, Debug, Encode, Decode)]
 pub struct SessionConfig {
+    /// seconds the opponent has to answer an undo request, 0 (default) means no limit
     pub undo_request_timeout: u64,
+    /// seconds added to the play clock when it resumes after an undo dialogue, 0 by default
     pub undo_dialogue_extra_seconds: u64,
+    /// seconds a player has for each move, 0 (default) means no limit
     pub play_timeout: u64,
+    /// 15 by default
+    pub board_width: u8,
+    /// 15 by default
+    pub board_height: u8,
+    /// the color that makes the opening move, black by default
+    pub first_player: Color,
+    /// how the room scores a game abandoned by one of the players, not scored by default
+    pub abandon_policy: AbandonPolicy,
+    /// roll back a move leading to an impossible state (a bug)
+    /// and answer `InvalidMove` instead of ending the game in error, off by default
+    pub recover_impossible: bool,
+    /// seconds during which the game ending move can still be undone
+    /// by mutual agreement before the result is final, 0 (default) means no such window
+    pub final_undo_window: u64,
+    /// opening moves placed before the players take over, free by default
+    pub opening_book: OpeningBook,
+    /// answer `NotYourTurn` to a move played on the opponent's turn,
+    /// instead of ignoring it silently (default)
+    pub notify_not_your_turn: bool,
+    /// end the game in a draw as soon as neither color can get five in a row,
+    /// instead of when the board is full (default)
+    pub early_draw: bool,
+    /// milliseconds a player must wait after a move before playing again,
+    /// faster moves are answered with `MovingTooFast`, 0 (default) means no limit
+    pub min_move_interval: u64,
+    /// whether the room survives its creator leaving, persistent by default
+    pub room_persistence: RoomPersistence,
+    /// seconds an undo dialogue may pause the play clock at most,
+    /// after which the undo is rejected as timed out even if
+    /// `undo_request_timeout` is longer or 0, 0 (default) means no ceiling
+    pub max_undo_pause_seconds: u64,
+    /// scoring of a move after which both colors have five in a row,
+    /// which is only possible from a seeded position such as an opening,
+    /// won by the mover by default
+    pub simultaneous_win: SimultaneousWin,
+    /// grant undo requests at once without asking the opponent,
+    /// e.g. for teaching, instead of the approval dialogue (default)
+    pub free_undo: bool,
+    /// answer `Ready` with `CannotReadyEmptyRoom` while alone in the room,
+    /// instead of waiting ready for an opponent (default)
+    pub ready_needs_opponent: bool,
+    /// players only see their own clock in `Commands::snapshot` and `Commands::events`,
+    /// spectators still see both, off by default
+    pub hide_opponent_clock: bool,
+    /// what happens when a player runs out of `play_timeout`, losing by default
+    pub timeout_behavior: TimeoutBehavior,
+    /// seconds both players have to answer `GameStarted` with `Ack` before the first clock starts,
+    /// the game is aborted if either does not, 0 (default) means the clock starts at once
+    pub start_ack_timeout: u64,
+    /// moves must be within this many cells of a stone along `x` and `y`, except on an empty board,
+    /// farther moves are answered with `InvalidMove`, `None` (default) means anywhere
+    pub proximity_rule: Option<u8>,
+}
+
+/// opening positions `(x, y)` with alternating colors starting from `first_player`,
+/// the players continue with the next color.
+///
+/// An opening is cut at its first move off the board or on an occupied position,
+/// and is assumed not to end the game.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub enum OpeningBook {
+    /// the players make all the moves
+    Free,
+    /// always the same opening
+    Fixed(Vec<(u8, u8)>),
+    /// one of the openings picked at random
+    Random(Vec<Vec<(u8, u8)>>),
+}
+
+/// scoring of a game that one player left by exit or disconnection
+#[derive(Clone, Copy, PartialEq, Debug, Encode, Decode)]
+pub enum AbandonPolicy {
+    /// neither exit nor disconnection is scored
+    NoContest,
+    /// exit counts as a loss for the exiting player,
+    /// disconnection is a no-contest
+    ExitAsLoss,
+    /// both exit and disconnection count as a loss for the leaving player
+    LeaveAsLoss,
+}
+
+/// what happens to a room when its creator leaves
+#[derive(Clone, Copy, PartialEq, Debug, Encode, Decode)]
+pub enum RoomPersistence {
+    /// the room stays open for the other player
+    Persistent,
+    /// the room closes, the other player is sent back to the lobby with `RoomClosed`
+    OwnerBound,
 }
 
-/// by default no restriction
+/// how a move completing five in a row for both colors is scored
+#[derive(Clone, Copy, PartialEq, Debug, Encode, Decode)]
+pub enum SimultaneousWin {
+    /// the player making the move wins
+    MoverWins,
+    /// the game is a draw
+    Draw,
+}
+
+/// what happens when a player runs out of time to move
+#[derive(Clone, Copy, PartialEq, Debug, Encode, Decode)]
+pub enum TimeoutBehavior {
+    /// the player loses the game
+    Lose,
+    /// the player skips the move and the game goes on
+    AutoPass,
+}
+
+/// the defaults are documented on each field
 impl Default for SessionConfig {
     fn default() -> Self {
         SessionConfig {
             undo_request_timeout: 0,
             undo_dialogue_extra_seconds: 0,
             play_timeout: 0,
+            board_width: 15,
+            board_height: 15,
+            first_player: Color::Black,
+            abandon_policy: AbandonPolicy::NoContest,
+            recover_impossible: false,
+            final_undo_window: 0,
+            opening_book: OpeningBook::Free,
+            notify_not_your_turn: false,
+            early_draw: false,
+            min_move_interval: 0,
+            room_persistence: RoomPersistence::Persistent,
+            max_undo_pause_seconds: 0,
+            simultaneous_win: SimultaneousWin::MoverWins,
+            free_undo: false,
+            ready_needs_opponent: false,
+            hide_opponent_clock: false,
+            timeout_behavior: TimeoutBehavior::Lose,
+            start_ack_timeout: 0,
+            proximity_rule: None,
         }
     }
 }
+
+impl SessionConfig {
+    /// start from the default config, see `SessionConfigBuilder`
+    pub fn builder() -> SessionConfigBuilder {
+        SessionConfigBuilder::default()
+    }
+
+    /// sides of at least `MIN_BOARD_SIZE`, timeouts of at most `MAX_TIMEOUT_SECONDS`,
+    /// and `min_move_interval` of at most `MAX_MOVE_INTERVAL_MILLIS`
+    pub fn is_valid(&self) -> bool {
+        let timeouts = [
+            self.undo_request_timeout,
+            self.undo_dialogue_extra_seconds,
+            self.play_timeout,
+            self.final_undo_window,
+            self.max_undo_pause_seconds,
+            self.start_ack_timeout,
+        ];
+        self.board_width >= MIN_BOARD_SIZE
+            && self.board_height >= MIN_BOARD_SIZE
+            && timeouts
+                .iter()
+                .all(|&seconds| seconds <= MAX_TIMEOUT_SECONDS)
+            && self.min_move_interval <= MAX_MOVE_INTERVAL_MILLIS
+    }
+
+    /// whether `(x, y)` is on the board, the bounds every layer checks moves against
+    pub fn on_board(&self, x: u8, y: u8) -> bool {
+        x < self.board_width && y < self.board_height
+    }
+}
+
+/// builds a `SessionConfig`, unset fields keep their default values
+#[derive(Clone, Debug, Default)]
+pub struct SessionConfigBuilder {
+    config: SessionConfig,
+}
+
+impl SessionConfigBuilder {
+    pub fn undo_request_timeout(mut self, seconds: u64) -> Self {
+        self.config.undo_request_timeout = seconds;
+        self
+    }
+
+    pub fn undo_dialogue_extra_seconds(mut self, seconds: u64) -> Self {
+        self.config.undo_dialogue_extra_seconds = seconds;
+        self
+    }
+
+    pub fn play_timeout(mut self, seconds: u64) -> Self {
+        self.config.play_timeout = seconds;
+        self
+    }
+
+    pub fn board_size(mut self, width: u8, height: u8) -> Self {
+        self.config.board_width = width;
+        self.config.board_height = height;
+        self
+    }
+
+    pub fn first_player(mut self, color: Color) -> Self {
+        self.config.first_player = color;
+        self
+    }
+
+    pub fn abandon_policy(mut self, policy: AbandonPolicy) -> Self {
+        self.config.abandon_policy = policy;
+        self
+    }
+
+    pub fn recover_impossible(mut self, recover: bool) -> Self {
+        self.config.recover_impossible = recover;
+        self
+    }
+
+    pub fn final_undo_window(mut self, seconds: u64) -> Self {
+        self.config.final_undo_window = seconds;
+        self
+    }
+
+    pub fn opening_book(mut self, book: OpeningBook) -> Self {
+        self.config.opening_book = book;
+        self
+    }
+
+    pub fn notify_not_your_turn(mut self, notify: bool) -> Self {
+        self.config.notify_not_your_turn = notify;
+        self
+    }
+
+    pub fn early_draw(mut self, early_draw: bool) -> Self {
+        self.config.early_draw = early_draw;
+        self
+    }
+
+    pub fn min_move_interval(mut self, millis: u64) -> Self {
+        self.config.min_move_interval = millis;
+        self
+    }
+
+    pub fn room_persistence(mut self, persistence: RoomPersistence) -> Self {
+        self.config.room_persistence = persistence;
+        self
+    }
+
+    pub fn max_undo_pause_seconds(mut self, seconds: u64) -> Self {
+        self.config.max_undo_pause_seconds = seconds;
+        self
+    }
+
+    pub fn simultaneous_win(mut self, resolution: SimultaneousWin) -> Self {
+        self.config.simultaneous_win = resolution;
+        self
+    }
+
+    pub fn free_undo(mut self, free_undo: bool) -> Self {
+        self.config.free_undo = free_undo;
+        self
+    }
+
+    pub fn ready_needs_opponent(mut self, needs_opponent: bool) -> Self {
+        self.config.ready_needs_opponent = needs_opponent;
+        self
+    }
+
+    pub fn hide_opponent_clock(mut self, hide: bool) -> Self {
+        self.config.hide_opponent_clock = hide;
+        self
+    }
+
+    pub fn timeout_behavior(mut self, behavior: TimeoutBehavior) -> Self {
+        self.config.timeout_behavior = behavior;
+        self
+    }
+
+    pub fn start_ack_timeout(mut self, seconds: u64) -> Self {
+        self.config.start_ack_timeout = seconds;
+        self
+    }
+
+    pub fn proximity_rule(mut self, radius: Option<u8>) -> Self {
+        self.config.proximity_rule = radius;
+        self
+    }
+
+    pub fn build(self) -> SessionConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod test_api {
+    use super::*;
+
+    #[test]
+    fn test_same_board() {
+        let mut field = vec![vec![State::E; 15]; 15];
+        field[5][5] = State::B;
+        field[5][6] = State::W;
+        let a = FieldState {
+            latest: (5, 6, Color::White),
+            field: FieldInner(field.clone()),
+        };
+        let b = FieldState {
+            latest: (5, 5, Color::Black),
+            field: FieldInner(field.clone()),
+        };
+        assert!(a.same_board(&b));
+        assert!(a != b);
+        field[7][7] = State::B;
+        let c = FieldState {
+            latest: (5, 6, Color::White),
+            field: FieldInner(field),
+        };
+        assert!(!a.same_board(&c));
+        assert!(a != c);
+    }
+
+    #[test]
+    fn test_session_config_builder() {
+        let config = SessionConfig::builder()
+            .play_timeout(30)
+            .board_size(10, 20)
+            .first_player(Color::White)
+            .build();
+        assert_eq!(config.play_timeout, 30);
+        assert_eq!(config.board_width, 10);
+        assert_eq!(config.board_height, 20);
+        assert_eq!(config.first_player, Color::White);
+        // unset fields are defaulted
+        let default = SessionConfig::default();
+        assert_eq!(config.undo_request_timeout, default.undo_request_timeout);
+        assert_eq!(
+            config.undo_dialogue_extra_seconds,
+            default.undo_dialogue_extra_seconds
+        );
+        assert_eq!(config.abandon_policy, default.abandon_policy);
+        assert_eq!(SessionConfig::builder().build(), default);
+    }
+
+    #[test]
+    fn test_session_config_is_valid() {
+        assert!(SessionConfig::default().is_valid());
+        let smallest = SessionConfig::builder()
+            .board_size(MIN_BOARD_SIZE, MIN_BOARD_SIZE)
+            .play_timeout(MAX_TIMEOUT_SECONDS)
+            .min_move_interval(MAX_MOVE_INTERVAL_MILLIS)
+            .build();
+        assert!(smallest.is_valid());
+        let empty_board = SessionConfig::builder().board_size(0, 0).build();
+        assert!(!empty_board.is_valid());
+        let narrow = SessionConfig::builder().board_size(4, 15).build();
+        assert!(!narrow.is_valid());
+        let endless = SessionConfig::builder().start_ack_timeout(u64::MAX).build();
+        assert!(!endless.is_valid());
+        let sluggish = SessionConfig::builder()
+            .min_move_interval(MAX_MOVE_INTERVAL_MILLIS + 1)
+            .build();
+        assert!(!sluggish.is_valid());
+    }
+}
@@ -1,21 +1,33 @@
 use crate::game::game_field::Color::{Black, White};
 use crate::game::game_field::{Color, GameCommand, GameResponse, State};
+use crate::game::session::utility::TimeoutGate;
 use crate::game::session::{
-    FieldState, FieldStateNullable, GameQuitResponse, GameResult, PlayerQuitReason, UndoResponse,
+    FieldInner, FieldState, FieldStateNullable, GameQuitResponse, GameResult, GameSummary,
+    PlayerQuitReason, RedoResponse, UndoResponse,
 };
 use crate::CHANNEL_SIZE;
 use anyhow::Result;
 use async_std::channel::{bounded, Receiver, Sender};
-use async_std::task;
+use async_std::task::{self, JoinHandle};
 use futures::{stream_select, StreamExt};
 use std::fmt::{Formatter, Write};
+use std::time::Duration;
 
 /// actions received from players
 #[derive(Debug)]
 pub(crate) enum PlayerAction {
     Play(u8, u8),
+    /// commit the pending move in confirm-moves mode
+    ConfirmMove,
+    /// discard the pending move in confirm-moves mode
+    CancelMove,
+    /// turn confirm-moves mode on or off
+    SetConfirmMoves(bool),
     RequestUndo,
     Undo(UndoAction),
+    RequestRedo,
+    /// answer a redo request, like an undo request
+    Redo(UndoAction),
     /// player sends this if it needs to quit
     Quit(PlayerQuitReason),
 }
@@ -34,6 +46,8 @@ pub(crate) enum SessionPlayerAction {
     PlayTimeout,
     RequestUndo,
     Undo(SessionUndoAction),
+    RequestRedo,
+    Redo(SessionUndoAction),
     /// player sends this if it needs to quit
     Quit(PlayerQuitReason),
 }
@@ -42,8 +56,20 @@ pub(crate) enum SessionPlayerAction {
 #[derive(Clone, Debug)]
 pub(crate) enum SessionPlayerResponse {
     FieldUpdate(FieldState),
+    /// a move of the opening, forwarded as a field update without changing turns
+    Opening(FieldState),
+    /// the move was rolled back, send only to the player who made it
+    InvalidMove(u8, u8),
     UndoRequest,
     Undo(UndoResponse),
+    RedoRequest,
+    Redo(RedoResponse),
+    /// the game ending move can still be undone
+    GameEndPending(FieldState, GameResult),
+    /// the player of the color ran out of time, the opponent is to move
+    TurnPassed(Color),
+    /// broadcast right before `Quit` when the game ends with a result
+    GameSummary(GameSummary),
     /// game end, player quit, error, and etc,
     Quit(GameQuitResponse),
 }
@@ -55,6 +81,7 @@ pub(crate) enum SessionUndoAction {
     Reject,
     AutoReject,
     TimeoutReject,
+    InProgressReject,
 }
 
 /// messages sent to the session from players or game
@@ -62,6 +89,8 @@ pub(crate) enum SessionUndoAction {
 pub(crate) enum SessionMessage {
     Player(Color, SessionPlayerAction),
     Game(GameResponse),
+    /// the window for undoing the game ending move is over
+    FinalizeGame,
     Kill(GameQuitResponse),
 }
 
@@ -78,31 +107,42 @@ impl SessionKiller {
     pub(crate) async fn kill(&self, q: GameQuitResponse) -> Result<()> {
         Ok(self.0.send(SessionMessage::Kill(q)).await?)
     }
+
+    /// send `FinalizeGame` after `delay`, pause the returned gate to cancel it
+    pub(crate) fn finalize_after(&self, delay: Duration) -> TimeoutGate<SessionMessage> {
+        TimeoutGate::new(Some(delay), self.0.clone(), SessionMessage::FinalizeGame)
+    }
 }
 
 /// This is a router tha distribute all messages to game, black player, and white player.
 ///
-/// Message sender stops when session ends or when all players are dropped.
+/// Message sender stops when session ends or when all of them are dropped.
+/// A player that is already gone does not keep the other from receiving
+/// the messages queued before the session ended, such as the final `Quit`.
 pub(crate) fn message_sender(
     black: Sender<SessionPlayerResponse>,
     white: Sender<SessionPlayerResponse>,
     game: Sender<GameCommand>,
-) -> Sender<SessionResponse> {
+) -> (Sender<SessionResponse>, JoinHandle<()>) {
     let (sender, mut receiver) = bounded(CHANNEL_SIZE);
-    task::spawn(async move {
+    let handle = task::spawn(async move {
+        let (mut black_open, mut white_open, mut game_open) = (true, true, true);
         while let Some(session_response) = receiver.next().await {
-            if match session_response {
-                SessionResponse::Player(color, action) => match color {
-                    Black => black.send(action).await.is_err(),
-                    White => white.send(action).await.is_err(),
-                },
-                SessionResponse::Game(cmd) => game.send(cmd).await.is_err(),
-            } {
+            match session_response {
+                SessionResponse::Player(Black, action) => {
+                    black_open = black_open && black.send(action).await.is_ok()
+                }
+                SessionResponse::Player(White, action) => {
+                    white_open = white_open && white.send(action).await.is_ok()
+                }
+                SessionResponse::Game(cmd) => game_open = game_open && game.send(cmd).await.is_ok(),
+            }
+            if !black_open && !white_open && !game_open {
                 break;
             }
         }
     });
-    sender
+    (sender, handle)
 }
 
 /// This is a router that collects all messages from game, black player, and white player.
@@ -112,10 +152,10 @@ pub(crate) fn message_receiver(
     black: Receiver<SessionPlayerAction>,
     white: Receiver<SessionPlayerAction>,
     game: Receiver<GameResponse>,
-) -> (SessionKiller, Receiver<SessionMessage>) {
+) -> (SessionKiller, Receiver<SessionMessage>, JoinHandle<()>) {
     let (message_sender, messages) = bounded(CHANNEL_SIZE);
     let killer = message_sender.clone();
-    task::spawn(async move {
+    let handle = task::spawn(async move {
         let black = black.map(|act| SessionMessage::Player(Black, act)).fuse();
         let white = white.map(|act| SessionMessage::Player(White, act)).fuse();
         let game = game.map(SessionMessage::Game).fuse();
@@ -126,7 +166,7 @@ pub(crate) fn message_receiver(
             }
         }
     });
-    (SessionKiller(killer), messages)
+    (SessionKiller(killer), messages, handle)
 }
 
 pub(crate) async fn broadcast_to_players(
@@ -150,6 +190,7 @@ impl std::fmt::Display for GameResult {
             GameResult::Draw => f.write_str("Draw"),
             GameResult::BlackTimeout => f.write_str("BlackTimeout"),
             GameResult::WhiteTimeout => f.write_str("WhiteTimeout"),
+            GameResult::Aborted => f.write_str("Aborted"),
         }
     }
 }
@@ -162,19 +203,35 @@ impl std::fmt::Debug for FieldState {
     }
 }
 
+impl std::fmt::Debug for FieldInner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for row in self.iter() {
+            for s in row {
+                match s {
+                    State::B => f.write_str("x  ")?,
+                    State::W => f.write_str("o  ")?,
+                    State::E => f.write_str(".  ")?,
+                }
+            }
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
 impl std::fmt::Debug for FieldStateNullable {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self.latest {
-            None => write_empty_field(f)?,
+            None => write_empty_field(&self.field, f)?,
             Some((x, y, _)) => write_field_with_latest(x, y, &self.field, f)?,
         }
         Ok(())
     }
 }
 
-fn write_empty_field(f: &mut Formatter<'_>) -> std::fmt::Result {
-    for _ in 0..15 {
-        for _ in 0..15 {
+fn write_empty_field(field: &[Vec<State>], f: &mut Formatter<'_>) -> std::fmt::Result {
+    for row in field {
+        for _ in row {
             f.write_str(".  ")?;
         }
         f.write_char('\n')?;
@@ -185,7 +242,7 @@ fn write_empty_field(f: &mut Formatter<'_>) -> std::fmt::Result {
 fn write_field_with_latest(
     x: u8,
     y: u8,
-    field: &[[State; 15]; 15],
+    field: &[Vec<State>],
     f: &mut Formatter<'_>,
 ) -> std::fmt::Result {
     for (i, row) in field.iter().enumerate() {
@@ -1,8 +1,16 @@
 mod api;
 mod messages;
 mod player;
+mod render;
 mod session_impl;
 mod utility;
+mod view;
 
 pub use api::*;
-pub use session_impl::new_session;
+pub use render::{render_board, BoardStyle, Glyphs};
+pub(crate) use session_impl::new_seeded_session_with_view;
+pub use session_impl::{
+    new_seeded_session, new_session, new_session_with_handle, new_session_with_listeners,
+    SessionHandle,
+};
+pub(crate) use view::GameView;
@@ -1,26 +1,36 @@
-use crate::game::game_field::Color;
+use crate::game::game_field::{near_stone, Color};
 use crate::game::session::messages::{
     PlayerAction, SessionPlayerAction, SessionPlayerResponse, SessionUndoAction, UndoAction,
 };
 use crate::game::session::utility::TimeoutGate;
+use crate::game::session::view::GameView;
 use crate::game::session::{
-    FieldState, GameQuitResponse, PlayerQuitReason, PlayerResponse, SessionConfig, UndoResponse,
+    FieldState, GameQuitResponse, GameResult, PlayerQuitReason, PlayerResponse, RedoResponse,
+    SessionConfig, UndoResponse,
 };
-use crate::game::Color::Black;
 use crate::{State, CHANNEL_SIZE};
 use anyhow::Result;
 use async_std::channel::{bounded, Receiver, Sender};
-use async_std::task;
+use async_std::task::{self, JoinHandle};
 use futures::{stream_select, StreamExt};
 #[allow(unused_imports)]
 use log::trace;
 use std::fmt::{Debug, Formatter};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// the play clock stops at most this long for a pending move, which is then cancelled
+#[cfg(not(test))]
+const MAX_CONFIRM_PAUSE: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const MAX_CONFIRM_PAUSE: Duration = Duration::from_secs(2);
 
 pub(crate) fn new_session_player(
     #[allow(unused_variables)] player_id: u64,
     my_color: Color,
     config: SessionConfig,
+    opening: &[(u8, u8, Color)],
+    view: GameView,
+    tasks: &mut Vec<JoinHandle<()>>,
 ) -> (
     Sender<PlayerAction>,
     Receiver<PlayerResponse>,
@@ -31,12 +41,18 @@ pub(crate) fn new_session_player(
     let response_pipe_to_session = bounded(CHANNEL_SIZE);
     let pub_action_pipe = bounded(CHANNEL_SIZE);
     let pub_response_pipe = bounded(CHANNEL_SIZE);
+    let opening = opening.to_vec();
 
-    task::spawn(async move {
-        let responses = message_sender(action_pipe_to_session.0, pub_response_pipe.0);
-        let (killer, mut messages) =
-            message_receiver(response_pipe_to_session.1, pub_action_pipe.1);
-        let mut player_state = PlayerState::new(my_color, responses.clone(), config);
+    let (killer, mut messages, receiver_task) =
+        message_receiver(response_pipe_to_session.1, pub_action_pipe.1);
+    let (responses, sender_task) = message_sender(
+        action_pipe_to_session.0,
+        pub_response_pipe.0,
+        killer.0.clone(),
+    );
+    let player_task = task::spawn(async move {
+        let mut player_state = PlayerState::new(my_color, responses.clone(), config, &opening);
+        player_state.publish(my_color, &view).await;
         while let Some(message) = messages.next().await {
             if match message {
                 Msg::Player(action) => {
@@ -77,9 +93,11 @@ pub(crate) fn new_session_player(
                 trace!("player {} stopped on err", player_id);
                 break;
             }
+            player_state.publish(my_color, &view).await;
         }
     });
 
+    tasks.extend([player_task, sender_task, receiver_task]);
     (
         pub_action_pipe.0,
         pub_response_pipe.1,
@@ -95,10 +113,28 @@ async fn handle_player_message(
     responses: &Sender<Response>,
     killer: &Killer,
 ) -> Result<()> {
+    // any other action confirms the pending move
+    if !matches!(
+        action,
+        PlayerAction::Play(..)
+            | PlayerAction::ConfirmMove
+            | PlayerAction::CancelMove
+            | PlayerAction::Quit(_)
+    ) {
+        on_confirm_move(player_state).await?;
+    }
     match action {
-        PlayerAction::Play(x, y) => on_player_play(x, y, player_state).await,
+        PlayerAction::Play(x, y) => on_player_play(x, y, player_state, responses).await,
+        PlayerAction::ConfirmMove => on_confirm_move(player_state).await,
+        PlayerAction::CancelMove => on_cancel_move(player_state, responses).await,
+        PlayerAction::SetConfirmMoves(on) => {
+            player_state.confirm_moves = on;
+            Ok(())
+        }
         PlayerAction::RequestUndo => on_request_undo(player_state, responses).await,
         PlayerAction::Undo(undo_action) => on_approving_undo(undo_action, player_state).await,
+        PlayerAction::RequestRedo => on_request_redo(player_state, responses).await,
+        PlayerAction::Redo(redo_action) => on_approving_redo(redo_action, player_state).await,
         PlayerAction::Quit(quit_message) => on_quit_message(quit_message, responses, killer).await,
     }
 }
@@ -115,27 +151,108 @@ async fn handle_session_message(
         SessionPlayerResponse::FieldUpdate(field_state) => {
             on_field_update(my_color, field_state, player_state, responses).await
         }
+        SessionPlayerResponse::Opening(field_state) => Ok(responses
+            .send(Response::Player(PlayerResponse::FieldUpdate(field_state)))
+            .await?),
+        SessionPlayerResponse::InvalidMove(x, y) => {
+            on_invalid_move(x, y, player_state, responses).await
+        }
         SessionPlayerResponse::UndoRequest => {
             on_opponent_undo_request(player_state, responses).await
         }
         SessionPlayerResponse::Undo(undo_rsp) => {
-            on_undo_response(my_color, undo_rsp, player_state, responses).await
+            on_undo_response(undo_rsp, player_state, responses).await
+        }
+        SessionPlayerResponse::RedoRequest => {
+            on_opponent_redo_request(player_state, responses).await
+        }
+        SessionPlayerResponse::Redo(redo_rsp) => {
+            on_redo_response(my_color, redo_rsp, player_state, responses).await
         }
+        SessionPlayerResponse::GameEndPending(field_state, result) => {
+            on_game_end_pending(my_color, field_state, result, player_state, responses).await
+        }
+        SessionPlayerResponse::TurnPassed(color) => {
+            on_turn_passed(my_color, color, player_state, responses).await
+        }
+        SessionPlayerResponse::GameSummary(summary) => Ok(responses
+            .send(Response::Player(PlayerResponse::GameSummary(summary)))
+            .await?),
         SessionPlayerResponse::Quit(quit_rsp) => on_game_quit(quit_rsp, responses, killer).await,
     }
 }
 
 /// play when is_my_turn, not_my_turn after play
 ///
+/// in confirm-moves mode, the move is pending until confirmed,
+/// playing the pending position again confirms it,
+/// the play clock stops meanwhile for at most `MAX_CONFIRM_PAUSE`,
+/// after which the move is cancelled.
+///
 /// ignore out of bound positions, (ignore also playing non-empty position)
-async fn on_player_play(x: u8, y: u8, player_state: &mut PlayerState) -> Result<()> {
+///
+/// moves within `min_move_interval` of the previous committed move are rejected
+async fn on_player_play(
+    x: u8,
+    y: u8,
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    let min_interval = Duration::from_millis(player_state.config.min_move_interval);
+    if player_state
+        .last_play
+        .is_some_and(|last| last.elapsed() < min_interval)
+    {
+        responses
+            .send(Response::Player(PlayerResponse::MovingTooFast))
+            .await?;
+        return Ok(());
+    }
     if player_state.undo_dialogue.is_none()
+        && !player_state.game_end_pending
         && player_state.my_turn.is_some()
         && player_state.is_valid_step(x, y)
     {
-        let timeout_sender = player_state.my_turn.take().unwrap();
-        timeout_sender
-            .send(Response::Session(SessionPlayerAction::Play(x, y)))
+        if !player_state.is_near_enough(x, y) {
+            // the clock keeps running
+            responses
+                .send(Response::Player(PlayerResponse::InvalidMove(x, y)))
+                .await?;
+        } else if !player_state.confirm_moves || player_state.pending_move == Some((x, y)) {
+            player_state.commit_move(x, y).await?;
+        } else {
+            player_state.hold_pending_move(x, y).await;
+            responses
+                .send(Response::Player(PlayerResponse::MovePending(x, y)))
+                .await?;
+        }
+    } else if player_state.my_turn.is_none() && player_state.config.notify_not_your_turn {
+        responses
+            .send(Response::Player(PlayerResponse::NotYourTurn))
+            .await?;
+    }
+    Ok(())
+}
+
+/// commit the pending move if any
+async fn on_confirm_move(player_state: &mut PlayerState) -> Result<()> {
+    if let Some((x, y)) = player_state.pending_move {
+        player_state.commit_move(x, y).await?;
+    }
+    Ok(())
+}
+
+/// discard the pending move if any, the play clock runs again without extra time
+async fn on_cancel_move(
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    if player_state.discard_pending_move().await {
+        if let Some(t_out) = &mut player_state.my_turn {
+            t_out.resume(Duration::ZERO).await
+        }
+        responses
+            .send(Response::Player(PlayerResponse::MoveCancelled))
             .await?;
     }
     Ok(())
@@ -188,17 +305,57 @@ async fn on_approving_undo(undo_action: UndoAction, player_state: &mut PlayerSta
     Ok(())
 }
 
+/// send redo request on my turn right after my move was undone,
+/// with the play clock stopped until the answer
+async fn on_request_redo(
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    if player_state.undo_dialogue.is_none() && player_state.allow_redo {
+        player_state.allow_redo = false;
+        player_state.undo_dialogue = Some(UndoDialogue::RequestingRedo);
+        player_state.pause_my_turn_timer().await;
+        responses
+            .send(Response::Session(SessionPlayerAction::RequestRedo))
+            .await?
+    }
+    Ok(())
+}
+
+/// send redo approval or rejection when in approving dialogue
+async fn on_approving_redo(redo_action: UndoAction, player_state: &mut PlayerState) -> Result<()> {
+    if let Some(UndoDialogue::ApprovingRedo(_)) = &player_state.undo_dialogue {
+        let undo_dialogue = player_state.undo_dialogue.take().unwrap();
+        if let UndoDialogue::ApprovingRedo(timeout_sender) = undo_dialogue {
+            let redo_action = match redo_action {
+                UndoAction::Approve => SessionUndoAction::Approve,
+                UndoAction::Reject => SessionUndoAction::Reject,
+            };
+            timeout_sender
+                .send(Response::Session(SessionPlayerAction::Redo(redo_action)))
+                .await?
+        }
+    }
+    Ok(())
+}
+
 /// quit, disconnect, error
+///
+/// The session answers `QuitSession` with a `Quit` response to this player too,
+/// so the player stops on that response instead, to make sure it is delivered.
 async fn on_quit_message(
     quit_message: PlayerQuitReason,
     responses: &Sender<Response>,
     killer: &Killer,
 ) -> Result<()> {
+    let await_response = matches!(quit_message, PlayerQuitReason::QuitSession);
     // send quit message to session and stop
     responses
         .send(Response::Session(SessionPlayerAction::Quit(quit_message)))
         .await?;
-    killer.kill().await?;
+    if !await_response {
+        killer.kill().await?;
+    }
     Ok(())
 }
 
@@ -210,6 +367,8 @@ async fn on_field_update(
     responses: &Sender<Response>,
 ) -> Result<()> {
     debug_assert!(player_state.undo_dialogue.is_none());
+    // a new move, nothing to redo
+    player_state.allow_redo = false;
     if field_state.latest.2 == my_color {
         // when the latest update is my color, allow undo
         player_state.allow_undo = true;
@@ -226,17 +385,105 @@ async fn on_field_update(
     Ok(())
 }
 
-/// on receiving undo request from opponent, forward undo_request to client
+/// the latest move ends the game unless undone in time,
+/// its player may request undo, and the opponent may only answer the request
+async fn on_game_end_pending(
+    my_color: Color,
+    field_state: FieldState,
+    result: GameResult,
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    player_state.game_end_pending = true;
+    if field_state.latest.2 == my_color {
+        player_state.allow_undo = true;
+    } else {
+        // able to approve undo, with the play clock stopped
+        player_state.now_my_turn();
+        player_state.pause_my_turn_timer().await;
+    }
+    player_state.update_field(field_state.field.0.clone());
+    responses
+        .send(Response::Player(PlayerResponse::GameEndPending(
+            field_state,
+            result,
+        )))
+        .await?;
+    Ok(())
+}
+
+/// the player of `color` ran out of time, the other one is to move,
+/// nothing to undo or redo across the skipped move
+async fn on_turn_passed(
+    my_color: Color,
+    color: Color,
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    player_state.allow_redo = false;
+    if color == my_color {
+        player_state.discard_pending_move().await;
+        player_state.my_turn = None;
+        player_state.allow_undo = false;
+    } else {
+        player_state.now_my_turn();
+    }
+    responses
+        .send(Response::Player(PlayerResponse::TurnPassed(color)))
+        .await?;
+    Ok(())
+}
+
+/// my move was rolled back, play again
+async fn on_invalid_move(
+    x: u8,
+    y: u8,
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    player_state.now_my_turn();
+    responses
+        .send(Response::Player(PlayerResponse::InvalidMove(x, y)))
+        .await?;
+    Ok(())
+}
+
+/// on receiving undo request from opponent, forward undo_request to client,
+/// or approve it at once with `free_undo`
 async fn on_opponent_undo_request(
     player_state: &mut PlayerState,
     responses: &Sender<Response>,
 ) -> Result<()> {
-    debug_assert!(player_state.undo_dialogue.is_none());
-    if player_state.my_turn.is_some() {
-        player_state.approving_undo().await;
+    if player_state.undo_dialogue.is_some() {
+        // reject the opponent's request while my own undo dialogue is open,
+        // so that at most one undo dialogue exists in a session at any time
         responses
-            .send(Response::Player(PlayerResponse::UndoRequest))
-            .await?;
+            .send(Response::Session(SessionPlayerAction::Undo(
+                SessionUndoAction::InProgressReject,
+            )))
+            .await?
+    } else if player_state.my_turn.is_some() {
+        // the field might change, drop the pending move,
+        // the play clock stays paused by the undo dialogue
+        if player_state.discard_pending_move().await {
+            responses
+                .send(Response::Player(PlayerResponse::MoveCancelled))
+                .await?;
+        }
+        if player_state.config.free_undo {
+            // no longer my turn, as upon approval
+            player_state.my_turn = None;
+            responses
+                .send(Response::Session(SessionPlayerAction::Undo(
+                    SessionUndoAction::Approve,
+                )))
+                .await?
+        } else {
+            player_state.approving_undo().await;
+            responses
+                .send(Response::Player(PlayerResponse::UndoRequest))
+                .await?;
+        }
     } else {
         // auto reject undo request when I have already moved
         responses
@@ -250,7 +497,6 @@ async fn on_opponent_undo_request(
 
 /// when player receives undo response from game session
 async fn on_undo_response(
-    my_color: Color,
     undo_rsp: UndoResponse,
     player_state: &mut PlayerState,
     responses: &Sender<Response>,
@@ -258,25 +504,24 @@ async fn on_undo_response(
     // close undo dialogue once received undo responses from game session
     match &undo_rsp {
         UndoResponse::Undo(f) => {
+            // the game ending move, if any, is undone
+            player_state.game_end_pending = false;
             player_state.update_field(f.field.0.clone());
-            match &f.latest {
-                // if undid the first step
-                None => {
-                    if my_color == Black {
-                        player_state.now_my_turn();
-                    }
-                }
-                Some((_, _, latest_step_color)) => {
-                    // upon receiving undo approval, and it is my turn
-                    if *latest_step_color != my_color {
-                        player_state.now_my_turn();
-                    }
-                }
+            // the requester's move is undone, the latest color alone
+            // does not tell since a passed turn repeats colors
+            let my_move_undone =
+                matches!(player_state.undo_dialogue, Some(UndoDialogue::Requesting));
+            if my_move_undone {
+                player_state.now_my_turn();
+                player_state.allow_redo = true;
             }
         }
         UndoResponse::TimeoutRejected => {
-            // need to resume timer if timeout rejected
-            player_state.resume_my_turn_timer().await;
+            // only the approver's clock was paused by the dialogue,
+            // the requester's clock is never compensated
+            if let Some(UndoDialogue::Approving(_)) = player_state.undo_dialogue {
+                player_state.resume_my_turn_timer().await;
+            }
             player_state.undo_dialogue = None;
         }
         _ => {
@@ -292,6 +537,65 @@ async fn on_undo_response(
     Ok(())
 }
 
+/// on receiving redo request from opponent, who is to move
+async fn on_opponent_redo_request(
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    let reject = if player_state.undo_dialogue.is_some() {
+        // at most one undo or redo dialogue exists in a session at any time
+        SessionUndoAction::InProgressReject
+    } else if player_state.my_turn.is_none() {
+        player_state.approving_redo();
+        responses
+            .send(Response::Player(PlayerResponse::RedoRequest))
+            .await?;
+        return Ok(());
+    } else {
+        // the opponent cannot redo on my turn
+        SessionUndoAction::AutoReject
+    };
+    responses
+        .send(Response::Session(SessionPlayerAction::Redo(reject)))
+        .await?;
+    Ok(())
+}
+
+/// when player receives redo response from game session
+async fn on_redo_response(
+    my_color: Color,
+    redo_rsp: RedoResponse,
+    player_state: &mut PlayerState,
+    responses: &Sender<Response>,
+) -> Result<()> {
+    match &redo_rsp {
+        RedoResponse::Redo(f) => {
+            player_state.update_field(f.field.0.clone());
+            if f.latest.2 == my_color {
+                // my move is back, as if played again
+                player_state.my_turn = None;
+                player_state.allow_undo = true;
+            } else {
+                player_state.now_my_turn();
+            }
+        }
+        _ => {
+            // the requester plays on without extra time
+            if let Some(UndoDialogue::RequestingRedo) = player_state.undo_dialogue {
+                if let Some(t_out) = &mut player_state.my_turn {
+                    t_out.resume(Duration::ZERO).await
+                }
+            }
+        }
+    }
+    // forward redo response, close redo dialogue
+    player_state.undo_dialogue = None;
+    responses
+        .send(Response::Player(PlayerResponse::Redo(redo_rsp)))
+        .await?;
+    Ok(())
+}
+
 async fn on_game_quit(
     quit_rsp: GameQuitResponse,
     responses: &Sender<Response>,
@@ -320,32 +624,46 @@ impl Killer {
 }
 
 /// utility: distribute message
+///
+/// Stops when all senders are dropped or both destinations are closed,
+/// `Loopback` actions go back to the player task through `loopback`.
 fn message_sender(
     session: Sender<SessionPlayerAction>,
     player: Sender<PlayerResponse>,
-) -> Sender<Response> {
+    loopback: Sender<Msg>,
+) -> (Sender<Response>, JoinHandle<()>) {
     let (sender, mut receiver) = bounded(CHANNEL_SIZE);
-    task::spawn(async move {
+    let handle = task::spawn(async move {
+        // a closed destination must not drop the messages queued for the other
+        let (mut player_open, mut session_open) = (true, true);
         while let Some(session_response) = receiver.next().await {
-            if match session_response {
-                Response::Player(rsp) => player.send(rsp).await.is_err(),
-                Response::Session(act) => session.send(act).await.is_err(),
-            } {
+            match session_response {
+                Response::Player(rsp) => {
+                    player_open = player_open && player.send(rsp).await.is_ok()
+                }
+                Response::Session(act) => {
+                    session_open = session_open && session.send(act).await.is_ok()
+                }
+                Response::Loopback(act) => {
+                    let _ = loopback.send(Msg::Player(act)).await;
+                }
+            }
+            if !player_open && !session_open {
                 break;
             }
         }
     });
-    sender
+    (sender, handle)
 }
 
 /// utility: fuse messages
 fn message_receiver(
     session: Receiver<SessionPlayerResponse>,
     player: Receiver<PlayerAction>,
-) -> (Killer, Receiver<Msg>) {
+) -> (Killer, Receiver<Msg>, JoinHandle<()>) {
     let (message_sender, messages) = bounded(CHANNEL_SIZE);
     let killer = message_sender.clone();
-    task::spawn(async move {
+    let handle = task::spawn(async move {
         let session = session.map(Msg::Session).fuse();
         let player = player.map(Msg::Player).fuse();
         let mut fused = stream_select!(session, player);
@@ -355,39 +673,84 @@ fn message_receiver(
             }
         }
     });
-    (Killer(killer), messages)
+    (Killer(killer), messages, handle)
 }
 
 struct PlayerState {
     message_sender: Sender<Response>,
     config: SessionConfig,
+    /// the color making the first move after the opening
+    first_to_move: Color,
     my_turn: Option<TimeoutGate<Response>>,
     allow_undo: bool,
+    /// my latest move was undone, and no move was played since
+    allow_redo: bool,
     undo_dialogue: Option<UndoDialogue>,
-    latest_field: [[State; 15]; 15],
+    latest_field: Vec<Vec<State>>,
+    confirm_moves: bool,
+    /// a move waiting for confirmation in confirm-moves mode
+    pending_move: Option<(u8, u8)>,
+    /// cancels the pending move after `MAX_CONFIRM_PAUSE`
+    confirm_timeout: Option<TimeoutGate<Response>>,
+    /// the latest move ends the game unless undone
+    game_end_pending: bool,
+    /// when my latest move was committed
+    last_play: Option<Instant>,
 }
 
 impl PlayerState {
-    fn new(my_color: Color, sender: Sender<Response>, config: SessionConfig) -> Self {
+    fn new(
+        my_color: Color,
+        sender: Sender<Response>,
+        config: SessionConfig,
+        opening: &[(u8, u8, Color)],
+    ) -> Self {
+        let mut latest_field =
+            vec![vec![State::E; config.board_height as usize]; config.board_width as usize];
+        for &(x, y, color) in opening {
+            latest_field[x as usize][y as usize] = match color {
+                Color::Black => State::B,
+                Color::White => State::W,
+            };
+        }
+        let first_to_move = match opening.last() {
+            Some((_, _, color)) => color.switch(),
+            None => config.first_player,
+        };
         let mut new_state = PlayerState {
             message_sender: sender,
             config,
+            first_to_move,
             my_turn: None,
             allow_undo: false,
+            allow_redo: false,
             undo_dialogue: None,
-            latest_field: [[State::E; 15]; 15],
+            latest_field,
+            confirm_moves: false,
+            pending_move: None,
+            confirm_timeout: None,
+            game_end_pending: false,
+            last_play: None,
         };
-        // black first
-        if let Black = my_color {
+        if my_color == new_state.first_to_move {
             PlayerState::now_my_turn(&mut new_state)
         }
         new_state
     }
 
-    fn update_field(&mut self, field: [[State; 15]; 15]) {
+    fn update_field(&mut self, field: Vec<Vec<State>>) {
         self.latest_field = field;
     }
 
+    /// share my part of the game with observers,
+    /// nobody is to move while the game end is pending
+    async fn publish(&self, my_color: Color, view: &GameView) {
+        let on_move = self.my_turn.is_some() && !self.game_end_pending;
+        let clock = self.my_turn.as_ref().and_then(|t| t.clock());
+        view.publish(my_color, &self.latest_field, on_move, clock)
+            .await;
+    }
+
     /// start the timeout immediately, called before calling play
     fn now_my_turn(&mut self) {
         let total_delay = if self.config.play_timeout == 0 {
@@ -403,12 +766,65 @@ impl PlayerState {
         self.allow_undo = false;
     }
 
+    /// on the board of the config and empty
     fn is_valid_step(&self, x: u8, y: u8) -> bool {
-        if x < 15 && y < 15 {
-            matches!(self.latest_field[x as usize][y as usize], State::E)
-        } else {
-            false
+        self.config.on_board(x, y)
+            && matches!(
+                self.latest_field
+                    .get(x as usize)
+                    .and_then(|row| row.get(y as usize)),
+                Some(State::E)
+            )
+    }
+
+    /// within `proximity_rule` of a stone, or the board is empty
+    fn is_near_enough(&self, x: u8, y: u8) -> bool {
+        match self.config.proximity_rule {
+            None => true,
+            Some(radius) => {
+                self.latest_field.iter().flatten().all(|s| *s == State::E)
+                    || near_stone(&self.latest_field, x as usize, y as usize, radius as usize)
+            }
+        }
+    }
+
+    /// send the move to session, no longer my turn
+    async fn commit_move(&mut self, x: u8, y: u8) -> Result<()> {
+        if self.discard_pending_move().await {
+            // a paused gate sends nothing
+            if let Some(t_out) = &mut self.my_turn {
+                t_out.resume(Duration::ZERO).await
+            }
+        }
+        self.allow_redo = false;
+        self.last_play = Some(Instant::now());
+        let timeout_sender = self.my_turn.take().unwrap();
+        timeout_sender
+            .send(Response::Session(SessionPlayerAction::Play(x, y)))
+            .await?;
+        Ok(())
+    }
+
+    /// the move waits for confirmation with the play clock paused,
+    /// until it is cancelled after `MAX_CONFIRM_PAUSE`
+    async fn hold_pending_move(&mut self, x: u8, y: u8) {
+        if self.pending_move.replace((x, y)).is_none() {
+            self.pause_my_turn_timer().await;
+            self.confirm_timeout = Some(TimeoutGate::new(
+                Some(MAX_CONFIRM_PAUSE),
+                self.message_sender.clone(),
+                Response::Loopback(PlayerAction::CancelMove),
+            ));
+        }
+    }
+
+    /// whether a move was pending, the play clock is left paused
+    async fn discard_pending_move(&mut self) -> bool {
+        if let Some(mut t_out) = self.confirm_timeout.take() {
+            // a dropped gate still fires unless paused
+            t_out.pause().await
         }
+        self.pending_move.take().is_some()
     }
 
     async fn pause_my_turn_timer(&mut self) {
@@ -417,8 +833,12 @@ impl PlayerState {
         }
     }
 
-    /// does nothing if it is not in a paused state
+    /// does nothing if it is not in a paused state,
+    /// or if the game end is pending, where nobody plays
     async fn resume_my_turn_timer(&mut self) {
+        if self.game_end_pending {
+            return;
+        }
         let extra_time = Duration::from_secs(self.config.undo_dialogue_extra_seconds);
         if let Some(t_out) = &mut self.my_turn {
             t_out.resume(extra_time).await
@@ -427,17 +847,35 @@ impl PlayerState {
 
     /// start the timeout immediately, called before calling play
     async fn approving_undo(&mut self) {
-        let total_delay = if self.config.undo_request_timeout == 0 {
-            None
-        } else {
-            Some(Duration::from_secs(self.config.undo_request_timeout))
-        };
-        self.undo_dialogue = Some(UndoDialogue::Approving(TimeoutGate::new(
+        let timeout_reject = SessionPlayerAction::Undo(SessionUndoAction::TimeoutReject);
+        self.undo_dialogue = Some(UndoDialogue::Approving(self.approval_gate(timeout_reject)));
+        self.pause_my_turn_timer().await;
+    }
+
+    /// start the timeout immediately, the requester's play clock is stopped
+    fn approving_redo(&mut self) {
+        let timeout_reject = SessionPlayerAction::Redo(SessionUndoAction::TimeoutReject);
+        self.undo_dialogue = Some(UndoDialogue::ApprovingRedo(
+            self.approval_gate(timeout_reject),
+        ));
+    }
+
+    /// times out after `undo_request_timeout`,
+    /// or earlier if `max_undo_pause_seconds` is shorter
+    fn approval_gate(&self, timeout_reject: SessionPlayerAction) -> TimeoutGate<Response> {
+        let total_delay = [
+            self.config.undo_request_timeout,
+            self.config.max_undo_pause_seconds,
+        ]
+        .into_iter()
+        .filter(|&seconds| seconds != 0)
+        .min()
+        .map(Duration::from_secs);
+        TimeoutGate::new(
             total_delay,
             self.message_sender.clone(),
-            Response::Session(SessionPlayerAction::Undo(SessionUndoAction::TimeoutReject)),
-        )));
-        self.pause_my_turn_timer().await;
+            Response::Session(timeout_reject),
+        )
     }
 }
 
@@ -450,11 +888,15 @@ enum Msg {
 enum Response {
     Player(PlayerResponse),
     Session(SessionPlayerAction),
+    /// handled by this player as if sent by the client
+    Loopback(PlayerAction),
 }
 
 enum UndoDialogue {
     Requesting,
     Approving(TimeoutGate<Response>),
+    RequestingRedo,
+    ApprovingRedo(TimeoutGate<Response>),
 }
 
 impl Debug for UndoDialogue {
@@ -462,6 +904,259 @@ impl Debug for UndoDialogue {
         match self {
             UndoDialogue::Requesting => f.write_str("Requesting"),
             UndoDialogue::Approving(_) => f.write_str("Approving"),
+            UndoDialogue::RequestingRedo => f.write_str("RequestingRedo"),
+            UndoDialogue::ApprovingRedo(_) => f.write_str("ApprovingRedo"),
         }
     }
 }
+
+#[cfg(test)]
+mod test_player {
+    use super::*;
+    use crate::game::Color::{Black, White};
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_concurrent_undo_request_rejected() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let mut state = PlayerState::new(Black, sender.clone(), SessionConfig::default(), &[]);
+        block_on(async {
+            // my own undo request is pending
+            state.allow_undo = true;
+            on_request_undo(&mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::RequestUndo))
+            ));
+            // opponent requests undo at the same instant
+            on_opponent_undo_request(&mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::Undo(
+                    SessionUndoAction::InProgressReject
+                )))
+            ));
+            // my own dialogue is untouched
+            assert!(matches!(
+                state.undo_dialogue,
+                Some(UndoDialogue::Requesting)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_max_undo_pause() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        // the approver would otherwise have forever to answer
+        let config = SessionConfig::builder()
+            .play_timeout(1)
+            .max_undo_pause_seconds(1)
+            .build();
+        let mut state = PlayerState::new(Black, sender.clone(), config, &[]);
+        block_on(async {
+            on_opponent_undo_request(&mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::UndoRequest))
+            ));
+            // the play clock is paused, the dialogue is force-resolved
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::Undo(
+                    SessionUndoAction::TimeoutReject
+                )))
+            ));
+            on_undo_response(UndoResponse::TimeoutRejected, &mut state, &sender)
+                .await
+                .unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::Undo(
+                    UndoResponse::TimeoutRejected
+                )))
+            ));
+            assert!(state.undo_dialogue.is_none());
+            // play resumes with the clock running
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::PlayTimeout))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_first_player() {
+        let (sender, _receiver) = bounded(CHANNEL_SIZE);
+        let white_first = SessionConfig {
+            first_player: White,
+            ..SessionConfig::default()
+        };
+        let black = PlayerState::new(Black, sender.clone(), white_first.clone(), &[]);
+        let white = PlayerState::new(White, sender.clone(), white_first, &[]);
+        assert!(black.my_turn.is_none());
+        assert!(white.my_turn.is_some());
+        let black = PlayerState::new(Black, sender.clone(), SessionConfig::default(), &[]);
+        let white = PlayerState::new(White, sender, SessionConfig::default(), &[]);
+        assert!(black.my_turn.is_some());
+        assert!(white.my_turn.is_none());
+    }
+
+    #[test]
+    fn test_confirm_move() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let config = SessionConfig {
+            play_timeout: 1,
+            ..SessionConfig::default()
+        };
+        let mut state = PlayerState::new(Black, sender.clone(), config, &[]);
+        state.confirm_moves = true;
+        block_on(async {
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovePending(3, 4)))
+            ));
+            // the play clock is paused during confirmation
+            task::sleep(Duration::from_millis(1500)).await;
+            assert!(receiver.try_recv().is_err());
+            on_confirm_move(&mut state).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::Play(3, 4)))
+            ));
+            assert!(state.my_turn.is_none());
+            assert!(state.pending_move.is_none());
+        });
+    }
+
+    #[test]
+    fn test_pending_move_timeout() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let config = SessionConfig::builder().play_timeout(1).build();
+        let mut state = PlayerState::new(Black, sender.clone(), config, &[]);
+        state.confirm_moves = true;
+        block_on(async {
+            let begin = Instant::now();
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovePending(3, 4)))
+            ));
+            // the clock is paused beyond the play timeout, then the move is cancelled
+            let cancel = match receiver.next().await {
+                Some(Response::Loopback(action)) => action,
+                _ => panic!("pending move not cancelled"),
+            };
+            assert!(begin.elapsed() >= MAX_CONFIRM_PAUSE);
+            handle_player_message(cancel, &mut state, &sender, &Killer(bounded(1).0))
+                .await
+                .unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MoveCancelled))
+            ));
+            assert!(state.pending_move.is_none());
+            // the play clock runs again, without the paused time
+            let resumed = Instant::now();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::PlayTimeout))
+            ));
+            assert!(resumed.elapsed() >= Duration::from_millis(900));
+        });
+    }
+
+    #[test]
+    fn test_not_your_turn() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let config = SessionConfig::builder().notify_not_your_turn(true).build();
+        let mut state = PlayerState::new(White, sender.clone(), config, &[]);
+        let field = state.latest_field.clone();
+        block_on(async {
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::NotYourTurn))
+            ));
+            assert_eq!(state.latest_field, field);
+            assert!(state.my_turn.is_none());
+            assert!(state.pending_move.is_none());
+        });
+        // silent by default
+        let mut state = PlayerState::new(White, sender.clone(), SessionConfig::default(), &[]);
+        block_on(async {
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(receiver.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_moving_too_fast() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let config = SessionConfig::builder().min_move_interval(300).build();
+        let mut state = PlayerState::new(Black, sender.clone(), config, &[]);
+        state.confirm_moves = true;
+        block_on(async {
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovePending(3, 4)))
+            ));
+            // confirming by playing the pending position again is not throttled
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::Play(3, 4)))
+            ));
+            // the opponent answers at once
+            state.latest_field[3][4] = State::B;
+            state.now_my_turn();
+            // throttled right after the previous move
+            on_player_play(5, 6, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovingTooFast))
+            ));
+            assert!(state.pending_move.is_none());
+            // accepted after the interval
+            task::sleep(Duration::from_millis(300)).await;
+            on_player_play(5, 6, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovePending(5, 6)))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_cancel_move() {
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let mut state = PlayerState::new(Black, sender.clone(), SessionConfig::default(), &[]);
+        state.confirm_moves = true;
+        block_on(async {
+            on_player_play(3, 4, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovePending(3, 4)))
+            ));
+            on_cancel_move(&mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MoveCancelled))
+            ));
+            assert!(state.pending_move.is_none());
+            assert!(state.my_turn.is_some());
+            // playing the pending position again confirms it
+            on_player_play(5, 6, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Player(PlayerResponse::MovePending(5, 6)))
+            ));
+            on_player_play(5, 6, &mut state, &sender).await.unwrap();
+            assert!(matches!(
+                receiver.next().await,
+                Some(Response::Session(SessionPlayerAction::Play(5, 6)))
+            ));
+        });
+    }
+}
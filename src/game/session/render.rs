@@ -0,0 +1,136 @@
+//! Textual rendering of the board for clients.
+//!
+//! Stones are told apart by their glyphs rather than colors,
+//! and the latest move is bracketed, e.g. `[x]`.
+use crate::game::game_field::State;
+use crate::game::session::api::FieldInner;
+
+/// glyphs of black stones, white stones and empty positions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Glyphs {
+    /// `x`, `o` and `.`
+    Ascii,
+    /// `B`, `W` and `.`
+    Letters,
+    /// `●`, `○` and `·`
+    Unicode,
+}
+
+impl Glyphs {
+    fn glyph(&self, state: State) -> char {
+        match (self, state) {
+            (Glyphs::Ascii, State::B) => 'x',
+            (Glyphs::Ascii, State::W) => 'o',
+            (Glyphs::Letters, State::B) => 'B',
+            (Glyphs::Letters, State::W) => 'W',
+            (Glyphs::Unicode, State::B) => '●',
+            (Glyphs::Unicode, State::W) => '○',
+            (Glyphs::Unicode, State::E) => '·',
+            (_, State::E) => '.',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoardStyle {
+    /// label rows with `x` and columns with `y`, as in `Messages::Play(x, y)`
+    pub axis_labels: bool,
+    pub glyphs: Glyphs,
+}
+
+impl Default for BoardStyle {
+    /// axis labels with `Ascii` glyphs
+    fn default() -> Self {
+        BoardStyle {
+            axis_labels: true,
+            glyphs: Glyphs::Ascii,
+        }
+    }
+}
+
+/// Render `field[x][y]` with `x` as rows and `y` as columns,
+/// bracketing the `latest` move if any.
+pub fn render_board(field: &FieldInner, latest: Option<(u8, u8)>, style: BoardStyle) -> String {
+    let label_width = field.len().saturating_sub(1).to_string().len();
+    let mut board = String::new();
+    if style.axis_labels {
+        board.push_str(&" ".repeat(label_width + 1));
+        let height = field.first().map_or(0, |column| column.len());
+        for y in 0..height {
+            board.push_str(&format!("{:^3}", y));
+        }
+        board.push('\n');
+    }
+    for (x, row) in field.iter().enumerate() {
+        if style.axis_labels {
+            board.push_str(&format!("{:>label_width$} ", x));
+        }
+        for (y, state) in row.iter().enumerate() {
+            let glyph = style.glyphs.glyph(*state);
+            if latest == Some((x as u8, y as u8)) {
+                board.push_str(&format!("[{}]", glyph));
+            } else {
+                board.push_str(&format!(" {} ", glyph));
+            }
+        }
+        board.push('\n');
+    }
+    // no trailing spaces
+    board
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test_render {
+    use super::*;
+
+    /// `[[B, W, E], [E, E, B]]`
+    fn field() -> FieldInner {
+        FieldInner(vec![
+            vec![State::B, State::W, State::E],
+            vec![State::E, State::E, State::B],
+        ])
+    }
+
+    #[test]
+    fn test_axis_labels() {
+        let board = render_board(&field(), None, BoardStyle::default());
+        assert_eq!(board, "   0  1  2\n0  x  o  .\n1  .  .  x");
+        let lines: Vec<_> = board.lines().collect();
+        assert!(lines[0].contains('2'));
+        assert!(lines[2].starts_with('1'));
+        let unlabelled = BoardStyle {
+            axis_labels: false,
+            glyphs: Glyphs::Letters,
+        };
+        assert_eq!(
+            render_board(&field(), None, unlabelled),
+            " B  W  .\n .  .  B"
+        );
+    }
+
+    #[test]
+    fn test_latest_move() {
+        let style = BoardStyle {
+            axis_labels: true,
+            glyphs: Glyphs::Unicode,
+        };
+        let board = render_board(&field(), Some((1, 2)), style);
+        assert_eq!(board, "   0  1  2\n0  ●  ○  ·\n1  ·  · [●]");
+        // only the latest move is bracketed
+        assert_eq!(board.matches('[').count(), 1);
+    }
+
+    #[test]
+    fn test_wide_board_labels() {
+        let field = FieldInner(vec![vec![State::E; 2]; 11]);
+        let board = render_board(&field, Some((10, 0)), BoardStyle::default());
+        let lines: Vec<_> = board.lines().collect();
+        assert_eq!(lines[0], "    0  1");
+        assert_eq!(lines[1], " 0  .  .");
+        assert_eq!(lines[11], "10 [.] .");
+    }
+}
@@ -1,43 +1,162 @@
 use crate::game::game_field::Color::{self, Black, White};
-use crate::game::game_field::{new_field, GameCommand, GameResponse};
+use crate::game::game_field::{new_field, GameCommand, GameResponse, State};
 use crate::game::session::api::SessionConfig;
 use crate::game::session::api::{
-    Commands, GameQuitResponse, GameResult, PlayerQuitReason, UndoResponse,
+    Commands, FieldInner, FieldState, GameQuitResponse, GameResult, GameSummary, OpeningBook,
+    PlayerQuitReason, PlayerResponse, RedoResponse, TimeoutBehavior, UndoResponse,
 };
 use crate::game::session::messages::{
     broadcast_to_players, message_receiver, message_sender, SessionKiller, SessionMessage,
     SessionPlayerAction, SessionPlayerResponse, SessionResponse, SessionUndoAction,
 };
 use crate::game::session::player::new_session_player;
+use crate::game::session::utility::TimeoutGate;
+use crate::game::session::view::GameView;
 use anyhow::Result;
-use async_std::channel::Sender;
-use async_std::task;
+use async_std::channel::{Receiver, Sender};
+use async_std::task::{self, JoinHandle};
 use futures::StreamExt;
 #[allow(unused_imports)]
 use log::trace;
 use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{random, SeedableRng};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// the tasks spawned by a game session
+pub struct SessionHandle {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SessionHandle {
+    /// Stop all tasks of the session, and wait until they have terminated.
+    ///
+    /// Players are not notified, and their `Commands` no longer respond.
+    /// Tasks that have already finished are simply awaited.
+    pub async fn shutdown(self) {
+        for task in self.tasks {
+            task.cancel().await;
+        }
+    }
+}
 
 /// start a new game session
+///
+/// The session tasks run detached until the game ends,
+/// use `new_session_with_handle` to stop them early.
 pub fn new_session(
     session_id: u64,
     black_player_id: u64,
     white_player_id: u64,
     session_config: SessionConfig,
 ) -> (Commands, Commands) {
+    let (black, white, _) =
+        new_session_with_handle(session_id, black_player_id, white_player_id, session_config);
+    (black, white)
+}
+
+/// start a new game session, returning a `SessionHandle` to shut it down
+pub fn new_session_with_handle(
+    session_id: u64,
+    black_player_id: u64,
+    white_player_id: u64,
+    session_config: SessionConfig,
+) -> (Commands, Commands, SessionHandle) {
+    new_seeded_session(
+        session_id,
+        black_player_id,
+        white_player_id,
+        session_config,
+        random(),
+    )
+}
+
+/// start a new game session with the `seed` of its randomization,
+/// so that the same seed picks the same opening from `OpeningBook::Random`
+pub fn new_seeded_session(
+    session_id: u64,
+    black_player_id: u64,
+    white_player_id: u64,
+    session_config: SessionConfig,
+    seed: u64,
+) -> (Commands, Commands, SessionHandle) {
+    let view = GameView::new(session_config.board_width, session_config.board_height);
+    new_seeded_session_with_view(
+        session_id,
+        black_player_id,
+        white_player_id,
+        session_config,
+        seed,
+        view,
+    )
+}
+
+/// start a new game session publishing to an existing `view`,
+/// which observers may hold before the session starts
+pub(crate) fn new_seeded_session_with_view(
+    session_id: u64,
+    black_player_id: u64,
+    white_player_id: u64,
+    session_config: SessionConfig,
+    seed: u64,
+    view: GameView,
+) -> (Commands, Commands, SessionHandle) {
     info!(
-        "game session {} launched with black player {} and white player {}",
-        session_id, black_player_id, white_player_id
+        "game session {} launched with black player {} and white player {}, seed {}",
+        session_id, black_player_id, white_player_id, seed
     );
+    let (width, height) = (session_config.board_width, session_config.board_height);
+    let recover_impossible = session_config.recover_impossible;
+    let early_draw = session_config.early_draw;
+    let simultaneous_win = session_config.simultaneous_win;
+    let final_undo_window = session_config.final_undo_window;
+    let hide_opponent_clock = session_config.hide_opponent_clock;
+    let timeout_behavior = session_config.timeout_behavior;
+    let opening = pick_opening(session_id, &session_config, seed);
+    let mut tasks = Vec::new();
     // start player tasks
-    let black_player = new_session_player(black_player_id, Black, session_config.clone());
-    let white_player = new_session_player(white_player_id, White, session_config);
+    let black_player = new_session_player(
+        black_player_id,
+        Black,
+        session_config.clone(),
+        &opening,
+        view.clone(),
+        &mut tasks,
+    );
+    let white_player = new_session_player(
+        white_player_id,
+        White,
+        session_config,
+        &opening,
+        view.clone(),
+        &mut tasks,
+    );
     // start field task
-    let (cmd, rsp) = new_field(session_id);
+    let (cmd, rsp, field_task) = new_field(
+        session_id,
+        width,
+        height,
+        recover_impossible,
+        early_draw,
+        simultaneous_win,
+        &opening,
+    );
     // start message receiver task
-    let (killer, mut messages) = message_receiver(black_player.2, white_player.2, rsp);
+    let (killer, mut messages, receiver_task) =
+        message_receiver(black_player.2, white_player.2, rsp);
     // start message sender task
-    let responses = message_sender(black_player.3, white_player.3, cmd);
-    task::spawn(async move {
+    let (responses, sender_task) = message_sender(black_player.3, white_player.3, cmd);
+    let session_task = task::spawn(async move {
+        if broadcast_opening(&opening, width, height, &responses)
+            .await
+            .is_err()
+        {
+            return;
+        }
+        let mut pending_end = None;
+        let mut stats = GameStats::new(opening.len());
         while let Some(message) = messages.next().await {
             #[cfg(debug_assertions)]
             trace!("message {:?} received by session {}", message, session_id);
@@ -52,12 +171,28 @@ pub fn new_session(
                         player_color,
                         player_action,
                         player_id,
+                        &mut stats,
+                        &mut pending_end,
+                        timeout_behavior,
                         &responses,
                         &killer,
                     )
                     .await
                 }
-                SessionMessage::Game(game_rsp) => handle_game_message(game_rsp, &responses).await,
+                SessionMessage::Game(game_rsp) => {
+                    handle_game_message(
+                        game_rsp,
+                        final_undo_window,
+                        &mut stats,
+                        &mut pending_end,
+                        &responses,
+                        &killer,
+                    )
+                    .await
+                }
+                SessionMessage::FinalizeGame => {
+                    finalize_game(&mut pending_end, &stats, &responses, &killer).await
+                }
                 SessionMessage::Kill(quit_rsp) => {
                     log_quit_response(session_id, quit_rsp);
                     break;
@@ -83,80 +218,320 @@ pub fn new_session(
             white_player_id
         )
     });
+    tasks.extend([session_task, sender_task, receiver_task, field_task]);
     (
-        Commands::new(black_player.0, black_player.1),
-        Commands::new(white_player.0, white_player.1),
+        Commands::new(
+            black_player.0,
+            black_player.1,
+            view.clone(),
+            hide_opponent_clock.then_some(White),
+        ),
+        Commands::new(
+            white_player.0,
+            white_player.1,
+            view,
+            hide_opponent_clock.then_some(Black),
+        ),
+        SessionHandle { tasks },
     )
 }
 
+/// start a new game session, returning the response listeners along with `Commands`
+///
+/// The returned `Commands` no longer hold their listeners,
+/// so `Commands::get_listener()` returns `None` on them.
+pub fn new_session_with_listeners(
+    session_id: u64,
+    black_player_id: u64,
+    white_player_id: u64,
+    session_config: SessionConfig,
+) -> (
+    (Commands, Receiver<PlayerResponse>),
+    (Commands, Receiver<PlayerResponse>),
+) {
+    let (mut black, mut white) =
+        new_session(session_id, black_player_id, white_player_id, session_config);
+    let black_listener = black.get_listener().unwrap();
+    let white_listener = white.get_listener().unwrap();
+    ((black, black_listener), (white, white_listener))
+}
+
+/// the opening from the book with alternating colors,
+/// cut at the first move off the board or on an occupied position
+fn pick_opening(session_id: u64, config: &SessionConfig, seed: u64) -> Vec<(u8, u8, Color)> {
+    let moves = match &config.opening_book {
+        OpeningBook::Free => return Vec::new(),
+        OpeningBook::Fixed(moves) => moves,
+        OpeningBook::Random(openings) => match openings.choose(&mut StdRng::seed_from_u64(seed)) {
+            Some(moves) => moves,
+            None => return Vec::new(),
+        },
+    };
+    let mut occupied = HashSet::new();
+    let mut color = config.first_player;
+    let mut opening = Vec::with_capacity(moves.len());
+    for &(x, y) in moves {
+        if !config.on_board(x, y) || !occupied.insert((x, y)) {
+            warn!(
+                "game session {} opening cut at invalid move ({}, {})",
+                session_id, x, y
+            );
+            break;
+        }
+        opening.push((x, y, color));
+        color = color.switch();
+    }
+    opening
+}
+
+/// send the opening to both players move by move
+async fn broadcast_opening(
+    opening: &[(u8, u8, Color)],
+    width: u8,
+    height: u8,
+    responses: &Sender<SessionResponse>,
+) -> Result<()> {
+    let mut field = vec![vec![State::E; height as usize]; width as usize];
+    for &(x, y, color) in opening {
+        field[x as usize][y as usize] = match color {
+            Black => State::B,
+            White => State::W,
+        };
+        let state = FieldState {
+            latest: (x, y, color),
+            field: FieldInner(field.clone()),
+        };
+        broadcast_to_players(SessionPlayerResponse::Opening(state), responses).await?;
+    }
+    Ok(())
+}
+
 /// return Error only when it cannot send
+#[allow(clippy::too_many_arguments)]
 async fn handle_player_message(
     player_color: Color,
     player_action: SessionPlayerAction,
     player_id: u64,
+    stats: &mut GameStats,
+    pending_end: &mut Option<PendingGameEnd>,
+    timeout_behavior: TimeoutBehavior,
     responses: &Sender<SessionResponse>,
     killer: &SessionKiller,
 ) -> Result<()> {
     match player_action {
         SessionPlayerAction::Play(x, y) => on_player_play((x, y), player_color, responses).await?,
+        // quitting within `final_undo_window` does not take back the result
+        SessionPlayerAction::Quit(_) if pending_end.is_some() => {
+            finalize_game(pending_end, stats, responses, killer).await?
+        }
         SessionPlayerAction::Quit(quit_action) => {
-            on_player_quit(quit_action, player_color, player_id, responses, killer).await?
+            on_player_quit(
+                quit_action,
+                player_color,
+                player_id,
+                stats.started(),
+                responses,
+                killer,
+            )
+            .await?
         }
         SessionPlayerAction::RequestUndo => on_player_request_undo(player_color, responses).await?,
         SessionPlayerAction::Undo(undo_action) => {
             on_player_undo(player_color, undo_action, responses).await?
         }
-        SessionPlayerAction::PlayTimeout => on_player_timeout(player_color, responses).await?,
+        SessionPlayerAction::RequestRedo => on_player_request_redo(player_color, responses).await?,
+        SessionPlayerAction::Redo(redo_action) => {
+            on_player_redo(player_color, redo_action, responses).await?
+        }
+        SessionPlayerAction::PlayTimeout => {
+            on_player_timeout(player_color, timeout_behavior, stats, responses, killer).await?
+        }
     }
     Ok(())
 }
 
+/// the moves and time used in the game, for `GameSummary`
+struct GameStats {
+    begin: Instant,
+    /// the latest change of the board
+    changed: Instant,
+    opening_stones: usize,
+    stones: usize,
+    black_time: Duration,
+    white_time: Duration,
+}
+
+impl GameStats {
+    fn new(opening_stones: usize) -> Self {
+        let now = Instant::now();
+        GameStats {
+            begin: now,
+            changed: now,
+            opening_stones,
+            stones: opening_stones,
+            black_time: Duration::ZERO,
+            white_time: Duration::ZERO,
+        }
+    }
+
+    /// whether any move is in the history (the opening is not)
+    fn started(&self) -> bool {
+        self.stones > self.opening_stones
+    }
+
+    /// the latest move took the time since the previous change of the board
+    fn on_move(&mut self, state: &FieldState) {
+        self.add_time(state.latest.2);
+        self.on_board(&state.field);
+    }
+
+    /// undo and redo change the board without timing a move
+    fn on_board(&mut self, field: &FieldInner) {
+        self.stones = field.0.iter().flatten().filter(|s| **s != State::E).count();
+        self.changed = Instant::now();
+    }
+
+    fn add_time(&mut self, color: Color) {
+        let now = Instant::now();
+        let used = now - self.changed;
+        match color {
+            Black => self.black_time += used,
+            White => self.white_time += used,
+        }
+        self.changed = now;
+    }
+
+    fn summary(&self, result: GameResult) -> GameSummary {
+        GameSummary {
+            moves: self.stones.saturating_sub(self.opening_stones) as u16,
+            duration: self.begin.elapsed(),
+            black_time: self.black_time,
+            white_time: self.white_time,
+            result,
+        }
+    }
+}
+
+/// a game ending move that can still be undone within `final_undo_window`
+struct PendingGameEnd {
+    field: FieldState,
+    result: GameResult,
+    finalizer: TimeoutGate<SessionMessage>,
+}
+
 async fn handle_game_message(
     game_message: GameResponse,
+    final_undo_window: u64,
+    stats: &mut GameStats,
+    pending_end: &mut Option<PendingGameEnd>,
     responses: &Sender<SessionResponse>,
+    killer: &SessionKiller,
 ) -> Result<()> {
     match game_message {
         GameResponse::Field(state) => {
+            stats.on_move(&state);
             broadcast_to_players(SessionPlayerResponse::FieldUpdate(state), responses).await
         }
         GameResponse::Undo(field) => {
+            stats.on_board(&field.field);
+            // undoing the game ending move resumes the game
+            if let Some(mut pending) = pending_end.take() {
+                pending.finalizer.pause().await;
+            }
             broadcast_to_players(
                 SessionPlayerResponse::Undo(UndoResponse::Undo(field)),
                 responses,
             )
             .await
         }
-        GameResponse::BlackWins => {
+        GameResponse::Redo(state) => {
+            stats.on_board(&state.field);
             broadcast_to_players(
-                SessionPlayerResponse::Quit(GameQuitResponse::GameEnd(GameResult::BlackWins)),
+                SessionPlayerResponse::Redo(RedoResponse::Redo(state)),
                 responses,
             )
             .await
         }
-        GameResponse::WhiteWins => {
+        GameResponse::GameEnd(state, result) if final_undo_window > 0 => {
+            stats.on_move(&state);
+            *pending_end = Some(PendingGameEnd {
+                field: state.clone(),
+                result: result.clone(),
+                finalizer: killer.finalize_after(Duration::from_secs(final_undo_window)),
+            });
             broadcast_to_players(
-                SessionPlayerResponse::Quit(GameQuitResponse::GameEnd(GameResult::WhiteWins)),
+                SessionPlayerResponse::GameEndPending(state, result),
                 responses,
             )
             .await
         }
-        GameResponse::Draw => {
-            broadcast_to_players(
-                SessionPlayerResponse::Quit(GameQuitResponse::GameEnd(GameResult::Draw)),
-                responses,
-            )
-            .await
+        GameResponse::GameEnd(state, result) => {
+            stats.on_move(&state);
+            let quit_rsp = GameQuitResponse::GameEndWithBoard(result, state);
+            end_game(quit_rsp, stats, responses, killer).await
         }
+        GameResponse::InvalidMove(x, y, color) => Ok(responses
+            .send(SessionResponse::Player(
+                color,
+                SessionPlayerResponse::InvalidMove(x, y),
+            ))
+            .await?),
         GameResponse::GameError(e) => {
-            broadcast_to_players(
-                SessionPlayerResponse::Quit(GameQuitResponse::GameError(e)),
-                responses,
-            )
-            .await
+            let quit_rsp = GameQuitResponse::GameError(e);
+            broadcast_to_players(SessionPlayerResponse::Quit(quit_rsp.clone()), responses).await?;
+            end_session(quit_rsp, responses, killer).await
         }
     }
 }
 
+/// the game ending move was not undone within the window, the result is final
+async fn finalize_game(
+    pending_end: &mut Option<PendingGameEnd>,
+    stats: &GameStats,
+    responses: &Sender<SessionResponse>,
+    killer: &SessionKiller,
+) -> Result<()> {
+    match pending_end.take() {
+        Some(PendingGameEnd { field, result, .. }) => {
+            let quit_rsp = GameQuitResponse::GameEndWithBoard(result, field);
+            end_game(quit_rsp, stats, responses, killer).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// the game ends with a result: both players get the same summary, then `quit_rsp`
+async fn end_game(
+    quit_rsp: GameQuitResponse,
+    stats: &GameStats,
+    responses: &Sender<SessionResponse>,
+    killer: &SessionKiller,
+) -> Result<()> {
+    if let GameQuitResponse::GameEnd(result) | GameQuitResponse::GameEndWithBoard(result, _) =
+        &quit_rsp
+    {
+        let summary = SessionPlayerResponse::GameSummary(stats.summary(result.clone()));
+        broadcast_to_players(summary, responses).await?;
+    }
+    broadcast_to_players(SessionPlayerResponse::Quit(quit_rsp.clone()), responses).await?;
+    end_session(quit_rsp, responses, killer).await
+}
+
+/// Stop the field and then the session, after the terminal responses are queued.
+///
+/// The router forwards everything queued before it stops,
+/// so both players receive their `Quit` before the session tasks terminate.
+async fn end_session(
+    quit_rsp: GameQuitResponse,
+    responses: &Sender<SessionResponse>,
+    killer: &SessionKiller,
+) -> Result<()> {
+    responses
+        .send(SessionResponse::Game(GameCommand::Kill))
+        .await?;
+    killer.kill(quit_rsp).await
+}
+
 async fn on_player_request_undo(
     player_color: Color,
     responses: &Sender<SessionResponse>,
@@ -169,13 +544,35 @@ async fn on_player_request_undo(
         .await?)
 }
 
-async fn on_player_timeout(player_color: Color, responses: &Sender<SessionResponse>) -> Result<()> {
+async fn on_player_request_redo(
+    player_color: Color,
+    responses: &Sender<SessionResponse>,
+) -> Result<()> {
+    Ok(responses
+        .send(SessionResponse::Player(
+            player_color.switch(),
+            SessionPlayerResponse::RedoRequest,
+        ))
+        .await?)
+}
+
+async fn on_player_timeout(
+    player_color: Color,
+    timeout_behavior: TimeoutBehavior,
+    stats: &mut GameStats,
+    responses: &Sender<SessionResponse>,
+    killer: &SessionKiller,
+) -> Result<()> {
+    stats.add_time(player_color);
+    if timeout_behavior == TimeoutBehavior::AutoPass {
+        let passed = SessionPlayerResponse::TurnPassed(player_color);
+        return broadcast_to_players(passed, responses).await;
+    }
     let quit_rsp = match player_color {
         Black => GameQuitResponse::GameEnd(GameResult::BlackTimeout),
         White => GameQuitResponse::GameEnd(GameResult::WhiteTimeout),
     };
-    broadcast_to_players(SessionPlayerResponse::Quit(quit_rsp), responses).await?;
-    Ok(())
+    end_game(quit_rsp, stats, responses, killer).await
 }
 
 /// handle events when player plays a step
@@ -217,6 +614,14 @@ async fn on_player_undo(
                 ))
                 .await?
         }
+        SessionUndoAction::InProgressReject => {
+            responses
+                .send(SessionResponse::Player(
+                    color.switch(),
+                    SessionPlayerResponse::Undo(UndoResponse::RequestInProgress),
+                ))
+                .await?
+        }
         SessionUndoAction::TimeoutReject => {
             broadcast_to_players(
                 SessionPlayerResponse::Undo(UndoResponse::TimeoutRejected),
@@ -228,44 +633,93 @@ async fn on_player_undo(
     Ok(())
 }
 
+/// answer to a redo request, like `on_player_undo`
+async fn on_player_redo(
+    color: Color,
+    redo_action: SessionUndoAction,
+    responses: &Sender<SessionResponse>,
+) -> Result<()> {
+    let redo_rsp = match redo_action {
+        SessionUndoAction::Approve => {
+            return Ok(responses
+                .send(SessionResponse::Game(GameCommand::Redo))
+                .await?);
+        }
+        SessionUndoAction::TimeoutReject => {
+            return broadcast_to_players(
+                SessionPlayerResponse::Redo(RedoResponse::TimeoutRejected),
+                responses,
+            )
+            .await;
+        }
+        SessionUndoAction::Reject => RedoResponse::RejectedByOpponent,
+        SessionUndoAction::AutoReject => RedoResponse::AutoRejected,
+        SessionUndoAction::InProgressReject => RedoResponse::RequestInProgress,
+    };
+    Ok(responses
+        .send(SessionResponse::Player(
+            color.switch(),
+            SessionPlayerResponse::Redo(redo_rsp),
+        ))
+        .await?)
+}
+
 /// handle event when one player quits game
+///
+/// If no move has been played, the opponent gets `GameEnd(Aborted)`
+/// instead of the reason of the quit.
 async fn on_player_quit(
     quit_action: PlayerQuitReason,
     player_color: Color,
     player_id: u64,
+    started: bool,
     responses: &Sender<SessionResponse>,
     killer: &SessionKiller,
 ) -> Result<()> {
+    let aborted = GameQuitResponse::GameEnd(GameResult::Aborted);
     // the reason for player's quit action
     let quit_rsp = match quit_action {
+        PlayerQuitReason::QuitSession if started => {
+            let rsp = GameQuitResponse::PlayerQuitSession(player_id);
+            broadcast_to_players(SessionPlayerResponse::Quit(rsp.clone()), responses).await?;
+            return end_session(rsp, responses, killer).await;
+        }
         PlayerQuitReason::QuitSession => {
             let rsp = GameQuitResponse::PlayerQuitSession(player_id);
-            broadcast_to_players(SessionPlayerResponse::Quit(rsp), responses).await?;
-            return Ok(());
+            responses
+                .send(SessionResponse::Player(
+                    player_color,
+                    SessionPlayerResponse::Quit(rsp.clone()),
+                ))
+                .await?;
+            responses
+                .send(SessionResponse::Player(
+                    player_color.switch(),
+                    SessionPlayerResponse::Quit(aborted),
+                ))
+                .await?;
+            return end_session(rsp, responses, killer).await;
         }
         PlayerQuitReason::Disconnected => GameQuitResponse::OpponentDisconnected(player_id),
         PlayerQuitReason::Error(e) => GameQuitResponse::OpponentError(player_id, e),
         PlayerQuitReason::ExitGame => GameQuitResponse::OpponentExitGame(player_id),
+        PlayerQuitReason::Kicked(reason) => GameQuitResponse::OpponentKicked(player_id, reason),
     };
     // notify the other player
+    let opponent_rsp = if started { quit_rsp.clone() } else { aborted };
     responses
         .send(SessionResponse::Player(
             player_color.switch(),
-            SessionPlayerResponse::Quit(quit_rsp.clone()),
+            SessionPlayerResponse::Quit(opponent_rsp),
         ))
         .await?;
-    // kill game
-    responses
-        .send(SessionResponse::Game(GameCommand::Kill))
-        .await?;
-    // kill game session
-    killer.kill(quit_rsp).await
+    end_session(quit_rsp, responses, killer).await
 }
 
 fn log_quit_response(game_id: u64, quit_rsp: GameQuitResponse) {
     match quit_rsp {
-        GameQuitResponse::GameEnd(e) => {
-            error!("game {} finished in error {}", game_id, e)
+        GameQuitResponse::GameEnd(e) | GameQuitResponse::GameEndWithBoard(e, _) => {
+            info!("game {} finished with {}", game_id, e)
         }
         GameQuitResponse::PlayerQuitSession(player_id) => {
             info!("player {} quit game {}", player_id, game_id)
@@ -276,6 +730,10 @@ fn log_quit_response(game_id: u64, quit_rsp: GameQuitResponse) {
         GameQuitResponse::OpponentDisconnected(player_id) => {
             warn!("player {} disconnected from game {}", player_id, game_id)
         }
+        GameQuitResponse::OpponentKicked(player_id, reason) => warn!(
+            "player {} kicked from game {}: {}",
+            player_id, game_id, reason
+        ),
         GameQuitResponse::OpponentError(player_id, e) => error!(
             "player {} error in game {}. Error: {}",
             player_id, game_id, e
@@ -20,11 +20,31 @@ pub(crate) struct TimeoutGate<T> {
     time: Instant,
     total_elapsed: Duration,
     total_delay: Option<Duration>,
+    /// counting down, neither paused nor finished
+    running: bool,
     msg_timeout: Arc<Mutex<Option<T>>>,
     state: Arc<Mutex<State>>,
     sender: Arc<Mutex<Option<Sender<T>>>>,
 }
 
+/// the time left on a `TimeoutGate`
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Clock {
+    /// counting down to the deadline
+    Running(Instant),
+    /// stopped, with the time left
+    Paused(Duration),
+}
+
+impl Clock {
+    pub(crate) fn remaining(&self) -> Duration {
+        match self {
+            Clock::Running(deadline) => deadline.saturating_duration_since(Instant::now()),
+            Clock::Paused(left) => *left,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum State {
     Waiting(usize),
@@ -41,6 +61,7 @@ impl<T: Send + 'static> TimeoutGate<T> {
             state: Arc::new(Mutex::new(State::Waiting(0))),
             msg_timeout: Arc::new(Mutex::new(Some(timeout_msg))),
             total_delay,
+            running: true,
             sender: Arc::new(Mutex::new(Some(sender))),
         };
         let total_delay = gate.total_delay.clone();
@@ -68,6 +89,7 @@ impl<T: Send + 'static> TimeoutGate<T> {
             // update total_elapsed
             self.total_elapsed += self.time.elapsed();
             self.time = Instant::now();
+            self.running = false;
             // invalidate previous timeout alarm
             *seq += 1;
             *seq
@@ -100,9 +122,22 @@ impl<T: Send + 'static> TimeoutGate<T> {
         };
         *state = State::Waiting(seq);
         drop(state);
+        // the paused time is not elapsed
+        self.time = Instant::now();
+        self.running = true;
         self.fire_alarm(delay, seq);
     }
 
+    /// `None` if the alarm never fires
+    pub(crate) fn clock(&self) -> Option<Clock> {
+        let left = self.total_delay?.saturating_sub(self.total_elapsed);
+        if self.running {
+            Some(Clock::Running(self.time + left))
+        } else {
+            Some(Clock::Paused(left))
+        }
+    }
+
     /// sleep for sometime and send Timeout message
     /// this may be called only once
     ///
@@ -268,6 +303,28 @@ mod test_timeout {
         })
     }
 
+    #[test]
+    fn clock_pause_resume() {
+        let (msg_sender, _msg_receiver) = bounded(1);
+        let mut gate = TimeoutGate::new(Some(Duration::from_millis(1000)), msg_sender, 0);
+        block_on(async {
+            task::sleep(Duration::from_millis(200)).await;
+            gate.pause().await;
+            let left = gate.clock().unwrap().remaining();
+            assert!(left <= Duration::from_millis(800));
+            assert!(left > Duration::from_millis(500));
+            // paused clock stands still
+            task::sleep(Duration::from_millis(200)).await;
+            assert_eq!(gate.clock().unwrap().remaining(), left);
+            gate.resume(Duration::from_millis(500)).await;
+            let resumed = gate.clock().unwrap().remaining();
+            assert!(resumed <= left + Duration::from_millis(500));
+            assert!(resumed > left);
+        });
+        let (msg_sender, _msg_receiver) = bounded(1);
+        assert!(TimeoutGate::new(None, msg_sender, 0).clock().is_none());
+    }
+
     #[test]
     fn multiple_pause_resume_timeout() {
         let (msg_sender, mut msg_receiver) = bounded(1);
@@ -0,0 +1,63 @@
+//! Observing a game session from outside.
+//!
+//! Both players publish their part of the game to a shared `GameView`
+//! after handling each message, which observers read without
+//! interfering with the session.
+use crate::game::game_field::{Color, State};
+use crate::game::session::api::{FieldInner, GameSnapshot};
+use crate::game::session::utility::Clock;
+use async_std::sync::Mutex;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub(crate) struct GameView(Arc<Mutex<ViewInner>>);
+
+struct ViewInner {
+    field: FieldInner,
+    to_move: Option<Color>,
+    black_clock: Option<Clock>,
+    white_clock: Option<Clock>,
+}
+
+impl GameView {
+    pub(crate) fn new(width: u8, height: u8) -> Self {
+        GameView(Arc::new(Mutex::new(ViewInner {
+            field: FieldInner(vec![vec![State::E; height as usize]; width as usize]),
+            to_move: None,
+            black_clock: None,
+            white_clock: None,
+        })))
+    }
+
+    /// update the field, and whether it is the turn of `color` with its play clock
+    pub(crate) async fn publish(
+        &self,
+        color: Color,
+        field: &[Vec<State>],
+        on_move: bool,
+        clock: Option<Clock>,
+    ) {
+        let mut inner = self.0.lock().await;
+        inner.field = FieldInner(field.to_vec());
+        if on_move {
+            inner.to_move = Some(color);
+        } else if inner.to_move == Some(color) {
+            inner.to_move = None;
+        }
+        let clock = if on_move { clock } else { None };
+        match color {
+            Color::Black => inner.black_clock = clock,
+            Color::White => inner.white_clock = clock,
+        }
+    }
+
+    pub(crate) async fn snapshot(&self) -> GameSnapshot {
+        let inner = self.0.lock().await;
+        GameSnapshot {
+            field: inner.field.clone(),
+            black_clock: inner.black_clock.map(|c| c.remaining()),
+            white_clock: inner.white_clock.map(|c| c.remaining()),
+            to_move: inner.to_move,
+        }
+    }
+}
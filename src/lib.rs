@@ -5,9 +5,15 @@ mod stream_utility;
 
 pub use game::*;
 pub use lobby::{
-    start_server, ConnectionInitError, CreateAccountFailure, InvalidAccountPassword, LoginFailure,
-    Messages, Responses, RoomState, RoomToken, UpdatePasswordFailure,
+    start_server, Blocklist, ConnectionInitError, CreateAccountFailure, GameOutcome,
+    InvalidAccountPassword, JoinRoomFailure, LoginFailure, Messages, NameFilter, NoTelemetry,
+    Normalization, Responses, RoomDirectory, RoomDirectoryEntry, RoomState, RoomStatus,
+    RoomSummary, RoomToken, ServerInfo, ServerOptions, StartServerError, TelemetryEvent,
+    TelemetrySink, UpdatePasswordFailure, DEFAULT_REJECTION_LIMIT, DEFAULT_SERVER_MAX_CONN,
+    DEFAULT_TOKEN_GRACE, DEFAULT_USER_MAX_CONN, PROTOCOL_VERSION,
+};
+pub use network::{
+    ClosePolicy, Conn, ConnReceiver, ConnSender, ConnectionError, Received, RequestError,
 };
-pub use network::{Conn, ConnectionError, Received};
 
 pub(crate) const CHANNEL_SIZE: usize = 5;
@@ -1,58 +1,160 @@
 use crate::lobby::messages::{CreateAccountFailure, LoginFailure, Messages, Responses};
+use crate::lobby::normalization::Normalization;
+use crate::lobby::rate_limit::RateLimit;
+use crate::lobby::telemetry::{LoginTelemetry, Telemetry, TelemetryEvent, TelemetrySink};
 use crate::lobby::user_db::{LoginValidator, Password};
-use crate::network::connection::{Conn, ConnectionError, Received};
-use async_std::channel::Sender;
+use crate::network::connection::{Conn, ConnReceiver, ConnectionError, Received};
+use crate::CHANNEL_SIZE;
+use async_std::channel::{bounded, Receiver, Sender};
+use async_std::future::{timeout, TimeoutError};
 use async_std::net::TcpStream;
 use async_std::prelude::Stream;
 use async_std::sync::Mutex;
-use async_std::task::block_on;
+use async_std::task::{self, block_on};
 use bincode::{Decode, Encode};
 use futures::StreamExt;
-use tokio_rustls::{TlsAcceptor, TlsStream};
-use log::{error, info};
+use log::{error, info, warn};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::ops::Deref;
 use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio_rustls::{TlsAcceptor, TlsStream};
 
 const PING_INTERVAL: Duration = Duration::from_secs(5);
 const MAX_DATA_SIZE: u32 = 1024 * 1024 * 20;
 const SINGLE_IP_MAX_CONN: u32 = 64;
+/// upper bound of the window of `CoalesceResponses`, in milliseconds
+const MAX_COALESCING_WINDOW_MILLIS: u64 = 100;
+/// responses of one `Batch`, so that a batch stays a small frame
+const MAX_BATCH_LEN: usize = 16;
+/// default maximum number of concurrent connections of one user
+pub const DEFAULT_USER_MAX_CONN: u32 = 1;
 const MAX_PLAYER_SEARCH_RESULT_COUNT: usize = u8::MAX as usize;
+/// no longer than user names, which never contain a longer query
+const MAX_PLAYER_SEARCH_QUERY_BYTES: usize = 32;
+/// default maximum number of rejected messages of a connection within `REJECTION_WINDOW`
+pub const DEFAULT_REJECTION_LIMIT: usize = 20;
+/// default maximum number of concurrent connections to the server
+pub const DEFAULT_SERVER_MAX_CONN: u32 = 4096;
+const REJECTION_WINDOW: Duration = Duration::from_secs(10);
+#[cfg(not(test))]
+const SLOW_CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const SLOW_CLIENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Send a response to a client without blocking indefinitely.
+///
+/// A client whose response channel stays full for `SLOW_CLIENT_TIMEOUT`
+/// gets `TimeoutError`, and should be treated as disconnected.
+/// Closed channels are ignored, since disconnection is handled on receiving.
+pub(crate) async fn send_or_timeout(
+    sender: &Sender<Responses>,
+    rsp: Responses,
+) -> Result<(), TimeoutError> {
+    timeout(SLOW_CLIENT_TIMEOUT, sender.send(rsp))
+        .await
+        .map(|_| ())
+}
 
 #[derive(Clone, Debug, PartialEq, Encode, Decode)]
 pub enum ConnectionInitError {
     TlsError,
     IpMaxConnExceed,
+    /// the user already has as many connections as allowed
+    UserMaxConnExceeded,
     ConnectionClosed,
     UserNameNotReceived,
     UserNameTooLong,
     UserNameExists,
     InvalidUserName,
     NetworkError(ConnectionError),
+    /// the server already has as many connections as allowed
+    ServerAtCapacity,
 }
 
 pub struct ClientConnection {
-    inner: Conn<Responses, Messages>,
+    inner: ConnReceiver<Messages>,
+    /// responses to the player, forwarded to the connection by `forward_responses`
+    sender: Sender<Responses>,
+    /// window of `CoalesceResponses` in milliseconds, 0 if off
+    coalescing: Arc<AtomicU64>,
+    /// the client closed the connection cleanly, instead of losing it
+    closed_cleanly: bool,
     player_name: String,
     player_id: u64,
     socket_address: SocketAddr,
     connection_stats: Arc<Mutex<ConnectionStats>>,
     name_dict: Arc<Mutex<HashMap<String, Sender<Responses>>>>,
+    is_admin: bool,
+    rejections: Rejections,
+    telemetry: Telemetry,
+    normalization: Normalization,
+}
+
+/// Counts messages rejected as invalid or out of context on a connection.
+///
+/// Shared with whoever handles the messages of the connection (e.g. rooms).
+#[derive(Clone)]
+pub(crate) struct Rejections {
+    socket_address: SocketAddr,
+    /// `None` before login
+    player_id: Option<u64>,
+    limit: Arc<Mutex<RateLimit>>,
+}
+
+impl Rejections {
+    pub(crate) fn new(socket_address: SocketAddr, limit: usize, window: Duration) -> Self {
+        Rejections {
+            socket_address,
+            player_id: None,
+            limit: Arc::new(Mutex::new(RateLimit::new(limit, window))),
+        }
+    }
+
+    /// Log the rejected message by its kind only.
+    ///
+    /// Returns `false` once too many messages are rejected,
+    /// and the connection should be closed.
+    pub(crate) async fn reject(&self, msg: &Messages) -> bool {
+        let mut limit = self.limit.lock().await;
+        let allowed = limit.allow(Instant::now());
+        let report = self.report(msg.kind(), limit.count());
+        if allowed {
+            warn!("{report}");
+        } else {
+            warn!("{report}, disconnecting");
+        }
+        allowed
+    }
+
+    fn report(&self, kind: &str, count: usize) -> String {
+        let player = match self.player_id {
+            Some(id) => format!("player {id}"),
+            None => "unknown player".to_string(),
+        };
+        format!(
+            "rejected {kind} of {player} ({}), {count} rejected recently",
+            self.socket_address
+        )
+    }
 }
 
 /// Handle Client Connection
 ///
 /// # Convention
 ///
+/// The server first sends `Responses::Welcome` right after TLS.
 /// The connection should start by sending `Messages::UserName(user_name)`,
 /// otherwise the connection will return `UserNameNotReceived`.
 impl ClientConnection {
+    #[allow(clippy::too_many_arguments)]
     pub async fn init(
         tcp: TcpStream,
         acceptor: TlsAcceptor,
@@ -60,6 +162,9 @@ impl ClientConnection {
         connection_stats: Arc<Mutex<ConnectionStats>>,
         name_dict: Arc<Mutex<HashMap<String, Sender<Responses>>>>,
         login_validator: LoginValidator,
+        admins: Arc<HashSet<String>>,
+        welcome: Responses,
+        telemetry: Arc<dyn TelemetrySink>,
     ) -> Result<Self, (ConnectionInitError, Option<Conn<Responses, Messages>>)> {
         // add connection, check if ip max connection number exceeded
         match connection_stats
@@ -67,7 +172,7 @@ impl ClientConnection {
             .await
             .add_conn(socket_address.clone(), SINGLE_IP_MAX_CONN)
         {
-            Ok(id) => id,
+            Ok(()) => {}
             Err(e) => {
                 return if let Ok(tls) = acceptor.accept(tcp).await {
                     let tls = TlsStream::Server(tls);
@@ -78,11 +183,22 @@ impl ClientConnection {
                 };
             }
         };
+        // released on every early return below
+        let slot = ConnSlot {
+            connection_stats: Some(connection_stats.clone()),
+            socket_address,
+        };
         let tls = TlsStream::Server(match acceptor.accept(tcp).await {
             Ok(tls) => tls,
             Err(_) => return Err((ConnectionInitError::TlsError, None)),
         });
         let mut inner = Conn::init(tls, Some(PING_INTERVAL), MAX_DATA_SIZE);
+        let telemetry = LoginTelemetry::opened(telemetry, socket_address);
+        let mut rejections = connection_stats.lock().await.rejections(socket_address);
+        let normalization = login_validator.normalization();
+        if inner.sender().send(welcome).await.is_err() {
+            return Err((ConnectionInitError::ConnectionClosed, Some(inner)));
+        }
         let (player_name, player_id) = loop {
             match inner.next().await {
                 None => return Err((ConnectionInitError::ConnectionClosed, Some(inner))),
@@ -90,8 +206,13 @@ impl ClientConnection {
                     match msg {
                         Received::Response(msg) => match msg {
                             Messages::Login(name, password) => {
+                                let name = normalization.normalize_name(&name);
                                 match login_validator.query_user_password(&name) {
                                     Err(e) => {
+                                        telemetry.emit(TelemetryEvent::LoginFailed {
+                                            player_name: name,
+                                            reason: e.clone(),
+                                        });
                                         if inner
                                             .sender()
                                             .send(Responses::LoginFailure(e))
@@ -107,6 +228,10 @@ impl ClientConnection {
                                     Ok(info) => {
                                         if info.password.deref().eq(&password) {
                                             if name_dict.lock().await.contains_key(&name) {
+                                                telemetry.emit(TelemetryEvent::LoginFailed {
+                                                    player_name: name,
+                                                    reason: LoginFailure::AlreadyLoggedIn,
+                                                });
                                                 if inner
                                                     .sender()
                                                     .send(Responses::LoginFailure(
@@ -124,6 +249,10 @@ impl ClientConnection {
                                                 break (name, info.user_id);
                                             }
                                         } else {
+                                            telemetry.emit(TelemetryEvent::LoginFailed {
+                                                player_name: name,
+                                                reason: LoginFailure::PasswordIncorrect,
+                                            });
                                             if inner
                                                 .sender()
                                                 .send(Responses::LoginFailure(
@@ -142,6 +271,7 @@ impl ClientConnection {
                                 }
                             }
                             Messages::CreateAccount(name, password) => {
+                                let name = normalization.normalize_name(&name);
                                 match login_validator
                                     .register_user(&name, Password(password.clone()))
                                 {
@@ -178,6 +308,7 @@ impl ClientConnection {
                                 }
                             }
                             Messages::UpdateAccount(name, old_password, new_password) => {
+                                let name = normalization.normalize_name(&name);
                                 match login_validator.update_user_info(
                                     &name,
                                     Password(old_password),
@@ -231,10 +362,17 @@ impl ClientConnection {
                                     }
                                 }
                             }
-                            _ => {}
+                            msg => {
+                                if !rejections.reject(&msg).await {
+                                    return Err((
+                                        ConnectionInitError::ConnectionClosed,
+                                        Some(inner),
+                                    ));
+                                }
+                            }
                         },
-                        Received::Ping => {
-                            // jump over Ping
+                        Received::Ping | Received::Reconnected | Received::Closed => {
+                            // jump over Ping, `None` follows `Closed`
                         }
                         Received::Error(e) => {
                             return Err((ConnectionInitError::NetworkError(e), Some(inner)))
@@ -246,50 +384,76 @@ impl ClientConnection {
                 }
             }
         };
+        // check if user max connection number exceeded
+        let user_added = connection_stats.lock().await.add_user(player_id);
+        if let Err(e) = user_added {
+            return Err((e, Some(inner)));
+        }
+        // from now on released by `ClientConnection::drop`
+        slot.keep();
         info!("player {player_id}: {player_name} login success");
+        let telemetry = telemetry.logged_in(player_id, &player_name);
         let _ = inner
             .sender()
             .send(Responses::LoginSuccess(player_name.clone()))
             .await;
+        let (conn_sender, inner) = inner.split();
+        let (sender, receiver) = bounded(CHANNEL_SIZE);
+        let coalescing = Arc::new(AtomicU64::new(0));
+        task::spawn(forward_responses(
+            receiver,
+            conn_sender.sender().clone(),
+            coalescing.clone(),
+        ));
         name_dict
             .lock()
             .await
-            .insert(player_name.clone(), inner.sender().clone());
+            .insert(player_name.clone(), sender.clone());
+        let is_admin = admins.contains(&player_name);
+        rejections.player_id = Some(player_id);
         Ok(ClientConnection {
             inner,
+            sender,
+            coalescing,
+            closed_cleanly: false,
             player_name,
             player_id,
             socket_address,
             connection_stats,
             name_dict,
+            is_admin,
+            rejections,
+            telemetry,
+            normalization,
         })
     }
 
     pub(crate) fn sender(&self) -> &Sender<Responses> {
-        self.inner.sender()
+        &self.sender
     }
 
-    pub(crate) async fn get_online_players(&self, name: Option<String>, n: usize) -> Vec<String> {
-        let name_dict = self.name_dict.lock().await;
-        let n = MAX_PLAYER_SEARCH_RESULT_COUNT.min(n);
-        if let Some(name) = name {
-            name_dict
-                .keys()
-                .filter(|&x| x.contains(&name))
-                .take(n)
-                .map(|x| x.clone())
-                .collect()
-        } else {
-            name_dict.keys().take(n).map(|x| x.clone()).collect()
+    /// at most `n` online players whose names contain `name`,
+    /// filtered after releasing the lock of the names,
+    /// `None` if `name` is not a valid query
+    pub(crate) async fn get_online_players(
+        &self,
+        name: Option<String>,
+        n: usize,
+    ) -> Option<Vec<String>> {
+        if !valid_query(name.as_deref()) {
+            return None;
         }
+        let name = name.map(|name| self.normalization.normalize_name(&name));
+        let names = online_names(&self.name_dict).await;
+        Some(filter_names(names, name.as_deref(), n))
     }
 
     pub(crate) async fn send_to_player(&self, name: &str, msg: Vec<u8>) {
+        let name = self.normalization.normalize_name(name);
         let name_dict = self.name_dict.lock().await;
-        if let Some(sender) = name_dict.get(name) {
-            let _ = sender
-                .send(Responses::FromPlayer(self.player_name.clone(), msg))
-                .await;
+        if let Some(sender) = name_dict.get(&name) {
+            let rsp = Responses::FromPlayer(self.player_name.clone(), msg);
+            let _ = send_or_timeout(sender, rsp).await;
         }
     }
 
@@ -300,6 +464,25 @@ impl ClientConnection {
     pub fn player_id(&self) -> u64 {
         self.player_id
     }
+
+    /// after the end of messages, whether the client closed the connection cleanly
+    /// (a deliberate exit) rather than the connection being lost or broken
+    pub(crate) fn closed_cleanly(&self) -> bool {
+        self.closed_cleanly
+    }
+
+    /// whether the player is allowed to send admin messages
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    pub(crate) fn rejections(&self) -> &Rejections {
+        &self.rejections
+    }
+
+    pub(crate) fn normalization(&self) -> Normalization {
+        self.normalization
+    }
 }
 
 impl Stream for ClientConnection {
@@ -312,29 +495,24 @@ impl Stream for ClientConnection {
                 Poll::Ready(msg) => {
                     match msg {
                         None => break Poll::Ready(None),
-                        Some(msg) => {
-                            match msg {
-                                Received::Response(msg) => {
-                                    if let Messages::ToPlayer(name, msg) = msg {
-                                        block_on(self.send_to_player(&name, msg));
-                                    } else {
-                                        break Poll::Ready(Some(msg));
-                                    }
-                                }
-                                Received::Ping => {}
-                                Received::Error(e) => {
-                                    // log and quit on connection error automatically
-                                    let address = self.socket_address;
-                                    error!("connection error ({e}) of {address}");
-                                    break Poll::Ready(None);
-                                }
-                                Received::RemoteError(e) => {
-                                    let address = self.socket_address;
-                                    error!("remote connection error ({e}) of {address}");
-                                    break Poll::Ready(None);
-                                }
+                        Some(Received::Closed) => self.closed_cleanly = true,
+                        Some(msg) => match msg.into_response() {
+                            Some(Ok(Messages::ToPlayer(name, msg))) => {
+                                block_on(self.send_to_player(&name, msg));
                             }
-                        }
+                            Some(Ok(Messages::CoalesceResponses(millis))) => {
+                                let millis = (millis as u64).min(MAX_COALESCING_WINDOW_MILLIS);
+                                self.coalescing.store(millis, Relaxed);
+                            }
+                            Some(Ok(msg)) => break Poll::Ready(Some(msg)),
+                            None => {}
+                            Some(Err(e)) => {
+                                // log and quit on local or remote connection error automatically
+                                let address = self.socket_address;
+                                error!("connection error ({e}) of {address}");
+                                break Poll::Ready(None);
+                            }
+                        },
                     }
                 }
                 Poll::Pending => break Poll::Pending,
@@ -349,31 +527,160 @@ impl Drop for ClientConnection {
             "player {}: {} ({}) disconnected from server",
             self.player_id, self.player_name, self.socket_address
         );
-        block_on(self.connection_stats.lock()).remove_conn(self.socket_address);
+        let mut connection_stats = block_on(self.connection_stats.lock());
+        connection_stats.remove_conn(self.socket_address);
+        connection_stats.remove_user(self.player_id);
+        drop(connection_stats);
         block_on(self.name_dict.lock()).remove(&self.player_name);
+        self.telemetry.emit(TelemetryEvent::ConnectionClosed {
+            address: self.socket_address,
+            player_id: Some(self.player_id),
+        });
+        // so that other holders of the sender (e.g. lobby subscribers) notice disconnection
+        self.sender().close();
+    }
+}
+
+/// A connection counted in `ConnectionStats` before login,
+/// uncounted when dropped unless kept.
+struct ConnSlot {
+    connection_stats: Option<Arc<Mutex<ConnectionStats>>>,
+    socket_address: SocketAddr,
+}
+
+impl ConnSlot {
+    /// keep the connection counted after the slot is dropped
+    fn keep(mut self) {
+        self.connection_stats = None;
+    }
+}
+
+impl Drop for ConnSlot {
+    fn drop(&mut self) {
+        if let Some(connection_stats) = self.connection_stats.take() {
+            block_on(connection_stats.lock()).remove_conn(self.socket_address);
+        }
+    }
+}
+
+/// Forward responses to the network connection.
+///
+/// With a coalescing window, responses following another one within the window
+/// are sent together in a `Batch`, unless the first response is alone.
+async fn forward_responses(
+    responses: Receiver<Responses>,
+    sender: Sender<Responses>,
+    coalescing: Arc<AtomicU64>,
+) {
+    while let Ok(rsp) = responses.recv().await {
+        let window = Duration::from_millis(coalescing.load(Relaxed));
+        if window.is_zero() {
+            if sender.send(rsp).await.is_err() {
+                break;
+            }
+            continue;
+        }
+        let mut batch = vec![rsp];
+        let deadline = Instant::now() + window;
+        while batch.len() < MAX_BATCH_LEN {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match timeout(left, responses.recv()).await {
+                Ok(Ok(rsp)) => batch.push(rsp),
+                _ => break,
+            }
+        }
+        let rsp = if batch.len() == 1 {
+            batch.pop().unwrap()
+        } else {
+            Responses::Batch(batch)
+        };
+        if sender.send(rsp).await.is_err() {
+            break;
+        }
     }
 }
 
-/// count number of connections from each ip address
+/// names of online players, the lock is held only to copy them
+async fn online_names(name_dict: &Mutex<HashMap<String, Sender<Responses>>>) -> Vec<String> {
+    name_dict.lock().await.keys().cloned().collect()
+}
+
+/// a query is at most `MAX_PLAYER_SEARCH_QUERY_BYTES` long without control characters
+fn valid_query(name: Option<&str>) -> bool {
+    name.is_none_or(|name| {
+        name.len() <= MAX_PLAYER_SEARCH_QUERY_BYTES && !name.chars().any(char::is_control)
+    })
+}
+
+/// at most `n` (capped at `MAX_PLAYER_SEARCH_RESULT_COUNT`) names containing `name`
+fn filter_names(names: Vec<String>, name: Option<&str>, n: usize) -> Vec<String> {
+    let n = MAX_PLAYER_SEARCH_RESULT_COUNT.min(n);
+    names
+        .into_iter()
+        .filter(|x| name.is_none_or(|name| x.contains(name)))
+        .take(n)
+        .collect()
+}
+
+/// count number of connections in total, from each ip address and of each user
 pub struct ConnectionStats {
+    conn_count: u32,
     conn_count_v4: HashMap<Ipv4Addr, u32>,
     conn_count_v6: HashMap<Ipv6Addr, u32>,
+    conn_count_user: HashMap<u64, u32>,
+    server_max_conn: u32,
+    user_max_conn: u32,
+    rejection_limit: usize,
 }
 
 impl ConnectionStats {
     pub fn new() -> Arc<Mutex<Self>> {
+        Self::with_user_max_conn(DEFAULT_USER_MAX_CONN)
+    }
+
+    /// allow at most `user_max_conn` concurrent connections of each user
+    pub fn with_user_max_conn(user_max_conn: u32) -> Arc<Mutex<Self>> {
+        Self::with_limits(user_max_conn, DEFAULT_REJECTION_LIMIT)
+    }
+
+    /// like `with_user_max_conn`, also closing connections
+    /// with more than `rejection_limit` rejected messages in 10 seconds
+    pub fn with_limits(user_max_conn: u32, rejection_limit: usize) -> Arc<Mutex<Self>> {
+        Self::with_server_max_conn(user_max_conn, rejection_limit, DEFAULT_SERVER_MAX_CONN)
+    }
+
+    /// like `with_limits`, also accepting at most `server_max_conn`
+    /// concurrent connections in total
+    pub fn with_server_max_conn(
+        user_max_conn: u32,
+        rejection_limit: usize,
+        server_max_conn: u32,
+    ) -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(Self {
+            conn_count: 0,
             conn_count_v4: Default::default(),
             conn_count_v6: Default::default(),
+            conn_count_user: Default::default(),
+            server_max_conn,
+            user_max_conn,
+            rejection_limit,
         }))
     }
 
+    /// rejected message counter of a new connection
+    fn rejections(&self, socket_address: SocketAddr) -> Rejections {
+        Rejections::new(socket_address, self.rejection_limit, REJECTION_WINDOW)
+    }
+
     /// add a new connection
     fn add_conn(
         &mut self,
         socket_address: SocketAddr,
         single_ip_max_conn: u32,
     ) -> Result<(), ConnectionInitError> {
+        if self.conn_count >= self.server_max_conn {
+            return Err(ConnectionInitError::ServerAtCapacity);
+        }
         match socket_address {
             SocketAddr::V4(v4) => {
                 Self::add_ip(&mut self.conn_count_v4, v4.ip().clone(), single_ip_max_conn)
@@ -382,26 +689,45 @@ impl ConnectionStats {
                 Self::add_ip(&mut self.conn_count_v6, v6.ip().clone(), single_ip_max_conn)
             }
         }
+        .map_err(|_| ConnectionInitError::IpMaxConnExceed)?;
+        self.conn_count += 1;
+        Ok(())
+    }
+
+    /// add a logged in connection of the user
+    fn add_user(&mut self, player_id: u64) -> Result<(), ConnectionInitError> {
+        Self::add_ip(&mut self.conn_count_user, player_id, self.user_max_conn)
+            .map_err(|_| ConnectionInitError::UserMaxConnExceeded)
+    }
+
+    /// drop a logged in connection of the user
+    fn remove_user(&mut self, player_id: u64) {
+        Self::remove_ip(&mut self.conn_count_user, player_id)
     }
 
     /// drop a connection
     fn remove_conn(&mut self, socket_address: SocketAddr) {
+        self.conn_count -= 1;
         match socket_address {
             SocketAddr::V4(v4) => Self::remove_ip(&mut self.conn_count_v4, v4.ip().clone()),
             SocketAddr::V6(v6) => Self::remove_ip(&mut self.conn_count_v6, v6.ip().clone()),
         }
     }
 
+    /// `Err` if `max_conn` is reached, also used for counting users
     fn add_ip<T: Eq + Hash>(
         count_table: &mut HashMap<T, u32>,
         ip: T,
-        single_ip_max_conn: u32,
-    ) -> Result<(), ConnectionInitError> {
+        max_conn: u32,
+    ) -> Result<(), ()> {
+        if max_conn == 0 {
+            return Err(());
+        }
         match count_table.entry(ip) {
             Entry::Occupied(mut o) => {
                 let count = o.get_mut();
-                if *count >= single_ip_max_conn {
-                    Err(ConnectionInitError::IpMaxConnExceed)
+                if *count >= max_conn {
+                    Err(())
                 } else {
                     *count += 1;
                     Ok(())
@@ -428,3 +754,219 @@ impl ConnectionStats {
         }
     }
 }
+
+#[cfg(test)]
+mod test_connection_stats {
+    use super::*;
+
+    #[test]
+    fn test_user_max_conn() {
+        let stats = ConnectionStats::with_user_max_conn(2);
+        let mut stats = block_on(stats.lock());
+        assert_eq!(stats.add_user(100), Ok(()));
+        assert_eq!(stats.add_user(100), Ok(()));
+        assert_eq!(
+            stats.add_user(100),
+            Err(ConnectionInitError::UserMaxConnExceeded)
+        );
+        // other users are not affected
+        assert_eq!(stats.add_user(200), Ok(()));
+        // a slot frees up on disconnection
+        stats.remove_user(100);
+        assert_eq!(stats.add_user(100), Ok(()));
+        assert_eq!(
+            stats.add_user(100),
+            Err(ConnectionInitError::UserMaxConnExceeded)
+        );
+    }
+
+    #[test]
+    fn test_server_max_conn() {
+        let stats = ConnectionStats::with_server_max_conn(1, DEFAULT_REJECTION_LIMIT, 2);
+        let mut stats = block_on(stats.lock());
+        let first = SocketAddr::from(([192, 168, 1, 7], 5000));
+        let second = SocketAddr::from(([192, 168, 1, 8], 5000));
+        let third = SocketAddr::from(([192, 168, 1, 9], 5000));
+        assert_eq!(stats.add_conn(first, 64), Ok(()));
+        assert_eq!(stats.add_conn(second, 64), Ok(()));
+        // the ceiling holds across addresses
+        assert_eq!(
+            stats.add_conn(third, 64),
+            Err(ConnectionInitError::ServerAtCapacity)
+        );
+        // a slot frees up on disconnection
+        stats.remove_conn(first);
+        assert_eq!(stats.add_conn(third, 64), Ok(()));
+        assert_eq!(
+            stats.add_conn(first, 64),
+            Err(ConnectionInitError::ServerAtCapacity)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_online_players {
+    use super::*;
+    use async_std::channel::bounded;
+
+    fn name_dict(count: usize) -> Mutex<HashMap<String, Sender<Responses>>> {
+        let (sender, _) = bounded(1);
+        Mutex::new(
+            (0..count)
+                .map(|i| (format!("player{i}"), sender.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_search_names() {
+        let dict = name_dict(300);
+        let names = block_on(online_names(&dict));
+        assert_eq!(names.len(), 300);
+        let mut found = filter_names(names.clone(), Some("player29"), 100);
+        found.sort();
+        let expected: Vec<_> = ["player29"]
+            .into_iter()
+            .map(String::from)
+            .chain((290..300).map(|i| format!("player{i}")))
+            .collect();
+        assert_eq!(found, expected);
+        assert_eq!(filter_names(names.clone(), Some("player29"), 3).len(), 3);
+        assert!(filter_names(names.clone(), Some("nobody"), 100).is_empty());
+        // capped without a name
+        assert_eq!(
+            filter_names(names, None, 1000).len(),
+            MAX_PLAYER_SEARCH_RESULT_COUNT
+        );
+    }
+
+    #[test]
+    fn test_search_query_validation() {
+        assert!(valid_query(None));
+        assert!(valid_query(Some("player29")));
+        assert!(valid_query(Some(&"巴".repeat(10))));
+        assert!(!valid_query(Some(&"巴".repeat(11))));
+        assert!(!valid_query(Some(&"a".repeat(10_000))));
+        assert!(!valid_query(Some("player\n")));
+        assert!(!valid_query(Some("\u{0}")));
+        let dict = name_dict(20);
+        let names = block_on(online_names(&dict));
+        assert_eq!(filter_names(names, Some("player1"), 20).len(), 11);
+    }
+
+    #[test]
+    fn test_lock_held_briefly() {
+        let dict = name_dict(100_000);
+        let start = Instant::now();
+        let names = block_on(online_names(&dict));
+        let held = start.elapsed();
+        // filtering runs without the lock
+        let guard = dict.try_lock();
+        assert!(guard.is_some());
+        assert_eq!(filter_names(names, Some("player9999"), 20).len(), 11);
+        drop(guard);
+        assert!(held < Duration::from_secs(1), "lock held for {:?}", held);
+    }
+}
+
+#[cfg(test)]
+mod test_rejections {
+    use super::*;
+
+    #[test]
+    fn test_rejected_burst() {
+        let address = SocketAddr::from(([192, 168, 1, 7], 5000));
+        let mut rejections = Rejections::new(address, 3, Duration::from_secs(10));
+        rejections.player_id = Some(42);
+        let login = Messages::Login("小雨".to_string(), "secret-password".to_string());
+        block_on(async {
+            for _ in 0..3 {
+                assert!(rejections.reject(&login).await);
+            }
+            // the burst exceeds the limit
+            assert!(!rejections.reject(&login).await);
+            assert!(!rejections.clone().reject(&Messages::Ready).await);
+        });
+        assert_eq!(block_on(rejections.limit.lock()).count(), 3);
+        let report = rejections.report(login.kind(), 3);
+        assert!(report.contains("Login"));
+        assert!(report.contains("player 42"));
+        assert!(report.contains("192.168.1.7:5000"));
+        assert!(!report.contains("secret-password"));
+        assert!(!report.contains("小雨"));
+    }
+}
+
+#[cfg(test)]
+mod test_coalescing {
+    use super::*;
+    use crate::game::Color;
+
+    fn forward(window_millis: u64) -> (Sender<Responses>, Receiver<Responses>) {
+        let (sender, responses) = bounded(CHANNEL_SIZE);
+        let (inner, received) = bounded(CHANNEL_SIZE);
+        let coalescing = Arc::new(AtomicU64::new(window_millis));
+        task::spawn(forward_responses(responses, inner, coalescing));
+        (sender, received)
+    }
+
+    #[test]
+    fn test_responses_within_window_batched() {
+        let (sender, received) = forward(50);
+        block_on(async {
+            sender.send(Responses::GameSeed(1)).await.unwrap();
+            sender
+                .send(Responses::GameStarted(Color::Black))
+                .await
+                .unwrap();
+            sender.send(Responses::OpponentReady).await.unwrap();
+            let batch = timeout(Duration::from_secs(1), received.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            // decoded from a single frame, in order
+            let frame: Vec<u8> = batch.into();
+            assert_eq!(
+                Responses::try_from(frame).unwrap(),
+                Responses::Batch(vec![
+                    Responses::GameSeed(1),
+                    Responses::GameStarted(Color::Black),
+                    Responses::OpponentReady,
+                ])
+            );
+            // a response after the window is not batched
+            task::sleep(Duration::from_millis(100)).await;
+            sender.send(Responses::OpponentUnready).await.unwrap();
+            assert_eq!(received.recv().await.unwrap(), Responses::OpponentUnready);
+        });
+    }
+
+    #[test]
+    fn test_coalescing_off() {
+        let (sender, received) = forward(0);
+        block_on(async {
+            for seed in 0..3 {
+                sender.send(Responses::GameSeed(seed)).await.unwrap();
+            }
+            for seed in 0..3 {
+                assert_eq!(received.recv().await.unwrap(), Responses::GameSeed(seed));
+            }
+        });
+    }
+
+    #[test]
+    fn test_batch_len_bounded() {
+        let (sender, received) = forward(50);
+        block_on(async {
+            for seed in 0..MAX_BATCH_LEN as u64 + 1 {
+                sender.send(Responses::GameSeed(seed)).await.unwrap();
+            }
+            match received.recv().await.unwrap() {
+                Responses::Batch(batch) => assert_eq!(batch.len(), MAX_BATCH_LEN),
+                rsp => panic!("unexpected {:?}", rsp),
+            }
+            let last = Responses::GameSeed(MAX_BATCH_LEN as u64);
+            assert_eq!(received.recv().await.unwrap(), last);
+        });
+    }
+}
@@ -1,20 +1,25 @@
 use crate::game::Color::{Black, White};
 use crate::game::{
-    new_session, Color, Commands, GameQuitResponse, GameResult, PlayerQuitReason, PlayerResponse,
-    SessionConfig, UndoResponse,
+    new_seeded_session_with_view, Color, Commands, GameQuitResponse, GameResult, GameView,
+    PlayerQuitReason, PlayerResponse, RedoResponse, SessionConfig, UndoResponse,
 };
-use crate::lobby::client_connection::ClientConnection;
+use crate::lobby::client_connection::{send_or_timeout, ClientConnection, Rejections};
 use crate::lobby::messages::{Messages, Responses};
+use crate::lobby::telemetry::{GameOutcome, Telemetry, TelemetryEvent};
 use crate::stream_utility::Plug;
 use crate::CHANNEL_SIZE;
 use async_std::channel::{bounded, Receiver, Sender};
+use async_std::future::{timeout, TimeoutError};
 use async_std::task;
 use async_std::task::JoinHandle;
-use futures::{select, StreamExt};
+use futures::future::join;
+use futures::{select, Future, StreamExt};
+use log::{error, warn};
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 
 pub(crate) enum ExitState {
-    ReturnRoom(ClientConnection, PlayerResult),
+    ReturnRoom(Box<ClientConnection>, PlayerResult),
     ExitGame,
 }
 
@@ -24,18 +29,92 @@ pub(crate) enum PlayerResult {
     Draw,
     Quit,
     OpponentQuit,
+    OpponentExited,
+    OpponentDisconnected,
+    /// removed by moderation, no contest
+    OpponentKicked,
+    /// a player left before any move, no contest
+    Aborted,
 }
 
-pub(crate) async fn start_game_session(
+/// start the session at once, returning the view of its game
+/// along with the future running it to the end
+///
+/// `seed` is reported to both players, and picks the opening of `OpeningBook::Random`.
+/// With `start_ack_timeout`, the game only starts once both players acknowledged it.
+pub(crate) fn start_game_session(
     session_id: u64,
-    black_player_id: u64,
-    white_player_id: u64,
     session_config: SessionConfig,
     black_player: ClientConnection,
     white_player: ClientConnection,
+    seed: u64,
+    telemetry: Telemetry,
+) -> (GameView, impl Future<Output = (ExitState, ExitState)>) {
+    let black_player_id = black_player.player_id();
+    let white_player_id = white_player.player_id();
+    telemetry.emit(TelemetryEvent::GameStarted {
+        session_id,
+        black_player_id,
+        white_player_id,
+    });
+    let view = GameView::new(session_config.board_width, session_config.board_height);
+    let session = run_game_session(
+        session_id,
+        black_player,
+        white_player,
+        session_config,
+        seed,
+        view.clone(),
+    );
+    (view, async move {
+        let (b_exit, w_exit) = session.await;
+        telemetry.emit(TelemetryEvent::GameEnded {
+            session_id,
+            outcome: game_outcome(&b_exit, &w_exit),
+        });
+        (b_exit, w_exit)
+    })
+}
+
+/// the outcome by the result of either player, the other one might have left
+fn game_outcome(black: &ExitState, white: &ExitState) -> GameOutcome {
+    use ExitState::ReturnRoom;
+    match (black, white) {
+        (ReturnRoom(_, PlayerResult::Win), _) | (_, ReturnRoom(_, PlayerResult::Lose)) => {
+            GameOutcome::BlackWins
+        }
+        (ReturnRoom(_, PlayerResult::Lose), _) | (_, ReturnRoom(_, PlayerResult::Win)) => {
+            GameOutcome::WhiteWins
+        }
+        (ReturnRoom(_, PlayerResult::Draw), _) | (_, ReturnRoom(_, PlayerResult::Draw)) => {
+            GameOutcome::Draw
+        }
+        (ReturnRoom(_, PlayerResult::Aborted), _) | (_, ReturnRoom(_, PlayerResult::Aborted)) => {
+            GameOutcome::Aborted
+        }
+        _ => GameOutcome::Abandoned,
+    }
+}
+
+/// `TimeControl` of the session, so that clients know how to render the clocks
+pub(crate) fn time_control(session_config: &SessionConfig) -> Responses {
+    Responses::TimeControl {
+        play_timeout: session_config.play_timeout,
+        undo_request_timeout: session_config.undo_request_timeout,
+        undo_dialogue_extra_seconds: session_config.undo_dialogue_extra_seconds,
+        final_undo_window: session_config.final_undo_window,
+    }
+}
+
+async fn run_game_session(
+    session_id: u64,
+    black_player: ClientConnection,
+    white_player: ClientConnection,
+    session_config: SessionConfig,
+    seed: u64,
+    view: GameView,
 ) -> (ExitState, ExitState) {
-    let (black_cmd, white_cmd) =
-        new_session(session_id, black_player_id, white_player_id, session_config);
+    let time_control = time_control(&session_config);
     let (b_chat_s, b_chat_r) = bounded(CHANNEL_SIZE);
     let (w_chat_s, w_chat_r) = bounded(CHANNEL_SIZE);
     // send start messages
@@ -47,25 +126,101 @@ pub(crate) async fn start_game_session(
         .sender()
         .send(Responses::GameStarted(White))
         .await;
+    for player in [&black_player, &white_player] {
+        let _ = player.sender().send(Responses::GameSeed(seed)).await;
+        let _ = player.sender().send(time_control.clone()).await;
+    }
+    let ack_timeout = session_config.start_ack_timeout;
+    let (black_player, white_player) =
+        match acknowledge_start(black_player, white_player, ack_timeout).await {
+            Ok(players) => players,
+            Err(exits) => return exits,
+        };
+    // no clock runs before the session starts
+    let (black_cmd, white_cmd, _) = new_seeded_session_with_view(
+        session_id,
+        black_player.player_id(),
+        white_player.player_id(),
+        session_config.clone(),
+        seed,
+        view,
+    );
     let b_exit = connect_player_game(
-        black_player_id,
+        black_player.player_id(),
         black_player,
         black_cmd,
         b_chat_r,
         &w_chat_s,
         Black,
+        session_config.clone(),
     );
     let w_exit = connect_player_game(
-        white_player_id,
+        white_player.player_id(),
         white_player,
         white_cmd,
         w_chat_r,
         &b_chat_s,
         White,
+        session_config,
     );
     (b_exit.await, w_exit.await)
 }
 
+/// wait up to `seconds` for both players to answer `GameStarted` with `Ack`,
+/// otherwise abort the game, 0 means no wait
+async fn acknowledge_start(
+    mut black_player: ClientConnection,
+    mut white_player: ClientConnection,
+    seconds: u64,
+) -> Result<(ClientConnection, ClientConnection), (ExitState, ExitState)> {
+    if seconds == 0 {
+        return Ok((black_player, white_player));
+    }
+    let deadline = Duration::from_secs(seconds);
+    let (black_ack, white_ack) = join(
+        timeout(deadline, wait_for_ack(&mut black_player)),
+        timeout(deadline, wait_for_ack(&mut white_player)),
+    )
+    .await;
+    if let (Ok(true), Ok(true)) = (&black_ack, &white_ack) {
+        return Ok((black_player, white_player));
+    }
+    warn!(
+        "game of players {} and {} aborted without acknowledgment",
+        black_player.player_id(),
+        white_player.player_id()
+    );
+    Err((
+        abort_start(black_player, black_ack).await,
+        abort_start(white_player, white_ack).await,
+    ))
+}
+
+/// players who left exit the game, the others return to the room
+async fn abort_start(player: ClientConnection, ack: Result<bool, TimeoutError>) -> ExitState {
+    if let Ok(false) = ack {
+        return ExitState::ExitGame;
+    }
+    let _ = send_or_timeout(player.sender(), Responses::GameEndAborted).await;
+    ExitState::ReturnRoom(Box::new(player), PlayerResult::Aborted)
+}
+
+/// `false` if the player left, or sent too many other messages, before `Ack`
+async fn wait_for_ack(player: &mut ClientConnection) -> bool {
+    while let Some(msg) = player.next().await {
+        match msg {
+            Messages::Ack => return true,
+            Messages::ExitGame => return false,
+            msg => {
+                if !player.rejections().reject(&msg).await {
+                    return false;
+                }
+            }
+        }
+    }
+    false
+}
+
 /// this function connects a `ClientConnection` with `Commands`.
 fn connect_player_game(
     player_id: u64,
@@ -74,16 +229,20 @@ fn connect_player_game(
     chat_receiver: Receiver<(String, String)>,
     chat_sender: &Sender<(String, String)>,
     color: Color,
+    session_config: SessionConfig,
 ) -> JoinHandle<ExitState> {
     let session_rsp = command.get_listener().unwrap();
     let player_sender = player.sender().clone();
     let player_chat_sender = player.sender().clone();
     let player_name = player.player_name().to_string();
+    let rejections = player.rejections().clone();
+    let normalization = player.normalization();
     let chat_sender = chat_sender.clone();
     let (mut chat_receiver, chat_stopper) = Plug::new(chat_receiver);
     // send chat messages
     task::spawn(async move {
         while let Some((name, msg)) = chat_receiver.next().await {
+            let msg = normalization.normalize_chat(msg);
             let _ = player_chat_sender
                 .send(Responses::ChatMessage(name, msg))
                 .await;
@@ -96,16 +255,30 @@ fn connect_player_game(
         loop {
             let next_step = select! {
                 cmd = player.next() => {
-                    handle_command(cmd, &command, &player_name, &chat_sender).await
+                    // closing the connection cleanly is a deliberate exit
+                    let cmd = match cmd {
+                        None if player.get_ref().closed_cleanly() => Some(Messages::ExitGame),
+                        cmd => cmd,
+                    };
+                    handle_command(
+                        cmd,
+                        &command,
+                        &session_config,
+                        &player_name,
+                        &player_sender,
+                        &chat_sender,
+                        &rejections,
+                    )
+                    .await
                 }
                 rsp = session.next() => {
-                    handle_session_response(player_id, rsp, &player_sender, color).await
+                    handle_session_response(player_id, rsp, &command, &player_sender, color).await
                 }
             } as NextStep;
             match next_step {
                 NextStep::EnterLobby(result) => {
                     let _ = chat_stopper.unplug().await;
-                    break ExitState::ReturnRoom(player.into_inner(), result);
+                    break ExitState::ReturnRoom(Box::new(player.into_inner()), result);
                 }
                 NextStep::ExitGame => {
                     let _ = chat_stopper.unplug().await;
@@ -126,19 +299,37 @@ enum NextStep {
 async fn handle_command(
     msg: Option<Messages>,
     command: &Commands,
+    session_config: &SessionConfig,
     player_name: &str,
+    player_sender: &Sender<Responses>,
     chat_sender: &Sender<(String, String)>,
+    rejections: &Rejections,
 ) -> NextStep {
     if let Some(msg) = msg {
         match msg {
-            Messages::Play(x, y) => command.play(x, y).await,
+            Messages::Play(x, y) => {
+                if session_config.on_board(x, y) {
+                    command.play(x, y).await
+                } else {
+                    // reject out of bound positions without a round-trip to the game
+                    let _ = send_or_timeout(player_sender, Responses::InvalidMove(x, y)).await;
+                }
+            }
+            Messages::ConfirmMove => command.confirm_move().await,
+            Messages::CancelMove => command.cancel_move().await,
+            Messages::SetConfirmMoves(on) => command.set_confirm_moves(on).await,
+            // acknowledged late, or not waited for
+            Messages::Ack => {}
             Messages::RequestUndo => command.request_undo().await,
             Messages::ApproveUndo => command.approve_undo().await,
             Messages::RejectUndo => command.reject_undo().await,
+            Messages::RequestRedo => command.request_redo().await,
+            Messages::ApproveRedo => command.approve_redo().await,
+            Messages::RejectRedo => command.reject_redo().await,
             Messages::ChatMessage(msg) => {
                 let _ = chat_sender.send((player_name.to_string(), msg)).await;
             }
-            Messages::QuitGameSession => {
+            Messages::QuitGameSession | Messages::Leave => {
                 command.quit(PlayerQuitReason::QuitSession).await;
                 return NextStep::EnterLobby(PlayerResult::Quit);
             }
@@ -146,11 +337,20 @@ async fn handle_command(
                 command.quit(PlayerQuitReason::ExitGame).await;
                 return NextStep::ExitGame;
             }
-            Messages::ClientError(e) => {
-                command.quit(PlayerQuitReason::Error(e)).await;
-                return NextStep::ExitGame;
+            Messages::ClientError { fatal, message } => {
+                if fatal {
+                    command.quit(PlayerQuitReason::Error(message)).await;
+                    return NextStep::ExitGame;
+                }
+                warn!("player {} non-fatal client error {}", player_name, message);
+                let _ = send_or_timeout(player_sender, Responses::ClientErrorAcknowledged).await;
+            }
+            msg => {
+                if !rejections.reject(&msg).await {
+                    command.quit(PlayerQuitReason::Disconnected).await;
+                    return NextStep::ExitGame;
+                }
             }
-            _ => {}
         };
         NextStep::Continue
     } else {
@@ -160,104 +360,230 @@ async fn handle_command(
     }
 }
 
-// do not need to handle disconnection at sending message
+// do not need to handle disconnection at sending message,
+// but slow clients are considered disconnected
 async fn handle_session_response(
     my_id: u64,
     rsp: Option<PlayerResponse>,
+    command: &Commands,
     player_sender: &Sender<Responses>,
     color: Color,
 ) -> NextStep {
     match rsp {
         Some(rsp) => {
-            let _ = match rsp {
+            let sent = match rsp {
                 PlayerResponse::FieldUpdate(f) => {
-                    player_sender.send(Responses::FieldUpdate(f)).await
+                    send_or_timeout(player_sender, Responses::FieldUpdate(f)).await
+                }
+                PlayerResponse::MovePending(x, y) => {
+                    send_or_timeout(player_sender, Responses::MovePending(x, y)).await
+                }
+                PlayerResponse::InvalidMove(x, y) => {
+                    send_or_timeout(player_sender, Responses::InvalidMove(x, y)).await
+                }
+                PlayerResponse::MoveCancelled => {
+                    send_or_timeout(player_sender, Responses::MoveCancelled).await
+                }
+                PlayerResponse::NotYourTurn => {
+                    send_or_timeout(player_sender, Responses::NotYourTurn).await
+                }
+                PlayerResponse::MovingTooFast => {
+                    send_or_timeout(player_sender, Responses::MovingTooFast).await
+                }
+                PlayerResponse::UndoRequest => {
+                    send_or_timeout(player_sender, Responses::UndoRequest).await
+                }
+                PlayerResponse::GameEndPending(field, result) => {
+                    send_or_timeout(player_sender, Responses::GameEndPending { result, field })
+                        .await
                 }
-                PlayerResponse::UndoRequest => player_sender.send(Responses::UndoRequest).await,
                 PlayerResponse::Undo(u_rsp) => match u_rsp {
                     UndoResponse::TimeoutRejected => {
-                        player_sender.send(Responses::UndoTimeoutRejected).await
+                        send_or_timeout(player_sender, Responses::UndoTimeoutRejected).await
+                    }
+                    UndoResponse::Undo(f) => {
+                        send_or_timeout(player_sender, Responses::Undo(f)).await
                     }
-                    UndoResponse::Undo(f) => player_sender.send(Responses::Undo(f)).await,
                     UndoResponse::RejectedByOpponent => {
-                        player_sender.send(Responses::UndoRejectedByOpponent).await
+                        send_or_timeout(player_sender, Responses::UndoRejectedByOpponent).await
                     }
                     UndoResponse::AutoRejected => {
-                        player_sender.send(Responses::UndoAutoRejected).await
+                        send_or_timeout(player_sender, Responses::UndoAutoRejected).await
+                    }
+                    UndoResponse::RequestInProgress => {
+                        send_or_timeout(player_sender, Responses::UndoRequestInProgress).await
+                    }
+                },
+                PlayerResponse::RedoRequest => {
+                    send_or_timeout(player_sender, Responses::RedoRequest).await
+                }
+                PlayerResponse::Redo(r_rsp) => match r_rsp {
+                    RedoResponse::TimeoutRejected => {
+                        send_or_timeout(player_sender, Responses::RedoTimeoutRejected).await
+                    }
+                    RedoResponse::Redo(f) => {
+                        send_or_timeout(player_sender, Responses::Redo(f)).await
+                    }
+                    RedoResponse::RejectedByOpponent => {
+                        send_or_timeout(player_sender, Responses::RedoRejectedByOpponent).await
+                    }
+                    RedoResponse::AutoRejected => {
+                        send_or_timeout(player_sender, Responses::RedoAutoRejected).await
+                    }
+                    RedoResponse::RequestInProgress => {
+                        send_or_timeout(player_sender, Responses::RedoRequestInProgress).await
                     }
                 },
+                PlayerResponse::TurnPassed(color) => {
+                    send_or_timeout(player_sender, Responses::TurnPassed(color)).await
+                }
+                PlayerResponse::GameSummary(summary) => {
+                    let summary = Responses::GameSummary {
+                        moves: summary.moves,
+                        duration_ms: summary.duration.as_millis() as u64,
+                        black_time_ms: summary.black_time.as_millis() as u64,
+                        white_time_ms: summary.white_time.as_millis() as u64,
+                        result: summary.result,
+                    };
+                    send_or_timeout(player_sender, summary).await
+                }
                 PlayerResponse::Quit(q) => {
                     return match q {
                         GameQuitResponse::GameEnd(end) => match end {
                             GameResult::BlackTimeout => {
-                                let _ = player_sender.send(Responses::GameEndBlackTimeout).await;
+                                let _ =
+                                    send_or_timeout(player_sender, Responses::GameEndBlackTimeout)
+                                        .await;
                                 match color {
                                     Black => NextStep::EnterLobby(PlayerResult::Lose),
                                     White => NextStep::EnterLobby(PlayerResult::Win),
                                 }
                             }
                             GameResult::WhiteTimeout => {
-                                let _ = player_sender.send(Responses::GameEndWhiteTimeout).await;
+                                let _ =
+                                    send_or_timeout(player_sender, Responses::GameEndWhiteTimeout)
+                                        .await;
                                 match color {
                                     Black => NextStep::EnterLobby(PlayerResult::Win),
                                     White => NextStep::EnterLobby(PlayerResult::Lose),
                                 }
                             }
                             GameResult::BlackWins => {
-                                let _ = player_sender.send(Responses::GameEndBlackWins).await;
+                                let _ = send_or_timeout(player_sender, Responses::GameEndBlackWins)
+                                    .await;
                                 match color {
                                     Black => NextStep::EnterLobby(PlayerResult::Win),
                                     White => NextStep::EnterLobby(PlayerResult::Lose),
                                 }
                             }
                             GameResult::WhiteWins => {
-                                let _ = player_sender.send(Responses::GameEndWhiteWins).await;
+                                let _ = send_or_timeout(player_sender, Responses::GameEndWhiteWins)
+                                    .await;
                                 match color {
                                     Black => NextStep::EnterLobby(PlayerResult::Lose),
                                     White => NextStep::EnterLobby(PlayerResult::Win),
                                 }
                             }
                             GameResult::Draw => {
-                                let _ = player_sender.send(Responses::GameEndDraw).await;
+                                let _ =
+                                    send_or_timeout(player_sender, Responses::GameEndDraw).await;
                                 NextStep::EnterLobby(PlayerResult::Draw)
                             }
+                            GameResult::Aborted => {
+                                let _ =
+                                    send_or_timeout(player_sender, Responses::GameEndAborted).await;
+                                NextStep::EnterLobby(PlayerResult::Aborted)
+                            }
                         },
+                        GameQuitResponse::GameEndWithBoard(result, field) => {
+                            let player_result = match (&result, color) {
+                                (GameResult::Draw, _) => PlayerResult::Draw,
+                                (GameResult::Aborted, _) => PlayerResult::Aborted,
+                                (GameResult::BlackWins | GameResult::WhiteTimeout, Black)
+                                | (GameResult::WhiteWins | GameResult::BlackTimeout, White) => {
+                                    PlayerResult::Win
+                                }
+                                _ => PlayerResult::Lose,
+                            };
+                            let _ = send_or_timeout(
+                                player_sender,
+                                Responses::GameEndWithBoard { result, field },
+                            )
+                            .await;
+                            NextStep::EnterLobby(player_result)
+                        }
                         GameQuitResponse::PlayerQuitSession(id) => {
                             if id == my_id {
-                                let _ = player_sender.send(Responses::QuitGameSessionSuccess).await;
+                                let _ = send_or_timeout(
+                                    player_sender,
+                                    Responses::QuitGameSessionSuccess,
+                                )
+                                .await;
                             } else {
-                                let _ =
-                                    player_sender.send(Responses::OpponentQuitGameSession).await;
+                                let _ = send_or_timeout(
+                                    player_sender,
+                                    Responses::OpponentQuitGameSession,
+                                )
+                                .await;
                             }
                             // enter lobby on opponent quit
                             NextStep::EnterLobby(PlayerResult::OpponentQuit)
                         }
                         GameQuitResponse::OpponentExitGame(_) => {
-                            let _ = player_sender.send(Responses::OpponentExitGame).await;
-                            NextStep::EnterLobby(PlayerResult::OpponentQuit)
+                            let _ =
+                                send_or_timeout(player_sender, Responses::OpponentExitGame).await;
+                            NextStep::EnterLobby(PlayerResult::OpponentExited)
                         }
                         GameQuitResponse::OpponentDisconnected(_) => {
-                            let _ = player_sender.send(Responses::OpponentDisconnected).await;
-                            NextStep::EnterLobby(PlayerResult::OpponentQuit)
+                            let _ = send_or_timeout(player_sender, Responses::OpponentDisconnected)
+                                .await;
+                            NextStep::EnterLobby(PlayerResult::OpponentDisconnected)
+                        }
+                        GameQuitResponse::OpponentKicked(_, reason) => {
+                            let _ =
+                                send_or_timeout(player_sender, Responses::OpponentKicked(reason))
+                                    .await;
+                            NextStep::EnterLobby(PlayerResult::OpponentKicked)
                         }
                         GameQuitResponse::OpponentError(_, e) => {
-                            let _ = player_sender
-                                .send(Responses::GameSessionError(format!("player error: {}", e)))
-                                .await;
+                            let _ = send_or_timeout(
+                                player_sender,
+                                Responses::GameSessionError(format!("player error: {}", e)),
+                            )
+                            .await;
                             NextStep::EnterLobby(PlayerResult::OpponentQuit)
                         }
                         GameQuitResponse::GameError(e) => {
-                            let _ = player_sender
-                                .send(Responses::GameSessionError(format!("game error: {}", e)))
-                                .await;
+                            let _ = send_or_timeout(
+                                player_sender,
+                                Responses::GameSessionError(format!("game error: {}", e)),
+                            )
+                            .await;
                             NextStep::ExitGame
                         }
                     };
                 }
             };
+            if sent.is_err() {
+                warn!(
+                    "player {} response channel full, considered disconnected",
+                    my_id
+                );
+                command.quit(PlayerQuitReason::Disconnected).await;
+                return NextStep::ExitGame;
+            }
             NextStep::Continue
         }
-        None => unreachable!(),
+        None => {
+            // session tasks died without a quit response
+            error!("game session of player {} ended unexpectedly", my_id);
+            let _ = send_or_timeout(
+                player_sender,
+                Responses::GameSessionError("game session ended unexpectedly".to_string()),
+            )
+            .await;
+            NextStep::ExitGame
+        }
     }
 }
 
@@ -269,6 +595,365 @@ impl Display for PlayerResult {
             PlayerResult::Draw => "draw",
             PlayerResult::Quit => "quit",
             PlayerResult::OpponentQuit => "opponent_quit",
+            PlayerResult::OpponentExited => "opponent_exited",
+            PlayerResult::OpponentDisconnected => "opponent_disconnected",
+            PlayerResult::OpponentKicked => "opponent_kicked",
+            PlayerResult::Aborted => "aborted",
         })
     }
 }
+
+#[cfg(test)]
+mod test_game_session {
+    use super::*;
+    use crate::game::{new_session, new_session_with_handle};
+    use futures::executor::block_on;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    fn rejections(limit: usize) -> Rejections {
+        let address = SocketAddr::from(([127, 0, 0, 1], 5000));
+        Rejections::new(address, limit, Duration::from_secs(10))
+    }
+
+    #[test]
+    fn test_time_control() {
+        let config = SessionConfig::builder()
+            .play_timeout(30)
+            .undo_request_timeout(10)
+            .undo_dialogue_extra_seconds(5)
+            .final_undo_window(3)
+            .build();
+        assert_eq!(
+            time_control(&config),
+            Responses::TimeControl {
+                play_timeout: 30,
+                undo_request_timeout: 10,
+                undo_dialogue_extra_seconds: 5,
+                final_undo_window: 3,
+            }
+        );
+        assert_eq!(
+            time_control(&SessionConfig::default()),
+            Responses::TimeControl {
+                play_timeout: 0,
+                undo_request_timeout: 0,
+                undo_dialogue_extra_seconds: 0,
+                final_undo_window: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_non_fatal_client_error() {
+        let (black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let (player_sender, mut player_receiver) = bounded(CHANNEL_SIZE);
+        let (chat_sender, _chat_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let msg = Messages::ClientError {
+                fatal: false,
+                message: "render error".to_string(),
+            };
+            let next_step = handle_command(
+                Some(msg),
+                &black,
+                &SessionConfig::default(),
+                "black",
+                &player_sender,
+                &chat_sender,
+                &rejections(20),
+            )
+            .await;
+            assert!(matches!(next_step, NextStep::Continue));
+            assert_eq!(
+                player_receiver.next().await,
+                Some(Responses::ClientErrorAcknowledged)
+            );
+        });
+    }
+
+    #[test]
+    fn test_out_of_bound_play() {
+        let (mut black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let listener = black.get_listener().unwrap();
+        let (player_sender, mut player_receiver) = bounded(CHANNEL_SIZE);
+        let (chat_sender, _chat_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let msg = Messages::Play(15, 3);
+            let next_step = handle_command(
+                Some(msg),
+                &black,
+                &SessionConfig::default(),
+                "black",
+                &player_sender,
+                &chat_sender,
+                &rejections(20),
+            )
+            .await;
+            assert!(matches!(next_step, NextStep::Continue));
+            assert_eq!(
+                player_receiver.next().await,
+                Some(Responses::InvalidMove(15, 3))
+            );
+            // the game receives nothing
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(listener.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_board_size_bounds() {
+        for (size, on_board) in [(19, true), (15, false)] {
+            let config = SessionConfig::builder().board_size(size, size).build();
+            assert_eq!(config.on_board(16, 3), on_board);
+            let (mut black, _white) = new_session(1000, 100, 200, config.clone());
+            let listener = black.get_listener().unwrap();
+            let (player_sender, mut player_receiver) = bounded(CHANNEL_SIZE);
+            let (chat_sender, _chat_receiver) = bounded(CHANNEL_SIZE);
+            block_on(async {
+                let next_step = handle_command(
+                    Some(Messages::Play(16, 3)),
+                    &black,
+                    &config,
+                    "black",
+                    &player_sender,
+                    &chat_sender,
+                    &rejections(20),
+                )
+                .await;
+                assert!(matches!(next_step, NextStep::Continue));
+                if on_board {
+                    // played on the field
+                    let latest = match listener.recv().await {
+                        Ok(PlayerResponse::FieldUpdate(f)) => (f.latest.0, f.latest.1),
+                        rsp => panic!("unexpected {:?}", rsp),
+                    };
+                    assert_eq!(latest, (16, 3));
+                    assert!(player_receiver.try_recv().is_err());
+                } else {
+                    assert_eq!(
+                        player_receiver.next().await,
+                        Some(Responses::InvalidMove(16, 3))
+                    );
+                    // the game ignores the move even without the lobby check
+                    black.play(16, 3).await;
+                    task::sleep(Duration::from_millis(100)).await;
+                    assert!(listener.try_recv().is_err());
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_invalid_message_burst() {
+        let (black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let (player_sender, _player_receiver) = bounded(CHANNEL_SIZE);
+        let (chat_sender, _chat_receiver) = bounded(CHANNEL_SIZE);
+        let rejections = rejections(3);
+        block_on(async {
+            // room messages are out of context in a game session
+            for _ in 0..3 {
+                let next_step = handle_command(
+                    Some(Messages::Ready),
+                    &black,
+                    &SessionConfig::default(),
+                    "black",
+                    &player_sender,
+                    &chat_sender,
+                    &rejections,
+                )
+                .await;
+                assert!(matches!(next_step, NextStep::Continue));
+            }
+            let next_step = handle_command(
+                Some(Messages::QuitRoom),
+                &black,
+                &SessionConfig::default(),
+                "black",
+                &player_sender,
+                &chat_sender,
+                &rejections,
+            )
+            .await;
+            assert!(matches!(next_step, NextStep::ExitGame));
+        });
+    }
+
+    #[test]
+    fn test_fatal_client_error() {
+        let (black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let (player_sender, _player_receiver) = bounded(CHANNEL_SIZE);
+        let (chat_sender, _chat_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let msg = Messages::ClientError {
+                fatal: true,
+                message: "corrupted state".to_string(),
+            };
+            let next_step = handle_command(
+                Some(msg),
+                &black,
+                &SessionConfig::default(),
+                "black",
+                &player_sender,
+                &chat_sender,
+                &rejections(20),
+            )
+            .await;
+            assert!(matches!(next_step, NextStep::ExitGame));
+        });
+    }
+
+    #[test]
+    fn test_slow_player_disconnected() {
+        let (black, mut white) = new_session(1000, 100, 200, SessionConfig::default());
+        let white_listener = white.get_listener().unwrap();
+        // the black player never drains its channel
+        let (player_sender, _player_receiver) = bounded(1);
+        player_sender.try_send(Responses::UndoRequest).unwrap();
+        block_on(async {
+            // start the game, so that leaving does not abort it
+            black.play(7, 7).await;
+            assert!(matches!(
+                white_listener.recv().await,
+                Ok(PlayerResponse::FieldUpdate(_))
+            ));
+            let rsp = Some(PlayerResponse::UndoRequest);
+            let next_step = handle_session_response(100, rsp, &black, &player_sender, Black).await;
+            assert!(matches!(next_step, NextStep::ExitGame));
+            assert!(matches!(
+                white_listener.recv().await,
+                Ok(PlayerResponse::Quit(
+                    GameQuitResponse::OpponentDisconnected(100)
+                ))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_disconnect_before_any_move_aborts() {
+        let (black, mut white) = new_session(1000, 100, 200, SessionConfig::default());
+        let white_listener = white.get_listener().unwrap();
+        let (black_sender, _black_receiver) = bounded(CHANNEL_SIZE);
+        let (chat_sender, _chat_receiver) = bounded(CHANNEL_SIZE);
+        let (white_sender, mut white_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let next_step = handle_command(
+                None,
+                &black,
+                &SessionConfig::default(),
+                "black",
+                &black_sender,
+                &chat_sender,
+                &rejections(20),
+            )
+            .await;
+            assert!(matches!(next_step, NextStep::ExitGame));
+            let rsp = white_listener.recv().await.ok();
+            assert!(matches!(
+                rsp,
+                Some(PlayerResponse::Quit(GameQuitResponse::GameEnd(
+                    GameResult::Aborted
+                )))
+            ));
+            let next_step = handle_session_response(200, rsp, &white, &white_sender, White).await;
+            assert!(matches!(
+                next_step,
+                NextStep::EnterLobby(PlayerResult::Aborted)
+            ));
+            assert_eq!(white_receiver.next().await, Some(Responses::GameEndAborted));
+        });
+    }
+
+    #[test]
+    fn test_exit_and_disconnection_distinguished() {
+        let (black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let (player_sender, _player_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let rsp = Some(PlayerResponse::Quit(GameQuitResponse::OpponentExitGame(
+                200,
+            )));
+            let next_step = handle_session_response(100, rsp, &black, &player_sender, Black).await;
+            assert!(matches!(
+                next_step,
+                NextStep::EnterLobby(PlayerResult::OpponentExited)
+            ));
+            let rsp = Some(PlayerResponse::Quit(
+                GameQuitResponse::OpponentDisconnected(200),
+            ));
+            let next_step = handle_session_response(100, rsp, &black, &player_sender, Black).await;
+            assert!(matches!(
+                next_step,
+                NextStep::EnterLobby(PlayerResult::OpponentDisconnected)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_opponent_kicked() {
+        let (black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let (player_sender, mut player_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let rsp = Some(PlayerResponse::Quit(GameQuitResponse::OpponentKicked(
+                200,
+                "idle".to_string(),
+            )));
+            let next_step = handle_session_response(100, rsp, &black, &player_sender, Black).await;
+            assert!(matches!(
+                next_step,
+                NextStep::EnterLobby(PlayerResult::OpponentKicked)
+            ));
+            assert_eq!(
+                player_receiver.next().await,
+                Some(Responses::OpponentKicked("idle".to_string()))
+            );
+        });
+    }
+
+    #[test]
+    fn test_game_end_with_board() {
+        use crate::game::{FieldInner, FieldState, State};
+        let (black, _white) = new_session(1000, 100, 200, SessionConfig::default());
+        let (player_sender, mut player_receiver) = bounded(CHANNEL_SIZE);
+        let field = FieldState {
+            latest: (7, 4, White),
+            field: FieldInner(vec![vec![State::W; 15]; 15]),
+        };
+        block_on(async {
+            let rsp = Some(PlayerResponse::Quit(GameQuitResponse::GameEndWithBoard(
+                GameResult::WhiteWins,
+                field.clone(),
+            )));
+            let next_step = handle_session_response(100, rsp, &black, &player_sender, Black).await;
+            assert!(matches!(
+                next_step,
+                NextStep::EnterLobby(PlayerResult::Lose)
+            ));
+            assert_eq!(
+                player_receiver.next().await,
+                Some(Responses::GameEndWithBoard {
+                    result: GameResult::WhiteWins,
+                    field,
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn test_session_ended_unexpectedly() {
+        let (mut black, _white, handle) =
+            new_session_with_handle(1000, 100, 200, SessionConfig::default());
+        let mut listener = black.get_listener().unwrap();
+        let (player_sender, mut player_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            handle.shutdown().await;
+            let rsp = listener.next().await;
+            assert!(rsp.is_none());
+            let next_step = handle_session_response(100, rsp, &black, &player_sender, Black).await;
+            assert!(matches!(next_step, NextStep::ExitGame));
+            assert!(matches!(
+                player_receiver.next().await,
+                Some(Responses::GameSessionError(_))
+            ));
+        });
+    }
+}
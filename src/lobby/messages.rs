@@ -1,6 +1,7 @@
 //! Implementation principles.
-//! - disconnection without clear exit signal is considered as disconnection.
-use crate::game::{Color, FieldState, FieldStateNullable, SessionConfig};
+//! - disconnection without clear exit signal is considered as disconnection,
+//!   closing the connection cleanly counts as `ExitGame`.
+use crate::game::{Color, FieldInner, FieldState, FieldStateNullable, GameResult, SessionConfig};
 use crate::lobby::client_connection::ConnectionInitError;
 use crate::lobby::token::RoomToken;
 use anyhow::Error;
@@ -19,19 +20,40 @@ pub enum Messages {
     Login(String, String),
     /// update password (username, old password, new password)
     UpdateAccount(String, String, String),
-    /// create a new room
-    CreateRoom(SessionConfig),
-    /// attempt to join a room with a RoomToken
-    JoinRoom(RoomToken),
+    /// create a new room, optionally protected by a password
+    CreateRoom(SessionConfig, Option<String>),
+    /// attempt to join a room with a RoomToken and the room password if any
+    JoinRoom(RoomToken, Option<String>),
+    /// preview a room with a RoomToken without joining it
+    QueryRoom(RoomToken),
+    /// observe the game in a room once, without joining the room,
+    /// with the room password if any
+    PeekGame(RoomToken, Option<String>),
+    /// receive `RoomListUpdate` when rooms are created, filled, or removed
+    SubscribeLobby,
+    /// stop receiving `RoomListUpdate`
+    UnsubscribeLobby,
+    /// (admin only) dump the internal state of a room
+    DebugRoom(RoomToken),
     /// Quit a room
     QuitRoom,
+    /// (room creator only) eject a player by username and block them from rejoining the room
+    BanFromRoom(String),
     /// when in a Room, get ready for a game session
     Ready,
     /// reverse `ready`
     Unready,
-    /// play a position in game [0, 15). Out of bounds are ignored.
+    /// play a position `(x, y)` in game, `x` in [0, board_width) and `y` in [0, board_height).
+    /// Out of bounds are rejected with `InvalidMove`.
     /// Repeatedly playing on an occupied position will result in `GameError`.
     Play(u8, u8),
+    /// commit the pending move in confirm-moves mode.
+    ConfirmMove,
+    /// discard the pending move in confirm-moves mode.
+    CancelMove,
+    /// turn confirm-moves mode on or off in game, `Play` then answers with `MovePending`.
+    /// Any other action, or playing the pending position again, confirms the move.
+    SetConfirmMoves(bool),
     /// request undo in game.
     RequestUndo,
     /// approve undo requests in game.
@@ -48,8 +70,33 @@ pub enum Messages {
     /// exit game (quit game and room), close connection
     /// exiting game without sending `ExitGame` signal is considered `Disconnected`
     ExitGame,
-    /// client error: other errors excluding network error
-    ClientError(String),
+    /// client error: other errors excluding network error.
+    /// fatal errors end the game and close the connection,
+    /// non-fatal errors are only logged and acknowledged.
+    ClientError {
+        fatal: bool,
+        message: String,
+    },
+    /// when in a Room, propose the config of the next games.
+    /// It takes effect once the opponent proposes the same config,
+    /// or immediately when alone in the room.
+    UpdateRoomConfig(SessionConfig),
+    /// request redo in game, on my turn right after my move was undone.
+    RequestRedo,
+    /// approve redo requests in game.
+    ApproveRedo,
+    /// reject redo requests in game.
+    RejectRedo,
+    /// leave the current context: quit the game session if in one
+    /// (back in the room with `JoinRoomSuccess`), else quit the room (`QuitRoomSuccess`),
+    /// else close the connection (`ConnectionClosing`)
+    Leave,
+    /// batch responses following each other within a window of milliseconds,
+    /// at most 100, into `Batch`, 0 to turn batching off (the default)
+    CoalesceResponses(u16),
+    /// acknowledge `GameStarted` once the board is shown,
+    /// the first clock waits for it with `start_ack_timeout`
+    Ack,
 }
 
 #[derive(Clone, PartialEq, Debug, Encode, Decode)]
@@ -59,6 +106,26 @@ pub enum RoomState {
     OpponentUnready(String),
 }
 
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub enum RoomStatus {
+    /// newly created
+    Open,
+    /// both seats taken
+    Full,
+    /// removed by the server
+    Closed,
+}
+
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub struct RoomSummary {
+    /// room token code
+    pub token: String,
+    /// number of players in the room
+    pub occupants: u8,
+    pub session_config: SessionConfig,
+    pub status: RoomStatus,
+}
+
 #[derive(Clone, Debug, PartialEq, Encode, Decode)]
 pub enum InvalidAccountPassword {
     BadCharacterAccountName,
@@ -67,6 +134,8 @@ pub enum InvalidAccountPassword {
     BadCharacterAccountPassword,
     PasswordTooShort,
     PasswordTooLong,
+    /// the name is rejected by the name filter of the server
+    NameNotAllowed,
 }
 
 #[derive(Clone, Debug, PartialEq, Encode, Decode)]
@@ -85,6 +154,20 @@ pub enum UpdatePasswordFailure {
     ServerError,
 }
 
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub enum JoinRoomFailure {
+    /// no room with the token, also for `QueryRoom`, `PeekGame` and `DebugRoom`
+    TokenNotFound,
+    /// both seats are taken
+    RoomFull,
+    /// the room is password protected, and the password is missing or wrong
+    BadPassword,
+    /// the player was banned by the room creator
+    Banned,
+    /// the room of the token closed recently, also for `QueryRoom`, `PeekGame` and `DebugRoom`
+    RoomClosed,
+}
+
 #[derive(Clone, Debug, PartialEq, Encode, Decode)]
 pub enum LoginFailure {
     BadInput(InvalidAccountPassword),
@@ -113,21 +196,51 @@ pub enum Responses {
     ConnectionInitFailure(ConnectionInitError),
     /// response to `CreateRoom`
     RoomCreated(String),
+    /// response to `CreateRoom`, too many rooms created recently
+    RoomCreationRateLimited,
+    /// the server is draining: no room can be created,
+    /// room occupants should finish the game and leave
+    ServerDraining,
+    /// the room is closed by the server, and the connection is closing,
+    /// or the creator left an owner bound room, and I am back in the lobby
+    RoomClosed,
     /// response to `SearchOnlinePlayers`
     PlayerList(Vec<String>),
     /// response to `JoinRoom`
     /// the two fields are correspondingly
     /// `room` token
     JoinRoomSuccess(String, RoomState),
-    /// response to `JoinRoom`
-    JoinRoomFailureTokenNotFound,
-    /// response to `JoinRoom`
-    JoinRoomFailureRoomFull,
+    /// response to `JoinRoom`, or `QueryRoom` with an unknown token
+    JoinRoomFailure(JoinRoomFailure),
+    /// response to `QueryRoom`
+    RoomInfo {
+        /// usernames of players in the room
+        occupants: Vec<String>,
+        session_config: SessionConfig,
+        /// whether a game session is running
+        in_game: bool,
+    },
+    /// response to `PeekGame`, clocks are the milliseconds left to play,
+    /// `None` unless the player is to move with a play timeout
+    GameSnapshot {
+        field: FieldInner,
+        black_clock: Option<u64>,
+        white_clock: Option<u64>,
+        to_move: Option<Color>,
+    },
+    /// response to `PeekGame` when the room has no game session running
+    NoGameInProgress,
+    /// pushed to subscribers after `SubscribeLobby`
+    RoomListUpdate(RoomSummary),
     /// when the other player gets `JoinRoomSuccess`
     /// the `String` is the username
     OpponentJoinRoom(String),
+    /// when both seats are taken, sent to both players
+    RoomReady,
     /// when I quit room
     QuitRoomSuccess,
+    /// when the room creator bans me, I am back in the lobby
+    BannedFromRoom,
     /// when the other player `QuitRoom`
     OpponentQuitRoom,
     /// when the other player is `Ready`
@@ -138,6 +251,17 @@ pub enum Responses {
     GameStarted(Color),
     /// update field
     FieldUpdate(FieldState),
+    /// response to `Play` at an invalid position (x, y),
+    /// or to a move rolled back by the server
+    InvalidMove(u8, u8),
+    /// in confirm-moves mode, the move (x, y) waits for `ConfirmMove`,
+    /// with the play clock paused for at most 10 seconds
+    MovePending(u8, u8),
+    /// in confirm-moves mode, the pending move is discarded,
+    /// also when not confirmed within 10 seconds
+    MoveCancelled,
+    /// response to `Play` on the opponent's turn, if the room config `notify_not_your_turn`
+    NotYourTurn,
     /// opponent request undo
     UndoRequest,
     /// undo rejected by timeout
@@ -158,6 +282,19 @@ pub enum Responses {
     GameEndWhiteWins,
     /// game session ends, draw
     GameEndDraw,
+    /// game session ends before any move, no contest
+    GameEndAborted,
+    /// game session ends by the latest move, with the final field
+    GameEndWithBoard {
+        result: GameResult,
+        field: FieldState,
+    },
+    /// the latest move ends the game with the result,
+    /// unless undone within `final_undo_window` by `RequestUndo` and `ApproveUndo`
+    GameEndPending {
+        result: GameResult,
+        field: FieldState,
+    },
     /// Room score information (player1, player2)
     RoomScores((String, u16), (String, u16)),
     /// when I quit game session
@@ -172,6 +309,118 @@ pub enum Responses {
     GameSessionError(String),
     /// ChatMessage: (user_name, message)
     ChatMessage(String, String),
+    /// response to non-fatal `ClientError`
+    ClientErrorAcknowledged,
+    /// response to `DebugRoom`: a snapshot of the room
+    RoomDebug(String),
+    /// the player is not allowed to send the message
+    PermissionDenied,
+    /// sent once right after TLS, before authentication
+    Welcome {
+        server_name: String,
+        protocol_version: u32,
+        features: Vec<String>,
+    },
+    /// sent to both players right after `GameStarted`, timeouts in seconds, 0 means none
+    TimeControl {
+        play_timeout: u64,
+        undo_request_timeout: u64,
+        undo_dialogue_extra_seconds: u64,
+        final_undo_window: u64,
+    },
+    /// the opponent proposed `UpdateRoomConfig`, send the same config to agree
+    RoomConfigProposed(SessionConfig),
+    /// the room config is updated, sent to all occupants
+    RoomConfigUpdated(SessionConfig),
+    /// response to `Play` within `min_move_interval` of the previous move, which is ignored
+    MovingTooFast,
+    /// response to `PeekGame` of a password protected room,
+    /// unless the password is given or the player created the room
+    SpectateDenied,
+    /// opponent request redo
+    RedoRequest,
+    /// redo rejected by timeout
+    RedoTimeoutRejected,
+    /// redo rejected due to synchronization reason
+    RedoAutoRejected,
+    /// redo approved, the undone move is played again
+    Redo(FieldState),
+    /// redo rejected by opponent
+    RedoRejectedByOpponent,
+    /// redo rejected since opponent has an undo or redo request pending
+    RedoRequestInProgress,
+    /// sent to both players right after `GameStarted`,
+    /// the seed of color assignment and opening randomization
+    GameSeed(u64),
+    /// response to `Ready` while alone in a room with `ready_needs_opponent`
+    CannotReadyEmptyRoom,
+    /// opponent removed from the game by moderation, with the reason, no contest
+    OpponentKicked(String),
+    /// response to `SearchOnlinePlayers`, the query is too long or has control characters
+    SearchRejected,
+    /// response to `Leave` in the lobby, the server closes the connection
+    ConnectionClosing,
+    /// responses within the window of `CoalesceResponses`, in order
+    Batch(Vec<Responses>),
+    /// the player of the color ran out of time and the opponent is to move,
+    /// with `TimeoutBehavior::AutoPass`
+    TurnPassed(Color),
+    /// the same recap to both players right before the end of a game with a result,
+    /// `moves` excludes the opening, and each move is timed since the previous change of the board
+    GameSummary {
+        moves: u16,
+        duration_ms: u64,
+        black_time_ms: u64,
+        white_time_ms: u64,
+        result: GameResult,
+    },
+    /// undo rejected since opponent has an undo request pending
+    UndoRequestInProgress,
+    /// response to `CreateRoom` or `UpdateRoomConfig` with a config failing
+    /// `SessionConfig::is_valid`, which is ignored
+    InvalidRoomConfig,
+}
+
+impl Messages {
+    /// name of the message, for logging without its contents (e.g. passwords)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Messages::ToPlayer(..) => "ToPlayer",
+            Messages::CreateAccount(..) => "CreateAccount",
+            Messages::Login(..) => "Login",
+            Messages::UpdateAccount(..) => "UpdateAccount",
+            Messages::CreateRoom(..) => "CreateRoom",
+            Messages::JoinRoom(..) => "JoinRoom",
+            Messages::QueryRoom(..) => "QueryRoom",
+            Messages::PeekGame(..) => "PeekGame",
+            Messages::SubscribeLobby => "SubscribeLobby",
+            Messages::UnsubscribeLobby => "UnsubscribeLobby",
+            Messages::DebugRoom(..) => "DebugRoom",
+            Messages::QuitRoom => "QuitRoom",
+            Messages::BanFromRoom(..) => "BanFromRoom",
+            Messages::Ready => "Ready",
+            Messages::Unready => "Unready",
+            Messages::Play(..) => "Play",
+            Messages::ConfirmMove => "ConfirmMove",
+            Messages::CancelMove => "CancelMove",
+            Messages::SetConfirmMoves(..) => "SetConfirmMoves",
+            Messages::RequestUndo => "RequestUndo",
+            Messages::ApproveUndo => "ApproveUndo",
+            Messages::RejectUndo => "RejectUndo",
+            Messages::QuitGameSession => "QuitGameSession",
+            Messages::SearchOnlinePlayers(..) => "SearchOnlinePlayers",
+            Messages::ChatMessage(..) => "ChatMessage",
+            Messages::ExitGame => "ExitGame",
+            Messages::ClientError { .. } => "ClientError",
+            Messages::UpdateRoomConfig(..) => "UpdateRoomConfig",
+            Messages::RequestRedo => "RequestRedo",
+            Messages::ApproveRedo => "ApproveRedo",
+            Messages::RejectRedo => "RejectRedo",
+            Messages::Leave => "Leave",
+            Messages::CoalesceResponses(..) => "CoalesceResponses",
+            Messages::Ack => "Ack",
+        }
+    }
 }
 
 impl Into<Vec<u8>> for Messages {
@@ -211,7 +460,9 @@ impl TryFrom<Vec<u8>> for Responses {
 #[cfg(test)]
 mod test_encode_decode {
     use super::*;
-    use crate::game::State;
+    use crate::game::{
+        AbandonPolicy, OpeningBook, RoomPersistence, SimultaneousWin, State, TimeoutBehavior,
+    };
     use crate::Color::{Black, White};
     use crate::FieldInner;
     use rand::thread_rng;
@@ -236,18 +487,58 @@ mod test_encode_decode {
     #[test]
     fn test_messages() {
         let mut rng = thread_rng();
-        assert_msg_eq(Messages::CreateRoom(SessionConfig {
-            undo_request_timeout: 1,
-            undo_dialogue_extra_seconds: 2,
-            play_timeout: 3,
-        }));
+        assert_msg_eq(Messages::CreateRoom(
+            SessionConfig {
+                undo_request_timeout: 1,
+                undo_dialogue_extra_seconds: 2,
+                play_timeout: 3,
+                board_width: 10,
+                board_height: 20,
+                first_player: White,
+                abandon_policy: AbandonPolicy::ExitAsLoss,
+                recover_impossible: true,
+                final_undo_window: 5,
+                opening_book: OpeningBook::Random(vec![vec![(7, 7), (7, 8)], vec![(3, 3)]]),
+                notify_not_your_turn: true,
+                early_draw: true,
+                min_move_interval: 200,
+                room_persistence: RoomPersistence::OwnerBound,
+                max_undo_pause_seconds: 60,
+                simultaneous_win: SimultaneousWin::Draw,
+                free_undo: true,
+                ready_needs_opponent: true,
+                hide_opponent_clock: true,
+                timeout_behavior: TimeoutBehavior::AutoPass,
+                start_ack_timeout: 10,
+                proximity_rule: Some(2),
+            },
+            Some("secret".to_string()),
+        ));
+        assert_msg_eq(Messages::CreateRoom(SessionConfig::default(), None));
+        assert_msg_eq(Messages::SetConfirmMoves(true));
+        assert_msg_eq(Messages::ConfirmMove);
+        assert_msg_eq(Messages::CancelMove);
         assert_msg_eq(Messages::Login("小雨".to_string(), "okk".to_string()));
         assert_msg_eq(Messages::CreateAccount(
             "雨雨".to_string(),
             "oh yeah".to_string(),
         ));
-        assert_msg_eq(Messages::JoinRoom(RoomToken::random(&mut rng)));
+        assert_msg_eq(Messages::JoinRoom(RoomToken::random(&mut rng), None));
+        assert_msg_eq(Messages::JoinRoom(
+            RoomToken::random(&mut rng),
+            Some("secret".to_string()),
+        ));
+        assert_msg_eq(Messages::QueryRoom(RoomToken::random(&mut rng)));
+        assert_msg_eq(Messages::PeekGame(RoomToken::random(&mut rng), None));
+        assert_msg_eq(Messages::PeekGame(
+            RoomToken::random(&mut rng),
+            Some("secret".to_string()),
+        ));
+        assert_msg_eq(Messages::SubscribeLobby);
+        assert_msg_eq(Messages::UnsubscribeLobby);
+        assert_msg_eq(Messages::DebugRoom(RoomToken::random(&mut rng)));
         assert_msg_eq(Messages::QuitRoom);
+        assert_msg_eq(Messages::BanFromRoom("雨雨".to_string()));
         assert_msg_eq(Messages::Ready);
         assert_msg_eq(Messages::Unready);
         assert_msg_eq(Messages::Play(5, 3));
@@ -256,7 +547,23 @@ mod test_encode_decode {
         assert_msg_eq(Messages::RejectUndo);
         assert_msg_eq(Messages::QuitGameSession);
         assert_msg_eq(Messages::ExitGame);
-        assert_msg_eq(Messages::ClientError("decode error".to_string()));
+        assert_msg_eq(Messages::ClientError {
+            fatal: true,
+            message: "decode error".to_string(),
+        });
+        assert_msg_eq(Messages::ClientError {
+            fatal: false,
+            message: "render error".to_string(),
+        });
+        assert_msg_eq(Messages::UpdateRoomConfig(
+            SessionConfig::builder().play_timeout(30).build(),
+        ));
+        assert_msg_eq(Messages::RequestRedo);
+        assert_msg_eq(Messages::ApproveRedo);
+        assert_msg_eq(Messages::RejectRedo);
+        assert_msg_eq(Messages::Leave);
+        assert_msg_eq(Messages::CoalesceResponses(5));
+        assert_msg_eq(Messages::Ack);
         assert_msg_eq(Messages::Login(
             "user name".to_string(),
             "password".to_string(),
@@ -288,34 +595,111 @@ mod test_encode_decode {
             RoomToken::random(&mut rng).as_code(),
             RoomState::OpponentReady("枫原万叶".to_string()),
         ));
-        assert_rsp_eq(Responses::JoinRoomFailureTokenNotFound);
-        assert_rsp_eq(Responses::JoinRoomFailureRoomFull);
+        assert_rsp_eq(Responses::JoinRoomFailure(JoinRoomFailure::TokenNotFound));
+        assert_rsp_eq(Responses::JoinRoomFailure(JoinRoomFailure::RoomFull));
+        assert_rsp_eq(Responses::JoinRoomFailure(JoinRoomFailure::BadPassword));
+        assert_rsp_eq(Responses::JoinRoomFailure(JoinRoomFailure::Banned));
+        assert_rsp_eq(Responses::JoinRoomFailure(JoinRoomFailure::RoomClosed));
+        assert_rsp_eq(Responses::BannedFromRoom);
+        assert_rsp_eq(Responses::RoomInfo {
+            occupants: vec!["小雨".to_string()],
+            session_config: SessionConfig::default(),
+            in_game: true,
+        });
+        let mut field = vec![vec![State::E; 15]; 15];
+        field[7][7] = State::B;
+        assert_rsp_eq(Responses::GameSnapshot {
+            field: FieldInner(field),
+            black_clock: None,
+            white_clock: Some(29_500),
+            to_move: Some(White),
+        });
+        assert_rsp_eq(Responses::NoGameInProgress);
+        assert_rsp_eq(Responses::RoomListUpdate(RoomSummary {
+            token: RoomToken::random(&mut rng).as_code(),
+            occupants: 1,
+            session_config: SessionConfig::default(),
+            status: RoomStatus::Full,
+        }));
         assert_rsp_eq(Responses::OpponentJoinRoom("some username".to_string()));
+        assert_rsp_eq(Responses::RoomCreationRateLimited);
+        assert_rsp_eq(Responses::ServerDraining);
+        assert_rsp_eq(Responses::RoomClosed);
+        assert_rsp_eq(Responses::RoomReady);
         assert_rsp_eq(Responses::OpponentQuitRoom);
         assert_rsp_eq(Responses::OpponentReady);
         assert_rsp_eq(Responses::OpponentUnready);
         assert_rsp_eq(Responses::GameStarted(Black));
         assert_rsp_eq(Responses::FieldUpdate(FieldState {
             latest: (5, 3, Black),
-            field: FieldInner([[State::B; 15]; 15]),
+            field: FieldInner(vec![vec![State::B; 15]; 15]),
+        }));
+        assert_rsp_eq(Responses::InvalidMove(15, 3));
+        assert_rsp_eq(Responses::MovePending(5, 3));
+        assert_rsp_eq(Responses::MoveCancelled);
+        assert_rsp_eq(Responses::NotYourTurn);
+        assert_rsp_eq(Responses::MovingTooFast);
+        assert_rsp_eq(Responses::SpectateDenied);
+        assert_rsp_eq(Responses::RedoRequest);
+        assert_rsp_eq(Responses::RedoTimeoutRejected);
+        assert_rsp_eq(Responses::RedoAutoRejected);
+        assert_rsp_eq(Responses::Redo(FieldState {
+            latest: (5, 3, Black),
+            field: FieldInner(vec![vec![State::B; 15]; 15]),
         }));
+        assert_rsp_eq(Responses::RedoRejectedByOpponent);
+        assert_rsp_eq(Responses::RedoRequestInProgress);
+        assert_rsp_eq(Responses::GameSeed(u64::MAX));
+        assert_rsp_eq(Responses::CannotReadyEmptyRoom);
+        assert_rsp_eq(Responses::OpponentKicked("闲置".to_string()));
+        assert_rsp_eq(Responses::SearchRejected);
+        assert_rsp_eq(Responses::ConnectionClosing);
+        assert_rsp_eq(Responses::Batch(vec![
+            Responses::GameSeed(7),
+            Responses::Batch(vec![]),
+        ]));
+        assert_rsp_eq(Responses::TurnPassed(White));
+        assert_rsp_eq(Responses::GameSummary {
+            moves: 9,
+            duration_ms: 1500,
+            black_time_ms: 700,
+            white_time_ms: 600,
+            result: GameResult::BlackWins,
+        });
         assert_rsp_eq(Responses::UndoRequest);
         assert_rsp_eq(Responses::UndoTimeoutRejected);
         assert_rsp_eq(Responses::UndoAutoRejected);
         assert_rsp_eq(Responses::Undo(FieldStateNullable {
             latest: None,
-            field: FieldInner([[State::W; 15]; 15]),
+            field: FieldInner(vec![vec![State::W; 19]; 15]),
         }));
         assert_rsp_eq(Responses::Undo(FieldStateNullable {
             latest: Some((5, 3, White)),
-            field: FieldInner([[State::E; 15]; 15]),
+            field: FieldInner(vec![vec![State::E; 20]; 10]),
         }));
         assert_rsp_eq(Responses::UndoRejectedByOpponent);
+        assert_rsp_eq(Responses::UndoRequestInProgress);
+        assert_rsp_eq(Responses::InvalidRoomConfig);
         assert_rsp_eq(Responses::GameEndBlackTimeout);
         assert_rsp_eq(Responses::GameEndWhiteTimeout);
         assert_rsp_eq(Responses::GameEndBlackWins);
         assert_rsp_eq(Responses::GameEndWhiteWins);
         assert_rsp_eq(Responses::GameEndDraw);
+        assert_rsp_eq(Responses::GameEndAborted);
+        assert_rsp_eq(Responses::GameEndWithBoard {
+            result: GameResult::BlackWins,
+            field: FieldState {
+                latest: (5, 3, Black),
+                field: FieldInner(vec![vec![State::B; 15]; 15]),
+            },
+        });
+        assert_rsp_eq(Responses::GameEndPending {
+            result: GameResult::WhiteWins,
+            field: FieldState {
+                latest: (7, 4, White),
+                field: FieldInner(vec![vec![State::W; 15]; 15]),
+            },
+        });
         assert_rsp_eq(Responses::OpponentQuitGameSession);
         assert_rsp_eq(Responses::OpponentExitGame);
         assert_rsp_eq(Responses::OpponentDisconnected);
@@ -328,9 +712,28 @@ mod test_encode_decode {
             "hi!".to_string(),
         ));
         assert_rsp_eq(Responses::GameSessionError("some error".to_string()));
+        assert_rsp_eq(Responses::ClientErrorAcknowledged);
+        assert_rsp_eq(Responses::RoomDebug("room ABC".to_string()));
+        assert_rsp_eq(Responses::PermissionDenied);
+        assert_rsp_eq(Responses::Welcome {
+            server_name: "wuziqi".to_string(),
+            protocol_version: 1,
+            features: vec!["chat".to_string(), "peek_game".to_string()],
+        });
+        assert_rsp_eq(Responses::TimeControl {
+            play_timeout: 30,
+            undo_request_timeout: 10,
+            undo_dialogue_extra_seconds: 5,
+            final_undo_window: 0,
+        });
+        assert_rsp_eq(Responses::RoomConfigProposed(SessionConfig::default()));
+        assert_rsp_eq(Responses::RoomConfigUpdated(SessionConfig::default()));
         assert_rsp_eq(Responses::ConnectionInitFailure(
             ConnectionInitError::UserNameTooLong,
         ));
+        assert_rsp_eq(Responses::ConnectionInitFailure(
+            ConnectionInitError::ServerAtCapacity,
+        ));
         assert_rsp_eq(Responses::FromPlayer("香菱".to_string(), Vec::from("good")));
         assert_rsp_eq(Responses::FromPlayer("香菱".to_string(), Vec::new()));
         assert_rsp_eq(Responses::PlayerList(vec![
@@ -340,4 +743,434 @@ mod test_encode_decode {
         ]));
         assert_rsp_eq(Responses::PlayerList(vec![]));
     }
+
+    #[test]
+    fn test_malformed_bytes() {
+        assert!(Messages::try_from(Vec::new()).is_err());
+        assert!(Responses::try_from(Vec::new()).is_err());
+        let field: Vec<u8> = Responses::FieldUpdate(FieldState {
+            latest: (5, 3, Black),
+            field: FieldInner(vec![vec![State::B; 15]; 15]),
+        })
+        .into();
+        for len in 0..field.len() {
+            assert!(Responses::try_from(field[..len].to_vec()).is_err());
+        }
+    }
+}
+
+/// The exact bytes on the wire, which deployed clients depend on.
+///
+/// Any failure here is a protocol change: new variants go last,
+/// and existing variants keep their fields.
+#[cfg(test)]
+mod test_golden_bytes {
+    use super::*;
+    use crate::game::{
+        AbandonPolicy, OpeningBook, RoomPersistence, SimultaneousWin, State, TimeoutBehavior,
+    };
+    use crate::Color::{Black, White};
+
+    fn assert_msg_bytes(msg: Messages, bytes: &[u8]) {
+        let encoded: Vec<u8> = msg.clone().into();
+        assert_eq!(encoded, bytes, "encoding of {:?} changed", msg);
+        assert_eq!(Messages::try_from(encoded).unwrap(), msg);
+    }
+
+    fn assert_rsp_bytes(rsp: Responses, bytes: &[u8]) {
+        let encoded: Vec<u8> = rsp.clone().into();
+        assert_eq!(encoded, bytes, "encoding of {:?} changed", rsp);
+        assert_eq!(Responses::try_from(encoded).unwrap(), rsp);
+    }
+
+    fn token() -> RoomToken {
+        RoomToken([0, 1, 2, 3, 4, 5, 6, 7, 8, 9])
+    }
+
+    /// `[[B, W, E], [E, E, B]]`
+    fn field() -> FieldInner {
+        FieldInner(vec![
+            vec![State::B, State::W, State::E],
+            vec![State::E, State::E, State::B],
+        ])
+    }
+
+    fn config() -> SessionConfig {
+        SessionConfig {
+            undo_request_timeout: 1,
+            undo_dialogue_extra_seconds: 2,
+            play_timeout: 300,
+            board_width: 15,
+            board_height: 15,
+            first_player: White,
+            abandon_policy: AbandonPolicy::ExitAsLoss,
+            recover_impossible: true,
+            final_undo_window: 5,
+            opening_book: OpeningBook::Fixed(vec![(7, 7)]),
+            notify_not_your_turn: false,
+            early_draw: false,
+            min_move_interval: 1000,
+            room_persistence: RoomPersistence::OwnerBound,
+            max_undo_pause_seconds: 30,
+            simultaneous_win: SimultaneousWin::Draw,
+            free_undo: false,
+            ready_needs_opponent: false,
+            hide_opponent_clock: false,
+            timeout_behavior: TimeoutBehavior::AutoPass,
+            start_ack_timeout: 10,
+            proximity_rule: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_messages_golden_bytes() {
+        assert_msg_bytes(
+            Messages::ToPlayer("ab".to_string(), vec![7]),
+            &[0, 2, 97, 98, 1, 7],
+        );
+        assert_msg_bytes(
+            Messages::CreateAccount("ab".to_string(), "cd".to_string()),
+            &[1, 2, 97, 98, 2, 99, 100],
+        );
+        assert_msg_bytes(
+            Messages::Login("雨".to_string(), "cd".to_string()),
+            &[2, 3, 233, 155, 168, 2, 99, 100],
+        );
+        assert_msg_bytes(
+            Messages::UpdateAccount("ab".to_string(), "cd".to_string(), "ef".to_string()),
+            &[3, 2, 97, 98, 2, 99, 100, 2, 101, 102],
+        );
+        assert_msg_bytes(
+            Messages::CreateRoom(config(), Some("pw".to_string())),
+            &[
+                4, 1, 2, 251, 44, 1, 15, 15, 1, 1, 1, 5, 1, 1, 7, 7, 0, 0, 251, 232, 3, 1, 30, 1,
+                0, 0, 0, 1, 10, 1, 2, 1, 2, 112, 119,
+            ],
+        );
+        assert_msg_bytes(
+            Messages::CreateRoom(SessionConfig::default(), None),
+            &[
+                4, 0, 0, 0, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        );
+        assert_msg_bytes(
+            Messages::JoinRoom(token(), None),
+            &[5, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0],
+        );
+        assert_msg_bytes(
+            Messages::JoinRoom(token(), Some("pw".to_string())),
+            &[5, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 1, 2, 112, 119],
+        );
+        assert_msg_bytes(
+            Messages::QueryRoom(token()),
+            &[6, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+        );
+        assert_msg_bytes(
+            Messages::PeekGame(token(), None),
+            &[7, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0],
+        );
+        assert_msg_bytes(Messages::SubscribeLobby, &[8]);
+        assert_msg_bytes(Messages::UnsubscribeLobby, &[9]);
+        assert_msg_bytes(
+            Messages::DebugRoom(token()),
+            &[10, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+        );
+        assert_msg_bytes(Messages::QuitRoom, &[11]);
+        assert_msg_bytes(Messages::BanFromRoom("ab".to_string()), &[12, 2, 97, 98]);
+        assert_msg_bytes(Messages::Ready, &[13]);
+        assert_msg_bytes(Messages::Unready, &[14]);
+        assert_msg_bytes(Messages::Play(5, 14), &[15, 5, 14]);
+        assert_msg_bytes(Messages::ConfirmMove, &[16]);
+        assert_msg_bytes(Messages::CancelMove, &[17]);
+        assert_msg_bytes(Messages::SetConfirmMoves(true), &[18, 1]);
+        assert_msg_bytes(Messages::RequestUndo, &[19]);
+        assert_msg_bytes(Messages::ApproveUndo, &[20]);
+        assert_msg_bytes(Messages::RejectUndo, &[21]);
+        assert_msg_bytes(Messages::QuitGameSession, &[22]);
+        assert_msg_bytes(Messages::SearchOnlinePlayers(None, 5), &[23, 0, 5]);
+        assert_msg_bytes(
+            Messages::SearchOnlinePlayers(Some("ab".to_string()), 255),
+            &[23, 1, 2, 97, 98, 255],
+        );
+        assert_msg_bytes(Messages::ChatMessage("hi".to_string()), &[24, 2, 104, 105]);
+        assert_msg_bytes(Messages::ExitGame, &[25]);
+        assert_msg_bytes(
+            Messages::ClientError {
+                fatal: true,
+                message: "ab".to_string(),
+            },
+            &[26, 1, 2, 97, 98],
+        );
+        assert_msg_bytes(
+            Messages::UpdateRoomConfig(SessionConfig::default()),
+            &[
+                27, 0, 0, 0, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        );
+        assert_msg_bytes(Messages::RequestRedo, &[28]);
+        assert_msg_bytes(Messages::ApproveRedo, &[29]);
+        assert_msg_bytes(Messages::RejectRedo, &[30]);
+        assert_msg_bytes(Messages::Leave, &[31]);
+        assert_msg_bytes(Messages::CoalesceResponses(20), &[32, 20]);
+        assert_msg_bytes(Messages::Ack, &[33]);
+    }
+
+    #[test]
+    fn test_responses_golden_bytes() {
+        assert_rsp_bytes(
+            Responses::FromPlayer("ab".to_string(), vec![7]),
+            &[0, 2, 97, 98, 1, 7],
+        );
+        assert_rsp_bytes(
+            Responses::CreateAccountFailure(CreateAccountFailure::BadInput(
+                InvalidAccountPassword::PasswordTooLong,
+            )),
+            &[1, 0, 5],
+        );
+        assert_rsp_bytes(
+            Responses::CreateAccountFailure(CreateAccountFailure::BadInput(
+                InvalidAccountPassword::NameNotAllowed,
+            )),
+            &[1, 0, 6],
+        );
+        assert_rsp_bytes(
+            Responses::CreateAccountFailure(CreateAccountFailure::AlreadyLoggedIn),
+            &[1, 3],
+        );
+        assert_rsp_bytes(Responses::LoginFailure(LoginFailure::ServerError), &[2, 4]);
+        assert_rsp_bytes(
+            Responses::UpdateAccountFailure(UpdatePasswordFailure::PasswordIncorrect),
+            &[3, 2],
+        );
+        assert_rsp_bytes(
+            Responses::CreateAccountSuccess("ab".to_string(), "cd".to_string()),
+            &[4, 2, 97, 98, 2, 99, 100],
+        );
+        assert_rsp_bytes(
+            Responses::UpdateAccountSuccess("ab".to_string(), "cd".to_string()),
+            &[5, 2, 97, 98, 2, 99, 100],
+        );
+        assert_rsp_bytes(Responses::LoginSuccess("ab".to_string()), &[6, 2, 97, 98]);
+        assert_rsp_bytes(
+            Responses::ConnectionInitFailure(ConnectionInitError::UserNameExists),
+            &[7, 6],
+        );
+        assert_rsp_bytes(
+            Responses::ConnectionInitFailure(ConnectionInitError::ServerAtCapacity),
+            &[7, 9],
+        );
+        assert_rsp_bytes(Responses::RoomCreated("ab".to_string()), &[8, 2, 97, 98]);
+        assert_rsp_bytes(Responses::RoomCreationRateLimited, &[9]);
+        assert_rsp_bytes(Responses::ServerDraining, &[10]);
+        assert_rsp_bytes(Responses::RoomClosed, &[11]);
+        assert_rsp_bytes(
+            Responses::PlayerList(vec!["ab".to_string(), "c".to_string()]),
+            &[12, 2, 2, 97, 98, 1, 99],
+        );
+        assert_rsp_bytes(
+            Responses::JoinRoomSuccess("ab".to_string(), RoomState::Empty),
+            &[13, 2, 97, 98, 0],
+        );
+        assert_rsp_bytes(
+            Responses::JoinRoomSuccess(
+                "ab".to_string(),
+                RoomState::OpponentUnready("c".to_string()),
+            ),
+            &[13, 2, 97, 98, 2, 1, 99],
+        );
+        assert_rsp_bytes(
+            Responses::JoinRoomFailure(JoinRoomFailure::Banned),
+            &[14, 3],
+        );
+        assert_rsp_bytes(
+            Responses::JoinRoomFailure(JoinRoomFailure::RoomClosed),
+            &[14, 4],
+        );
+        assert_rsp_bytes(
+            Responses::RoomInfo {
+                occupants: vec!["ab".to_string()],
+                session_config: config(),
+                in_game: true,
+            },
+            &[
+                15, 1, 2, 97, 98, 1, 2, 251, 44, 1, 15, 15, 1, 1, 1, 5, 1, 1, 7, 7, 0, 0, 251, 232,
+                3, 1, 30, 1, 0, 0, 0, 1, 10, 1, 2, 1,
+            ],
+        );
+        assert_rsp_bytes(
+            Responses::GameSnapshot {
+                field: field(),
+                black_clock: None,
+                white_clock: Some(29_500),
+                to_move: Some(White),
+            },
+            &[16, 2, 3, 161, 154, 0, 1, 251, 60, 115, 1, 1],
+        );
+        assert_rsp_bytes(Responses::NoGameInProgress, &[17]);
+        assert_rsp_bytes(
+            Responses::RoomListUpdate(RoomSummary {
+                token: "ab".to_string(),
+                occupants: 1,
+                session_config: SessionConfig::default(),
+                status: RoomStatus::Full,
+            }),
+            &[
+                18, 2, 97, 98, 1, 0, 0, 0, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 1,
+            ],
+        );
+        assert_rsp_bytes(
+            Responses::OpponentJoinRoom("ab".to_string()),
+            &[19, 2, 97, 98],
+        );
+        assert_rsp_bytes(Responses::RoomReady, &[20]);
+        assert_rsp_bytes(Responses::QuitRoomSuccess, &[21]);
+        assert_rsp_bytes(Responses::BannedFromRoom, &[22]);
+        assert_rsp_bytes(Responses::OpponentQuitRoom, &[23]);
+        assert_rsp_bytes(Responses::OpponentReady, &[24]);
+        assert_rsp_bytes(Responses::OpponentUnready, &[25]);
+        assert_rsp_bytes(Responses::GameStarted(Black), &[26, 0]);
+        assert_rsp_bytes(
+            Responses::FieldUpdate(FieldState {
+                latest: (1, 2, Black),
+                field: field(),
+            }),
+            &[27, 1, 2, 0, 2, 3, 161, 154],
+        );
+        assert_rsp_bytes(Responses::InvalidMove(15, 3), &[28, 15, 3]);
+        assert_rsp_bytes(Responses::MovePending(5, 3), &[29, 5, 3]);
+        assert_rsp_bytes(Responses::MoveCancelled, &[30]);
+        assert_rsp_bytes(Responses::NotYourTurn, &[31]);
+        assert_rsp_bytes(Responses::UndoRequest, &[32]);
+        assert_rsp_bytes(Responses::UndoTimeoutRejected, &[33]);
+        assert_rsp_bytes(Responses::UndoAutoRejected, &[34]);
+        assert_rsp_bytes(
+            Responses::Undo(FieldStateNullable {
+                latest: None,
+                field: field(),
+            }),
+            &[35, 0, 2, 3, 161, 154],
+        );
+        assert_rsp_bytes(
+            Responses::Undo(FieldStateNullable {
+                latest: Some((0, 1, White)),
+                field: field(),
+            }),
+            &[35, 1, 0, 1, 1, 2, 3, 161, 154],
+        );
+        assert_rsp_bytes(Responses::UndoRejectedByOpponent, &[36]);
+        assert_rsp_bytes(Responses::GameEndBlackTimeout, &[37]);
+        assert_rsp_bytes(Responses::GameEndWhiteTimeout, &[38]);
+        assert_rsp_bytes(Responses::GameEndBlackWins, &[39]);
+        assert_rsp_bytes(Responses::GameEndWhiteWins, &[40]);
+        assert_rsp_bytes(Responses::GameEndDraw, &[41]);
+        assert_rsp_bytes(Responses::GameEndAborted, &[42]);
+        assert_rsp_bytes(
+            Responses::GameEndWithBoard {
+                result: GameResult::BlackWins,
+                field: FieldState {
+                    latest: (1, 2, Black),
+                    field: field(),
+                },
+            },
+            &[43, 2, 1, 2, 0, 2, 3, 161, 154],
+        );
+        assert_rsp_bytes(
+            Responses::GameEndPending {
+                result: GameResult::Draw,
+                field: FieldState {
+                    latest: (1, 2, Black),
+                    field: field(),
+                },
+            },
+            &[44, 4, 1, 2, 0, 2, 3, 161, 154],
+        );
+        assert_rsp_bytes(
+            Responses::RoomScores(("ab".to_string(), 300), ("c".to_string(), 3)),
+            &[45, 2, 97, 98, 251, 44, 1, 1, 99, 3],
+        );
+        assert_rsp_bytes(Responses::QuitGameSessionSuccess, &[46]);
+        assert_rsp_bytes(Responses::OpponentQuitGameSession, &[47]);
+        assert_rsp_bytes(Responses::OpponentExitGame, &[48]);
+        assert_rsp_bytes(Responses::OpponentDisconnected, &[49]);
+        assert_rsp_bytes(
+            Responses::GameSessionError("ab".to_string()),
+            &[50, 2, 97, 98],
+        );
+        assert_rsp_bytes(
+            Responses::ChatMessage("ab".to_string(), "hi".to_string()),
+            &[51, 2, 97, 98, 2, 104, 105],
+        );
+        assert_rsp_bytes(Responses::ClientErrorAcknowledged, &[52]);
+        assert_rsp_bytes(Responses::RoomDebug("ab".to_string()), &[53, 2, 97, 98]);
+        assert_rsp_bytes(Responses::PermissionDenied, &[54]);
+        assert_rsp_bytes(
+            Responses::Welcome {
+                server_name: "ab".to_string(),
+                protocol_version: 1,
+                features: vec!["c".to_string()],
+            },
+            &[55, 2, 97, 98, 1, 1, 1, 99],
+        );
+        assert_rsp_bytes(
+            Responses::TimeControl {
+                play_timeout: 300,
+                undo_request_timeout: 10,
+                undo_dialogue_extra_seconds: 5,
+                final_undo_window: 0,
+            },
+            &[56, 251, 44, 1, 10, 5, 0],
+        );
+        assert_rsp_bytes(
+            Responses::RoomConfigProposed(SessionConfig::default()),
+            &[
+                57, 0, 0, 0, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        );
+        assert_rsp_bytes(
+            Responses::RoomConfigUpdated(SessionConfig::default()),
+            &[
+                58, 0, 0, 0, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        );
+        assert_rsp_bytes(Responses::MovingTooFast, &[59]);
+        assert_rsp_bytes(Responses::SpectateDenied, &[60]);
+        assert_rsp_bytes(Responses::RedoRequest, &[61]);
+        assert_rsp_bytes(Responses::RedoTimeoutRejected, &[62]);
+        assert_rsp_bytes(Responses::RedoAutoRejected, &[63]);
+        assert_rsp_bytes(
+            Responses::Redo(FieldState {
+                latest: (1, 2, Black),
+                field: field(),
+            }),
+            &[64, 1, 2, 0, 2, 3, 161, 154],
+        );
+        assert_rsp_bytes(Responses::RedoRejectedByOpponent, &[65]);
+        assert_rsp_bytes(Responses::RedoRequestInProgress, &[66]);
+        assert_rsp_bytes(Responses::GameSeed(42), &[67, 42]);
+        assert_rsp_bytes(Responses::CannotReadyEmptyRoom, &[68]);
+        assert_rsp_bytes(
+            Responses::OpponentKicked("ab".to_string()),
+            &[69, 2, 97, 98],
+        );
+        assert_rsp_bytes(Responses::SearchRejected, &[70]);
+        assert_rsp_bytes(Responses::ConnectionClosing, &[71]);
+        assert_rsp_bytes(
+            Responses::Batch(vec![Responses::SearchRejected, Responses::GameSeed(7)]),
+            &[72, 2, 70, 67, 7],
+        );
+        assert_rsp_bytes(Responses::TurnPassed(White), &[73, 1]);
+        assert_rsp_bytes(
+            Responses::GameSummary {
+                moves: 9,
+                duration_ms: 1500,
+                black_time_ms: 700,
+                white_time_ms: 600,
+                result: GameResult::BlackWins,
+            },
+            &[74, 9, 251, 220, 5, 251, 188, 2, 251, 88, 2, 2],
+        );
+        assert_rsp_bytes(Responses::UndoRequestInProgress, &[75]);
+        assert_rsp_bytes(Responses::InvalidRoomConfig, &[76]);
+    }
 }
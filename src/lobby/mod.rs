@@ -1,40 +1,148 @@
 mod client_connection;
 mod game_session;
 pub(crate) mod messages;
+mod normalization;
+mod rate_limit;
 mod room;
 mod room_manager;
+mod server_info;
+mod telemetry;
 pub(crate) mod token;
 mod user_db;
 
 use crate::lobby::user_db::LoginValidator;
-use anyhow::Result;
 use async_std::net::TcpListener;
 use async_std::sync::Mutex;
-pub use client_connection::{ClientConnection, ConnectionInitError, ConnectionStats};
-use tokio_rustls::TlsAcceptor;
+pub use client_connection::{
+    ClientConnection, ConnectionInitError, ConnectionStats, DEFAULT_REJECTION_LIMIT,
+    DEFAULT_SERVER_MAX_CONN, DEFAULT_USER_MAX_CONN,
+};
 pub use messages::{
-    CreateAccountFailure, InvalidAccountPassword, LoginFailure, Messages, Responses, RoomState,
-    UpdatePasswordFailure,
+    CreateAccountFailure, InvalidAccountPassword, JoinRoomFailure, LoginFailure, Messages,
+    Responses, RoomState, RoomStatus, RoomSummary, UpdatePasswordFailure,
 };
+pub use normalization::Normalization;
 use room_manager::RoomManager;
+pub use room_manager::{RoomDirectory, RoomDirectoryEntry, DEFAULT_TOKEN_GRACE};
 use rustls::ServerConfig;
-use std::collections::HashMap;
+pub use server_info::{ServerInfo, FEATURES, FEATURE_DEBUG_ROOM, PROTOCOL_VERSION};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::net::SocketAddrV4;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+pub use telemetry::{GameOutcome, NoTelemetry, TelemetryEvent, TelemetrySink};
 pub use token::RoomToken;
+use tokio_rustls::TlsAcceptor;
+pub use user_db::{Blocklist, NameFilter};
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// reason for the server failing to start
+#[derive(Debug)]
+pub enum StartServerError {
+    /// cannot listen on the address, e.g. the port is in use
+    BindFailed(std::io::Error),
+    /// cannot open the user database
+    DbInitFailed(anyhow::Error),
+}
+
+impl Display for StartServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartServerError::BindFailed(e) => write!(f, "bind failed: {}", e),
+            StartServerError::DbInitFailed(e) => write!(f, "user db init failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StartServerError {}
+
+/// options of `start_server`, see `ServerOptions::default()`
+#[derive(Clone)]
+pub struct ServerOptions {
+    /// user names allowed to send admin messages (e.g. `DebugRoom`)
+    pub admins: HashSet<String>,
+    /// at most this many concurrent connections of each user
+    pub user_max_conn: u32,
+    /// at most this many concurrent connections in total
+    pub server_max_conn: u32,
+    /// what the server tells clients in `Welcome`
+    pub info: ServerInfo,
+    /// receives lifecycle events of connections, rooms and games
+    pub telemetry: Arc<dyn TelemetrySink>,
+    /// rejects the registration of names it does not allow (e.g. a `Blocklist`)
+    pub name_filter: Option<Arc<dyn NameFilter>>,
+    /// how names and chat are normalized,
+    /// names are always in unicode NFC, including the names of `admins`
+    pub normalization: Normalization,
+    /// lists the rooms of the server
+    pub directory: RoomDirectory,
+    /// the token of a closed room answers `RoomClosed`
+    /// and is not given to a new room within this duration
+    pub token_grace: Duration,
+}
+
+impl Default for ServerOptions {
+    /// no admins, `DEFAULT_USER_MAX_CONN`, `DEFAULT_SERVER_MAX_CONN`, default `ServerInfo`, no telemetry,
+    /// no name filter, NFC of names only, and `DEFAULT_TOKEN_GRACE`
+    fn default() -> Self {
+        ServerOptions {
+            admins: HashSet::new(),
+            user_max_conn: DEFAULT_USER_MAX_CONN,
+            server_max_conn: DEFAULT_SERVER_MAX_CONN,
+            info: ServerInfo::default(),
+            telemetry: Arc::new(NoTelemetry),
+            name_filter: None,
+            normalization: Normalization::default(),
+            directory: RoomDirectory::new(),
+            token_grace: DEFAULT_TOKEN_GRACE,
+        }
+    }
+}
 
 pub async fn start_server(
     addrs: SocketAddrV4,
     server_config: Arc<ServerConfig>,
     db_path: &Path,
-) -> Result<()> {
-    let connection_stats = ConnectionStats::new();
+    options: ServerOptions,
+) -> Result<(), StartServerError> {
+    let ServerOptions {
+        admins,
+        user_max_conn,
+        server_max_conn,
+        info,
+        telemetry,
+        name_filter,
+        normalization,
+        directory,
+        token_grace,
+    } = options;
+    let admins = admins
+        .iter()
+        .map(|name| normalization.normalize_name(name))
+        .collect::<HashSet<_>>();
+    let welcome = info.welcome(!admins.is_empty());
+    let admins = Arc::new(admins);
+    let connection_stats = ConnectionStats::with_server_max_conn(
+        user_max_conn,
+        DEFAULT_REJECTION_LIMIT,
+        server_max_conn,
+    );
     let user_name_set = Arc::new(Mutex::new(HashMap::new()));
-    let room_manager = RoomManager::new();
-    let listener = TcpListener::bind(addrs).await?;
+    let room_manager = RoomManager::with_token_grace(token_grace, telemetry.clone());
+    directory.attach(room_manager.clone());
+    let listener = TcpListener::bind(addrs)
+        .await
+        .map_err(StartServerError::BindFailed)?;
     let acceptor = TlsAcceptor::from(server_config);
-    let login_validator = LoginValidator::init(db_path)?;
+    let mut login_validator =
+        LoginValidator::init(db_path).map_err(StartServerError::DbInitFailed)?;
+    if let Some(name_filter) = name_filter {
+        login_validator = login_validator.with_name_filter(name_filter);
+    }
+    login_validator = login_validator.with_normalization(normalization);
     while let Ok((stream, socket)) = listener.accept().await {
         match ClientConnection::init(
             stream,
@@ -43,6 +151,9 @@ pub async fn start_server(
             connection_stats.clone(),
             user_name_set.clone(),
             login_validator.clone(),
+            admins.clone(),
+            welcome.clone(),
+            telemetry.clone(),
         )
         .await
         {
@@ -56,5 +167,642 @@ pub async fn start_server(
             _ => {}
         }
     }
+    // let ongoing games finish once the listener stops
+    room_manager.drain(DRAIN_TIMEOUT).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod test_start_server {
+    use super::*;
+    use crate::game::{AbandonPolicy, Color, GameResult, SessionConfig};
+    use crate::network::{Conn, Received};
+    use async_std::net::TcpStream;
+    use async_std::task::{self, block_on};
+    use futures::future::{select, Either};
+    use futures::StreamExt;
+    use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName};
+    use rustls_pemfile::{certs, pkcs8_private_keys};
+    use std::fs::File;
+    use std::future::Future;
+    use std::io::BufReader;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::path::PathBuf;
+    use tokio_rustls::{TlsConnector, TlsStream};
+
+    type Client = Conn<Messages, Responses>;
+
+    /// records all events
+    #[derive(Default)]
+    struct CapturingSink(std::sync::Mutex<Vec<TelemetryEvent>>);
+
+    impl TelemetrySink for CapturingSink {
+        fn emit(&self, event: TelemetryEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl CapturingSink {
+        fn events(&self) -> Vec<TelemetryEvent> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    fn server_config() -> Arc<ServerConfig> {
+        let certs_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test-certs");
+        let mut cert_reader = BufReader::new(File::open(certs_folder.join("end.cert")).unwrap());
+        let mut key_reader = BufReader::new(File::open(certs_folder.join("end.rsa")).unwrap());
+        let cert = certs(&mut cert_reader)
+            .unwrap()
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = PrivateKey(pkcs8_private_keys(&mut key_reader).unwrap().pop().unwrap());
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(cert, key)
+            .unwrap();
+        Arc::new(config)
+    }
+
+    /// runs `test` against a server with default options
+    fn with_test_server<F, Fut>(name: &str, test: F) -> Fut::Output
+    where
+        F: FnOnce(SocketAddrV4) -> Fut,
+        Fut: Future,
+    {
+        with_test_server_options(name, ServerOptions::default(), test)
+    }
+
+    /// runs `test` against a server on a free port with a fresh database,
+    /// panics if the server stops first
+    fn with_test_server_options<F, Fut>(name: &str, options: ServerOptions, test: F) -> Fut::Output
+    where
+        F: FnOnce(SocketAddrV4) -> Fut,
+        Fut: Future,
+    {
+        let addrs = block_on(async {
+            let probe = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            match probe.local_addr().unwrap() {
+                std::net::SocketAddr::V4(addrs) => addrs,
+                _ => unreachable!(),
+            }
+        });
+        let db_path = std::env::temp_dir().join(format!("wuziqi_test_{}", name));
+        let _ = std::fs::remove_dir_all(&db_path);
+        let server = start_server(addrs, server_config(), &db_path, options);
+        let output = match block_on(select(Box::pin(server), Box::pin(test(addrs)))) {
+            Either::Right((output, _)) => output,
+            Either::Left((result, _)) => panic!("server stopped: {:?}", result),
+        };
+        output
+    }
+
+    #[test]
+    fn test_bind_failed() {
+        block_on(async {
+            let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let addrs = match taken.local_addr().unwrap() {
+                std::net::SocketAddr::V4(addrs) => addrs,
+                _ => unreachable!(),
+            };
+            let db_path = std::env::temp_dir().join("wuziqi_test_bind_failed");
+            let options = ServerOptions::default();
+            let result = start_server(addrs, server_config(), &db_path, options).await;
+            assert!(matches!(result, Err(StartServerError::BindFailed(_))));
+        });
+    }
+
+    fn client_config() -> Arc<ClientConfig> {
+        let certs_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test-certs");
+        let mut chain_reader = BufReader::new(File::open(certs_folder.join("end.chain")).unwrap());
+        let mut root_certs = RootCertStore::empty();
+        root_certs.add_parsable_certificates(&certs(&mut chain_reader).unwrap());
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_certs)
+            .with_no_client_auth();
+        Arc::new(config)
+    }
+
+    /// connect once the server listens, returning the local address
+    async fn connect(addrs: SocketAddrV4) -> (Client, SocketAddr) {
+        let (client, socket) = connect_with_socket(addrs).await;
+        (client, socket.local_addr().unwrap())
+    }
+
+    /// connect once the server listens, returning the socket under TLS
+    async fn connect_with_socket(addrs: SocketAddrV4) -> (Client, TcpStream) {
+        let tcp = loop {
+            match TcpStream::connect(addrs).await {
+                Ok(tcp) => break tcp,
+                Err(_) => task::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        let socket = tcp.clone();
+        let tls = TlsConnector::from(client_config())
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        (
+            Conn::init(TlsStream::Client(tls), None, 1024 * 1024),
+            socket,
+        )
+    }
+
+    /// skip responses until `expected` matches
+    async fn wait_for(client: &mut Client, expected: impl Fn(&Responses) -> bool) -> Responses {
+        loop {
+            match client.next().await {
+                Some(Received::Response(rsp)) if expected(&rsp) => break rsp,
+                Some(_) => {}
+                None => panic!("connection closed"),
+            }
+        }
+    }
+
+    async fn create_account(addrs: SocketAddrV4, name: &str) -> (Client, SocketAddr) {
+        let (mut client, address) = connect(addrs).await;
+        sign_up(&mut client, name).await;
+        (client, address)
+    }
+
+    async fn sign_up(client: &mut Client, name: &str) {
+        let account = Messages::CreateAccount(name.to_string(), "password".to_string());
+        client.sender().send(account).await.unwrap();
+        wait_for(client, |rsp| matches!(rsp, Responses::LoginSuccess(_))).await;
+    }
+
+    async fn create_room(owner: &mut Client, config: SessionConfig) -> RoomToken {
+        let create_room = Messages::CreateRoom(config, None);
+        owner.sender().send(create_room).await.unwrap();
+        match wait_for(owner, |rsp| matches!(rsp, Responses::RoomCreated(_))).await {
+            Responses::RoomCreated(code) => RoomToken::from_code(&code).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// the first player creates a room, which the second joins, and both get ready
+    /// returns the color of the first player
+    async fn start_game(first: &mut Client, second: &mut Client, config: SessionConfig) -> Color {
+        let token = create_room(first, config).await;
+        start_game_in(first, second, token).await
+    }
+
+    /// the second player joins the room of the first, and both get ready
+    /// returns the color of the first player
+    async fn start_game_in(first: &mut Client, second: &mut Client, token: RoomToken) -> Color {
+        let join = Messages::JoinRoom(token, None);
+        second.sender().send(join).await.unwrap();
+        wait_for(first, |rsp| *rsp == Responses::RoomReady).await;
+        first.sender().send(Messages::Ready).await.unwrap();
+        second.sender().send(Messages::Ready).await.unwrap();
+        wait_for(second, |rsp| matches!(rsp, Responses::GameStarted(_))).await;
+        match wait_for(first, |rsp| matches!(rsp, Responses::GameStarted(_))).await {
+            Responses::GameStarted(color) => color,
+            _ => unreachable!(),
+        }
+    }
+
+    /// the move at (x, y) is on the field
+    fn played(x: u8, y: u8) -> impl Fn(&Responses) -> bool {
+        move |rsp| match rsp {
+            Responses::FieldUpdate(f) => (f.latest.0, f.latest.1) == (x, y),
+            _ => false,
+        }
+    }
+
+    /// black wins in 9 moves, each player seeing the opponent's move before playing
+    async fn play_black_wins(black: &mut Client, white: &mut Client) {
+        for y in 0..4 {
+            black.sender().send(Messages::Play(0, y)).await.unwrap();
+            wait_for(white, played(0, y)).await;
+            white.sender().send(Messages::Play(2, y)).await.unwrap();
+            wait_for(black, played(2, y)).await;
+        }
+        black.sender().send(Messages::Play(0, 4)).await.unwrap();
+    }
+
+    /// two players create accounts and play a game, which black wins
+    async fn play_full_game(addrs: SocketAddrV4) -> (SocketAddr, SocketAddr) {
+        let (mut first, first_address) = create_account(addrs, "小雨").await;
+        let (mut second, second_address) = create_account(addrs, "雨雨").await;
+        let (black, white) =
+            match start_game(&mut first, &mut second, SessionConfig::default()).await {
+                Color::Black => (&mut first, &mut second),
+                Color::White => (&mut second, &mut first),
+            };
+        play_black_wins(black, white).await;
+        // both players are back in the room
+        for player in [black, white] {
+            wait_for(player, |rsp| matches!(rsp, Responses::JoinRoomSuccess(..))).await;
+        }
+        (first_address, second_address)
+    }
+
+    #[test]
+    fn test_telemetry_of_full_game() {
+        let sink = Arc::new(CapturingSink::default());
+        let options = ServerOptions {
+            telemetry: sink.clone(),
+            ..ServerOptions::default()
+        };
+        let recorded = &sink;
+        let (first, second) = with_test_server_options("telemetry", options, |addrs| async move {
+            let addresses = play_full_game(addrs).await;
+            // both clients are dropped, wait for the server to notice
+            while recorded.events().len() < 9 {
+                task::sleep(Duration::from_millis(50)).await;
+            }
+            addresses
+        });
+        let events = sink.events();
+        assert_eq!(
+            events[0],
+            TelemetryEvent::ConnectionOpened { address: first }
+        );
+        let first_id = match &events[1] {
+            TelemetryEvent::LoginSucceeded {
+                player_id,
+                player_name,
+            } if player_name == "小雨" => *player_id,
+            event => panic!("unexpected {:?}", event),
+        };
+        assert_eq!(
+            events[2],
+            TelemetryEvent::ConnectionOpened { address: second }
+        );
+        let second_id = match &events[3] {
+            TelemetryEvent::LoginSucceeded {
+                player_id,
+                player_name,
+            } if player_name == "雨雨" => *player_id,
+            event => panic!("unexpected {:?}", event),
+        };
+        assert!(matches!(events[4], TelemetryEvent::RoomCreated { .. }));
+        let (session_id, black_id) = match events[5] {
+            TelemetryEvent::GameStarted {
+                session_id,
+                black_player_id,
+                white_player_id,
+            } => {
+                assert_eq!(
+                    [black_player_id, white_player_id].iter().sum::<u64>(),
+                    first_id + second_id
+                );
+                (session_id, black_player_id)
+            }
+            ref event => panic!("unexpected {:?}", event),
+        };
+        assert!([first_id, second_id].contains(&black_id));
+        assert_eq!(
+            events[6],
+            TelemetryEvent::GameEnded {
+                session_id,
+                outcome: GameOutcome::BlackWins,
+            }
+        );
+        // the order of disconnections is not determined
+        let closed = &events[7..];
+        assert_eq!(closed.len(), 2);
+        for (address, player_id) in [(first, first_id), (second, second_id)] {
+            assert!(closed.contains(&TelemetryEvent::ConnectionClosed {
+                address,
+                player_id: Some(player_id),
+            }));
+        }
+    }
+
+    #[test]
+    fn test_leave_in_each_state() {
+        with_test_server("leave", |addrs| async move {
+            let (mut first, _) = create_account(addrs, "小雨").await;
+            let (mut second, _) = create_account(addrs, "雨雨").await;
+            let create_room = Messages::CreateRoom(SessionConfig::default(), None);
+            first.sender().send(create_room).await.unwrap();
+            let code =
+                match wait_for(&mut first, |rsp| matches!(rsp, Responses::RoomCreated(_))).await {
+                    Responses::RoomCreated(code) => code,
+                    _ => unreachable!(),
+                };
+            let token = RoomToken::from_code(&code).unwrap();
+            let join = Messages::JoinRoom(token, None);
+            second.sender().send(join).await.unwrap();
+            wait_for(&mut first, |rsp| *rsp == Responses::RoomReady).await;
+            first.sender().send(Messages::Ready).await.unwrap();
+            second.sender().send(Messages::Ready).await.unwrap();
+            wait_for(&mut first, |rsp| matches!(rsp, Responses::GameStarted(_))).await;
+            // in game: quit the game session, both back in the room
+            first.sender().send(Messages::Leave).await.unwrap();
+            for player in [&mut first, &mut second] {
+                wait_for(player, |rsp| matches!(rsp, Responses::JoinRoomSuccess(..))).await;
+            }
+            // in a room: quit the room
+            first.sender().send(Messages::Leave).await.unwrap();
+            wait_for(&mut first, |rsp| *rsp == Responses::QuitRoomSuccess).await;
+            wait_for(&mut second, |rsp| *rsp == Responses::OpponentQuitRoom).await;
+            // in the lobby: close the connection
+            first.sender().send(Messages::Leave).await.unwrap();
+            wait_for(&mut first, |rsp| *rsp == Responses::ConnectionClosing).await;
+            while let Some(received) = first.next().await {
+                assert!(!matches!(received, Received::Response(_)));
+            }
+        });
+    }
+
+    #[test]
+    fn test_close_reasons() {
+        with_test_server("close_reasons", |addrs| async move {
+            for (clean, names) in [(true, ["小雨", "雨雨"]), (false, ["小雪", "雪雪"])] {
+                let (mut closing, socket) = connect_with_socket(addrs).await;
+                sign_up(&mut closing, names[0]).await;
+                let (mut opponent, _) = create_account(addrs, names[1]).await;
+                // leaving before any move aborts the game instead
+                let config = SessionConfig::default();
+                let (black, white) = match start_game(&mut closing, &mut opponent, config).await {
+                    Color::Black => (&mut closing, &mut opponent),
+                    Color::White => (&mut opponent, &mut closing),
+                };
+                black.sender().send(Messages::Play(7, 7)).await.unwrap();
+                let played = |rsp: &Responses| matches!(rsp, Responses::FieldUpdate(_));
+                wait_for(white, played).await;
+                if clean {
+                    // closing the connection counts as an exit
+                    drop(closing);
+                } else {
+                    // losing the connection does not
+                    socket.shutdown(std::net::Shutdown::Both).unwrap();
+                }
+                let expected = if clean {
+                    Responses::OpponentExitGame
+                } else {
+                    Responses::OpponentDisconnected
+                };
+                let quit = |rsp: &Responses| {
+                    matches!(
+                        rsp,
+                        Responses::OpponentExitGame | Responses::OpponentDisconnected
+                    )
+                };
+                assert_eq!(wait_for(&mut opponent, quit).await, expected);
+            }
+        });
+    }
+
+    #[test]
+    fn test_start_acknowledgment() {
+        let config = SessionConfig::builder()
+            .play_timeout(1)
+            .start_ack_timeout(2)
+            .build();
+        let ended = |rsp: &Responses| {
+            matches!(
+                rsp,
+                Responses::FieldUpdate(_)
+                    | Responses::GameEndAborted
+                    | Responses::GameEndBlackTimeout
+            )
+        };
+        with_test_server("start_acknowledgment", |addrs| async move {
+            let (mut first, _) = create_account(addrs, "小晴").await;
+            let (mut second, _) = create_account(addrs, "晴晴").await;
+            let color = start_game(&mut first, &mut second, config.clone()).await;
+            let (black, mut white) = match color {
+                Color::Black => (first, second),
+                Color::White => (second, first),
+            };
+            black.sender().send(Messages::Ack).await.unwrap();
+            // longer than the play timeout of black
+            task::sleep(Duration::from_millis(1500)).await;
+            white.sender().send(Messages::Ack).await.unwrap();
+            black.sender().send(Messages::Play(7, 7)).await.unwrap();
+            let played = wait_for(&mut white, ended).await;
+            assert!(matches!(played, Responses::FieldUpdate(_)));
+            // white never acknowledges
+            let (mut first, _) = create_account(addrs, "小雷").await;
+            let (mut second, _) = create_account(addrs, "雷雷").await;
+            let (mut black, mut white) = match start_game(&mut first, &mut second, config).await {
+                Color::Black => (first, second),
+                Color::White => (second, first),
+            };
+            black.sender().send(Messages::Ack).await.unwrap();
+            for player in [&mut black, &mut white] {
+                assert_eq!(wait_for(player, ended).await, Responses::GameEndAborted);
+            }
+        });
+    }
+
+    #[test]
+    fn test_game_summary() {
+        with_test_server("game_summary", |addrs| async move {
+            let (mut first, _) = create_account(addrs, "小霜").await;
+            let (mut second, _) = create_account(addrs, "霜霜").await;
+            let color = start_game(&mut first, &mut second, SessionConfig::default()).await;
+            let (mut black, mut white) = match color {
+                Color::Black => (first, second),
+                Color::White => (second, first),
+            };
+            play_black_wins(&mut black, &mut white).await;
+            let summary = |rsp: &Responses| matches!(rsp, Responses::GameSummary { .. });
+            let black_summary = wait_for(&mut black, summary).await;
+            // both players get the same summary
+            assert_eq!(wait_for(&mut white, summary).await, black_summary);
+            match black_summary {
+                Responses::GameSummary {
+                    moves,
+                    duration_ms,
+                    black_time_ms,
+                    white_time_ms,
+                    result,
+                } => {
+                    assert_eq!(moves, 9);
+                    assert_eq!(result, GameResult::BlackWins);
+                    assert!(black_time_ms + white_time_ms <= duration_ms);
+                }
+                _ => unreachable!(),
+            }
+        });
+    }
+
+    #[test]
+    fn test_disconnect_within_final_undo_window() {
+        let config = SessionConfig::builder().final_undo_window(60).build();
+        with_test_server("final_undo_disconnect", |addrs| async move {
+            let (mut first, _) = create_account(addrs, "小霁").await;
+            let (mut second, _) = create_account(addrs, "霁霁").await;
+            let color = start_game(&mut first, &mut second, config).await;
+            let (mut black, mut white) = match color {
+                Color::Black => (first, second),
+                Color::White => (second, first),
+            };
+            play_black_wins(&mut black, &mut white).await;
+            wait_for(&mut white, |rsp| {
+                matches!(rsp, Responses::GameEndPending { .. })
+            })
+            .await;
+            // the loser leaves before the result is final
+            drop(white);
+            let ended = |rsp: &Responses| {
+                matches!(
+                    rsp,
+                    Responses::GameEndWithBoard { .. }
+                        | Responses::OpponentExitGame
+                        | Responses::OpponentDisconnected
+                )
+            };
+            match wait_for(&mut black, ended).await {
+                Responses::GameEndWithBoard { result, .. } => {
+                    assert_eq!(result, GameResult::BlackWins)
+                }
+                rsp => panic!("game ended without the result: {:?}", rsp),
+            }
+            let black_name = match color {
+                Color::Black => "小霁",
+                Color::White => "霁霁",
+            };
+            match wait_for(&mut black, |rsp| matches!(rsp, Responses::RoomScores(..))).await {
+                Responses::RoomScores(first, second) => {
+                    for (name, score) in [first, second] {
+                        assert_eq!(score, (name == black_name) as u16);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        });
+    }
+
+    #[test]
+    fn test_abandon_score_kept() {
+        let config = SessionConfig::builder()
+            .abandon_policy(AbandonPolicy::ExitAsLoss)
+            .build();
+        let scores = |rsp: &Responses| matches!(rsp, Responses::RoomScores(..));
+        with_test_server("abandon_score", |addrs| async move {
+            let (mut owner, _) = create_account(addrs, "小雹").await;
+            let (mut opponent, _) = create_account(addrs, "雹雹").await;
+            let token = create_room(&mut owner, config).await;
+            let (black, white) = match start_game_in(&mut owner, &mut opponent, token.clone()).await
+            {
+                Color::Black => (&mut owner, &mut opponent),
+                Color::White => (&mut opponent, &mut owner),
+            };
+            black.sender().send(Messages::Play(7, 7)).await.unwrap();
+            wait_for(white, played(7, 7)).await;
+            opponent.sender().send(Messages::ExitGame).await.unwrap();
+            assert_eq!(
+                wait_for(&mut owner, scores).await,
+                Responses::RoomScores(("小雹".to_string(), 1), ("雹雹".to_string(), 0))
+            );
+            // the opponent comes back to the same room
+            let (mut opponent, _) = connect(addrs).await;
+            let login = Messages::Login("雹雹".to_string(), "password".to_string());
+            opponent.sender().send(login).await.unwrap();
+            wait_for(&mut opponent, |rsp| {
+                matches!(rsp, Responses::LoginSuccess(_))
+            })
+            .await;
+            let owner_color = start_game_in(&mut owner, &mut opponent, token).await;
+            let (black, white) = match owner_color {
+                Color::Black => (&mut owner, &mut opponent),
+                Color::White => (&mut opponent, &mut owner),
+            };
+            play_black_wins(black, white).await;
+            // the abandoned game still counts
+            let owner_wins = (owner_color == Color::Black) as u16;
+            assert_eq!(
+                wait_for(&mut owner, scores).await,
+                Responses::RoomScores(
+                    ("小雹".to_string(), 1 + owner_wins),
+                    ("雹雹".to_string(), 1 - owner_wins)
+                )
+            );
+        });
+    }
+
+    #[test]
+    fn test_failed_logins_release_capacity() {
+        let options = ServerOptions {
+            server_max_conn: 1,
+            ..ServerOptions::default()
+        };
+        with_test_server_options("failed_logins", options, |addrs| async move {
+            // closed before login
+            let (client, socket) = connect_with_socket(addrs).await;
+            drop(client);
+            drop(socket);
+            // closed before TLS
+            let tcp = TcpStream::connect(addrs).await.unwrap();
+            drop(tcp);
+            // closed after a failed login
+            let (mut client, _) = connect(addrs).await;
+            let login = Messages::Login("小雷".to_string(), "password".to_string());
+            client.sender().send(login).await.unwrap();
+            wait_for(&mut client, |rsp| matches!(rsp, Responses::LoginFailure(_))).await;
+            drop(client);
+            // the only slot is free again
+            create_account(addrs, "小雷").await;
+        });
+    }
+
+    #[test]
+    fn test_invalid_room_config() {
+        with_test_server("invalid_room_config", |addrs| async move {
+            let (mut owner, _) = create_account(addrs, "小雾").await;
+            let no_board = SessionConfig::builder().board_size(0, 0).build();
+            let invalid = Messages::CreateRoom(no_board, None);
+            owner.sender().send(invalid).await.unwrap();
+            let answered = |rsp: &Responses| {
+                matches!(
+                    rsp,
+                    Responses::InvalidRoomConfig | Responses::RoomCreated(_)
+                )
+            };
+            assert_eq!(
+                wait_for(&mut owner, answered).await,
+                Responses::InvalidRoomConfig
+            );
+            // still in the lobby
+            create_room(&mut owner, SessionConfig::default()).await;
+        });
+    }
+
+    #[test]
+    fn test_name_normalization() {
+        let (composed, decomposed) = ("Andr\u{e9}", "Andre\u{301}");
+        let failed = |rsp: &Responses| {
+            matches!(
+                rsp,
+                Responses::CreateAccountFailure(_) | Responses::LoginFailure(_)
+            )
+        };
+        with_test_server("name_normalization", |addrs| async move {
+            let (_online, _) = create_account(addrs, composed).await;
+            let (mut client, _) = connect(addrs).await;
+            let account = Messages::CreateAccount(decomposed.to_string(), "password".to_string());
+            client.sender().send(account).await.unwrap();
+            assert_eq!(
+                wait_for(&mut client, failed).await,
+                Responses::CreateAccountFailure(CreateAccountFailure::AccountAlreadyExist)
+            );
+            let login = Messages::Login(decomposed.to_string(), "password".to_string());
+            client.sender().send(login).await.unwrap();
+            assert_eq!(
+                wait_for(&mut client, failed).await,
+                Responses::LoginFailure(LoginFailure::AlreadyLoggedIn)
+            );
+        });
+    }
+
+    #[test]
+    fn test_db_init_failed() {
+        let addrs = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        // a file cannot contain a database directory
+        let file_path = std::env::temp_dir().join("wuziqi_test_db_init_failed");
+        File::create(&file_path).unwrap();
+        let db_path = file_path.join("db");
+        let options = ServerOptions::default();
+        let result = block_on(start_server(addrs, server_config(), &db_path, options));
+        assert!(matches!(result, Err(StartServerError::DbInitFailed(_))));
+    }
+}
@@ -0,0 +1,75 @@
+//! Unicode normalization of user names and chat.
+//!
+//! Names are always put in NFC, so that a composed "é" and an "e" followed by
+//! a combining accent are the same name, both as the key of the user database
+//! and of the online players.
+use unicode_normalization::UnicodeNormalization;
+
+/// how names and chat are normalized besides NFC of names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Normalization {
+    /// names differing only in case are the same name, kept in lower case
+    pub fold_name_case: bool,
+    /// chat messages are put in NFC too
+    pub chat: bool,
+}
+
+impl Normalization {
+    pub fn normalize_name(&self, name: &str) -> String {
+        let name = name.nfc().collect::<String>();
+        if self.fold_name_case {
+            // lower case may decompose, compose again
+            name.to_lowercase().nfc().collect()
+        } else {
+            name
+        }
+    }
+
+    pub fn normalize_chat(&self, msg: String) -> String {
+        if self.chat {
+            msg.nfc().collect()
+        } else {
+            msg
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_normalization {
+    use super::*;
+
+    const COMPOSED: &str = "Andr\u{e9}";
+    const DECOMPOSED: &str = "Andre\u{301}";
+
+    #[test]
+    fn test_name_forms_collide() {
+        let normalization = Normalization::default();
+        assert_ne!(COMPOSED, DECOMPOSED);
+        assert_eq!(
+            normalization.normalize_name(COMPOSED),
+            normalization.normalize_name(DECOMPOSED)
+        );
+        assert_eq!(normalization.normalize_name(DECOMPOSED), COMPOSED);
+        // case is kept unless folded
+        assert_ne!(normalization.normalize_name("andré"), COMPOSED);
+        let folding = Normalization {
+            fold_name_case: true,
+            ..Normalization::default()
+        };
+        assert_eq!(
+            folding.normalize_name(DECOMPOSED),
+            folding.normalize_name("ANDRÉ")
+        );
+    }
+
+    #[test]
+    fn test_chat_normalization() {
+        let msg = DECOMPOSED.to_string();
+        assert_eq!(Normalization::default().normalize_chat(msg.clone()), msg);
+        let normalization = Normalization {
+            chat: true,
+            ..Normalization::default()
+        };
+        assert_eq!(normalization.normalize_chat(msg), COMPOSED);
+    }
+}
@@ -0,0 +1,69 @@
+//! Sliding window rate limits.
+//!
+//! Used for room creation per player, and for rejected messages per connection.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// at most `limit` events within any `window`
+pub(crate) struct RateLimit {
+    limit: usize,
+    window: Duration,
+    times: VecDeque<Instant>,
+}
+
+impl RateLimit {
+    pub(crate) fn new(limit: usize, window: Duration) -> Self {
+        RateLimit {
+            limit,
+            window,
+            times: VecDeque::new(),
+        }
+    }
+
+    /// record an event at `now`, `false` if the limit is reached
+    pub(crate) fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.window)
+        {
+            self.times.pop_front();
+        }
+        if self.times.len() >= self.limit {
+            false
+        } else {
+            self.times.push_back(now);
+            true
+        }
+    }
+
+    /// number of events recorded within the window before the last `allow`
+    pub(crate) fn count(&self) -> usize {
+        self.times.len()
+    }
+
+    /// whether the latest event is still within the window
+    pub(crate) fn is_active(&self) -> bool {
+        self.times.back().is_some_and(|t| t.elapsed() < self.window)
+    }
+}
+
+#[cfg(test)]
+mod test_rate_limit {
+    use super::*;
+
+    #[test]
+    fn test_sliding_window() {
+        let window = Duration::from_secs(10);
+        let mut limit = RateLimit::new(2, window);
+        let start = Instant::now();
+        assert!(limit.allow(start));
+        assert!(limit.allow(start + Duration::from_secs(1)));
+        assert!(!limit.allow(start + Duration::from_secs(2)));
+        assert_eq!(limit.count(), 2);
+        // the first event leaves the window
+        assert!(limit.allow(start + window));
+        assert!(!limit.allow(start + window));
+        assert!(limit.is_active());
+    }
+}
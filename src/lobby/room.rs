@@ -1,9 +1,13 @@
-use crate::game::SessionConfig;
-use crate::lobby::client_connection::ClientConnection;
+use crate::game::{AbandonPolicy, GameView, RoomPersistence, SessionConfig, State};
+use crate::lobby::client_connection::{send_or_timeout, ClientConnection, Rejections};
 use crate::lobby::game_session::{start_game_session, ExitState, PlayerResult};
-use crate::lobby::messages::{Messages, Responses, RoomState};
+use crate::lobby::messages::{
+    JoinRoomFailure, Messages, Responses, RoomState, RoomStatus, RoomSummary,
+};
+use crate::lobby::normalization::Normalization;
 use crate::lobby::room::Position::{First, Second};
-use crate::lobby::room_manager::RoomManager;
+use crate::lobby::room_manager::{RoomDirectoryEntry, RoomManager};
+use crate::lobby::telemetry::{Telemetry, TelemetryEvent};
 use crate::lobby::token::RoomToken;
 use crate::stream_utility::{Plug, UnplugHandle};
 use crate::CHANNEL_SIZE;
@@ -12,25 +16,41 @@ use async_std::sync::Mutex;
 use async_std::task;
 use async_std::task::block_on;
 use futures::StreamExt;
-use log::{error, info};
+use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::{random, Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub(crate) struct Room {
     inner: Arc<Mutex<RoomInner>>,
+    telemetry: Telemetry,
 }
 
+/// reads a room without owning it, dropping a handle leaves the room running
+#[derive(Clone)]
+pub(crate) struct RoomHandle(Arc<Mutex<RoomInner>>);
+
 impl Room {
     pub(crate) fn empty(
         token: RoomToken,
         session_config: SessionConfig,
+        password: Option<String>,
         session_counter: Arc<AtomicU64>,
         manager: RoomManager,
     ) -> Self {
+        let telemetry = manager.telemetry().clone();
+        telemetry.emit(TelemetryEvent::RoomCreated {
+            room: token.clone(),
+        });
         Room {
-            inner: RoomInner::empty(token, session_config, session_counter, manager),
+            inner: RoomInner::empty(token, session_config, password, session_counter, manager),
+            telemetry,
         }
     }
 
@@ -38,9 +58,144 @@ impl Room {
         self.inner.lock().await.join(conn).await
     }
 
+    /// join after checking the ban list and the room password
+    pub(crate) async fn try_join(
+        &self,
+        conn: ClientConnection,
+        password: Option<String>,
+    ) -> Result<Position, ClientConnection> {
+        let mut inner = self.inner.lock().await;
+        match inner.check_join(conn.player_name(), password.as_deref()) {
+            Ok(()) => inner.join(conn).await,
+            Err(failure) => {
+                warn!(
+                    "player ({}: {}) refused by room {}: {:?}",
+                    conn.player_name(),
+                    conn.player_id(),
+                    inner.token.as_code(),
+                    failure
+                );
+                let _ = conn
+                    .sender()
+                    .send(Responses::JoinRoomFailure(failure))
+                    .await;
+                Err(conn)
+            }
+        }
+    }
+
     pub(crate) async fn inactive_since(&self) -> Option<Instant> {
         self.inner.lock().await.inactive_since
     }
+
+    /// response to `QueryRoom`, has no side effect
+    pub(crate) async fn info(&self) -> Responses {
+        self.inner.lock().await.info()
+    }
+
+    /// response to `PeekGame`, has no side effect on the game
+    pub(crate) async fn peek(&self, player_id: u64, password: Option<&str>) -> Responses {
+        let inner = self.inner.lock().await;
+        if !inner.may_spectate(player_id, password) {
+            warn!(
+                "player {} denied spectating room {}",
+                player_id,
+                inner.token.as_code()
+            );
+            return Responses::SpectateDenied;
+        }
+        let game = inner.game.clone();
+        drop(inner);
+        let Some(view) = game else {
+            return Responses::NoGameInProgress;
+        };
+        let snapshot = view.snapshot().await;
+        let millis = |clock: Option<Duration>| clock.map(|c| c.as_millis() as u64);
+        Responses::GameSnapshot {
+            field: snapshot.field,
+            black_clock: millis(snapshot.black_clock),
+            white_clock: millis(snapshot.white_clock),
+            to_move: snapshot.to_move,
+        }
+    }
+
+    pub(crate) fn handle(&self) -> RoomHandle {
+        RoomHandle(self.inner.clone())
+    }
+
+    pub(crate) async fn summary(&self, status: RoomStatus) -> RoomSummary {
+        let inner = self.inner.lock().await;
+        RoomSummary {
+            token: inner.token.as_code(),
+            occupants: inner.occupant_count(),
+            session_config: inner.session_config.clone(),
+            status,
+        }
+    }
+
+    /// response to `DebugRoom`
+    pub(crate) async fn debug(&self) -> Responses {
+        Responses::RoomDebug(self.inner.lock().await.debug_string())
+    }
+
+    pub(crate) async fn is_full(&self) -> bool {
+        self.inner.lock().await.empty_position().is_none()
+    }
+
+    pub(crate) async fn is_empty(&self) -> bool {
+        self.inner.lock().await.occupant_count() == 0
+    }
+
+    /// send `rsp` to all occupants
+    pub(crate) async fn notify(&self, rsp: Responses) {
+        let inner = self.inner.lock().await;
+        inner.send_response(First, rsp.clone()).await;
+        inner.send_response(Second, rsp).await;
+    }
+
+    /// Disconnect occupants not in a game session, who receive `RoomClosed`.
+    ///
+    /// Occupants in a game session are left to finish the game,
+    /// and get disconnected on returning if the room is dropped by then.
+    pub(crate) async fn force_close(&self) {
+        let mut inner = self.inner.lock().await;
+        for pos in [First, Second] {
+            let in_session = inner
+                .player_info(pos)
+                .as_ref()
+                .is_some_and(|info| info.in_session());
+            if !in_session {
+                drop(inner.leave(pos, Responses::RoomClosed).await);
+            }
+        }
+    }
+}
+
+/// salted hash of a room password, the password itself is never stored
+struct RoomPassword {
+    salt: u64,
+    hash: u64,
+}
+
+impl RoomPassword {
+    fn new(password: &str) -> Self {
+        let salt = random();
+        RoomPassword {
+            salt,
+            hash: Self::hash(salt, password),
+        }
+    }
+
+    fn matches(&self, password: &str) -> bool {
+        Self::hash(self.salt, password) == self.hash
+    }
+
+    fn hash(salt: u64, password: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        salt.hash(&mut hasher);
+        password.hash(&mut hasher);
+        hasher.finish()
+    }
 }
 
 /// dropping a `Room`
@@ -50,20 +205,38 @@ struct RoomInner {
     seats: (Option<PlayerInfo>, Option<PlayerInfo>),
     room_msg_sender: Sender<(Position, Messages)>,
     session_config: SessionConfig,
+    // config proposed for the next games, and by whom
+    config_proposal: Option<(Position, SessionConfig)>,
+    password: Option<RoomPassword>,
+    // player id of the first player joining the room
+    creator: Option<u64>,
+    // usernames banned by the creator
+    banned: HashSet<String>,
+    // shared by all rooms of the server, see `RoomManager`
     session_counter: Arc<AtomicU64>,
     // number of winnings
     scores: (u16, u16),
+    // names of the players the scores count for, kept after they leave
+    score_holders: (Option<String>, Option<String>),
     // stop background message task
     killer: Option<UnplugHandle<Receiver<(Position, Messages)>>>,
     // room lifetime management
     inactive_since: Option<Instant>,
+    // view of the running game session
+    game: Option<GameView>,
 }
 
 impl RoomInner {
+    /// id of the next game session, unique among all rooms of the server
+    fn next_session_id(&self) -> u64 {
+        self.session_counter.fetch_add(1, SeqCst)
+    }
+
     /// create an empty room
     fn empty(
         token: RoomToken,
         session_config: SessionConfig,
+        password: Option<String>,
         session_counter: Arc<AtomicU64>,
         room_manager: RoomManager,
     ) -> Arc<Mutex<RoomInner>> {
@@ -74,10 +247,16 @@ impl RoomInner {
             seats: (None, None),
             room_msg_sender: inner_channel.0,
             session_config,
+            config_proposal: None,
+            password: password.as_deref().map(RoomPassword::new),
+            creator: None,
+            banned: HashSet::new(),
             session_counter,
             scores: (0, 0),
+            score_holders: (None, None),
             killer: Some(room_killer),
             inactive_since: Some(Instant::now()),
+            game: None,
         }));
         run_room(room.clone(), recv, room_manager);
         room
@@ -87,7 +266,7 @@ impl RoomInner {
     /// - send join success message to player
     /// - send OpponentJoinRoom to opponent
     /// - start listening to player
-    /// - clear session score board
+    /// - clear session score board, unless the player comes back to their seat
     async fn join(&mut self, conn: ClientConnection) -> Result<Position, ClientConnection> {
         if let Some(pos) = self.empty_position() {
             let room_state = match self.player_info(pos.opponent()) {
@@ -108,8 +287,9 @@ impl RoomInner {
             );
             self.run_player_message_loop(conn, pos);
             let my_name = player_info.player_name.clone();
+            self.creator.get_or_insert(player_info.player_id);
             self.player_info_mut(pos).replace(player_info);
-            self.clear_score();
+            self.hold_score(pos, &my_name);
             self.inactive_since = None;
             let _ = self
                 .send_response(
@@ -120,13 +300,103 @@ impl RoomInner {
             let _ = self
                 .send_response(pos.opponent(), Responses::OpponentJoinRoom(my_name))
                 .await;
+            self.notify_if_full().await;
             Ok(pos)
         } else {
-            let _ = conn.sender().send(Responses::JoinRoomFailureRoomFull).await;
+            let _ = conn
+                .sender()
+                .send(Responses::JoinRoomFailure(JoinRoomFailure::RoomFull))
+                .await;
             Err(conn)
         }
     }
 
+    /// banned players are refused, rooms without a password accept any password
+    fn check_join(&self, player_name: &str, password: Option<&str>) -> Result<(), JoinRoomFailure> {
+        if self.banned.contains(player_name) {
+            return Err(JoinRoomFailure::Banned);
+        }
+        if self.password_matches(password) {
+            Ok(())
+        } else {
+            Err(JoinRoomFailure::BadPassword)
+        }
+    }
+
+    /// password protected rooms are spectated with the password or by their creator
+    fn may_spectate(&self, player_id: u64, password: Option<&str>) -> bool {
+        self.creator == Some(player_id) || self.password_matches(password)
+    }
+
+    /// rooms without a password accept any password
+    fn password_matches(&self, password: Option<&str>) -> bool {
+        match (&self.password, password) {
+            (None, _) => true,
+            (Some(expected), Some(password)) => expected.matches(password),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// send `RoomReady` to both players when both seats are taken
+    async fn notify_if_full(&self) {
+        if self.occupant_count() == 2 {
+            self.send_response(First, Responses::RoomReady).await;
+            self.send_response(Second, Responses::RoomReady).await;
+        }
+    }
+
+    fn info(&self) -> Responses {
+        Responses::RoomInfo {
+            occupants: self.occupant_names(),
+            session_config: self.session_config.clone(),
+            in_game: self.in_game(),
+        }
+    }
+
+    fn occupant_names(&self) -> Vec<String> {
+        [&self.seats.0, &self.seats.1]
+            .iter()
+            .filter_map(|s| s.as_ref().map(|info| info.player_name.clone()))
+            .collect()
+    }
+
+    fn in_game(&self) -> bool {
+        [&self.seats.0, &self.seats.1]
+            .iter()
+            .any(|s| s.as_ref().is_some_and(|info| info.in_session()))
+    }
+
+    /// a snapshot of room state for diagnostics
+    fn debug_string(&self) -> String {
+        let seat = |s: &Option<PlayerInfo>| match s {
+            None => "empty".to_string(),
+            Some(info) => format!(
+                "{} ({}, {}{})",
+                info.player_name,
+                info.player_id,
+                if info.is_ready() { "ready" } else { "unready" },
+                if info.in_session() {
+                    ", in session"
+                } else {
+                    ""
+                }
+            ),
+        };
+        let inactive = match self.inactive_since {
+            None => "active".to_string(),
+            Some(t) => format!("inactive for {}s", t.elapsed().as_secs()),
+        };
+        format!(
+            "room {}: seats [{}, {}], scores {:?}, {}, {:?}",
+            self.token.as_code(),
+            seat(&self.seats.0),
+            seat(&self.seats.1),
+            self.scores,
+            inactive,
+            self.session_config
+        )
+    }
+
     fn player_scored(&mut self, pos: Position) {
         let score = match pos {
             First => &mut self.scores.0,
@@ -135,25 +405,52 @@ impl RoomInner {
         *score += 1;
     }
 
+    /// put `conn` back to its seat unready after a game session,
+    /// or return it if the seat was emptied during the session
+    fn return_to_seat(
+        &mut self,
+        conn: ClientConnection,
+        pos: Position,
+    ) -> Option<ClientConnection> {
+        let Some(info) = self.player_info_mut(pos) else {
+            error!(
+                "player ({}: {}) seat emptied during the game in room {}",
+                conn.player_name(),
+                conn.player_id(),
+                self.token.as_code()
+            );
+            return Some(conn);
+        };
+        let conn = info.return_from_session(conn);
+        info.unready();
+        self.run_player_message_loop(conn, pos);
+        None
+    }
+
     /// when both players have returned from game session.
-    /// call this only when both seats are `Some`
     ///
-    /// update score responses
+    /// update score responses,
+    /// return the connections whose seats were emptied, which should go back to the lobby
     async fn join_both_on_session_return(
         &mut self,
         conn1: ClientConnection,
         conn2: ClientConnection,
-    ) {
-        let p1_info = self.player_info_mut(First).as_mut().unwrap();
-        let conn1 = p1_info.return_from_session(conn1);
-        let p1_name = p1_info.player_name.clone();
-        p1_info.unready();
-        let p2_info = self.player_info_mut(Second).as_mut().unwrap();
-        let conn2 = p2_info.return_from_session(conn2);
-        let p2_name = p2_info.player_name.clone();
-        p2_info.unready();
-        self.run_player_message_loop(conn1, First);
-        self.run_player_message_loop(conn2, Second);
+    ) -> Vec<ClientConnection> {
+        let stray1 = self.return_to_seat(conn1, First);
+        let stray2 = self.return_to_seat(conn2, Second);
+        let (Some(p1_info), Some(p2_info)) = (self.player_info(First), self.player_info(Second))
+        else {
+            // the player still seated is alone in the room
+            for pos in [First, Second] {
+                self.send_response(
+                    pos,
+                    Responses::JoinRoomSuccess(self.token.as_code(), RoomState::Empty),
+                )
+                .await;
+            }
+            return stray1.into_iter().chain(stray2).collect();
+        };
+        let (p1_name, p2_name) = (p1_info.player_name.clone(), p2_info.player_name.clone());
         // send responses on returning to room
         self.send_response(
             First,
@@ -174,33 +471,105 @@ impl RoomInner {
         let score_rsp = Responses::RoomScores((p1_name, self.scores.0), (p2_name, self.scores.1));
         self.send_response(First, score_rsp.clone()).await;
         self.send_response(Second, score_rsp).await;
+        Vec::new()
+    }
+
+    /// score a game for `pos` whose opponent lost or abandoned it and left,
+    /// only `pos` receives the scores since the opponent is leaving,
+    /// the scores stand if the opponent comes back
+    async fn score_abandoned_game(&mut self, pos: Position) {
+        self.player_scored(pos);
+        if let (Some(p1), Some(p2)) = &self.seats {
+            let score_rsp = Responses::RoomScores(
+                (p1.player_name.clone(), self.scores.0),
+                (p2.player_name.clone(), self.scores.1),
+            );
+            self.send_response(pos, score_rsp).await;
+        }
     }
 
-    /// this function does not deal with score boards
-    async fn join_single_on_session_return(&mut self, exit_state: ExitState, pos: Position) {
+    /// this function does not deal with score boards,
+    /// return the connection if its seat was emptied, which should go back to the lobby
+    async fn join_single_on_session_return(
+        &mut self,
+        exit_state: ExitState,
+        pos: Position,
+    ) -> Option<ClientConnection> {
         if let ExitState::ReturnRoom(conn, _) = exit_state {
-            let p_info = self.player_info_mut(pos).as_mut().unwrap();
-            let conn = p_info.return_from_session(conn);
-            p_info.unready();
-            self.run_player_message_loop(conn, pos);
+            if let Some(conn) = self.return_to_seat(*conn, pos) {
+                return Some(conn);
+            }
             self.send_response(
                 pos,
                 Responses::JoinRoomSuccess(self.token.as_code(), RoomState::Empty),
             )
-            .await
+            .await;
         } else {
             self.exit(pos).await;
         }
+        None
     }
 
     /// `pos` is *my* position
     async fn chat(&mut self, pos: Position, message: String) {
-        let name = self.player_info(pos).as_ref().unwrap().player_name.clone();
+        let Some(info) = self.player_info(pos) else {
+            return;
+        };
+        let name = info.player_name.clone();
+        let message = info.normalization.normalize_chat(message);
         self.send_response(pos.opponent(), Responses::ChatMessage(name, message))
             .await;
     }
 
-    /// return unplug handles if both ready
+    /// log non-fatal client error and acknowledge it
+    async fn client_error(&mut self, pos: Position, message: String) {
+        if let Some(info) = self.player_info(pos) {
+            warn!(
+                "player ({}: {}) non-fatal client error {} in room {}",
+                info.player_name,
+                info.player_id,
+                message,
+                self.token.as_code()
+            );
+        }
+        self.send_response(pos, Responses::ClientErrorAcknowledged)
+            .await;
+    }
+
+    /// Propose `config` for the next games, return whether it is applied.
+    ///
+    /// It is applied when the opponent proposed the same config,
+    /// or when there is no opponent, an invalid config is refused.
+    async fn update_config(&mut self, pos: Position, config: SessionConfig) -> bool {
+        if !config.is_valid() {
+            self.send_response(pos, Responses::InvalidRoomConfig).await;
+            return false;
+        }
+        let agreed = match (&self.config_proposal, self.player_info(pos.opponent())) {
+            (_, None) => true,
+            (Some((proposer, proposed)), Some(_)) => {
+                *proposer == pos.opponent() && *proposed == config
+            }
+            (None, Some(_)) => false,
+        };
+        if agreed {
+            info!("room {} config updated: {:?}", self.token.as_code(), config);
+            self.config_proposal = None;
+            self.session_config = config.clone();
+            self.send_response(First, Responses::RoomConfigUpdated(config.clone()))
+                .await;
+            self.send_response(Second, Responses::RoomConfigUpdated(config))
+                .await;
+        } else {
+            self.config_proposal = Some((pos, config.clone()));
+            self.send_response(pos.opponent(), Responses::RoomConfigProposed(config))
+                .await;
+        }
+        agreed
+    }
+
+    /// return unplug handles if both ready,
+    /// a player alone is not ready with `ready_needs_opponent`
     async fn ready(
         &mut self,
         pos: Position,
@@ -208,38 +577,62 @@ impl RoomInner {
         UnplugHandle<ClientConnection>,
         UnplugHandle<ClientConnection>,
     )> {
+        if self.session_config.ready_needs_opponent && self.player_info(pos.opponent()).is_none() {
+            self.send_response(pos, Responses::CannotReadyEmptyRoom)
+                .await;
+            return None;
+        }
         let both_ready = if let Some(info_1) = self.player_info_mut(pos) {
             info_1.ready();
             // if opponent is ready
             if let Some(info_2) = self.player_info(pos.opponent()) {
-                let _ = info_2.sender.send(Responses::OpponentReady).await;
-                info_2.is_ready()
+                let opponent_ready = info_2.is_ready();
+                self.send_response(pos.opponent(), Responses::OpponentReady)
+                    .await;
+                opponent_ready
             } else {
                 false
             }
         } else {
             false
         };
-        if both_ready {
-            Some((
-                self.player_info_mut(First)
-                    .as_mut()
-                    .unwrap()
-                    .start_session()
-                    .unwrap(),
-                self.player_info_mut(Second)
-                    .as_mut()
-                    .unwrap()
-                    .start_session()
-                    .unwrap(),
-            ))
-        } else {
-            None
+        if !both_ready {
+            return None;
+        }
+        let handle1 = self
+            .player_info_mut(First)
+            .as_mut()
+            .and_then(PlayerInfo::start_session);
+        let handle2 = self
+            .player_info_mut(Second)
+            .as_mut()
+            .and_then(PlayerInfo::start_session);
+        match (handle1, handle2) {
+            (Some(handle1), Some(handle2)) => {
+                // the game starts with the current config
+                self.config_proposal = None;
+                Some((handle1, handle2))
+            }
+            (handle1, handle2) => {
+                // a seat lost its connection, keep the room as it was
+                error!(
+                    "room {} cannot start a game, a player is not in the room",
+                    self.token.as_code()
+                );
+                for (handle, pos) in [(handle1, First), (handle2, Second)] {
+                    if let Some(info) = self.player_info_mut(pos) {
+                        info.unplug_handle = handle;
+                    }
+                }
+                None
+            }
         }
     }
 
     async fn unready(&mut self, pos: Position) {
-        let info = self.player_info_mut(pos).as_mut().unwrap();
+        let Some(info) = self.player_info_mut(pos) else {
+            return;
+        };
         info.unready();
         let _ = self
             .send_response(pos.opponent(), Responses::OpponentUnready)
@@ -247,23 +640,87 @@ impl RoomInner {
     }
 
     async fn exit(&mut self, pos: Position) -> Option<ClientConnection> {
-        let _ = self
-            .send_response(pos.opponent(), Responses::OpponentQuitRoom)
-            .await;
-        let _ = self.send_response(pos, Responses::QuitRoomSuccess).await;
-        self.clear_score();
+        self.leave(pos, Responses::QuitRoomSuccess).await
+    }
+
+    /// whether the room closes when the player at `pos` exits,
+    /// i.e. the room is `OwnerBound` and the player is its creator
+    fn closes_on_exit(&self, pos: Position) -> bool {
+        self.session_config.room_persistence == RoomPersistence::OwnerBound
+            && self
+                .player_info(pos)
+                .as_ref()
+                .is_some_and(|info| self.creator == Some(info.player_id))
+    }
+
+    /// the room creator bans `player_name`, ejecting the opponent if that is them
+    async fn ban(&mut self, pos: Position, player_name: String) -> Option<ClientConnection> {
+        let info = self.player_info(pos).as_ref()?;
+        if self.creator != Some(info.player_id) {
+            warn!(
+                "player ({}: {}) BanFromRoom permission denied in room {}",
+                info.player_name,
+                info.player_id,
+                self.token.as_code()
+            );
+            self.send_response(pos, Responses::PermissionDenied).await;
+            return None;
+        }
+        // as the names of players are normalized at login
+        let player_name = info.normalization.normalize_name(&player_name);
+        if info.player_name == player_name {
+            return None;
+        }
+        info!(
+            "player {} banned from room {}",
+            player_name,
+            self.token.as_code()
+        );
+        let is_seated = self
+            .player_info(pos.opponent())
+            .as_ref()
+            .is_some_and(|info| info.player_name == player_name);
+        self.banned.insert(player_name);
+        if is_seated {
+            self.leave(pos.opponent(), Responses::BannedFromRoom).await
+        } else {
+            None
+        }
+    }
+
+    /// remove the player at `pos`, who receives `rsp`
+    async fn leave(&mut self, pos: Position, rsp: Responses) -> Option<ClientConnection> {
         let mut info = self.player_info_mut(pos).take()?;
+        self.config_proposal = None;
+        self.send_response(pos.opponent(), Responses::OpponentQuitRoom)
+            .await;
+        let _ = send_or_timeout(&info.sender, rsp).await;
         if let (None, None) = self.seats {
             self.inactive_since = Some(Instant::now());
+            self.clear_score();
+            self.score_holders = (None, None);
         }
         info.unplug_handle.take()?.unplug().await
     }
 
-    /// internal function for sending responses to a player
+    /// internal function for sending responses to a player,
+    /// a slow player (response channel full) gets `ExitGame`
     async fn send_response(&self, pos: Position, rsp: Responses) {
         let seat = self.player_info(pos);
         if let Some(info) = seat {
-            let _ = info.sender.send(rsp).await;
+            if send_or_timeout(&info.sender, rsp).await.is_err() {
+                warn!(
+                    "player ({}: {}) response channel full in room {}, exit game",
+                    info.player_name,
+                    info.player_id,
+                    self.token.as_code()
+                );
+                // the room message loop might be waiting for the lock held by the caller
+                let sender = self.room_msg_sender.clone();
+                task::spawn(async move {
+                    let _ = sender.send((pos, Messages::ExitGame)).await;
+                });
+            }
         }
     }
 
@@ -272,6 +729,19 @@ impl RoomInner {
         self.scores = (0, 0);
     }
 
+    /// the scores of the seat at `pos` count for `name`,
+    /// cleared unless `name` held them before
+    fn hold_score(&mut self, pos: Position, name: &str) {
+        let holder = match pos {
+            First => &mut self.score_holders.0,
+            Second => &mut self.score_holders.1,
+        };
+        if holder.as_deref() != Some(name) {
+            *holder = Some(name.to_string());
+            self.clear_score();
+        }
+    }
+
     /// receive messages from player, on accidental disconnection send `ExitGame`
     fn run_player_message_loop(&self, mut conn: Plug<ClientConnection>, pos: Position) {
         let sender = self.room_msg_sender.clone();
@@ -288,6 +758,13 @@ impl RoomInner {
         });
     }
 
+    /// rejected message counter of the player at `pos`
+    fn rejections(&self, pos: Position) -> Option<Rejections> {
+        self.player_info(pos)
+            .as_ref()
+            .and_then(|info| info.rejections.clone())
+    }
+
     fn player_info(&self, pos: Position) -> &Option<PlayerInfo> {
         match pos {
             First => &self.seats.0,
@@ -303,6 +780,10 @@ impl RoomInner {
         }
     }
 
+    fn occupant_count(&self) -> u8 {
+        self.seats.0.is_some() as u8 + self.seats.1.is_some() as u8
+    }
+
     /// return the first empty position, `None` if the room is full
     fn empty_position(&self) -> Option<Position> {
         match self.seats {
@@ -323,7 +804,10 @@ fn run_room(
         while let Some((pos, msg)) = recv.next().await {
             match msg {
                 Messages::Ready => {
-                    on_player_ready(&room, pos).await;
+                    if on_player_ready(&room, pos, &room_manager).await {
+                        let token = room.lock().await.token.clone();
+                        room_manager.close_if_empty(&token).await;
+                    }
                 }
                 Messages::Unready => {
                     room.lock().await.unready(pos).await;
@@ -331,93 +815,338 @@ fn run_room(
                 Messages::ChatMessage(msg) => {
                     room.lock().await.chat(pos, msg).await;
                 }
-                Messages::QuitRoom => {
-                    if let Some(conn) = room.lock().await.exit(pos).await {
+                Messages::QuitRoom | Messages::Leave => {
+                    if let Some(conn) = exit_room(&room, pos, &room_manager).await {
+                        room_manager.accept_connection(conn);
+                    }
+                }
+                Messages::UpdateRoomConfig(config) => {
+                    let mut inner = room.lock().await;
+                    if inner.update_config(pos, config).await {
+                        let token = inner.token.clone();
+                        let status = if inner.empty_position().is_some() {
+                            RoomStatus::Open
+                        } else {
+                            RoomStatus::Full
+                        };
+                        drop(inner);
+                        room_manager.room_update(&token, status).await;
+                    }
+                }
+                Messages::BanFromRoom(player_name) => {
+                    if let Some(conn) = room.lock().await.ban(pos, player_name).await {
                         room_manager.accept_connection(conn);
                     }
                 }
-                Messages::ExitGame | Messages::ClientError(_) => {
-                    room.lock().await.exit(pos).await;
+                Messages::ExitGame | Messages::ClientError { fatal: true, .. } => {
+                    exit_room(&room, pos, &room_manager).await;
+                }
+                Messages::ClientError {
+                    fatal: false,
+                    message,
+                } => {
+                    room.lock().await.client_error(pos, message).await;
+                }
+                msg => {
+                    let rejections = room.lock().await.rejections(pos);
+                    if let Some(rejections) = rejections {
+                        if !rejections.reject(&msg).await {
+                            exit_room(&room, pos, &room_manager).await;
+                        }
+                    }
                 }
-                _ => {}
             }
         }
     });
 }
 
-async fn on_player_ready(room: &Arc<Mutex<RoomInner>>, pos: Position) {
+/// the player at `pos` exits the room, returning their connection,
+/// an `OwnerBound` room closes once its creator exits
+async fn exit_room(
+    room: &Arc<Mutex<RoomInner>>,
+    pos: Position,
+    room_manager: &RoomManager,
+) -> Option<ClientConnection> {
+    let mut inner = room.lock().await;
+    let closing = inner.closes_on_exit(pos);
+    let conn = inner.exit(pos).await;
+    drop(inner);
+    if closing {
+        close_room(room, room_manager).await;
+    }
+    conn
+}
+
+/// the creator left an `OwnerBound` room, send the remaining player
+/// back to the lobby with `RoomClosed` and remove the room
+async fn close_room(room: &Arc<Mutex<RoomInner>>, room_manager: &RoomManager) {
+    let mut inner = room.lock().await;
+    info!(
+        "creator left owner bound room {}, closing",
+        inner.token.as_code()
+    );
+    let mut ejected = Vec::new();
+    for pos in [First, Second] {
+        ejected.extend(inner.leave(pos, Responses::RoomClosed).await);
+    }
+    let token = inner.token.clone();
+    drop(inner);
+    for conn in ejected {
+        room_manager.accept_connection(conn);
+    }
+    room_manager.close_if_empty(&token).await;
+}
+
+/// start a game session when both players are ready,
+/// return whether both players left the game, leaving the room empty
+async fn on_player_ready(
+    room: &Arc<Mutex<RoomInner>>,
+    pos: Position,
+    room_manager: &RoomManager,
+) -> bool {
     let ready_result = room.lock().await.ready(pos).await;
     if let Some((conn1, conn2)) = ready_result {
         // when player connection ended, player_message_loop will send `QuitRoom` command
-        let (conn1, conn2) = (conn1.unplug().await.unwrap(), conn2.unplug().await.unwrap());
-        // randomly assign colors
-        let is_p1_black = rand::random::<bool>();
+        let (conn1, conn2) = match (conn1.unplug().await, conn2.unplug().await) {
+            (Some(conn1), Some(conn2)) => (conn1, conn2),
+            (conn1, conn2) => {
+                // the one disconnected leaves by its message loop, the other is back to its seat
+                let mut inner = room.lock().await;
+                warn!(
+                    "player disconnected before the game started in room {}",
+                    inner.token.as_code()
+                );
+                let returned = [(conn1, First), (conn2, Second)]
+                    .into_iter()
+                    .filter_map(|(conn, pos)| inner.return_to_seat(conn?, pos))
+                    .collect::<Vec<_>>();
+                drop(inner);
+                for conn in returned {
+                    room_manager.accept_connection(conn);
+                }
+                return false;
+            }
+        };
+        // randomly assign colors, reproducible by the seed of the session
+        let seed = random();
+        let is_p1_black = is_first_black(seed);
         let (b_conn, w_conn) = if is_p1_black {
             (conn1, conn2)
         } else {
             (conn2, conn1)
         };
-        let s_id = room.lock().await.session_counter.fetch_add(1, SeqCst);
+        let s_id = room.lock().await.next_session_id();
         let s_config = room.lock().await.session_config.clone();
-        let b_id = b_conn.player_id();
-        let w_id = w_conn.player_id();
-        let (b_exit, w_exit) = start_game_session(s_id, b_id, w_id, s_config, b_conn, w_conn).await;
+        let abandon_policy = s_config.abandon_policy;
+        let telemetry = room_manager.telemetry().clone();
+        let (view, session) = start_game_session(s_id, s_config, b_conn, w_conn, seed, telemetry);
+        room.lock().await.game = Some(view);
+        let (b_exit, w_exit) = session.await;
+        room.lock().await.game = None;
         let (exit1, exit2) = if is_p1_black {
             (b_exit, w_exit)
         } else {
             (w_exit, b_exit)
         };
-        match (exit1, exit2) {
-            (ExitState::ReturnRoom(conn1, result1), ExitState::ReturnRoom(conn2, result2)) => {
-                // update score board
-                match (result1, result2) {
-                    (PlayerResult::Win, PlayerResult::Lose)
-                    | (PlayerResult::OpponentQuit, PlayerResult::Quit) => {
-                        room.lock().await.player_scored(First);
-                    }
-                    (PlayerResult::Lose, PlayerResult::Win)
-                    | (PlayerResult::Quit, PlayerResult::OpponentQuit) => {
-                        room.lock().await.player_scored(Second);
-                    }
-                    (PlayerResult::Draw, PlayerResult::Draw) => {}
-                    (result1, result2) => {
-                        error!("game session end in bad state (p1: {result1}, p2: {result2})");
-                    }
+        settle_session(room, exit1, exit2, abandon_policy, room_manager).await
+    } else {
+        false
+    }
+}
+
+/// whether the player in the first seat plays black in the session of `seed`
+fn is_first_black(seed: u64) -> bool {
+    StdRng::seed_from_u64(seed).gen()
+}
+
+/// update the score board and seats after a game session,
+/// return whether both players left the game
+async fn settle_session(
+    room: &Arc<Mutex<RoomInner>>,
+    exit1: ExitState,
+    exit2: ExitState,
+    abandon_policy: AbandonPolicy,
+    room_manager: &RoomManager,
+) -> bool {
+    match (exit1, exit2) {
+        (ExitState::ReturnRoom(conn1, result1), ExitState::ReturnRoom(conn2, result2)) => {
+            // update score board
+            match (result1, result2) {
+                (PlayerResult::Win, PlayerResult::Lose)
+                | (PlayerResult::OpponentQuit, PlayerResult::Quit) => {
+                    room.lock().await.player_scored(First);
+                }
+                (PlayerResult::Lose, PlayerResult::Win)
+                | (PlayerResult::Quit, PlayerResult::OpponentQuit) => {
+                    room.lock().await.player_scored(Second);
+                }
+                (PlayerResult::Draw, PlayerResult::Draw) => {}
+                // no contest, regardless of quit scoring
+                (PlayerResult::Aborted, _) | (_, PlayerResult::Aborted) => {}
+                (result1, result2) => {
+                    error!("game session end in bad state (p1: {result1}, p2: {result2})");
                 }
-                room.lock()
-                    .await
-                    .join_both_on_session_return(conn1, conn2)
-                    .await;
             }
-            (exit1, exit2) => {
-                // one of the players quit, do not need to update score board
-                room.lock()
-                    .await
-                    .join_single_on_session_return(exit1, First)
-                    .await;
-                room.lock()
+            let stray = room
+                .lock()
+                .await
+                .join_both_on_session_return(*conn1, *conn2)
+                .await;
+            for conn in stray {
+                room_manager.accept_connection(conn);
+            }
+            false
+        }
+        (ExitState::ExitGame, ExitState::ExitGame) => {
+            // e.g. both disconnected at once, the game is abandoned without scoring
+            let mut inner = room.lock().await;
+            info!(
+                "both players left the game in room {}",
+                inner.token.as_code()
+            );
+            inner.exit(First).await;
+            inner.exit(Second).await;
+            true
+        }
+        (exit1, exit2) => {
+            // one of the players left, score the remaining player by policy
+            let scored = match (&exit1, &exit2) {
+                (ExitState::ReturnRoom(_, result), ExitState::ExitGame)
+                    if is_abandon_scored(abandon_policy, result) =>
+                {
+                    Some(First)
+                }
+                (ExitState::ExitGame, ExitState::ReturnRoom(_, result))
+                    if is_abandon_scored(abandon_policy, result) =>
+                {
+                    Some(Second)
+                }
+                _ => None,
+            };
+            if let Some(pos) = scored {
+                room.lock().await.score_abandoned_game(pos).await;
+            }
+            let closing = {
+                let inner = room.lock().await;
+                [(&exit1, First), (&exit2, Second)]
+                    .into_iter()
+                    .any(|(exit, pos)| {
+                        matches!(exit, ExitState::ExitGame) && inner.closes_on_exit(pos)
+                    })
+            };
+            for (exit, pos) in [(exit1, First), (exit2, Second)] {
+                let stray = room
+                    .lock()
                     .await
-                    .join_single_on_session_return(exit2, Second)
+                    .join_single_on_session_return(exit, pos)
                     .await;
+                if let Some(conn) = stray {
+                    room_manager.accept_connection(conn);
+                }
             }
+            if closing {
+                close_room(room, room_manager).await;
+            }
+            false
         }
     }
 }
 
+/// whether the remaining player scores when the opponent left the game,
+/// a game won before the opponent left scores regardless of the policy
+fn is_abandon_scored(policy: AbandonPolicy, result: &PlayerResult) -> bool {
+    matches!(
+        (policy, result),
+        (_, PlayerResult::Win)
+            | (
+                AbandonPolicy::ExitAsLoss | AbandonPolicy::LeaveAsLoss,
+                PlayerResult::OpponentExited
+            )
+            | (
+                AbandonPolicy::LeaveAsLoss,
+                PlayerResult::OpponentDisconnected
+            )
+    )
+}
+
+#[cfg(test)]
+impl Room {
+    /// seat a player in a game session, whose responses go to `sender`
+    pub(crate) async fn seat_in_session(&self, name: &str, id: u64, sender: Sender<Responses>) {
+        let mut inner = self.inner.lock().await;
+        let pos = inner.empty_position().unwrap();
+        inner.player_info_mut(pos).replace(PlayerInfo {
+            player_name: name.to_string(),
+            player_id: id,
+            sender,
+            unplug_handle: None,
+            rejections: None,
+            normalization: Normalization::default(),
+            ready: true,
+        });
+        inner.inactive_since = None;
+    }
+
+    /// id of the next game session started in this room
+    pub(crate) async fn next_session_id(&self) -> u64 {
+        self.inner.lock().await.next_session_id()
+    }
+
+    /// the game in progress in this room, as started by `on_player_ready`
+    pub(crate) async fn show_game(&self, view: GameView) {
+        self.inner.lock().await.game = Some(view);
+    }
+
+    /// hand `msg` to the message loop of the room as sent by the player at `pos`
+    pub(crate) async fn receive(&self, pos: Position, msg: Messages) {
+        let sender = self.inner.lock().await.room_msg_sender.clone();
+        sender.send((pos, msg)).await.unwrap();
+    }
+}
+
 impl Drop for Room {
     fn drop(&mut self) {
-        if let Some(killer) = block_on(self.inner.lock()).killer.take() {
+        let mut inner = block_on(self.inner.lock());
+        self.telemetry.emit(TelemetryEvent::RoomReaped {
+            room: inner.token.clone(),
+        });
+        if let Some(killer) = inner.killer.take() {
+            drop(inner);
             block_on(killer.unplug());
         }
     }
 }
 
+impl RoomHandle {
+    /// the room for an operator, the game is observed after releasing the room lock
+    pub(crate) async fn directory_entry(&self) -> RoomDirectoryEntry {
+        let inner = self.0.lock().await;
+        let mut entry = RoomDirectoryEntry {
+            token: inner.token.clone(),
+            occupants: inner.occupant_names(),
+            in_game: inner.in_game(),
+            move_count: 0,
+            inactive_since: inner.inactive_since,
+        };
+        let game = inner.game.clone();
+        drop(inner);
+        if let Some(view) = game {
+            let field = view.snapshot().await.field;
+            entry.move_count = field.0.iter().flatten().filter(|s| **s != State::E).count();
+        }
+        entry
+    }
+}
+
 // player info records information about a player
 struct PlayerInfo {
     player_name: String,
     player_id: u64,
     sender: Sender<Responses>,
     unplug_handle: Option<UnplugHandle<ClientConnection>>,
+    rejections: Option<Rejections>,
+    // of the connection, for names and chat sent by the player
+    normalization: Normalization,
     ready: bool,
 }
 
@@ -426,6 +1155,8 @@ impl PlayerInfo {
         let player_name = conn.player_name().to_string();
         let player_id = conn.player_id();
         let sender = conn.sender().clone();
+        let rejections = conn.rejections().clone();
+        let normalization = conn.normalization();
         let (plug, unplug) = Plug::new(conn);
         (
             PlayerInfo {
@@ -433,6 +1164,8 @@ impl PlayerInfo {
                 player_id,
                 sender,
                 unplug_handle: Some(unplug),
+                rejections: Some(rejections),
+                normalization,
                 ready: false,
             },
             plug,
@@ -451,6 +1184,11 @@ impl PlayerInfo {
         self.ready = false;
     }
 
+    /// the connection is taken by a game session
+    fn in_session(&self) -> bool {
+        self.unplug_handle.is_none()
+    }
+
     /// this function will turn `unplug_handle` into `None`
     fn start_session(&mut self) -> Option<UnplugHandle<ClientConnection>> {
         self.unplug_handle.take()
@@ -464,7 +1202,7 @@ impl PlayerInfo {
 }
 
 // position of seats in the room
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub(crate) enum Position {
     First,
     Second,
@@ -478,3 +1216,588 @@ impl Position {
         }
     }
 }
+
+#[cfg(test)]
+mod test_room {
+    use super::*;
+    use futures::executor::block_on;
+    use rand::thread_rng;
+
+    fn test_player(name: &str, id: u64, sender: Sender<Responses>) -> PlayerInfo {
+        PlayerInfo {
+            player_name: name.to_string(),
+            player_id: id,
+            sender,
+            unplug_handle: None,
+            rejections: None,
+            normalization: Normalization::default(),
+            ready: true,
+        }
+    }
+
+    #[test]
+    fn test_room_info() {
+        let config = SessionConfig {
+            board_width: 10,
+            board_height: 20,
+            ..SessionConfig::default()
+        };
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            config.clone(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        block_on(async {
+            room.inner.lock().await.seats = (Some(test_player("小雨", 1, bounded(1).0)), None);
+            assert_eq!(
+                room.info().await,
+                Responses::RoomInfo {
+                    occupants: vec!["小雨".to_string()],
+                    session_config: config.clone(),
+                    in_game: true,
+                }
+            );
+            room.inner.lock().await.seats = (None, None);
+            assert_eq!(
+                room.info().await,
+                Responses::RoomInfo {
+                    occupants: vec![],
+                    session_config: config,
+                    in_game: false,
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn test_slow_player_dropped() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        // the slow player never drains its channel
+        let (slow_sender, _slow_receiver) = bounded(1);
+        slow_sender.try_send(Responses::OpponentReady).unwrap();
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            room.inner.lock().await.seats = (
+                Some(test_player("slow", 1, slow_sender)),
+                Some(test_player("fast", 2, sender)),
+            );
+            room.inner
+                .lock()
+                .await
+                .chat(Second, "hello".to_string())
+                .await;
+            assert_eq!(receiver.next().await, Some(Responses::OpponentQuitRoom));
+            assert!(room.inner.lock().await.seats.0.is_none());
+            // the opponent is not blocked
+            room.inner
+                .lock()
+                .await
+                .send_response(Second, Responses::OpponentUnready)
+                .await;
+            assert_eq!(receiver.next().await, Some(Responses::OpponentUnready));
+        });
+    }
+
+    #[test]
+    fn test_room_debug() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let first = test_player("小雨", 1, bounded(1).0);
+        let mut second = test_player("雨雨", 2, bounded(1).0);
+        second.unready();
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.seats = (Some(first), Some(second));
+            inner.scores = (2, 1);
+            inner.inactive_since = None;
+            let expected = format!(
+                "room {}: seats [小雨 (1, ready, in session), 雨雨 (2, unready, in session)], \
+                scores (2, 1), active, {:?}",
+                inner.token.as_code(),
+                SessionConfig::default()
+            );
+            drop(inner);
+            assert_eq!(room.debug().await, Responses::RoomDebug(expected));
+        });
+    }
+
+    #[test]
+    fn test_abandon_policy() {
+        use AbandonPolicy::*;
+        use PlayerResult::{OpponentDisconnected, OpponentExited};
+        assert!(!is_abandon_scored(NoContest, &OpponentExited));
+        assert!(!is_abandon_scored(NoContest, &OpponentDisconnected));
+        // exit counts as a loss, disconnection is a no-contest
+        assert!(is_abandon_scored(ExitAsLoss, &OpponentExited));
+        assert!(!is_abandon_scored(ExitAsLoss, &OpponentDisconnected));
+        assert!(is_abandon_scored(LeaveAsLoss, &OpponentExited));
+        assert!(is_abandon_scored(LeaveAsLoss, &OpponentDisconnected));
+        assert!(!is_abandon_scored(LeaveAsLoss, &PlayerResult::OpponentQuit));
+        // a game aborted before any move is never scored
+        assert!(!is_abandon_scored(LeaveAsLoss, &PlayerResult::Aborted));
+        // nor a game the opponent was kicked from
+        assert!(!is_abandon_scored(
+            LeaveAsLoss,
+            &PlayerResult::OpponentKicked
+        ));
+        // the opponent left after losing, e.g. within `final_undo_window`
+        assert!(is_abandon_scored(NoContest, &PlayerResult::Win));
+    }
+
+    #[test]
+    fn test_score_abandoned_game() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig {
+                abandon_policy: AbandonPolicy::ExitAsLoss,
+                ..SessionConfig::default()
+            },
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let (first_sender, mut first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.seats = (
+                Some(test_player("小雨", 1, first_sender)),
+                Some(test_player("雨雨", 2, second_sender)),
+            );
+            inner.score_abandoned_game(First).await;
+            assert_eq!(inner.scores, (1, 0));
+            assert_eq!(
+                first_receiver.next().await,
+                Some(Responses::RoomScores(
+                    ("小雨".to_string(), 1),
+                    ("雨雨".to_string(), 0)
+                ))
+            );
+            assert!(second_receiver.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_mutual_disconnect() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig {
+                abandon_policy: AbandonPolicy::LeaveAsLoss,
+                ..SessionConfig::default()
+            },
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.seats = (
+                Some(test_player("小雨", 1, first_sender)),
+                Some(test_player("雨雨", 2, second_sender)),
+            );
+            inner.scores = (1, 2);
+            inner.inactive_since = None;
+            drop(inner);
+            // both connections dropped during the game
+            let both_left = settle_session(
+                &room.inner,
+                ExitState::ExitGame,
+                ExitState::ExitGame,
+                AbandonPolicy::LeaveAsLoss,
+                &RoomManager::new(),
+            )
+            .await;
+            assert!(both_left);
+            let inner = room.inner.lock().await;
+            assert!(inner.seats.0.is_none() && inner.seats.1.is_none());
+            assert_eq!(inner.scores, (0, 0));
+            assert!(inner.inactive_since.is_some());
+            drop(inner);
+            // nobody is scored
+            for receiver in [&first_receiver, &second_receiver] {
+                while let Ok(rsp) = receiver.try_recv() {
+                    assert!(!matches!(rsp, Responses::RoomScores(..)));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_room_persistence() {
+        let manager = RoomManager::new();
+        let room = |persistence| {
+            Room::empty(
+                RoomToken::random(&mut thread_rng()),
+                SessionConfig::builder()
+                    .room_persistence(persistence)
+                    .build(),
+                None,
+                Arc::new(AtomicU64::default()),
+                manager.clone(),
+            )
+        };
+        let seat_both = |room: &Room| {
+            let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+            let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+            let mut inner = room.inner.try_lock().unwrap();
+            inner.creator = Some(1);
+            inner.seats = (
+                Some(test_player("小雨", 1, first_sender)),
+                Some(test_player("雨雨", 2, second_sender)),
+            );
+            (first_receiver, second_receiver)
+        };
+        block_on(async {
+            // a persistent room survives its creator quitting
+            let persistent = room(RoomPersistence::Persistent);
+            let (_first, second) = seat_both(&persistent);
+            exit_room(&persistent.inner, First, &manager).await;
+            assert!(persistent.inner.lock().await.seats.1.is_some());
+            assert_eq!(second.try_recv(), Ok(Responses::OpponentQuitRoom));
+            assert!(second.try_recv().is_err());
+            // an owner bound room survives the other player quitting
+            let owner_bound = room(RoomPersistence::OwnerBound);
+            let (first, _second) = seat_both(&owner_bound);
+            exit_room(&owner_bound.inner, Second, &manager).await;
+            assert!(owner_bound.inner.lock().await.seats.0.is_some());
+            assert_eq!(first.try_recv(), Ok(Responses::OpponentQuitRoom));
+            assert!(first.try_recv().is_err());
+            // but closes when the creator quits
+            let owner_bound = room(RoomPersistence::OwnerBound);
+            let (first, second) = seat_both(&owner_bound);
+            exit_room(&owner_bound.inner, First, &manager).await;
+            assert!(owner_bound.is_empty().await);
+            assert_eq!(first.try_recv(), Ok(Responses::QuitRoomSuccess));
+            assert_eq!(second.try_recv(), Ok(Responses::OpponentQuitRoom));
+            assert_eq!(second.try_recv(), Ok(Responses::RoomClosed));
+        });
+    }
+
+    #[test]
+    fn test_room_ready_when_full() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.seats = (Some(test_player("小雨", 1, first_sender)), None);
+            inner.notify_if_full().await;
+            assert!(first_receiver.try_recv().is_err());
+            inner.seats.1 = Some(test_player("雨雨", 2, second_sender));
+            inner.notify_if_full().await;
+            assert_eq!(first_receiver.try_recv(), Ok(Responses::RoomReady));
+            assert_eq!(second_receiver.try_recv(), Ok(Responses::RoomReady));
+        });
+    }
+
+    #[test]
+    fn test_ready_in_empty_room() {
+        for needs_opponent in [false, true] {
+            let config = SessionConfig::builder()
+                .ready_needs_opponent(needs_opponent)
+                .build();
+            let room = Room::empty(
+                RoomToken::random(&mut thread_rng()),
+                config,
+                None,
+                Arc::new(AtomicU64::default()),
+                RoomManager::new(),
+            );
+            let (sender, receiver) = bounded(CHANNEL_SIZE);
+            block_on(async {
+                let mut inner = room.inner.lock().await;
+                let mut player = test_player("小雨", 1, sender);
+                player.unready();
+                inner.seats = (Some(player), None);
+                assert!(inner.ready(First).await.is_none());
+                let ready = inner.player_info(First).as_ref().unwrap().is_ready();
+                if needs_opponent {
+                    assert!(!ready);
+                    assert_eq!(receiver.try_recv(), Ok(Responses::CannotReadyEmptyRoom));
+                } else {
+                    // waiting ready for an opponent
+                    assert!(ready);
+                    assert!(receiver.try_recv().is_err());
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_seat_emptied_before_game() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            // both ready, but the connections are not in the room
+            inner.seats = (
+                Some(test_player("小雨", 1, first_sender)),
+                Some(test_player("雨雨", 2, second_sender)),
+            );
+            assert!(inner.ready(First).await.is_none());
+            assert!(inner.seats.0.is_some() && inner.seats.1.is_some());
+            assert_eq!(second_receiver.try_recv(), Ok(Responses::OpponentReady));
+            // the second seat emptied meanwhile
+            inner.seats.1 = None;
+            inner.unready(Second).await;
+            inner.chat(Second, "hello".to_string()).await;
+            assert!(first_receiver.try_recv().is_err());
+            assert!(inner.ready(First).await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_room_password() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            Some("secret".to_string()),
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        block_on(async {
+            let inner = room.inner.lock().await;
+            assert_eq!(
+                inner.check_join("小雨", Some("wrong")),
+                Err(JoinRoomFailure::BadPassword)
+            );
+            assert_eq!(
+                inner.check_join("小雨", None),
+                Err(JoinRoomFailure::BadPassword)
+            );
+            assert_eq!(inner.check_join("小雨", Some("secret")), Ok(()));
+        });
+        // rooms without a password accept anyone
+        let open_room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        block_on(async {
+            let inner = open_room.inner.lock().await;
+            assert_eq!(inner.check_join("小雨", None), Ok(()));
+            assert_eq!(inner.check_join("小雨", Some("anything")), Ok(()));
+        });
+    }
+
+    #[test]
+    fn test_spectate_private_room() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            Some("secret".to_string()),
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        block_on(async {
+            room.inner.lock().await.creator = Some(1);
+            assert_eq!(room.peek(2, None).await, Responses::SpectateDenied);
+            assert_eq!(room.peek(2, Some("wrong")).await, Responses::SpectateDenied);
+            assert_eq!(
+                room.peek(2, Some("secret")).await,
+                Responses::NoGameInProgress
+            );
+            // the creator needs no password
+            assert_eq!(room.peek(1, None).await, Responses::NoGameInProgress);
+        });
+        // public rooms are spectated freely
+        let open_room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        block_on(async {
+            assert_eq!(open_room.peek(2, None).await, Responses::NoGameInProgress);
+        });
+    }
+
+    #[test]
+    fn test_ban_from_room() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.creator = Some(1);
+            inner.seats = (
+                Some(test_player("小雨", 1, first_sender)),
+                Some(test_player("雨雨", 2, second_sender)),
+            );
+            // only the creator may ban
+            inner.ban(Second, "小雨".to_string()).await;
+            assert_eq!(second_receiver.try_recv(), Ok(Responses::PermissionDenied));
+            assert!(inner.seats.0.is_some());
+            // the banned player is ejected
+            inner.ban(First, "雨雨".to_string()).await;
+            assert!(inner.seats.1.is_none());
+            assert_eq!(second_receiver.try_recv(), Ok(Responses::BannedFromRoom));
+            assert_eq!(first_receiver.try_recv(), Ok(Responses::OpponentQuitRoom));
+            // and cannot rejoin, while others can
+            assert_eq!(inner.check_join("雨雨", None), Err(JoinRoomFailure::Banned));
+            assert_eq!(inner.check_join("小小", None), Ok(()));
+        });
+    }
+
+    #[test]
+    fn test_normalized_chat_and_ban() {
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::default(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let normalization = Normalization {
+            fold_name_case: true,
+            chat: true,
+        };
+        let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.creator = Some(1);
+            let mut first = test_player("小雨", 1, first_sender);
+            first.normalization = normalization;
+            let mut second = test_player("andr\u{e9}", 2, second_sender);
+            second.normalization = normalization;
+            inner.seats = (Some(first), Some(second));
+            // chat is put in NFC
+            inner.chat(First, "Andre\u{301}".to_string()).await;
+            assert_eq!(
+                second_receiver.try_recv(),
+                Ok(Responses::ChatMessage(
+                    "小雨".to_string(),
+                    "Andr\u{e9}".to_string()
+                ))
+            );
+            // the name banned is normalized like the names of players
+            inner.ban(First, "ANDRE\u{301}".to_string()).await;
+            assert!(inner.seats.1.is_none());
+            assert_eq!(second_receiver.try_recv(), Ok(Responses::BannedFromRoom));
+            assert_eq!(first_receiver.try_recv(), Ok(Responses::OpponentQuitRoom));
+            assert_eq!(
+                inner.check_join("andr\u{e9}", None),
+                Err(JoinRoomFailure::Banned)
+            );
+        });
+    }
+
+    #[test]
+    fn test_update_room_config() {
+        use crate::lobby::game_session::time_control;
+        let room = Room::empty(
+            RoomToken::random(&mut thread_rng()),
+            SessionConfig::builder().play_timeout(300).build(),
+            None,
+            Arc::new(AtomicU64::default()),
+            RoomManager::new(),
+        );
+        let blitz = SessionConfig::builder().play_timeout(10).build();
+        let rapid = SessionConfig::builder().play_timeout(60).build();
+        let (first_sender, first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut inner = room.inner.lock().await;
+            inner.seats = (
+                Some(test_player("小雨", 1, first_sender)),
+                Some(test_player("雨雨", 2, second_sender)),
+            );
+            // a proposal alone changes nothing
+            assert!(!inner.update_config(First, blitz.clone()).await);
+            assert_eq!(
+                second_receiver.try_recv(),
+                Ok(Responses::RoomConfigProposed(blitz.clone()))
+            );
+            // a counter proposal replaces it
+            assert!(!inner.update_config(Second, rapid.clone()).await);
+            assert_eq!(
+                first_receiver.try_recv(),
+                Ok(Responses::RoomConfigProposed(rapid.clone()))
+            );
+            assert!(!inner.update_config(Second, blitz.clone()).await);
+            assert_eq!(
+                first_receiver.try_recv(),
+                Ok(Responses::RoomConfigProposed(blitz.clone()))
+            );
+            assert_eq!(inner.session_config.play_timeout, 300);
+            // the opponent confirms with the same config
+            assert!(inner.update_config(First, blitz.clone()).await);
+            for receiver in [&first_receiver, &second_receiver] {
+                assert_eq!(
+                    receiver.try_recv(),
+                    Ok(Responses::RoomConfigUpdated(blitz.clone()))
+                );
+            }
+            // the next game session starts with the new timers
+            assert!(matches!(
+                time_control(&inner.session_config),
+                Responses::TimeControl {
+                    play_timeout: 10,
+                    ..
+                }
+            ));
+            // without an opponent, the config is applied immediately
+            inner.seats.1 = None;
+            assert!(inner.update_config(First, rapid.clone()).await);
+            assert_eq!(inner.session_config, rapid);
+            assert_eq!(
+                first_receiver.try_recv(),
+                Ok(Responses::RoomConfigUpdated(rapid.clone()))
+            );
+            // unless invalid
+            let no_board = SessionConfig::builder().board_size(0, 0).build();
+            assert!(!inner.update_config(First, no_board).await);
+            assert_eq!(first_receiver.try_recv(), Ok(Responses::InvalidRoomConfig));
+            assert_eq!(inner.session_config, rapid);
+        });
+    }
+
+    #[test]
+    fn test_seeded_colors() {
+        // the same seed reproduces the color assignment
+        for seed in 0..10 {
+            assert_eq!(is_first_black(seed), is_first_black(seed));
+        }
+        // either seat may play black
+        assert!((0..10).any(is_first_black));
+        assert!(!(0..10).all(is_first_black));
+    }
+}
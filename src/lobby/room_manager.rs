@@ -1,7 +1,11 @@
+use crate::game::SessionConfig;
 use crate::lobby::client_connection::ClientConnection;
-use crate::lobby::messages::{Messages, Responses};
-use crate::lobby::room::Room;
+use crate::lobby::messages::{JoinRoomFailure, Messages, Responses, RoomStatus, RoomSummary};
+use crate::lobby::rate_limit::RateLimit;
+use crate::lobby::room::{Room, RoomHandle};
+use crate::lobby::telemetry::Telemetry;
 use crate::lobby::token::RoomToken;
+use async_std::channel::{Sender, TrySendError};
 use async_std::sync::Mutex;
 use async_std::task;
 use futures::StreamExt;
@@ -9,24 +13,118 @@ use log::{info, warn};
 use rand::thread_rng;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::sync::atomic::AtomicU64;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const CLEAN_INTERVAL: Duration = Duration::from_secs(30);
 const ROOM_LIFE_LENGTH: Duration = Duration::from_secs(60);
+const ROOM_CREATION_LIMIT: usize = 5;
+const ROOM_CREATION_WINDOW: Duration = Duration::from_secs(60);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// how long the token of a closed room is not reused by default
+pub const DEFAULT_TOKEN_GRACE: Duration = Duration::from_secs(600);
+
+/// a room as listed to an operator by `RoomManager::snapshot`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomDirectoryEntry {
+    pub token: RoomToken,
+    pub occupants: Vec<String>,
+    pub in_game: bool,
+    /// stones on the board including the opening, 0 when not in game
+    pub move_count: usize,
+    /// `None` while the room is occupied
+    pub inactive_since: Option<Instant>,
+}
+
+/// Lists the rooms of a running server, e.g. for an operator dashboard.
+///
+/// Lists nothing until passed to `start_server` in `ServerOptions`.
+#[derive(Clone, Default)]
+pub struct RoomDirectory(Arc<OnceLock<RoomManager>>);
+
+impl RoomDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// every room of the server, see `RoomDirectoryEntry`
+    pub async fn snapshot(&self) -> Vec<RoomDirectoryEntry> {
+        match self.0.get() {
+            Some(manager) => manager.snapshot().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// list the rooms of `manager`, a directory lists a single server
+    pub(crate) fn attach(&self, manager: RoomManager) {
+        if self.0.set(manager).is_err() {
+            warn!("room directory already attached to a server");
+        }
+    }
+}
 
 #[derive(Clone)]
 pub(crate) struct RoomManager {
     rooms: Arc<Mutex<HashMap<RoomToken, Room>>>,
-    counter: Arc<AtomicU64>,
+    // game session ids, shared by all rooms so that ids never collide
+    session_counter: Arc<AtomicU64>,
+    // lobby subscribers by player id
+    subscribers: Arc<Mutex<HashMap<u64, Sender<Responses>>>>,
+    // recent room creation times by player id
+    creations: Arc<Mutex<HashMap<u64, RateLimit>>>,
+    creation_limit: usize,
+    creation_window: Duration,
+    // no room can be created once draining
+    draining: Arc<AtomicBool>,
+    // when the rooms were closed by token, not reused within `token_grace`
+    closed_tokens: Arc<Mutex<HashMap<RoomToken, Instant>>>,
+    token_grace: Duration,
+    telemetry: Telemetry,
 }
 
 impl RoomManager {
+    /// without telemetry
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_telemetry(Arc::new(crate::lobby::telemetry::NoTelemetry))
+    }
+
+    /// rooms and game sessions emit events to `telemetry`
+    #[cfg(test)]
+    pub fn with_telemetry(telemetry: Telemetry) -> Self {
+        Self::with_creation_limit(ROOM_CREATION_LIMIT, ROOM_CREATION_WINDOW, telemetry)
+    }
+
+    /// each connection may create at most `limit` rooms within `window`
+    #[cfg(test)]
+    pub fn with_creation_limit(limit: usize, window: Duration, telemetry: Telemetry) -> Self {
+        Self::with_limits(limit, window, DEFAULT_TOKEN_GRACE, telemetry)
+    }
+
+    /// the token of a closed room answers `RoomClosed` and is not reused within `grace`
+    pub fn with_token_grace(grace: Duration, telemetry: Telemetry) -> Self {
+        Self::with_limits(ROOM_CREATION_LIMIT, ROOM_CREATION_WINDOW, grace, telemetry)
+    }
+
+    fn with_limits(
+        limit: usize,
+        window: Duration,
+        token_grace: Duration,
+        telemetry: Telemetry,
+    ) -> Self {
         let manager = Self {
             rooms: Arc::new(Mutex::new(HashMap::new())),
-            counter: Arc::new(AtomicU64::default()),
+            session_counter: Arc::new(AtomicU64::new(first_session_id())),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+            creations: Arc::new(Mutex::new(HashMap::new())),
+            creation_limit: limit,
+            creation_window: window,
+            draining: Arc::new(AtomicBool::new(false)),
+            closed_tokens: Arc::new(Mutex::new(HashMap::new())),
+            token_grace,
+            telemetry,
         };
         let manager_clone = manager.clone();
         task::spawn(async move {
@@ -43,66 +141,339 @@ impl RoomManager {
         task::spawn(async move {
             while let Some(msg) = conn.next().await {
                 match msg {
-                    Messages::CreateRoom(config) => {
-                        let mut rooms = manager.rooms.lock().await;
-                        let counter = manager.counter.clone();
-                        loop {
-                            let token = RoomToken::random(&mut thread_rng());
-                            match rooms.entry(token.clone()) {
-                                Entry::Occupied(_) => {}
-                                Entry::Vacant(e) => {
-                                    let room = Room::empty(
-                                        token.clone(),
-                                        config,
-                                        counter,
-                                        manager.clone(),
-                                    );
-                                    let _ = conn
-                                        .sender()
-                                        .send(Responses::RoomCreated(token.as_code()))
-                                        .await;
-                                    let _ = room.join(conn).await;
-                                    e.insert(room);
-                                    break;
-                                }
-                            }
+                    Messages::CreateRoom(_, _) if manager.draining.load(SeqCst) => {
+                        let _ = conn.sender().send(Responses::ServerDraining).await;
+                    }
+                    Messages::CreateRoom(config, _) if !config.is_valid() => {
+                        warn!(
+                            "player ({}: {}) invalid room config {:?}",
+                            conn.player_name(),
+                            conn.player_id(),
+                            config
+                        );
+                        let _ = conn.sender().send(Responses::InvalidRoomConfig).await;
+                    }
+                    Messages::CreateRoom(config, password) => {
+                        if manager.allow_creation(conn.player_id()).await {
+                            let token = manager.create_room(config, password, conn).await;
+                            manager.room_update(&token, RoomStatus::Open).await;
+                            break;
                         }
-                        break;
+                        warn!(
+                            "player ({}: {}) room creation rate limited",
+                            conn.player_name(),
+                            conn.player_id()
+                        );
+                        let _ = conn.sender().send(Responses::RoomCreationRateLimited).await;
                     }
                     Messages::SearchOnlinePlayers(name, n) => {
-                        let names = conn.get_online_players(name, n as usize).await;
-                        let _ = conn.sender().send(Responses::PlayerList(names)).await;
+                        let rsp = match conn.get_online_players(name, n as usize).await {
+                            Some(names) => Responses::PlayerList(names),
+                            None => {
+                                warn!(
+                                    "player ({}: {}) search query rejected",
+                                    conn.player_name(),
+                                    conn.player_id()
+                                );
+                                Responses::SearchRejected
+                            }
+                        };
+                        let _ = conn.sender().send(rsp).await;
                     }
-                    Messages::JoinRoom(token) => {
+                    Messages::JoinRoom(token, password) => {
                         let rooms = manager.rooms.lock().await;
                         if let Some(room) = rooms.get(&token) {
-                            match room.join(conn).await {
-                                Ok(_) => break,
+                            match room.try_join(conn, password).await {
+                                Ok(_) => {
+                                    let is_full = room.is_full().await;
+                                    drop(rooms);
+                                    if is_full {
+                                        manager.room_update(&token, RoomStatus::Full).await;
+                                    }
+                                    break;
+                                }
                                 Err(conn_returned) => conn = conn_returned,
                             }
                         } else {
-                            let _ = conn
-                                .sender()
-                                .send(Responses::JoinRoomFailureTokenNotFound)
-                                .await;
+                            drop(rooms);
+                            let rsp = manager.missing_room(&token).await;
+                            let _ = conn.sender().send(rsp).await;
                         }
                     }
+                    Messages::QueryRoom(token) => {
+                        let rsp = manager.query_room(&token).await;
+                        let _ = conn.sender().send(rsp).await;
+                    }
+                    Messages::PeekGame(token, password) => {
+                        let rsp = match manager.rooms.lock().await.get(&token) {
+                            Some(room) => room.peek(conn.player_id(), password.as_deref()).await,
+                            None => manager.missing_room(&token).await,
+                        };
+                        let _ = conn.sender().send(rsp).await;
+                    }
+                    Messages::SubscribeLobby => {
+                        manager
+                            .subscribers
+                            .lock()
+                            .await
+                            .insert(conn.player_id(), conn.sender().clone());
+                    }
+                    Messages::UnsubscribeLobby => {
+                        manager.subscribers.lock().await.remove(&conn.player_id());
+                    }
+                    Messages::DebugRoom(token) => {
+                        let rsp = if conn.is_admin() {
+                            match manager.rooms.lock().await.get(&token) {
+                                Some(room) => room.debug().await,
+                                None => manager.missing_room(&token).await,
+                            }
+                        } else {
+                            warn!(
+                                "player ({}: {}) DebugRoom permission denied",
+                                conn.player_name(),
+                                conn.player_id()
+                            );
+                            Responses::PermissionDenied
+                        };
+                        let _ = conn.sender().send(rsp).await;
+                    }
                     Messages::ExitGame => break,
-                    Messages::ClientError(e) => {
+                    Messages::Leave => {
+                        let _ = conn.sender().send(Responses::ConnectionClosing).await;
+                        break;
+                    }
+                    Messages::ClientError { fatal, message } => {
+                        if fatal {
+                            warn!(
+                                "player ({}: {}) quit on client error {}",
+                                conn.player_name(),
+                                conn.player_id(),
+                                message
+                            );
+                            break;
+                        }
                         warn!(
-                            "player ({}: {}) quit on client error {}",
+                            "player ({}: {}) non-fatal client error {}",
                             conn.player_name(),
                             conn.player_id(),
-                            e
+                            message
                         );
-                        break;
+                        let _ = conn.sender().send(Responses::ClientErrorAcknowledged).await;
+                    }
+                    msg => {
+                        if !conn.rejections().reject(&msg).await {
+                            break;
+                        }
                     }
-                    _ => {}
                 }
             }
         });
     }
 
+    pub(crate) fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    pub(crate) async fn room_count(&self) -> usize {
+        self.rooms.lock().await.len()
+    }
+
+    /// All rooms for monitoring, each gathered under its own lock in turn.
+    ///
+    /// The rooms are only listed under the lock of all rooms,
+    /// which is released before reading any of them.
+    /// Unlike `RoomListUpdate` for clients, this lists occupants and game progress.
+    pub(crate) async fn snapshot(&self) -> Vec<RoomDirectoryEntry> {
+        let rooms: Vec<RoomHandle> = self.rooms.lock().await.values().map(Room::handle).collect();
+        let mut entries = Vec::with_capacity(rooms.len());
+        for room in rooms {
+            entries.push(room.directory_entry().await);
+        }
+        entries
+    }
+
+    /// Stop room creation, notify all occupants with `ServerDraining`,
+    /// and wait up to `timeout` for the rooms to empty.
+    ///
+    /// Emptied rooms are removed as soon as they are noticed. Rooms still occupied
+    /// after `timeout` are removed and force closed, see `Room::force_close`.
+    /// Returns the number of rooms force closed.
+    pub(crate) async fn drain(&self, timeout: Duration) -> usize {
+        self.draining.store(true, SeqCst);
+        info!("draining {} rooms", self.room_count().await);
+        for room in self.rooms.lock().await.values() {
+            room.notify(Responses::ServerDraining).await;
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut rooms = self.rooms.lock().await;
+            let mut emptied = Vec::new();
+            for (token, room) in rooms.iter() {
+                if room.is_empty().await {
+                    emptied.push(token.clone());
+                }
+            }
+            let closed = self.remove_rooms(&mut rooms, &emptied).await;
+            let remaining = rooms.len();
+            drop(rooms);
+            self.broadcast_closed(closed).await;
+            if remaining == 0 || Instant::now() >= deadline {
+                break;
+            }
+            task::sleep(DRAIN_POLL_INTERVAL.min(deadline - Instant::now())).await;
+        }
+        let remaining: Vec<_> = self.rooms.lock().await.drain().collect();
+        let mut closed = Vec::with_capacity(remaining.len());
+        for (_, room) in remaining.iter() {
+            room.force_close().await;
+            closed.push(room.summary(RoomStatus::Closed).await);
+        }
+        warn!("{} rooms force closed on draining", remaining.len());
+        // dropping rooms stops their background tasks
+        drop(remaining);
+        let force_closed = closed.len();
+        self.broadcast_closed(closed).await;
+        force_closed
+    }
+
+    /// record a room creation unless the player reached the limit within the window
+    async fn allow_creation(&self, player_id: u64) -> bool {
+        self.creations
+            .lock()
+            .await
+            .entry(player_id)
+            .or_insert_with(|| RateLimit::new(self.creation_limit, self.creation_window))
+            .allow(Instant::now())
+    }
+
+    /// create a room with a random token, and let `conn` join it
+    async fn create_room(
+        &self,
+        config: SessionConfig,
+        password: Option<String>,
+        conn: ClientConnection,
+    ) -> RoomToken {
+        let mut rooms = self.rooms.lock().await;
+        let token = self.insert_room(&mut rooms, config, password).await;
+        let _ = conn
+            .sender()
+            .send(Responses::RoomCreated(token.as_code()))
+            .await;
+        let _ = rooms[&token].join(conn).await;
+        token
+    }
+
+    /// insert an empty room with a random unused token,
+    /// nor one of a room closed within `token_grace`
+    async fn insert_room(
+        &self,
+        rooms: &mut HashMap<RoomToken, Room>,
+        config: SessionConfig,
+        password: Option<String>,
+    ) -> RoomToken {
+        let closed_tokens = self.closed_tokens.lock().await;
+        loop {
+            let token = RoomToken::random(&mut thread_rng());
+            if self.in_grace(&closed_tokens, &token) {
+                continue;
+            }
+            if let Entry::Vacant(e) = rooms.entry(token.clone()) {
+                e.insert(Room::empty(
+                    token.clone(),
+                    config,
+                    password,
+                    self.session_counter.clone(),
+                    self.clone(),
+                ));
+                break token;
+            }
+        }
+    }
+
+    /// notify lobby subscribers of a room update
+    pub(crate) async fn room_update(&self, token: &RoomToken, status: RoomStatus) {
+        let summary = match self.rooms.lock().await.get(token) {
+            Some(room) => room.summary(status).await,
+            None => return,
+        };
+        self.broadcast(summary).await;
+    }
+
+    /// subscribers that disconnected are removed, full channels skip this update
+    async fn broadcast(&self, summary: RoomSummary) {
+        self.subscribers.lock().await.retain(|_, sender| {
+            !matches!(
+                sender.try_send(Responses::RoomListUpdate(summary.clone())),
+                Err(TrySendError::Closed(_))
+            )
+        });
+    }
+
+    /// `RoomInfo` of a room, or `JoinRoomFailure` as by `missing_room`
+    async fn query_room(&self, token: &RoomToken) -> Responses {
+        match self.rooms.lock().await.get(token) {
+            Some(room) => room.info().await,
+            None => self.missing_room(token).await,
+        }
+    }
+
+    /// `RoomClosed` if the room of `token` closed within `token_grace`,
+    /// otherwise `TokenNotFound`
+    async fn missing_room(&self, token: &RoomToken) -> Responses {
+        let closed_tokens = self.closed_tokens.lock().await;
+        Responses::JoinRoomFailure(if self.in_grace(&closed_tokens, token) {
+            JoinRoomFailure::RoomClosed
+        } else {
+            JoinRoomFailure::TokenNotFound
+        })
+    }
+
+    fn in_grace(&self, closed_tokens: &HashMap<RoomToken, Instant>, token: &RoomToken) -> bool {
+        closed_tokens
+            .get(token)
+            .is_some_and(|closed| closed.elapsed() < self.token_grace)
+    }
+
+    /// remove the room of `token` right away if nobody is in it
+    pub(crate) async fn close_if_empty(&self, token: &RoomToken) {
+        let mut rooms = self.rooms.lock().await;
+        let is_empty = match rooms.get(token) {
+            Some(room) => room.is_empty().await,
+            None => false,
+        };
+        if is_empty {
+            info!("room {} closed as empty", token.as_code());
+            let closed = self
+                .remove_rooms(&mut rooms, std::slice::from_ref(token))
+                .await;
+            drop(rooms);
+            self.broadcast_closed(closed).await;
+        }
+    }
+
+    /// remove rooms of `tokens`, returning their closed summaries
+    async fn remove_rooms(
+        &self,
+        rooms: &mut HashMap<RoomToken, Room>,
+        tokens: &[RoomToken],
+    ) -> Vec<RoomSummary> {
+        let mut closed = Vec::with_capacity(tokens.len());
+        let mut closed_tokens = self.closed_tokens.lock().await;
+        for k in tokens.iter() {
+            if let Some(room) = rooms.remove(k) {
+                closed.push(room.summary(RoomStatus::Closed).await);
+                closed_tokens.insert(k.clone(), Instant::now());
+            }
+        }
+        closed
+    }
+
+    async fn broadcast_closed(&self, closed: Vec<RoomSummary>) {
+        for summary in closed {
+            self.broadcast(summary).await;
+        }
+    }
+
     // clean rooms
     async fn run_cleaner(&self, threshold: Duration) {
         let mut rooms = self.rooms.lock().await;
@@ -115,8 +486,341 @@ impl RoomManager {
             }
         }
         info!("{} room cleaned", to_clean.len());
-        for k in to_clean.iter() {
-            rooms.remove(k);
+        let closed = self.remove_rooms(&mut rooms, &to_clean).await;
+        drop(rooms);
+        self.broadcast_closed(closed).await;
+        // tokens past the grace period can be reused
+        let grace = self.token_grace;
+        self.closed_tokens
+            .lock()
+            .await
+            .retain(|_, closed| closed.elapsed() < grace);
+        // forget players without recent room creations
+        self.creations
+            .lock()
+            .await
+            .retain(|_, limit| limit.is_active());
+    }
+}
+
+/// microseconds since the unix epoch,
+/// such that session ids do not repeat those of earlier server runs
+fn first_session_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+#[cfg(test)]
+mod test_room_manager {
+    use super::*;
+    use crate::game::{Color, GameView, State};
+    use crate::lobby::room::Position::First;
+    use crate::lobby::telemetry::{NoTelemetry, TelemetryEvent, TelemetrySink};
+    use crate::CHANNEL_SIZE;
+    use async_std::channel::bounded;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_lobby_subscription() {
+        let manager = RoomManager::new();
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let (closed_sender, closed_receiver) = bounded(CHANNEL_SIZE);
+        drop(closed_receiver);
+        block_on(async {
+            let mut subscribers = manager.subscribers.lock().await;
+            subscribers.insert(1, sender);
+            subscribers.insert(2, closed_sender);
+            drop(subscribers);
+            // another connection creates a room
+            let mut rooms = manager.rooms.lock().await;
+            let token = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            drop(rooms);
+            manager.room_update(&token, RoomStatus::Open).await;
+            assert_eq!(
+                receiver.next().await,
+                Some(Responses::RoomListUpdate(RoomSummary {
+                    token: token.as_code(),
+                    occupants: 0,
+                    session_config: SessionConfig::default(),
+                    status: RoomStatus::Open,
+                }))
+            );
+            // disconnected subscriber removed
+            assert!(!manager.subscribers.lock().await.contains_key(&2));
+            // unsubscribed
+            manager.subscribers.lock().await.remove(&1);
+            manager.room_update(&token, RoomStatus::Full).await;
+            assert!(receiver.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_room_creation_rate_limit() {
+        let window = Duration::from_millis(200);
+        let manager = RoomManager::with_creation_limit(3, window, Arc::new(NoTelemetry));
+        block_on(async {
+            for _ in 0..3 {
+                assert!(manager.allow_creation(1).await);
+            }
+            assert!(!manager.allow_creation(1).await);
+            // other players are not affected
+            assert!(manager.allow_creation(2).await);
+            // recovers after the window
+            task::sleep(window).await;
+            assert!(manager.allow_creation(1).await);
+        });
+    }
+
+    #[test]
+    fn test_drain() {
+        let manager = RoomManager::new();
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        let timeout = Duration::from_millis(300);
+        block_on(async {
+            let mut rooms = manager.rooms.lock().await;
+            manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            let occupied = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            rooms[&occupied]
+                .seat_in_session("小雨", 1, sender.clone())
+                .await;
+            drop(rooms);
+            assert_eq!(manager.room_count().await, 2);
+            let start = Instant::now();
+            let drain = task::spawn({
+                let manager = manager.clone();
+                async move { manager.drain(timeout).await }
+            });
+            assert_eq!(receiver.next().await, Some(Responses::ServerDraining));
+            // the empty room is removed right away
+            task::sleep(timeout / 2).await;
+            assert_eq!(manager.room_count().await, 1);
+            // the occupied room is force closed after the timeout
+            assert_eq!(drain.await, 1);
+            assert!(start.elapsed() >= timeout);
+            assert_eq!(manager.room_count().await, 0);
+            // the player in session is left to finish the game
+            assert!(receiver.try_recv().is_err());
+            assert!(manager.draining.load(SeqCst));
+        });
+    }
+
+    #[test]
+    fn test_close_if_empty() {
+        let manager = RoomManager::new();
+        let (sender, mut receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut rooms = manager.rooms.lock().await;
+            let empty = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            let occupied = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            rooms[&occupied]
+                .seat_in_session("小雨", 1, bounded(1).0)
+                .await;
+            drop(rooms);
+            manager.subscribers.lock().await.insert(2, sender);
+            manager.close_if_empty(&occupied).await;
+            assert_eq!(manager.room_count().await, 2);
+            manager.close_if_empty(&empty).await;
+            assert_eq!(manager.room_count().await, 1);
+            assert!(matches!(
+                receiver.next().await,
+                Some(Responses::RoomListUpdate(RoomSummary {
+                    status: RoomStatus::Closed,
+                    ..
+                }))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_token_grace() {
+        let grace = Duration::from_millis(300);
+        let manager = RoomManager::with_token_grace(grace, Arc::new(NoTelemetry));
+        block_on(async {
+            let mut rooms = manager.rooms.lock().await;
+            let token = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            drop(rooms);
+            manager.close_if_empty(&token).await;
+            assert_eq!(
+                manager.query_room(&token).await,
+                Responses::JoinRoomFailure(JoinRoomFailure::RoomClosed)
+            );
+            let closed_tokens = manager.closed_tokens.lock().await;
+            assert!(manager.in_grace(&closed_tokens, &token));
+            drop(closed_tokens);
+            // reusable after the grace period
+            task::sleep(grace).await;
+            assert_eq!(
+                manager.query_room(&token).await,
+                Responses::JoinRoomFailure(JoinRoomFailure::TokenNotFound)
+            );
+            let closed_tokens = manager.closed_tokens.lock().await;
+            assert!(!manager.in_grace(&closed_tokens, &token));
+            drop(closed_tokens);
+            manager.run_cleaner(ROOM_LIFE_LENGTH).await;
+            assert!(manager.closed_tokens.lock().await.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_session_ids_unique_across_rooms() {
+        let before = first_session_id();
+        let manager = RoomManager::new();
+        block_on(async {
+            let mut rooms = manager.rooms.lock().await;
+            let first = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            let second = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            let mut ids = vec![];
+            for _ in 0..3 {
+                ids.push(rooms[&first].next_session_id().await);
+                ids.push(rooms[&second].next_session_id().await);
+            }
+            let unique: std::collections::HashSet<_> = ids.iter().collect();
+            assert_eq!(unique.len(), ids.len());
+            // ids continue from the server start time rather than 0
+            assert!(ids.iter().all(|&id| id >= before));
+        });
+        // another server run does not reuse the ids
+        let restarted = RoomManager::new();
+        assert!(restarted.session_counter.load(SeqCst) > before);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let manager = RoomManager::new();
+        block_on(async {
+            let mut rooms = manager.rooms.lock().await;
+            let idle = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            let playing = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            rooms[&playing]
+                .seat_in_session("小雨", 1, bounded(1).0)
+                .await;
+            rooms[&playing]
+                .seat_in_session("雨雨", 2, bounded(1).0)
+                .await;
+            let view = GameView::new(15, 15);
+            let mut field = vec![vec![State::E; 15]; 15];
+            field[7][7] = State::B;
+            field[7][8] = State::W;
+            view.publish(Color::Black, &field, true, None).await;
+            rooms[&playing].show_game(view).await;
+            drop(rooms);
+            let directory = RoomDirectory::new();
+            assert!(directory.snapshot().await.is_empty());
+            directory.attach(manager.clone());
+            let mut entries = directory.snapshot().await;
+            entries.sort_by_key(|entry| entry.in_game);
+            let [idle_entry, playing_entry] = <[_; 2]>::try_from(entries).unwrap();
+            assert_eq!(idle_entry.token, idle);
+            assert!(idle_entry.occupants.is_empty());
+            assert!(!idle_entry.in_game);
+            assert_eq!(idle_entry.move_count, 0);
+            assert!(idle_entry.inactive_since.is_some());
+            assert_eq!(
+                playing_entry,
+                RoomDirectoryEntry {
+                    token: playing,
+                    occupants: vec!["小雨".to_string(), "雨雨".to_string()],
+                    in_game: true,
+                    move_count: 2,
+                    inactive_since: None,
+                }
+            );
+        });
+    }
+
+    /// records all events
+    #[derive(Default)]
+    struct CapturingSink(std::sync::Mutex<Vec<TelemetryEvent>>);
+
+    impl TelemetrySink for CapturingSink {
+        fn emit(&self, event: TelemetryEvent) {
+            self.0.lock().unwrap().push(event);
         }
     }
+
+    #[test]
+    fn test_snapshot_leaves_rooms_running() {
+        let sink = Arc::new(CapturingSink::default());
+        let manager = RoomManager::with_telemetry(sink.clone());
+        let (first_sender, _first_receiver) = bounded(CHANNEL_SIZE);
+        let (second_sender, second_receiver) = bounded(CHANNEL_SIZE);
+        block_on(async {
+            let mut rooms = manager.rooms.lock().await;
+            let token = manager
+                .insert_room(&mut rooms, SessionConfig::default(), None)
+                .await;
+            rooms[&token].seat_in_session("小雨", 1, first_sender).await;
+            rooms[&token]
+                .seat_in_session("雨雨", 2, second_sender)
+                .await;
+            drop(rooms);
+            assert_eq!(manager.snapshot().await.len(), 1);
+            let reaped =
+                |event: &TelemetryEvent| matches!(event, TelemetryEvent::RoomReaped { .. });
+            assert!(!sink.0.lock().unwrap().iter().any(reaped));
+            // the message loop of the room still runs
+            let chat = Messages::ChatMessage("hi".to_string());
+            manager.rooms.lock().await[&token]
+                .receive(First, chat)
+                .await;
+            assert_eq!(
+                second_receiver.recv().await,
+                Ok(Responses::ChatMessage("小雨".to_string(), "hi".to_string()))
+            );
+        });
+    }
+
+    #[test]
+    fn test_query_room() {
+        let manager = RoomManager::new();
+        let token = RoomToken::random(&mut thread_rng());
+        let config = SessionConfig {
+            play_timeout: 30,
+            ..SessionConfig::default()
+        };
+        let room = Room::empty(
+            token.clone(),
+            config.clone(),
+            None,
+            manager.session_counter.clone(),
+            manager.clone(),
+        );
+        block_on(async {
+            manager.rooms.lock().await.insert(token.clone(), room);
+            assert_eq!(
+                manager.query_room(&token).await,
+                Responses::RoomInfo {
+                    occupants: vec![],
+                    session_config: config,
+                    in_game: false,
+                }
+            );
+            let unknown = RoomToken::random(&mut thread_rng());
+            assert_eq!(
+                manager.query_room(&unknown).await,
+                Responses::JoinRoomFailure(JoinRoomFailure::TokenNotFound)
+            );
+        });
+    }
 }
@@ -0,0 +1,93 @@
+//! Feature discovery.
+//!
+//! Right after TLS, before authentication, the server sends
+//! `Responses::Welcome` once, so that clients can adapt to the server.
+use crate::lobby::messages::Responses;
+
+/// version of `Messages` and `Responses` on the wire,
+/// variants are tagged by their position, so adding, removing
+/// or reordering variants needs a new version
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// features every server of this version supports
+pub const FEATURES: [&str; 3] = ["chat", "peek_game", "confirm_moves"];
+/// `DebugRoom` is available, when the server has admins
+pub const FEATURE_DEBUG_ROOM: &str = "debug_room";
+
+/// what the server tells about itself in `Responses::Welcome`
+#[derive(Clone, Debug)]
+pub struct ServerInfo {
+    pub server_name: String,
+    /// features beyond those derived from the server configuration
+    pub features: Vec<String>,
+}
+
+impl Default for ServerInfo {
+    /// server name `wuziqi`, with all `FEATURES`
+    fn default() -> Self {
+        ServerInfo {
+            server_name: "wuziqi".to_string(),
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+}
+
+impl ServerInfo {
+    pub(crate) fn welcome(&self, has_admins: bool) -> Responses {
+        let mut features = self.features.clone();
+        if has_admins {
+            features.push(FEATURE_DEBUG_ROOM.to_string());
+        }
+        Responses::Welcome {
+            server_name: self.server_name.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            features,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_server_info {
+    use super::*;
+
+    fn features(welcome: Responses) -> Vec<String> {
+        match welcome {
+            Responses::Welcome {
+                protocol_version,
+                features,
+                ..
+            } => {
+                assert_eq!(protocol_version, PROTOCOL_VERSION);
+                features
+            }
+            _ => panic!("not a welcome"),
+        }
+    }
+
+    #[test]
+    fn test_welcome_features() {
+        let info = ServerInfo::default();
+        let default_features = features(info.welcome(false));
+        assert_eq!(default_features, FEATURES);
+        // admins enable `DebugRoom`
+        let with_admins = features(info.welcome(true));
+        assert!(with_admins.iter().any(|f| f == FEATURE_DEBUG_ROOM));
+        assert!(FEATURES.iter().all(|f| with_admins.iter().any(|g| g == f)));
+        // configured name and features
+        let info = ServerInfo {
+            server_name: "雨".to_string(),
+            features: vec!["peek_game".to_string()],
+        };
+        match info.welcome(false) {
+            Responses::Welcome {
+                server_name,
+                features,
+                ..
+            } => {
+                assert_eq!(server_name, "雨");
+                assert_eq!(features, vec!["peek_game".to_string()]);
+            }
+            _ => panic!("not a welcome"),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Structured events of the server lifecycle.
+//!
+//! Operators provide a `TelemetrySink` to `start_server` in `ServerOptions`
+//! to collect metrics without parsing logs.
+use crate::lobby::messages::LoginFailure;
+use crate::lobby::token::RoomToken;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// receives telemetry events, `emit` should return quickly
+pub trait TelemetrySink: Send + Sync {
+    fn emit(&self, event: TelemetryEvent);
+}
+
+/// discards all events
+pub struct NoTelemetry;
+
+impl TelemetrySink for NoTelemetry {
+    fn emit(&self, _: TelemetryEvent) {}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TelemetryEvent {
+    /// TLS established
+    ConnectionOpened {
+        address: SocketAddr,
+    },
+    /// `player_id` is `None` if the connection closed before login
+    ConnectionClosed {
+        address: SocketAddr,
+        player_id: Option<u64>,
+    },
+    LoginSucceeded {
+        player_id: u64,
+        player_name: String,
+    },
+    /// response to `Login`
+    LoginFailed {
+        player_name: String,
+        reason: LoginFailure,
+    },
+    RoomCreated {
+        room: RoomToken,
+    },
+    /// the room is removed, e.g. inactive for too long, or emptied
+    RoomReaped {
+        room: RoomToken,
+    },
+    GameStarted {
+        session_id: u64,
+        black_player_id: u64,
+        white_player_id: u64,
+    },
+    GameEnded {
+        session_id: u64,
+        outcome: GameOutcome,
+    },
+}
+
+/// how a game session ended, timeouts counting as wins
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameOutcome {
+    BlackWins,
+    WhiteWins,
+    Draw,
+    /// a player left before any move, no contest
+    Aborted,
+    /// a player quit, exited or disconnected before the game ended
+    Abandoned,
+}
+
+/// the sink of the server, which is shared by connections, rooms and game sessions
+pub(crate) type Telemetry = Arc<dyn TelemetrySink>;
+
+/// telemetry of a connection before login,
+/// emitting `ConnectionClosed` when dropped unless logged in
+pub(crate) struct LoginTelemetry {
+    telemetry: Telemetry,
+    address: SocketAddr,
+    logged_in: bool,
+}
+
+impl LoginTelemetry {
+    /// emit `ConnectionOpened`
+    pub(crate) fn opened(telemetry: Telemetry, address: SocketAddr) -> Self {
+        telemetry.emit(TelemetryEvent::ConnectionOpened { address });
+        LoginTelemetry {
+            telemetry,
+            address,
+            logged_in: false,
+        }
+    }
+
+    pub(crate) fn emit(&self, event: TelemetryEvent) {
+        self.telemetry.emit(event);
+    }
+
+    /// emit `LoginSucceeded`, the connection then emits `ConnectionClosed`
+    pub(crate) fn logged_in(mut self, player_id: u64, player_name: &str) -> Telemetry {
+        self.logged_in = true;
+        self.emit(TelemetryEvent::LoginSucceeded {
+            player_id,
+            player_name: player_name.to_string(),
+        });
+        self.telemetry.clone()
+    }
+}
+
+impl Drop for LoginTelemetry {
+    fn drop(&mut self) {
+        if !self.logged_in {
+            self.emit(TelemetryEvent::ConnectionClosed {
+                address: self.address,
+                player_id: None,
+            });
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use crate::lobby::messages::{
     CreateAccountFailure, InvalidAccountPassword, LoginFailure, UpdatePasswordFailure,
 };
+use crate::lobby::normalization::Normalization;
 use anyhow::Error;
 use bincode::config::Configuration;
+use bincode::error::{DecodeError, EncodeError};
 use bincode::{config, decode_from_slice, encode_to_vec, Decode, Encode};
-use log::error;
+use log::{error, info};
 use sled::{CompareAndSwapError, Db, Tree};
 use std::ops::Deref;
 use std::path::Path;
@@ -18,6 +20,12 @@ const MAX_PASSWORD_BYTES: usize = 32;
 const USER_INFO_TREE: &[u8] = b"user_info";
 const META_INFO: &[u8] = b"meta";
 const LATEST_USER_ID: &[u8] = b"latest_user_id";
+/// Version byte prefixed to stored `UserInfo` records.
+///
+/// Version 1 records are bincode encoded `UserInfo` without prefix.
+/// They start with the password length (at least `MIN_PASSWORD_BYTES`),
+/// thus never collide with a version byte smaller than `MIN_PASSWORD_BYTES`.
+const USER_INFO_VERSION: u8 = 2;
 
 #[derive(Encode, Decode, PartialEq, Eq)]
 pub struct Password(pub String);
@@ -36,12 +44,48 @@ impl Deref for Password {
     }
 }
 
+/// decides whether a user name may be registered, e.g. by community standards
+pub trait NameFilter: Send + Sync {
+    fn allows(&self, name: &str) -> bool;
+}
+
+impl<F: Fn(&str) -> bool + Send + Sync> NameFilter for F {
+    fn allows(&self, name: &str) -> bool {
+        self(name)
+    }
+}
+
+/// rejects names containing any of the words, ignoring case
+pub struct Blocklist(Vec<String>);
+
+impl Blocklist {
+    pub fn new<S: Into<String>>(words: impl IntoIterator<Item = S>) -> Self {
+        Blocklist(
+            words
+                .into_iter()
+                .map(|word| word.into().to_lowercase())
+                .collect(),
+        )
+    }
+}
+
+impl NameFilter for Blocklist {
+    fn allows(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        !self.0.iter().any(|word| name.contains(word.as_str()))
+    }
+}
+
 #[derive(Clone)]
 pub struct LoginValidator {
     db: Db,
     meta: Tree,
     user_info: Tree,
     current_id: Arc<AtomicU64>,
+    /// consulted on registration only, existing users can still log in
+    name_filter: Option<Arc<dyn NameFilter>>,
+    /// applied to names by the connections before they query the validator
+    normalization: Normalization,
 }
 
 impl LoginValidator {
@@ -77,17 +121,39 @@ impl LoginValidator {
             meta,
             user_info,
             current_id,
+            name_filter: None,
+            normalization: Normalization::default(),
         })
     }
 
+    /// reject registering names not allowed by `name_filter` with `NameNotAllowed`
+    pub fn with_name_filter(mut self, name_filter: Arc<dyn NameFilter>) -> Self {
+        self.name_filter = Some(name_filter);
+        self
+    }
+
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
     pub fn query_user_password(&self, name: &str) -> Result<UserInfo, LoginFailure> {
         let name = match validate_name(name) {
             Ok(name) => name,
             Err(e) => return Err(LoginFailure::BadInput(e)),
         };
         match self.user_info.get(name.as_bytes()) {
-            Ok(Some(data)) => match decode_from_slice(data.as_ref(), DB_BIN_CONFIG) {
-                Ok((info, _)) => Ok(info),
+            Ok(Some(data)) => match decode_user_info(data.as_ref()) {
+                Ok((info, version)) => {
+                    if version != USER_INFO_VERSION {
+                        self.migrate_user_info(name, &info, version);
+                    }
+                    Ok(info)
+                }
                 Err(e) => {
                     error!("user db decode error: {}", e);
                     Err(LoginFailure::ServerError)
@@ -110,6 +176,14 @@ impl LoginValidator {
         let name = validate_name(name).map_err(|e| CreateAccountFailure::BadInput(e))?;
         let password =
             validate_password(password).map_err(|e| CreateAccountFailure::BadInput(e))?;
+        if let Some(name_filter) = &self.name_filter {
+            if !name_filter.allows(name) {
+                info!("registration of name ({}) not allowed", name);
+                return Err(CreateAccountFailure::BadInput(
+                    InvalidAccountPassword::NameNotAllowed,
+                ));
+            }
+        }
 
         // generate a new user_id by incrementing a counter
         let new_id = self.current_id.load(Ordering::SeqCst) + 1;
@@ -133,7 +207,7 @@ impl LoginValidator {
             user_id: new_id,
         };
 
-        let pass_bytes = encode_to_vec(new_user_info, DB_BIN_CONFIG).map_err(|_| {
+        let pass_bytes = encode_user_info(&new_user_info).map_err(|_| {
             error!("user_info encode error");
             CreateAccountFailure::ServerError
         })?;
@@ -177,11 +251,10 @@ impl LoginValidator {
                     return Err(UpdatePasswordFailure::UserDoesNotExist);
                 }
                 Some(info) => {
-                    let (info, _) =
-                        decode_from_slice(info.as_ref(), DB_BIN_CONFIG).map_err(|_| {
-                            error!("password encode error");
-                            UpdatePasswordFailure::ServerError
-                        })?;
+                    let (info, _) = decode_user_info(info.as_ref()).map_err(|_| {
+                        error!("password encode error");
+                        UpdatePasswordFailure::ServerError
+                    })?;
                     info
                 }
             },
@@ -197,7 +270,7 @@ impl LoginValidator {
                 user_id: old_info.user_id,
             };
 
-            let new_info_bytes = encode_to_vec(new_info, DB_BIN_CONFIG).map_err(|_| {
+            let new_info_bytes = encode_user_info(&new_info).map_err(|_| {
                 error!("user info encode error");
                 UpdatePasswordFailure::ServerError
             })?;
@@ -213,6 +286,41 @@ impl LoginValidator {
             Err(UpdatePasswordFailure::PasswordIncorrect)
         }
     }
+
+    /// rewrite an outdated record in the latest format, failure only gets logged
+    fn migrate_user_info(&self, name: &str, info: &UserInfo, version: u8) {
+        match encode_user_info(info) {
+            Ok(bytes) => match self.user_info.insert(name, bytes) {
+                Ok(_) => info!("user info of {} migrated from version {}", name, version),
+                Err(e) => error!("user info migration error ({}): {}", name, e),
+            },
+            Err(e) => error!("user info migration encode error ({}): {}", name, e),
+        }
+    }
+}
+
+/// encode `UserInfo` with the latest version byte
+fn encode_user_info(info: &UserInfo) -> Result<Vec<u8>, EncodeError> {
+    let mut bytes = vec![USER_INFO_VERSION];
+    bytes.extend(encode_to_vec(info, DB_BIN_CONFIG)?);
+    Ok(bytes)
+}
+
+/// decode `UserInfo` of any known version, returns `(info, version)`
+fn decode_user_info(data: &[u8]) -> Result<(UserInfo, u8), DecodeError> {
+    match data.split_first() {
+        Some((&USER_INFO_VERSION, rest)) => {
+            let (info, _) = decode_from_slice(rest, DB_BIN_CONFIG)?;
+            Ok((info, USER_INFO_VERSION))
+        }
+        Some((&version, _)) if (version as usize) < MIN_PASSWORD_BYTES => {
+            Err(DecodeError::Other("unknown user info version"))
+        }
+        _ => {
+            let (info, _) = decode_from_slice(data, DB_BIN_CONFIG)?;
+            Ok((info, 1))
+        }
+    }
 }
 
 fn validate_name(name: &str) -> Result<&str, InvalidAccountPassword> {
@@ -245,3 +353,71 @@ impl Drop for LoginValidator {
         let _ = self.db.flush();
     }
 }
+
+#[cfg(test)]
+mod test_user_db {
+    use super::*;
+    use rand::random;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn test_migrate_v1_user_info() {
+        let path = std::env::temp_dir().join(format!("wuziqi_user_db_{}", random::<u64>()));
+        {
+            let validator = LoginValidator::init(&path).unwrap();
+            let v1_info = UserInfo {
+                password: Password("小雨的密码".to_string()),
+                user_id: 42,
+            };
+            // v1 records have no version byte
+            let v1_bytes = encode_to_vec(&v1_info, DB_BIN_CONFIG).unwrap();
+            validator.user_info.insert("小雨", v1_bytes).unwrap();
+
+            let info = validator.query_user_password("小雨").unwrap();
+            assert!(info == v1_info);
+            // record rewritten in the latest format
+            let stored = validator.user_info.get("小雨").unwrap().unwrap();
+            assert_eq!(stored[0], USER_INFO_VERSION);
+            let (migrated, version) = decode_user_info(stored.as_ref()).unwrap();
+            assert!(migrated == v1_info);
+            assert_eq!(version, USER_INFO_VERSION);
+
+            // migrated records remain usable
+            let id = validator
+                .update_user_info(
+                    "小雨",
+                    Password("小雨的密码".to_string()),
+                    Password("new password".to_string()),
+                )
+                .unwrap();
+            assert_eq!(id, 42);
+            let info = validator.query_user_password("小雨").unwrap();
+            assert_eq!(info.password.as_str(), "new password");
+        }
+        let _ = remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_name_filter() {
+        let path = std::env::temp_dir().join(format!("wuziqi_user_db_{}", random::<u64>()));
+        {
+            let blocklist = Arc::new(Blocklist::new(["badword"]));
+            let validator = LoginValidator::init(&path)
+                .unwrap()
+                .with_name_filter(blocklist);
+            let password = || Password("password".to_string());
+            assert_eq!(
+                validator.register_user("xBadWordx", password()),
+                Err(CreateAccountFailure::BadInput(
+                    InvalidAccountPassword::NameNotAllowed
+                ))
+            );
+            assert!(validator.register_user("小雨", password()).is_ok());
+            // a predicate works as a filter too
+            let validator = validator.with_name_filter(Arc::new(|name: &str| name != "admin"));
+            assert!(validator.register_user("admin", password()).is_err());
+            assert!(validator.register_user("雨雨", password()).is_ok());
+        }
+        let _ = remove_dir_all(path);
+    }
+}
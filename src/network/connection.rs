@@ -4,14 +4,16 @@
 //!
 //! - Automatic disconnection handling: user may be guaranteed that
 //!   on `send` error, `next` will eventually receive `None`.
+//! - Low latency: Nagle's algorithm is disabled (`TCP_NODELAY`)
+//!   on the TCP stream under TLS.
 //!
 //! The wrapper may repeatedly send `Ping` message to check whether the connection
 //! is still active. Any tcp write failure will result in connection close,
 //! for both write and read.
 //!
 //! When remote connection closed the `write` side, `next()` will eventually
-//! return `None`. However, the `Sender` may still be used to send messages
-//! indefinitely.
+//! return `None`, after `Received::Closed` if it closed cleanly between messages.
+//! However, the `Sender` may still be used to send messages indefinitely.
 //!
 //! When remote connection closed the `read` side, sender will eventually return
 //! error. Both sides of the connection will be closed in this situation.
@@ -20,7 +22,16 @@
 //! if pinging is enabled, then eventually both sides of the connection will be
 //! closed due to write error.
 //!
-//! Dropping the `Conn` struct will close both sides of the connection.
+//! When remote connection is silent but stays open, reading waits forever,
+//! unless a read timeout is set: if no complete message (including `Ping`)
+//! arrives within it, both sides are closed with `ReadTimeout`.
+//! Remote pinging more often than the timeout keeps the connection open.
+//!
+//! Dropping the `Conn` struct, or both halves returned by `Conn::split`,
+//! will close both sides of the connection.
+//! Messages queued but not yet written at that moment are either flushed
+//! to remote or discarded, depending on `ClosePolicy` (flushed by default).
+//! On connection error (both sides shutdown), queued messages are discarded.
 //!
 //! The following error on receiving messages will be sent to remote socket,
 //! and then the connection will be closed.
@@ -29,8 +40,10 @@
 //! - MaxDataLengthExceeded: data payload top long
 //! - DataCorrupted: checksum does not match
 //! - UnknownMessageType: message type byte does not match
+//! - ReadTimeout: nothing received within the read timeout
+use crate::network::resumable::handle_resumable_connection;
 use crate::network::utility;
-use async_std::channel::{bounded, Receiver, Sender};
+use async_std::channel::{bounded, Receiver, SendError, Sender};
 use async_std::io::BufReader;
 use async_std::net::TcpStream;
 use async_std::prelude::Stream;
@@ -42,15 +55,15 @@ use futures::channel::oneshot;
 use futures::io::{ReadHalf, WriteHalf};
 use futures::{select, AsyncWriteExt, StreamExt};
 use futures::{AsyncReadExt, FutureExt};
-use tokio_rustls::TlsStream;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::ErrorKind;
 use std::net::Shutdown;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::Duration;
+use tokio_rustls::TlsStream;
 
-const NET_CHANNEL_SIZE: usize = 20;
+pub(crate) const NET_CHANNEL_SIZE: usize = 20;
 
 /// Connection portal, returned by `handle_connection`.
 ///
@@ -73,12 +86,94 @@ where
         ping_interval: Option<Duration>,
         max_data_size: u32,
     ) -> Self {
-        handle_connection(tls, ping_interval, max_data_size)
+        handle_connection(tls, ping_interval, max_data_size, ClosePolicy::Flush, None)
+    }
+
+    /// same as `init`, with specified handling of queued messages on close
+    pub fn init_with_close_policy(
+        tls: TlsStream<TcpStream>,
+        ping_interval: Option<Duration>,
+        max_data_size: u32,
+        close_policy: ClosePolicy,
+    ) -> Self {
+        handle_connection(tls, ping_interval, max_data_size, close_policy, None)
+    }
+
+    /// same as `init`, closing the connection with `ReadTimeout`
+    /// if nothing is received within `read_timeout`
+    pub fn init_with_read_timeout(
+        tls: TlsStream<TcpStream>,
+        ping_interval: Option<Duration>,
+        max_data_size: u32,
+        read_timeout: Duration,
+    ) -> Self {
+        handle_connection(
+            tls,
+            ping_interval,
+            max_data_size,
+            ClosePolicy::Flush,
+            Some(read_timeout),
+        )
+    }
+
+    /// A connection that survives transport drops, see `network::resumable`.
+    ///
+    /// Each transport received from `transports` replaces the dropped one.
+    /// Remote must also be in resumable mode.
+    pub fn init_resumable(
+        transports: Receiver<TlsStream<TcpStream>>,
+        ping_interval: Option<Duration>,
+        max_data_size: u32,
+    ) -> Self {
+        let (sender, receiver) =
+            handle_resumable_connection(transports, ping_interval, max_data_size);
+        Conn { sender, receiver }
     }
 
     pub fn sender(&self) -> &Sender<Msg> {
         &self.sender
     }
+
+    /// Split into halves that can be owned by separate tasks.
+    ///
+    /// Dropping both halves closes the connection as dropping `Conn` does.
+    pub fn split(self) -> (ConnSender<Msg>, ConnReceiver<Rsp>) {
+        (
+            ConnSender {
+                sender: self.sender,
+            },
+            ConnReceiver {
+                receiver: self.receiver,
+            },
+        )
+    }
+
+    /// Send `msg` and wait for the next response, skipping pings.
+    ///
+    /// Responses received before calling this are not skipped,
+    /// so this suits strict request/response interactions such as login.
+    pub async fn request(&mut self, msg: Msg, timeout: Duration) -> Result<Rsp, RequestError> {
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| RequestError::Closed)?;
+        async_std::future::timeout(timeout, self.next_response())
+            .await
+            .map_err(|_| RequestError::Timeout)?
+    }
+
+    async fn next_response(&mut self) -> Result<Rsp, RequestError> {
+        loop {
+            match self.receiver.next().await {
+                None => return Err(RequestError::Closed),
+                Some(received) => {
+                    if let Some(rsp) = received.into_response() {
+                        return rsp.map_err(RequestError::Connection);
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl<Msg, Rsp> Stream for Conn<Msg, Rsp> {
@@ -89,6 +184,46 @@ impl<Msg, Rsp> Stream for Conn<Msg, Rsp> {
     }
 }
 
+/// sending half of a `Conn`, see `Conn::split`
+///
+/// dropping this struct and all its clones closes the write side
+#[derive(Clone)]
+pub struct ConnSender<Msg> {
+    sender: Sender<Msg>,
+}
+
+impl<Msg> ConnSender<Msg> {
+    pub async fn send(&self, msg: Msg) -> Result<(), SendError<Msg>> {
+        self.sender.send(msg).await
+    }
+
+    pub fn sender(&self) -> &Sender<Msg> {
+        &self.sender
+    }
+}
+
+/// receiving half of a `Conn`, see `Conn::split`
+pub struct ConnReceiver<Rsp> {
+    receiver: Receiver<Received<Rsp>>,
+}
+
+impl<Rsp> Stream for ConnReceiver<Rsp> {
+    type Item = Received<Rsp>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_next_unpin(cx)
+    }
+}
+
+/// What to do with messages still queued when all senders are dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClosePolicy {
+    /// write all queued messages before closing
+    Flush,
+    /// discard queued messages not yet being written
+    Discard,
+}
+
 /// wrapper of responses received
 pub enum Received<T> {
     /// normal message received
@@ -99,6 +234,25 @@ pub enum Received<T> {
     Error(ConnectionError),
     /// remote socket error: reason for connection close
     RemoteError(ConnectionError),
+    /// resumable connection switched to a new transport
+    Reconnected,
+    /// remote closed the connection cleanly between messages,
+    /// the last item before `None`
+    Closed,
+}
+
+impl<T> Received<T> {
+    /// The payload of `Response`, or the error of `Error` and `RemoteError`,
+    /// which end the connection.
+    ///
+    /// `Ping`, `Reconnected` and `Closed` carry nothing and give `None`.
+    pub fn into_response(self) -> Option<Result<T, ConnectionError>> {
+        match self {
+            Received::Response(rsp) => Some(Ok(rsp)),
+            Received::Ping | Received::Reconnected | Received::Closed => None,
+            Received::Error(e) | Received::RemoteError(e) => Some(Err(e)),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Encode, Decode)]
@@ -113,6 +267,8 @@ pub enum ConnectionError {
     DecodeError,
     /// Cannot decode error message
     UnknownError,
+    /// nothing received within the read timeout
+    ReadTimeout,
 }
 
 impl Display for ConnectionError {
@@ -123,6 +279,28 @@ impl Display for ConnectionError {
             ConnectionError::DataCorrupted => f.write_str("data corrupted"),
             ConnectionError::DecodeError => f.write_str("decode error"),
             ConnectionError::UnknownError => f.write_str("unknown error"),
+            ConnectionError::ReadTimeout => f.write_str("read timeout"),
+        }
+    }
+}
+
+/// error returned by `Conn::request`
+#[derive(Clone, Debug, PartialEq)]
+pub enum RequestError {
+    /// no response within timeout
+    Timeout,
+    /// connection closed
+    Closed,
+    /// local or remote connection error
+    Connection(ConnectionError),
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Timeout => f.write_str("request timeout"),
+            RequestError::Closed => f.write_str("connection closed"),
+            RequestError::Connection(e) => write!(f, "connection error: {}", e),
         }
     }
 }
@@ -131,27 +309,26 @@ fn handle_connection<Msg, Rsp>(
     tls: TlsStream<TcpStream>,
     ping_interval: Option<Duration>,
     max_data_size: u32,
+    close_policy: ClosePolicy,
+    read_timeout: Option<Duration>,
 ) -> Conn<Msg, Rsp>
 where
     Msg: Send + 'static + Into<Vec<u8>>,
     Rsp: Send + 'static + TryFrom<Vec<u8>>,
 {
-    let (msg_sender, mut inner_msg_receiver) = bounded(NET_CHANNEL_SIZE);
+    let (msg_sender, inner_msg_receiver) = bounded(NET_CHANNEL_SIZE);
     let (inner_msg_sender, msg_receiver) = bounded(NET_CHANNEL_SIZE);
     let (rsp_sender, rsp_receiver) = bounded(NET_CHANNEL_SIZE);
     let inner_ping_sender = inner_msg_sender.clone();
+    utility::set_nodelay(&tls);
     let (read_tls, write_tls) = tls.split();
     // define three stoppers for stopping three tasks
     let (ping_stopper, stop_pinging) = oneshot::channel::<()>();
     let (send_stopper, stop_sending) = oneshot::channel::<()>();
     let (recv_stopper, stop_receiving) = oneshot::channel::<()>();
+    let queue = msg_receiver.clone();
     task::spawn(async move {
-        // wrap messages from `msg_sender` with `MessageType::Data`
-        while let Some(msg) = inner_msg_receiver.next().await {
-            if inner_msg_sender.send(MessageType::Data(msg)).await.is_err() {
-                break;
-            }
-        }
+        forward_messages(inner_msg_receiver, inner_msg_sender, queue, close_policy).await;
         ping_stopper.send(())
     });
     // start pinging task
@@ -161,8 +338,13 @@ where
     // start messages sender loop
     let send_joiner = send_messages::<Msg>(write_tls, msg_receiver, stop_sending, max_data_size);
     // start messages receiver loop
-    let receive_joiner =
-        retrieve_messages::<Msg, Rsp>(read_tls, rsp_sender, stop_receiving, max_data_size);
+    let receive_joiner = retrieve_messages::<Msg, Rsp>(
+        read_tls,
+        rsp_sender,
+        stop_receiving,
+        max_data_size,
+        read_timeout,
+    );
     // deal with connection shutdown
     task::spawn(async move {
         let mut recv_stopper = Some(recv_stopper);
@@ -219,6 +401,31 @@ where
     }
 }
 
+/// Wrap messages from `msg_sender` with `MessageType::Data`,
+/// until all `msg_sender` are dropped.
+///
+/// With `ClosePolicy::Discard`, messages remaining in the channels
+/// (`queue` is a clone of the receiver of `inner_msg_sender`) are dropped.
+async fn forward_messages<Msg>(
+    mut msg_receiver: Receiver<Msg>,
+    inner_msg_sender: Sender<MessageType<Msg>>,
+    queue: Receiver<MessageType<Msg>>,
+    close_policy: ClosePolicy,
+) {
+    while let Some(msg) = msg_receiver.next().await {
+        if close_policy == ClosePolicy::Discard && msg_receiver.is_closed() {
+            break;
+        }
+        if inner_msg_sender.send(MessageType::Data(msg)).await.is_err() {
+            break;
+        }
+    }
+    if close_policy == ClosePolicy::Discard {
+        while msg_receiver.try_recv().is_ok() {}
+        while queue.try_recv().is_ok() {}
+    }
+}
+
 enum MessageType<Msg> {
     Data(Msg),
     Ping,
@@ -227,18 +434,19 @@ enum MessageType<Msg> {
 
 // message types
 const DATA: u8 = 0;
-const PING: u8 = 100;
+pub(crate) const PING: u8 = 100;
 const ERROR: u8 = 200;
 
 /// This task returns in three possible ways:
 /// - the receiver of the retrieved message is dropped: shutdown read
-/// - remote write closed (eof read): shutdown read
-/// - data decode error: shutdown both sides
+/// - remote write closed (eof read): shutdown read, with `Closed` if clean
+/// - data decode error or read timeout: shutdown both sides
 fn retrieve_messages<Msg, Rsp>(
     read_tls: ReadHalf<TlsStream<TcpStream>>,
     rsp_sender: Sender<Received<Rsp>>,
     stop_receiving: oneshot::Receiver<()>,
     max_data_size: u32,
+    read_timeout: Option<Duration>,
 ) -> JoinHandle<(
     ReadHalf<TlsStream<TcpStream>>,
     Option<Shutdown>,
@@ -256,8 +464,13 @@ where
                 _ = stop_receiving => {
                     break (None, None);
                 }
-                read = read_rsp::<Rsp>(&mut reader, max_data_size).fuse() => {
+                read = read_rsp_within::<Rsp>(&mut reader, max_data_size, read_timeout).fuse() => {
                     match read {
+                        Ok(Some(Received::Closed)) => {
+                            // allow sender to send
+                            let _ = rsp_sender.send(Received::Closed).await;
+                            break (Some(Shutdown::Read), None);
+                        }
                         Ok(Some(rsp)) => {
                             // if receiver got dropped, allow sender to send
                             if rsp_sender.send(rsp).await.is_err() {
@@ -357,7 +570,7 @@ fn send_ping<Msg>(
     });
 }
 
-/// `Ok(Some)` if read succeed.
+/// `Ok(Some)` if read succeed, `Ok(Some(Received::Closed))` on a clean close.
 /// `Ok(None)` if no more data to read.
 ///
 /// `Err()` if error occurred:
@@ -373,9 +586,10 @@ async fn read_rsp<Rsp>(
 where
     Rsp: TryFrom<Vec<u8>> + 'static,
 {
-    let packet_type = match utility::read_one_byte(reader).await {
-        None => return Ok(None),
-        Some(pt) => pt,
+    let packet_type = match utility::read_first_byte(reader).await {
+        Ok(None) => return Ok(Some(Received::Closed)),
+        Err(_) => return Ok(None),
+        Ok(Some(pt)) => pt,
     };
     match packet_type {
         DATA => {
@@ -396,6 +610,10 @@ where
             };
             if checksum(&pay_load) != check_sum {
                 Err(ConnectionError::DataCorrupted)
+            } else if pay_load.is_empty() {
+                // no message encodes to zero bytes,
+                // do not pass an empty buffer to `try_from`
+                Err(ConnectionError::DecodeError)
             } else {
                 match Rsp::try_from(pay_load) {
                     Ok(rsp) => Ok(Some(Received::Response(rsp))),
@@ -417,6 +635,25 @@ where
     }
 }
 
+/// `read_rsp`, or `Err(ReadTimeout)` if no message is read within `read_timeout`
+async fn read_rsp_within<Rsp>(
+    reader: &mut BufReader<ReadHalf<TlsStream<TcpStream>>>,
+    max_data_size: u32,
+    read_timeout: Option<Duration>,
+) -> Result<Option<Received<Rsp>>, ConnectionError>
+where
+    Rsp: TryFrom<Vec<u8>> + 'static,
+{
+    match read_timeout {
+        None => read_rsp(reader, max_data_size).await,
+        Some(read_timeout) => {
+            async_std::future::timeout(read_timeout, read_rsp(reader, max_data_size))
+                .await
+                .unwrap_or(Err(ConnectionError::ReadTimeout))
+        }
+    }
+}
+
 /// Attempt to write message to TcpStream.
 ///
 /// On write error, return `WriteZero`.
@@ -474,6 +711,7 @@ impl ConnectionError {
             ConnectionError::UnknownMessageType => 201,
             ConnectionError::DecodeError => 202,
             ConnectionError::DataCorrupted => 203,
+            ConnectionError::ReadTimeout => 204,
         }
     }
 
@@ -483,6 +721,7 @@ impl ConnectionError {
             201 => ConnectionError::UnknownMessageType,
             202 => ConnectionError::DecodeError,
             203 => ConnectionError::DataCorrupted,
+            204 => ConnectionError::ReadTimeout,
             _ => ConnectionError::UnknownError,
         }
     }
@@ -498,32 +737,37 @@ where
             Received::Ping => f.write_str("Responses::Ping"),
             Received::Error(_) => f.write_str("Responses::Error"),
             Received::RemoteError(_) => f.write_str("Responses::RemoteError"),
+            Received::Reconnected => f.write_str("Responses::Reconnected"),
+            Received::Closed => f.write_str("Responses::Closed"),
         }
     }
 }
 
 #[cfg(test)]
 mod test_network_module {
-    use crate::network::connection::{handle_connection, Conn, ConnectionError, Received};
+    use crate::network::connection::{
+        forward_messages, handle_connection, ClosePolicy, Conn, ConnectionError, MessageType,
+        Received, RequestError, NET_CHANNEL_SIZE,
+    };
     use async_std::channel::{bounded, Receiver};
     use async_std::net::{TcpListener, TcpStream};
     use async_std::task;
     use futures::executor::block_on;
     use futures::StreamExt;
-    use tokio_rustls::TlsAcceptor;
-    use tokio_rustls::{TlsConnector, TlsStream};
     use lazy_static::lazy_static;
     use rand::random;
     use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName};
     use rustls_pemfile::{certs, pkcs8_private_keys};
     use std::fs::File;
     use std::io::BufReader;
-    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4};
     use std::ops::Deref;
     use std::path::PathBuf;
     use std::sync::Arc;
     use std::time::Duration;
     use std::vec;
+    use tokio_rustls::TlsAcceptor;
+    use tokio_rustls::{TlsConnector, TlsStream};
 
     struct NotEmpty(Vec<u8>);
 
@@ -637,8 +881,13 @@ mod test_network_module {
         // send bytes from server
         let server_life = task::spawn(async move {
             let (tls, _) = conn.next().await.unwrap();
-            let server: Conn<Vec<u8>, Vec<u8>> =
-                handle_connection(tls, Some(Duration::from_millis(10)), 128);
+            let server: Conn<Vec<u8>, Vec<u8>> = handle_connection(
+                tls,
+                Some(Duration::from_millis(10)),
+                128,
+                ClosePolicy::Flush,
+                None,
+            );
             for bytes in rand_bytes_clone.iter() {
                 task::sleep(Duration::from_millis(10)).await;
                 server.sender().send(bytes.clone()).await.unwrap();
@@ -652,8 +901,13 @@ mod test_network_module {
             let tcp = TcpStream::connect(test_address(8889)).await.unwrap();
             client_tls(tcp).await
         });
-        let mut client: Conn<Vec<u8>, Vec<u8>> =
-            handle_connection(tls, Some(Duration::from_millis(10)), 128);
+        let mut client: Conn<Vec<u8>, Vec<u8>> = handle_connection(
+            tls,
+            Some(Duration::from_millis(10)),
+            128,
+            ClosePolicy::Flush,
+            None,
+        );
         let responses = block_on(async move {
             let mut responses: Vec<Vec<u8>> = Vec::with_capacity(100);
             while let Some(b) = client.next().await {
@@ -683,7 +937,8 @@ mod test_network_module {
         // send bytes from server
         let server_future = task::spawn(async move {
             let (tcp, _) = conn.next().await.unwrap();
-            let server: Conn<Vec<u8>, Vec<u8>> = handle_connection(tcp, None, 128);
+            let server: Conn<Vec<u8>, Vec<u8>> =
+                handle_connection(tcp, None, 128, ClosePolicy::Flush, None);
             server
         });
 
@@ -693,7 +948,8 @@ mod test_network_module {
             let tcp = TcpStream::connect(test_address(8888)).await.unwrap();
             client_tls(tcp).await
         });
-        let client: Conn<Vec<u8>, Vec<u8>> = handle_connection(tls, None, 128);
+        let client: Conn<Vec<u8>, Vec<u8>> =
+            handle_connection(tls, None, 128, ClosePolicy::Flush, None);
         task::spawn(async move {
             for bytes in rand_bytes_clone.iter() {
                 client.sender().send(bytes.clone()).await.unwrap();
@@ -705,6 +961,8 @@ mod test_network_module {
             while let Some(b) = server.next().await {
                 match b {
                     Received::Response(b) => responses.push(b),
+                    // the client is dropped after sending
+                    Received::Closed => {}
                     _ => panic!("error receiving message"),
                 }
             }
@@ -714,6 +972,132 @@ mod test_network_module {
         assert_eq!(rand_bytes.deref(), &responses)
     }
 
+    #[test]
+    fn clean_close_reported() {
+        let port: u16 = 9992;
+        let mut conn = start_server(port);
+        let server_future = task::spawn(async move {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let (tls, _) = conn.next().await.unwrap();
+                let mut server: Conn<Vec<u8>, Vec<u8>> =
+                    handle_connection(tls, None, 128, ClosePolicy::Flush, None);
+                let mut items = Vec::new();
+                while let Some(item) = server.next().await {
+                    items.push(item);
+                }
+                received.push(items);
+            }
+            received
+        });
+        block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            // dropping the connection closes it cleanly
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            let client: Conn<Vec<u8>, Vec<u8>> =
+                handle_connection(client_tls(tcp).await, None, 128, ClosePolicy::Flush, None);
+            client.sender().send(vec![1]).await.unwrap();
+            drop(client);
+            // shutting down the socket under TLS is abrupt
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            let socket = tcp.clone();
+            let client: Conn<Vec<u8>, Vec<u8>> =
+                handle_connection(client_tls(tcp).await, None, 128, ClosePolicy::Flush, None);
+            client.sender().send(vec![2]).await.unwrap();
+            task::sleep(Duration::from_millis(100)).await;
+            socket.shutdown(Shutdown::Both).unwrap();
+            let received = server_future.await;
+            assert!(matches!(
+                received[0][..],
+                [Received::Response(_), Received::Closed]
+            ));
+            assert!(matches!(received[1][..], [Received::Response(_)]));
+            drop(client);
+        });
+    }
+
+    #[test]
+    fn nodelay_enabled() {
+        let port: u16 = 9991;
+        let mut conn = start_server(port);
+        block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            let connected = tcp.clone();
+            assert!(!connected.nodelay().unwrap());
+            let _client: Conn<Vec<u8>, Vec<u8>> = Conn::init(client_tls(tcp).await, None, 128);
+            assert!(connected.nodelay().unwrap());
+            let (tls, _) = conn.next().await.unwrap();
+            let accepted = tls.get_ref().0.clone();
+            let _server: Conn<Vec<u8>, Vec<u8>> = Conn::init(tls, None, 128);
+            assert!(accepted.nodelay().unwrap());
+        });
+    }
+
+    #[test]
+    fn silent_peer_read_timeout() {
+        let port: u16 = 9990;
+        let mut conn = start_server(port);
+        block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            // the client stays connected without sending anything
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            let silent = client_tls(tcp).await;
+            let (tls, _) = conn.next().await.unwrap();
+            let read_timeout = Duration::from_millis(300);
+            let mut server: Conn<Vec<u8>, Vec<u8>> =
+                Conn::init_with_read_timeout(tls, None, 128, read_timeout);
+            let closed = async_std::future::timeout(Duration::from_secs(2), async {
+                (server.next().await, server.next().await)
+            })
+            .await;
+            assert!(matches!(
+                closed,
+                Ok((Some(Received::Error(ConnectionError::ReadTimeout)), None))
+            ));
+            // a pinging client stays connected past the read timeout
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            let ping_interval = Some(Duration::from_millis(50));
+            let client: Conn<Vec<u8>, Vec<u8>> =
+                Conn::init(client_tls(tcp).await, ping_interval, 128);
+            let (tls, _) = conn.next().await.unwrap();
+            let mut server: Conn<Vec<u8>, Vec<u8>> =
+                Conn::init_with_read_timeout(tls, None, 128, read_timeout);
+            task::sleep(Duration::from_millis(600)).await;
+            client.sender().send(vec![1]).await.unwrap();
+            let received = async_std::future::timeout(Duration::from_secs(1), async {
+                loop {
+                    match server.next().await {
+                        Some(Received::Ping) => {}
+                        received => return received,
+                    }
+                }
+            })
+            .await;
+            assert!(matches!(received, Ok(Some(Received::Response(_)))));
+            drop(silent);
+        });
+    }
+
+    #[test]
+    fn received_into_response() {
+        let rsp: Received<Vec<u8>> = Received::Response(vec![1, 2]);
+        assert_eq!(rsp.into_response(), Some(Ok(vec![1, 2])));
+        assert_eq!(Received::<Vec<u8>>::Ping.into_response(), None);
+        assert_eq!(Received::<Vec<u8>>::Reconnected.into_response(), None);
+        assert_eq!(Received::<Vec<u8>>::Closed.into_response(), None);
+        let error = Received::<Vec<u8>>::Error(ConnectionError::DecodeError);
+        assert_eq!(
+            error.into_response(),
+            Some(Err(ConnectionError::DecodeError))
+        );
+        let remote_error = Received::<Vec<u8>>::RemoteError(ConnectionError::DataCorrupted);
+        assert_eq!(
+            remote_error.into_response(),
+            Some(Err(ConnectionError::DataCorrupted))
+        );
+    }
+
     #[test]
     fn send_bytes_decode_fail() {
         let port: u16 = 9999;
@@ -722,7 +1106,8 @@ mod test_network_module {
         // send bytes from server
         let server_future = task::spawn(async move {
             let (tcp, _) = conn.next().await.unwrap();
-            let server: Conn<Vec<u8>, Vec<u8>> = handle_connection(tcp, None, 128);
+            let server: Conn<Vec<u8>, Vec<u8>> =
+                handle_connection(tcp, None, 128, ClosePolicy::Flush, None);
             let _ = server.sender().send(vec![0]).await;
             let _ = server.sender().send(Vec::new()).await;
             server
@@ -734,7 +1119,8 @@ mod test_network_module {
             client_tls(tcp).await
         });
 
-        let mut client: Conn<Vec<u8>, NotEmpty> = handle_connection(tls, None, 128);
+        let mut client: Conn<Vec<u8>, NotEmpty> =
+            handle_connection(tls, None, 128, ClosePolicy::Flush, None);
         let responses = block_on(async move {
             let mut responses: Vec<Received<NotEmpty>> = Vec::with_capacity(100);
             while let Some(b) = client.next().await {
@@ -753,7 +1139,8 @@ mod test_network_module {
         });
 
         assert_eq!(responses.len(), 2);
-        assert_eq!(server_msg.len(), 1);
+        // the client closes cleanly after reporting the error
+        assert_eq!(server_msg.len(), 2);
         assert!(matches!(responses[0], Received::Response(_)));
         assert!(matches!(
             responses[1],
@@ -763,5 +1150,249 @@ mod test_network_module {
             server_msg[0],
             Received::RemoteError(ConnectionError::DecodeError)
         ));
+        assert!(matches!(server_msg[1], Received::Closed));
+    }
+
+    #[test]
+    fn zero_length_frame_decode_error() {
+        let port: u16 = 9998;
+        let mut conn = start_server(port);
+
+        // send a zero-length frame from server
+        let server_future = task::spawn(async move {
+            let (tcp, _) = conn.next().await.unwrap();
+            let server: Conn<Vec<u8>, Vec<u8>> =
+                handle_connection(tcp, None, 128, ClosePolicy::Flush, None);
+            let _ = server.sender().send(Vec::new()).await;
+            server
+        });
+
+        let tls = block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            client_tls(tcp).await
+        });
+
+        // `Vec<u8>` accepts any bytes, the frame is rejected by the reader
+        let mut client: Conn<Vec<u8>, Vec<u8>> =
+            handle_connection(tls, None, 128, ClosePolicy::Flush, None);
+        let responses = block_on(async move {
+            let mut responses: Vec<Received<Vec<u8>>> = Vec::new();
+            while let Some(b) = client.next().await {
+                responses.push(b);
+            }
+            responses
+        });
+        drop(server_future);
+
+        assert_eq!(responses.len(), 1);
+        assert!(matches!(
+            responses[0],
+            Received::Error(ConnectionError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn request_response() {
+        let port: u16 = 9996;
+        let mut conn = start_server(port);
+
+        // reply to the first message only, ping in between
+        let server_future = task::spawn(async move {
+            let (tls, _) = conn.next().await.unwrap();
+            let mut server: Conn<Vec<u8>, Vec<u8>> = handle_connection(
+                tls,
+                Some(Duration::from_millis(10)),
+                128,
+                ClosePolicy::Flush,
+                None,
+            );
+            while let Some(received) = server.next().await {
+                if let Received::Response(login) = received {
+                    task::sleep(Duration::from_millis(50)).await;
+                    server.sender().send(login).await.unwrap();
+                    break;
+                }
+            }
+            server
+        });
+
+        let tls = block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            client_tls(tcp).await
+        });
+        let mut client: Conn<Vec<u8>, Vec<u8>> = handle_connection(
+            tls,
+            Some(Duration::from_millis(10)),
+            128,
+            ClosePolicy::Flush,
+            None,
+        );
+        block_on(async move {
+            let timeout = Duration::from_millis(500);
+            assert_eq!(
+                client.request(vec![1, 2, 3], timeout).await,
+                Ok(vec![1, 2, 3])
+            );
+            assert_eq!(
+                client.request(vec![4, 5, 6], timeout).await,
+                Err(RequestError::Timeout)
+            );
+        });
+        drop(server_future);
+    }
+
+    #[test]
+    fn split_halves_in_separate_tasks() {
+        let port: u16 = 9994;
+        let mut conn = start_server(port);
+        let rand_bytes = Arc::new(gen_rand_bytes(50, 5));
+        let rand_bytes_clone = rand_bytes.clone();
+
+        // echo with the sending half and the receiving half owned by separate tasks
+        let server_future = task::spawn(async move {
+            let (tls, _) = conn.next().await.unwrap();
+            let server: Conn<Vec<u8>, Vec<u8>> =
+                handle_connection(tls, None, 128, ClosePolicy::Flush, None);
+            let (sender, mut receiver) = server.split();
+            let (echo, mut echoed) = bounded(NET_CHANNEL_SIZE);
+            let sending = task::spawn(async move {
+                while let Some(bytes) = echoed.next().await {
+                    sender.send(bytes).await.unwrap();
+                }
+                sender
+            });
+            let receiving = task::spawn(async move {
+                for _ in 0..rand_bytes_clone.len() {
+                    match receiver.next().await {
+                        Some(Received::Response(b)) => echo.send(b).await.unwrap(),
+                        _ => panic!("error receiving message"),
+                    }
+                }
+                receiver
+            });
+            (sending.await, receiving.await)
+        });
+
+        let tls = block_on(async move {
+            task::sleep(Duration::from_millis(100)).await;
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            client_tls(tcp).await
+        });
+        let mut client: Conn<Vec<u8>, Vec<u8>> =
+            handle_connection(tls, None, 128, ClosePolicy::Flush, None);
+        let responses = block_on(async {
+            for bytes in rand_bytes.iter() {
+                client.sender().send(bytes.clone()).await.unwrap();
+            }
+            let mut responses: Vec<Vec<u8>> = Vec::with_capacity(50);
+            while responses.len() < rand_bytes.len() {
+                match client.next().await {
+                    Some(Received::Response(b)) => responses.push(b),
+                    _ => panic!("error receiving message"),
+                }
+            }
+            responses
+        });
+        assert_eq!(rand_bytes.deref(), &responses);
+
+        // dropping both halves closes the connection cleanly
+        let (sender, receiver) = block_on(server_future);
+        drop(sender);
+        drop(receiver);
+        let closed = block_on(async_std::future::timeout(Duration::from_secs(1), async {
+            (client.next().await, client.next().await)
+        }));
+        assert!(matches!(closed, Ok((Some(Received::Closed), None))));
+    }
+
+    fn queue_then_close(close_policy: ClosePolicy) -> Vec<u8> {
+        let (msg_sender, msg_receiver) = bounded(10);
+        let (inner_msg_sender, inner_msg_receiver) = bounded(10);
+        for i in 0..5u8 {
+            msg_sender.try_send(vec![i]).unwrap();
+        }
+        // close right after queueing
+        drop(msg_sender);
+        block_on(forward_messages(
+            msg_receiver,
+            inner_msg_sender,
+            inner_msg_receiver.clone(),
+            close_policy,
+        ));
+        let mut sent = Vec::new();
+        while let Ok(msg) = inner_msg_receiver.try_recv() {
+            match msg {
+                MessageType::Data(b) => sent.extend(b),
+                _ => panic!("unexpected message type"),
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn queued_messages_flushed_on_close() {
+        assert_eq!(queue_then_close(ClosePolicy::Flush), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn queued_messages_discarded_on_close() {
+        assert!(queue_then_close(ClosePolicy::Discard).is_empty());
+    }
+
+    #[test]
+    fn resumable_connection_survives_transport_drop() {
+        let port = 9995;
+        let mut accepted = start_server(port);
+        let (server_transports, transports) = bounded(1);
+        task::spawn(async move {
+            while let Some((tls, _)) = accepted.next().await {
+                if server_transports.send(tls).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let server_future = task::spawn(async move {
+            let mut server: Conn<Vec<u8>, Vec<u8>> = Conn::init_resumable(transports, None, 128);
+            let mut received = Vec::new();
+            while received.len() < 50 {
+                match server.next().await.unwrap() {
+                    Received::Response(b) => received.push(b),
+                    Received::Ping | Received::Reconnected | Received::Closed => {}
+                    Received::Error(e) | Received::RemoteError(e) => panic!("{e}"),
+                }
+            }
+            received
+        });
+
+        let (client_transports, transports) = bounded(1);
+        let mut client: Conn<Vec<u8>, Vec<u8>> = Conn::init_resumable(transports, None, 128);
+        let rand_bytes = gen_rand_bytes(50, 5);
+        block_on(async {
+            task::sleep(Duration::from_millis(100)).await;
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            let tcp_clone = tcp.clone();
+            client_transports.send(client_tls(tcp).await).await.unwrap();
+            for bytes in rand_bytes[..25].iter() {
+                client.sender().send(bytes.clone()).await.unwrap();
+            }
+            // drop the transport, then keep sending before reconnecting
+            tcp_clone.shutdown(Shutdown::Both).unwrap();
+            for bytes in rand_bytes[25..].iter() {
+                client.sender().send(bytes.clone()).await.unwrap();
+            }
+            let tcp = TcpStream::connect(test_address(port)).await.unwrap();
+            client_transports.send(client_tls(tcp).await).await.unwrap();
+            loop {
+                match client.next().await.unwrap() {
+                    Received::Reconnected => break,
+                    Received::Ping => {}
+                    _ => panic!("unexpected response"),
+                }
+            }
+        });
+        let received = block_on(server_future);
+        assert_eq!(received, rand_bytes);
     }
 }
@@ -3,6 +3,9 @@
 //! This module contains basic utility for establishing
 //! stable network connection.
 pub(crate) mod connection;
+pub(crate) mod resumable;
 pub(crate) mod utility;
 
-pub use connection::{Conn, ConnectionError, Received};
+pub use connection::{
+    ClosePolicy, Conn, ConnReceiver, ConnSender, ConnectionError, Received, RequestError,
+};
@@ -0,0 +1,355 @@
+//! A connection surviving transport drops.
+//!
+//! Data frames carry sequence numbers, and are kept until remote acknowledges them.
+//! When the transport drops, the connection waits for a replacement transport,
+//! resends all unacknowledged frames on it, and reports `Received::Reconnected`.
+//! Remote skips the frames it has already received,
+//! so no message is lost or duplicated across transports.
+//!
+//! Both sides must run in resumable mode. The owner of each side supplies
+//! the replacement transports: a client reconnects to the server,
+//! and a server routes the new transport of the client to its existing connection.
+//!
+//! Messages sent while no transport is available are queued,
+//! and written once a replacement arrives.
+//! The connection closes when all senders are dropped (remote receives `BYE`),
+//! on receiving `BYE`, on frame errors, or when no more transport can be supplied.
+//!
+//! frame structures:
+//! - data: `[SEQ_DATA, SEQ, SIZE, PAYLOAD, CHECKSUM]`
+//! - acknowledgement of all data frames up to `SEQ`: `[ACK, SEQ]`
+//! - ping: `[PING]`
+//! - close: `[BYE]`
+use crate::network::connection::{ConnectionError, Received, NET_CHANNEL_SIZE, PING};
+use crate::network::utility;
+use async_std::channel::{bounded, Receiver, Sender};
+use async_std::io::BufReader;
+use async_std::net::TcpStream;
+use async_std::task;
+use crc32fast::hash as checksum;
+use futures::channel::oneshot;
+use futures::io::{ReadHalf, WriteHalf};
+use futures::stream::Fuse;
+use futures::{select, AsyncReadExt, AsyncWriteExt, FutureExt, StreamExt};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio_rustls::TlsStream;
+
+// frame types
+const SEQ_DATA: u8 = 1;
+const ACK: u8 = 2;
+const BYE: u8 = 3;
+
+enum Frame {
+    Data(u64, Vec<u8>),
+    Ack(u64),
+    Ping,
+    Bye,
+}
+
+/// how serving a transport ended
+enum Served {
+    /// the transport dropped, wait for a replacement
+    Dropped,
+    /// the connection is closed
+    Closed,
+}
+
+/// state kept across transports
+struct Resumable {
+    /// sequence number of the next data frame, starting from 1
+    next_seq: u64,
+    /// data frames sent but not yet acknowledged by remote
+    unacked: VecDeque<(u64, Vec<u8>)>,
+    /// sequence number of the latest data frame received, 0 if none
+    received: u64,
+}
+
+pub(crate) fn handle_resumable_connection<Msg, Rsp>(
+    transports: Receiver<TlsStream<TcpStream>>,
+    ping_interval: Option<Duration>,
+    max_data_size: u32,
+) -> (Sender<Msg>, Receiver<Received<Rsp>>)
+where
+    Msg: Send + 'static + Into<Vec<u8>>,
+    Rsp: Send + 'static + TryFrom<Vec<u8>>,
+{
+    let (msg_sender, msg_receiver) = bounded::<Msg>(NET_CHANNEL_SIZE);
+    let (rsp_sender, rsp_receiver) = bounded(NET_CHANNEL_SIZE);
+    task::spawn(async move {
+        let mut state = Resumable {
+            next_seq: 1,
+            unacked: VecDeque::new(),
+            received: 0,
+        };
+        let mut transports = transports.fuse();
+        let mut messages = msg_receiver.fuse();
+        let mut reconnected = false;
+        loop {
+            // queue messages while waiting for a transport
+            let tls = loop {
+                select! {
+                    tls = transports.next() => match tls {
+                        Some(tls) => break tls,
+                        None => return,
+                    },
+                    msg = messages.next() => match msg {
+                        Some(msg) => {
+                            if state.queue(msg.into(), max_data_size).is_none() {
+                                let e = ConnectionError::MaxDataLengthExceeded;
+                                let _ = rsp_sender.send(Received::Error(e)).await;
+                                return;
+                            }
+                        }
+                        None => return,
+                    },
+                }
+            };
+            if reconnected && rsp_sender.send(Received::Reconnected).await.is_err() {
+                return;
+            }
+            reconnected = true;
+            let served = state
+                .serve::<Msg, Rsp>(
+                    tls,
+                    &mut messages,
+                    &rsp_sender,
+                    ping_interval,
+                    max_data_size,
+                )
+                .await;
+            if let Served::Closed = served {
+                return;
+            }
+        }
+    });
+    (msg_sender, rsp_receiver)
+}
+
+impl Resumable {
+    /// assign a sequence number to the payload and keep it until acknowledged,
+    /// `None` if the payload is too large
+    fn queue(&mut self, payload: Vec<u8>, max_data_size: u32) -> Option<u64> {
+        if payload.len() > max_data_size as usize {
+            return None;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.unacked.push_back((seq, payload));
+        Some(seq)
+    }
+
+    /// remote received all data frames up to `seq`
+    fn acknowledge(&mut self, seq: u64) {
+        while self.unacked.front().is_some_and(|(s, _)| *s <= seq) {
+            self.unacked.pop_front();
+        }
+    }
+
+    /// exchange frames over `tls` until it drops or the connection closes
+    async fn serve<Msg, Rsp>(
+        &mut self,
+        tls: TlsStream<TcpStream>,
+        messages: &mut Fuse<Receiver<Msg>>,
+        rsp_sender: &Sender<Received<Rsp>>,
+        ping_interval: Option<Duration>,
+        max_data_size: u32,
+    ) -> Served
+    where
+        Msg: Into<Vec<u8>>,
+        Rsp: TryFrom<Vec<u8>>,
+    {
+        utility::set_nodelay(&tls);
+        let (read_tls, mut write_tls) = tls.split();
+        let (stopper, stop_reading) = oneshot::channel::<()>();
+        let mut frames = read_frames(read_tls, stop_reading, max_data_size).fuse();
+        let served = 'serve: {
+            // remote might have missed these on the previous transport
+            for (seq, payload) in self.unacked.iter() {
+                if write_data(&mut write_tls, *seq, payload).await.is_err() {
+                    break 'serve Served::Dropped;
+                }
+            }
+            let mut ping_sleeper = Box::pin(ping_timer(ping_interval).fuse());
+            loop {
+                select! {
+                    msg = messages.next() => match msg {
+                        Some(msg) => {
+                            let Some(seq) = self.queue(msg.into(), max_data_size) else {
+                                let e = ConnectionError::MaxDataLengthExceeded;
+                                let _ = rsp_sender.send(Received::Error(e)).await;
+                                break Served::Closed;
+                            };
+                            let payload = &self.unacked.back().unwrap().1;
+                            if write_data(&mut write_tls, seq, payload).await.is_err() {
+                                break Served::Dropped;
+                            }
+                        }
+                        None => {
+                            let _ = write_frame(&mut write_tls, &[BYE]).await;
+                            let _ = write_tls.close().await;
+                            break Served::Closed;
+                        }
+                    },
+                    frame = frames.next() => match frame {
+                        Some(Ok(Frame::Data(seq, payload))) => {
+                            if seq > self.received {
+                                self.received = seq;
+                                let rsp = match Rsp::try_from(payload).ok() {
+                                    Some(rsp) => Received::Response(rsp),
+                                    None => {
+                                        let e = ConnectionError::DecodeError;
+                                        let _ = rsp_sender.send(Received::Error(e)).await;
+                                        break Served::Closed;
+                                    }
+                                };
+                                if rsp_sender.send(rsp).await.is_err() {
+                                    break Served::Closed;
+                                }
+                            }
+                            if write_ack(&mut write_tls, self.received).await.is_err() {
+                                break Served::Dropped;
+                            }
+                        }
+                        Some(Ok(Frame::Ack(seq))) => self.acknowledge(seq),
+                        Some(Ok(Frame::Ping)) => {
+                            if rsp_sender.send(Received::Ping).await.is_err() {
+                                break Served::Closed;
+                            }
+                        }
+                        Some(Ok(Frame::Bye)) => break Served::Closed,
+                        Some(Err(e)) => {
+                            let _ = rsp_sender.send(Received::Error(e)).await;
+                            break Served::Closed;
+                        }
+                        None => break Served::Dropped,
+                    },
+                    _ = ping_sleeper => {
+                        if write_frame(&mut write_tls, &[PING]).await.is_err() {
+                            break Served::Dropped;
+                        }
+                        ping_sleeper = Box::pin(ping_timer(ping_interval).fuse());
+                    }
+                }
+            }
+        };
+        let _ = stopper.send(());
+        served
+    }
+}
+
+/// sleep for `ping_interval`, or forever if pinging is disabled
+async fn ping_timer(ping_interval: Option<Duration>) {
+    match ping_interval {
+        Some(interval) => task::sleep(interval).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// read frames from a transport until it ends, errs, or gets stopped
+fn read_frames(
+    read_tls: ReadHalf<TlsStream<TcpStream>>,
+    stop_reading: oneshot::Receiver<()>,
+    max_data_size: u32,
+) -> Receiver<Result<Frame, ConnectionError>> {
+    let (frame_sender, frames) = bounded(NET_CHANNEL_SIZE);
+    task::spawn(async move {
+        let mut reader = BufReader::new(read_tls);
+        let mut stop_reading = stop_reading.fuse();
+        loop {
+            select! {
+                _ = stop_reading => break,
+                frame = read_frame(&mut reader, max_data_size).fuse() => match frame {
+                    Ok(Some(frame)) => {
+                        if frame_sender.send(Ok(frame)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = frame_sender.send(Err(e)).await;
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    frames
+}
+
+/// `Ok(None)` if no more data to read
+async fn read_frame(
+    reader: &mut BufReader<ReadHalf<TlsStream<TcpStream>>>,
+    max_data_size: u32,
+) -> Result<Option<Frame>, ConnectionError> {
+    let frame_type = match utility::read_one_byte(reader).await {
+        None => return Ok(None),
+        Some(t) => t,
+    };
+    match frame_type {
+        SEQ_DATA => {
+            let seq = match utility::read_be_u64(reader).await {
+                None => return Ok(None),
+                Some(s) => s,
+            };
+            let size = match utility::read_be_u32(reader).await {
+                None => return Ok(None),
+                Some(s) => s,
+            };
+            if size > max_data_size {
+                return Err(ConnectionError::MaxDataLengthExceeded);
+            }
+            let pay_load = match utility::read_n_bytes(reader, size).await {
+                None => return Ok(None),
+                Some(p) => p,
+            };
+            let check_sum = match utility::read_be_u32(reader).await {
+                None => return Ok(None),
+                Some(s) => s,
+            };
+            if checksum(&pay_load) != check_sum {
+                Err(ConnectionError::DataCorrupted)
+            } else if pay_load.is_empty() {
+                Err(ConnectionError::DecodeError)
+            } else {
+                Ok(Some(Frame::Data(seq, pay_load)))
+            }
+        }
+        ACK => Ok(utility::read_be_u64(reader).await.map(Frame::Ack)),
+        PING => Ok(Some(Frame::Ping)),
+        BYE => Ok(Some(Frame::Bye)),
+        _ => Err(ConnectionError::UnknownMessageType),
+    }
+}
+
+async fn write_data(
+    tls: &mut WriteHalf<TlsStream<TcpStream>>,
+    seq: u64,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let size = u32::try_from(payload.len()).map_err(|_| ErrorKind::InvalidData)?;
+    // type + seq + payload size + payload + checksum
+    let mut dat = Vec::with_capacity(1 + 8 + 4 + payload.len() + 4);
+    dat.push(SEQ_DATA);
+    dat.extend(seq.to_be_bytes());
+    dat.extend(size.to_be_bytes());
+    dat.extend(payload);
+    dat.extend(checksum(payload).to_be_bytes());
+    write_frame(tls, &dat).await
+}
+
+async fn write_ack(tls: &mut WriteHalf<TlsStream<TcpStream>>, seq: u64) -> std::io::Result<()> {
+    let mut dat = Vec::with_capacity(1 + 8);
+    dat.push(ACK);
+    dat.extend(seq.to_be_bytes());
+    write_frame(tls, &dat).await
+}
+
+async fn write_frame(
+    tls: &mut WriteHalf<TlsStream<TcpStream>>,
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    tls.write_all(bytes).await?;
+    tls.flush().await
+}
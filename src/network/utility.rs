@@ -1,5 +1,13 @@
 use async_std::io::BufReader;
+use async_std::net::TcpStream;
 use futures::{AsyncRead, AsyncReadExt};
+use tokio_rustls::TlsStream;
+
+/// disable Nagle's algorithm, so that small messages such as moves are sent at once
+pub fn set_nodelay(tls: &TlsStream<TcpStream>) {
+    // failing to do so only costs latency
+    let _ = tls.get_ref().0.set_nodelay(true);
+}
 
 pub async fn read_n_bytes<S>(reader: &mut BufReader<S>, n: u32) -> Option<Vec<u8>>
 where
@@ -25,6 +33,35 @@ where
     }
 }
 
+pub async fn read_be_u64<S>(reader: &mut BufReader<S>) -> Option<u64>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut bytes = [0u8; 8];
+    if reader.read_exact(&mut bytes).await.is_err() {
+        None
+    } else {
+        Some(u64::from_be_bytes(bytes))
+    }
+}
+
+/// `Ok(None)` if the stream ends cleanly before the byte,
+/// e.g. on TLS `close_notify`, which an abrupt close lacks
+pub async fn read_first_byte<S>(reader: &mut BufReader<S>) -> std::io::Result<Option<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut byte = [0u8; 1];
+    loop {
+        match reader.read(&mut byte).await {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub async fn read_one_byte<S>(reader: &mut BufReader<S>) -> Option<u8>
 where
     S: AsyncRead + Unpin,